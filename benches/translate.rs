@@ -0,0 +1,108 @@
+// Parse, codegen and write timed separately, over one large synthetic file
+// and a multi-file project shaped like compiled Jack.
+
+use std::{env, fs, path::PathBuf};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use n2t_vm_translator::translator::Translator;
+
+// every command kind, repeated to 100k commands
+fn synthetic() -> String {
+    let body = "push constant 7\npush local 2\nadd\npop argument 1\n\
+                push static 3\npush that 5\nlt\nif-goto SKIP\nneg\nnot\n\
+                push temp 6\npush pointer 1\neq\npop this 4\nlabel SKIP\n\
+                push argument 0\ngt\nsub\nand\nor\n";
+    let mut text = "function Big.main 4\n".to_string();
+    for _ in 0..100_000 / 20 {
+        text.push_str(body);
+    }
+    text.push_str("return\n");
+    text
+}
+
+// classes of small functions calling each other, written to a temp dir
+fn project() -> PathBuf {
+    let dir = env::temp_dir().join(format!("n2t-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for class in 0..8 {
+        let mut text = String::new();
+        for function in 0..12 {
+            text.push_str(&format!(
+                "function Class{0}.f{1} 2\n\
+                 push constant 0\npop local 0\n\
+                 label LOOP\n\
+                 push local 0\npush argument 0\nlt\nnot\nif-goto END\n\
+                 push this 1\npush local 0\ncall Class{2}.f{1} 2\n\
+                 pop static {1}\n\
+                 push local 0\npush constant 1\nadd\npop local 0\n\
+                 goto LOOP\n\
+                 label END\n\
+                 push static {1}\nreturn\n",
+                class,
+                function,
+                (class + 1) % 8
+            ));
+        }
+        fs::write(dir.join(format!("Class{}.vm", class)), text).unwrap();
+    }
+    fs::write(
+        dir.join("Sys.vm"),
+        "function Sys.init 0\npush constant 10\ncall Class0.f0 1\n\
+         label HALT\ngoto HALT\n",
+    )
+    .unwrap();
+    dir
+}
+
+// routines hand the translator back so dropping it isn't timed
+fn phases(c: &mut Criterion, name: &str, new: &dyn Fn() -> Translator) {
+    let mut group = c.benchmark_group(name);
+    let out = env::temp_dir()
+        .join(format!("n2t-bench-{}-{}.asm", name, std::process::id()))
+        .display()
+        .to_string();
+
+    group.bench_function("parse", |b| {
+        b.iter_batched(
+            new,
+            |mut t| {
+                t.parse().unwrap();
+                t
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("codegen", |b| {
+        b.iter_batched(
+            || {
+                let mut t = new();
+                t.parse().unwrap();
+                t
+            },
+            |mut t| {
+                t.generate().unwrap();
+                t
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    let mut t = new();
+    t.process().unwrap();
+    group.bench_function("write", |b| b.iter(|| t.write_bin(&out).unwrap()));
+
+    group.finish();
+    let _ = fs::remove_file(&out);
+}
+
+fn translate(c: &mut Criterion) {
+    let text = synthetic();
+    phases(c, "synthetic", &|| Translator::from_source("Big", &text));
+
+    let dir = project();
+    phases(c, "project", &|| Translator::new(&dir).unwrap());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+criterion_group!(benches, translate);
+criterion_main!(benches);
@@ -0,0 +1,55 @@
+// sequences of VM words, numbers and symbols, which get much further into
+// codegen than random bytes do
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use n2t_vm_translator::{assembler, parser::Line, translator::Translator};
+
+const WORDS: &[&str] = &[
+    "push", "pop", "add", "sub", "neg", "eq", "gt", "lt", "and", "or", "not",
+    "label", "goto", "if-goto", "function", "call", "return", "constant",
+    "local", "argument", "this", "that", "temp", "pointer", "static",
+    "Sys.init", "//",
+];
+
+#[derive(Arbitrary, Debug)]
+enum Token {
+    Word(u8),
+    Number(i64),
+    Symbol(String),
+    Space,
+    Tab,
+    Newline,
+    CrLf,
+}
+
+fuzz_target!(|tokens: Vec<Token>| {
+    let mut text = String::new();
+    for token in &tokens {
+        match token {
+            Token::Word(i) => {
+                text.push_str(WORDS[*i as usize % WORDS.len()]);
+                text.push(' ');
+            }
+            Token::Number(n) => text.push_str(&format!("{} ", n)),
+            Token::Symbol(s) => text.push_str(s),
+            Token::Space => text.push(' '),
+            Token::Tab => text.push('\t'),
+            Token::Newline => text.push('\n'),
+            Token::CrLf => text.push_str("\r\n"),
+        }
+    }
+
+    let mut translator = Translator::from_source("Fuzz", &text);
+    if translator.parse().is_err() {
+        return;
+    }
+    // whatever parses prints back to text that parses to the same line
+    for line in translator.lines() {
+        assert_eq!(&Line::new(&line.to_string()).unwrap(), line);
+    }
+    if translator.generate().is_ok() {
+        let _ = assembler::assemble(&translator.render());
+    }
+});
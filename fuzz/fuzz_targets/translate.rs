@@ -0,0 +1,15 @@
+// arbitrary bytes as a .vm file: translating may fail, but never panic
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use n2t_vm_translator::{assembler, translator::Translator};
+
+fuzz_target!(|data: &[u8]| {
+    let text = String::from_utf8_lossy(data);
+    let mut translator = Translator::from_source("Fuzz", &text);
+    if translator.process().is_ok() {
+        let _ = assembler::assemble(&translator.render());
+        let _ = translator.render_map();
+        let _ = translator.split(3);
+    }
+});
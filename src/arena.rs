@@ -0,0 +1,48 @@
+use std::ops::Range;
+
+/// Strings stored end to end in one buffer, so that the text of a whole
+/// file takes a few growing allocations rather than one per line, and is
+/// read back in order from memory that's close together.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Arena {
+    text: String,
+    // where each string ends in text
+    ends: Vec<usize>,
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena::default()
+    }
+
+    pub fn push(&mut self, s: &str) {
+        self.text.push_str(s);
+        self.ends.push(self.text.len());
+    }
+
+    /// The `i`th string pushed.
+    pub fn get(&self, i: usize) -> Option<&str> {
+        self.range(i).map(|range| &self.text[range])
+    }
+
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        (0..self.len()).filter_map(move |i| self.get(i))
+    }
+
+    fn range(&self, i: usize) -> Option<Range<usize>> {
+        let end = *self.ends.get(i)?;
+        let start = match i {
+            0 => 0,
+            _ => self.ends[i - 1],
+        };
+        Some(start..end)
+    }
+}
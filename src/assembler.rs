@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::instruction::Instruction;
+
+// first address handed out to variables
+const VARIABLE_BASE: u16 = 16;
+
+#[derive(Debug)]
+pub struct Assembled {
+    pub words: Vec<u16>,
+    pub symbols: HashMap<String, u16>,
+    // the instruction each word was assembled from, whitespace removed
+    pub source: Vec<String>,
+}
+
+impl Assembled {
+    // one 16 character binary string per instruction, as in a .hack file
+    pub fn to_hack(&self) -> String {
+        self.words.iter().map(|w| format!("{:016b}\n", w)).collect()
+    }
+}
+
+pub(crate) fn predefined() -> HashMap<String, u16> {
+    let mut symbols = HashMap::new();
+    for (name, address) in &[
+        ("SP", 0),
+        ("LCL", 1),
+        ("ARG", 2),
+        ("THIS", 3),
+        ("THAT", 4),
+        ("SCREEN", 16384),
+        ("KBD", 24576),
+    ] {
+        symbols.insert(name.to_string(), *address);
+    }
+    for r in 0..16 {
+        symbols.insert(format!("R{}", r), r);
+    }
+    symbols
+}
+
+// dest=comp;jump with dest and jump optional, and comps either way round
+fn encode_c(raw: &str) -> Result<u16> {
+    let instruction = Instruction::read_c(raw, true)?;
+    Ok(instruction.word().expect("a C-instruction"))
+}
+
+/// Assemble Hack assembly into machine words, resolving labels and
+/// allocating variables from RAM[16] in order of first use.
+pub fn assemble(asm: &str) -> Result<Assembled> {
+    // strip comments and all whitespace, keeping source line numbers
+    let lines: Vec<(usize, String)> = asm
+        .lines()
+        .enumerate()
+        .map(|(i, raw)| {
+            let code = raw.split("//").next().unwrap_or("");
+            (i + 1, code.chars().filter(|c| !c.is_whitespace()).collect())
+        })
+        .filter(|(_, code): &(usize, String)| !code.is_empty())
+        .collect();
+
+    // first pass: labels
+    let mut symbols = predefined();
+    let mut rom: u16 = 0;
+    for (n, code) in &lines {
+        if let Some(label) = code.strip_prefix('(') {
+            let label = label
+                .strip_suffix(')')
+                .ok_or_else(|| anyhow!("line {}: unclosed label", n))?;
+            symbols.insert(label.to_string(), rom);
+        } else {
+            rom = rom
+                .checked_add(1)
+                .ok_or_else(|| anyhow!("line {}: program too large", n))?;
+        }
+    }
+
+    // second pass: instructions
+    let mut words = Vec::new();
+    let mut source = Vec::new();
+    let mut next_variable = VARIABLE_BASE;
+    for (n, code) in &lines {
+        if code.starts_with('(') {
+            continue;
+        }
+        let word = match code.strip_prefix('@') {
+            Some(value) if value.starts_with(|c: char| c.is_ascii_digit()) => {
+                match value.parse::<u16>() {
+                    Ok(v) if v < 0x8000 => v,
+                    _ => return Err(anyhow!("line {}: invalid address", n)),
+                }
+            }
+            Some(symbol) => match symbols.get(symbol) {
+                Some(address) => *address,
+                None => {
+                    let address = next_variable;
+                    symbols.insert(symbol.to_string(), address);
+                    next_variable += 1;
+                    address
+                }
+            },
+            None => encode_c(code).map_err(|e| anyhow!("line {}: {}", n, e))?,
+        };
+        words.push(word);
+        source.push(code.clone());
+    }
+
+    Ok(Assembled {
+        words,
+        symbols,
+        source,
+    })
+}
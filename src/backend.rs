@@ -0,0 +1,264 @@
+use std::fmt::{self, Write};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    emulator::MEMORY,
+    interpreter::{self, Linked, Program},
+    parser::{
+        BinaryToken, BranchToken, ComparisonToken, ExtensionToken,
+        FunctionToken, Line, Segment, StackToken, UnaryToken,
+    },
+    translator::Translator,
+};
+
+/// A machine a translated program can be generated for, from the one
+/// parse and optimize run.
+pub trait Backend: fmt::Debug {
+    fn name(&self) -> &'static str;
+    /// The extension of the file it writes, in place of `.vm`.
+    fn extension(&self) -> &'static str;
+    fn generate(&self, translator: &Translator) -> Result<String>;
+}
+
+/// Hack assembly, the translator's own output.
+#[derive(Debug)]
+pub struct Hack;
+
+impl Backend for Hack {
+    fn name(&self) -> &'static str {
+        "hack"
+    }
+
+    fn extension(&self) -> &'static str {
+        "asm"
+    }
+
+    fn generate(&self, translator: &Translator) -> Result<String> {
+        Ok(translator.render())
+    }
+}
+
+/// A C program that runs the VM commands over the same RAM layout, for
+/// checking a program's behaviour off the Hack platform.
+#[derive(Debug)]
+pub struct C;
+
+impl Backend for C {
+    fn name(&self) -> &'static str {
+        "c"
+    }
+
+    fn extension(&self) -> &'static str {
+        "c"
+    }
+
+    fn generate(&self, translator: &Translator) -> Result<String> {
+        // every command is numbered, so jumps and return addresses are
+        // cases of one switch
+        let Program {
+            commands,
+            targets,
+            statics,
+        } = interpreter::link(translator.ir()?);
+        let end = commands.len();
+        // function names resolve whatever the caller's scope
+        let function = |name: &str| {
+            targets
+                .get(name)
+                .copied()
+                .ok_or_else(|| anyhow!("call to undefined function {}", name))
+        };
+        let target = |scope: &str, label: &str| {
+            targets
+                .get(&format!("{}${}", scope, label))
+                .copied()
+                .ok_or_else(|| anyhow!("no label {} in {}", label, scope))
+        };
+
+        let mut c = String::new();
+        writeln!(c, "/* C generated from VM code by n2t-vm-translator */")?;
+        writeln!(c, "#include <stdint.h>\n#include <stdio.h>\n")?;
+        writeln!(c, "static int16_t ram[{}];\n", MEMORY)?;
+        writeln!(c, "#define PUSH(v) (ram[ram[0]++] = (v))")?;
+        writeln!(c, "#define POP() (ram[--ram[0]])\n")?;
+        writeln!(c, "int main(void) {{")?;
+        writeln!(c, "    int16_t x, y;\n    int pc = 0, frame, i;\n")?;
+        match targets.get("Sys.init") {
+            Some(_) => {
+                writeln!(c, "    ram[0] = 256;")?;
+                // returning from Sys.init ends the program
+                writeln!(c, "    {}", call("Sys.init", 0, end, &function)?)?;
+            }
+            // where the course's test scripts point the segments
+            None => writeln!(
+                c,
+                "    ram[0] = 256; ram[1] = 300; ram[2] = 400; \
+                 ram[3] = 3000; ram[4] = 3010;"
+            )?,
+        }
+        writeln!(c, "    for (;;) {{\n        switch (pc) {{")?;
+        for (i, linked) in commands.iter().enumerate() {
+            let Linked {
+                line, file, scope, ..
+            } = linked;
+            let case = matches!(
+                line,
+                Line::Function(FunctionToken::Function { .. })
+                    | Line::Branch(BranchToken::Label(_))
+            ) || matches!(
+                i.checked_sub(1).map(|i| commands[i].line),
+                Some(Line::Function(FunctionToken::Call { .. }))
+            );
+            if i == 0 {
+                writeln!(c, "        case 0:")?;
+            } else if case {
+                writeln!(
+                    c,
+                    "            /* fall through */\n        case {}:",
+                    i
+                )?;
+            }
+            writeln!(c, "            /* {} */", line)?;
+            let statement = match line {
+                Line::Stack(StackToken::Push { segment, index }) => {
+                    match segment {
+                        Segment::Constant => format!("PUSH({});", index),
+                        _ => format!(
+                            "PUSH(ram[{}]);",
+                            address(segment, *index, statics[*file])
+                        ),
+                    }
+                }
+                Line::Stack(StackToken::Pop { segment, index }) => {
+                    if *segment == Segment::Constant {
+                        return Err(anyhow!("cannot pop constant"));
+                    }
+                    format!(
+                        "x = POP(); ram[{}] = x;",
+                        address(segment, *index, statics[*file])
+                    )
+                }
+                Line::Binary(op) => format!(
+                    "y = POP(); x = POP(); PUSH(x {} y);",
+                    match op {
+                        BinaryToken::Add => "+",
+                        BinaryToken::Sub => "-",
+                        BinaryToken::And => "&",
+                        BinaryToken::Or => "|",
+                    }
+                ),
+                Line::Unary(op) => format!(
+                    "x = POP(); PUSH({}x);",
+                    match op {
+                        UnaryToken::Neg => "-",
+                        UnaryToken::Not => "~",
+                    }
+                ),
+                // on the difference, as the generated asm compares
+                Line::Comparison(op) => format!(
+                    "y = POP(); x = POP(); \
+                     PUSH((int16_t)(x - y) {} 0 ? -1 : 0);",
+                    match op {
+                        ComparisonToken::Equal => "==",
+                        ComparisonToken::GreaterThan => ">",
+                        ComparisonToken::LessThan => "<",
+                    }
+                ),
+                Line::Branch(BranchToken::Label(_)) => continue,
+                Line::Branch(BranchToken::GoTo(label)) => {
+                    match target(scope, label)? {
+                        // a label straight before its own goto is how a VM
+                        // program stops
+                        to if to + 1 == i => "return 0;".to_string(),
+                        to => format!("pc = {}; continue;", to),
+                    }
+                }
+                Line::Branch(BranchToken::IfGoTo(label)) => format!(
+                    "if (POP()) {{ pc = {}; continue; }}",
+                    target(scope, label)?
+                ),
+                Line::Function(FunctionToken::Function {
+                    locals: 0, ..
+                }) => continue,
+                Line::Function(FunctionToken::Function { locals, .. }) => {
+                    format!("for (i = 0; i < {}; i++) PUSH(0);", locals)
+                }
+                Line::Function(FunctionToken::Call { name, args }) => {
+                    format!(
+                        "{} continue;",
+                        call(name, *args, i + 1, &function)?
+                    )
+                }
+                Line::Function(FunctionToken::Return) => "frame = ram[1]; \
+                     pc = (uint16_t)ram[frame - 5]; \
+                     ram[ram[2]] = POP(); ram[0] = ram[2] + 1; \
+                     ram[4] = ram[frame - 1]; ram[3] = ram[frame - 2]; \
+                     ram[2] = ram[frame - 3]; ram[1] = ram[frame - 4]; \
+                     continue;"
+                    .to_string(),
+                Line::Extension(ExtensionToken::Assert) => {
+                    "if (!POP()) { fputs(\"an assert failed\\n\", stderr); \
+                     return 1; }"
+                        .to_string()
+                }
+                Line::Extension(ExtensionToken::Halt) => {
+                    "return 0;".to_string()
+                }
+                Line::Extension(ExtensionToken::Print) => {
+                    "printf(\"%d\\n\", POP());".to_string()
+                }
+            };
+            writeln!(c, "            {}", statement)?;
+        }
+        writeln!(c, "            /* fall through */\n        default:")?;
+        writeln!(c, "            return 0;\n        }}\n    }}\n}}")?;
+        Ok(c)
+    }
+}
+
+// push the frame and jump, coming back to the case `ret`
+fn call(
+    name: &str,
+    args: u16,
+    ret: usize,
+    function: &impl Fn(&str) -> Result<usize>,
+) -> Result<String> {
+    Ok(format!(
+        "PUSH({}); PUSH(ram[1]); PUSH(ram[2]); PUSH(ram[3]); PUSH(ram[4]); \
+         ram[2] = ram[0] - {}; ram[1] = ram[0]; pc = {};",
+        ret,
+        5 + args,
+        function(name)?
+    ))
+}
+
+// the C expression for the address of a segment word
+fn address(segment: &Segment, index: u16, statics: usize) -> String {
+    match segment {
+        Segment::Local => format!("ram[1] + {}", index),
+        Segment::Argument => format!("ram[2] + {}", index),
+        Segment::This => format!("ram[3] + {}", index),
+        Segment::That => format!("ram[4] + {}", index),
+        Segment::Temp => (5 + index).to_string(),
+        Segment::Pointer => (3 + index).to_string(),
+        Segment::Static => (statics + index as usize).to_string(),
+        Segment::Constant => unreachable!("constants have no address"),
+    }
+}
+
+/// The backends named in a comma-separated list, each once.
+pub fn targets(raw: &str) -> Result<Vec<Box<dyn Backend>>> {
+    let mut targets: Vec<Box<dyn Backend>> = Vec::new();
+    for name in raw.split(',').map(str::trim) {
+        let target: Box<dyn Backend> = match name {
+            "hack" => Box::new(Hack),
+            "c" => Box::new(C),
+            _ => return Err(anyhow!("unexpected target: {}", name)),
+        };
+        if targets.iter().all(|t| t.name() != target.name()) {
+            targets.push(target);
+        }
+    }
+    Ok(targets)
+}
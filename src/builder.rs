@@ -0,0 +1,121 @@
+use anyhow::Result;
+
+use crate::parser::{BinaryToken, ComparisonToken, Line, Segment, UnaryToken};
+
+/// Builds a list of VM commands, validating each one as it is added. The
+/// first invalid command is reported by `build`, so calls can be chained:
+///
+/// ```
+/// use n2t_vm_translator::{builder::Builder, parser::Segment};
+///
+/// let lines = Builder::new()
+///     .function("Main.main", 0)
+///     .push(Segment::Constant, 7)
+///     .push(Segment::Constant, 8)
+///     .add()
+///     .ret()
+///     .build()
+///     .unwrap();
+/// assert_eq!(lines.len(), 5);
+/// ```
+#[derive(Debug, Default)]
+pub struct Builder {
+    lines: Vec<Line>,
+    err: Option<anyhow::Error>,
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder::default()
+    }
+
+    pub fn line(mut self, line: Result<Line>) -> Builder {
+        match line {
+            Ok(line) => self.lines.push(line),
+            Err(err) => {
+                if self.err.is_none() {
+                    self.err = Some(err);
+                }
+            }
+        }
+        self
+    }
+
+    pub fn push(self, segment: Segment, index: u16) -> Builder {
+        self.line(Line::push(segment, index))
+    }
+
+    pub fn pop(self, segment: Segment, index: u16) -> Builder {
+        self.line(Line::pop(segment, index))
+    }
+
+    pub fn add(self) -> Builder {
+        self.line(Ok(Line::Binary(BinaryToken::Add)))
+    }
+
+    pub fn sub(self) -> Builder {
+        self.line(Ok(Line::Binary(BinaryToken::Sub)))
+    }
+
+    pub fn and(self) -> Builder {
+        self.line(Ok(Line::Binary(BinaryToken::And)))
+    }
+
+    pub fn or(self) -> Builder {
+        self.line(Ok(Line::Binary(BinaryToken::Or)))
+    }
+
+    // named after the VM commands, not the std::ops traits
+    #[allow(clippy::should_implement_trait)]
+    pub fn neg(self) -> Builder {
+        self.line(Ok(Line::Unary(UnaryToken::Neg)))
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Builder {
+        self.line(Ok(Line::Unary(UnaryToken::Not)))
+    }
+
+    pub fn eq(self) -> Builder {
+        self.line(Ok(Line::Comparison(ComparisonToken::Equal)))
+    }
+
+    pub fn lt(self) -> Builder {
+        self.line(Ok(Line::Comparison(ComparisonToken::LessThan)))
+    }
+
+    pub fn gt(self) -> Builder {
+        self.line(Ok(Line::Comparison(ComparisonToken::GreaterThan)))
+    }
+
+    pub fn label(self, label: &str) -> Builder {
+        self.line(Line::label(label))
+    }
+
+    pub fn goto(self, label: &str) -> Builder {
+        self.line(Line::goto(label))
+    }
+
+    pub fn if_goto(self, label: &str) -> Builder {
+        self.line(Line::if_goto(label))
+    }
+
+    pub fn function(self, name: &str, locals: u16) -> Builder {
+        self.line(Line::function(name, locals))
+    }
+
+    pub fn call(self, name: &str, args: u16) -> Builder {
+        self.line(Line::call(name, args))
+    }
+
+    pub fn ret(self) -> Builder {
+        self.line(Ok(Line::ret()))
+    }
+
+    pub fn build(self) -> Result<Vec<Line>> {
+        match self.err {
+            Some(err) => Err(err),
+            None => Ok(self.lines),
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use crate::parser::{BranchToken, ExtensionToken, FunctionToken, Line};
+
+#[derive(Debug)]
+struct Block<'a> {
+    lines: Vec<&'a Line>,
+}
+
+impl<'a> Block<'a> {
+    fn label(&self) -> Option<&'a str> {
+        match self.lines.first() {
+            Some(Line::Branch(BranchToken::Label(label))) => Some(label),
+            _ => None,
+        }
+    }
+
+    fn terminated(&self) -> bool {
+        matches!(
+            self.lines.last(),
+            Some(Line::Branch(BranchToken::GoTo(_)))
+                | Some(Line::Branch(BranchToken::IfGoTo(_)))
+                | Some(Line::Function(FunctionToken::Return))
+                | Some(Line::Extension(ExtensionToken::Halt))
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Function<'a> {
+    name: String,
+    blocks: Vec<Block<'a>>,
+}
+
+// group lines by function, leaving anything before the first function
+// declaration in a top level pseudo function
+fn split_functions<'a>(
+    lines: impl IntoIterator<Item = &'a Line>,
+) -> Vec<(String, Vec<&'a Line>)> {
+    let mut functions: Vec<(String, Vec<&Line>)> = Vec::new();
+    for line in lines {
+        match line {
+            Line::Function(FunctionToken::Function { name, .. }) => {
+                functions.push((name.to_string(), vec![line]));
+            }
+            _ => match functions.last_mut() {
+                Some((_, body)) => body.push(line),
+                None => functions.push(("(top level)".to_string(), vec![line])),
+            },
+        }
+    }
+    functions
+}
+
+fn split_blocks(body: Vec<&Line>) -> Vec<Block<'_>> {
+    let mut blocks: Vec<Block> = Vec::new();
+    for line in body {
+        let leader = matches!(line, Line::Branch(BranchToken::Label(_)));
+        match blocks.last_mut() {
+            Some(block) if !block.terminated() && !leader => {
+                block.lines.push(line)
+            }
+            _ => blocks.push(Block { lines: vec![line] }),
+        }
+    }
+    blocks
+}
+
+fn escape(raw: &str) -> String {
+    raw.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Render the control-flow graph of every function in `lines` as a DOT
+/// digraph, one cluster per function.
+pub fn to_dot<'a>(lines: impl IntoIterator<Item = &'a Line>) -> String {
+    let functions: Vec<Function> = split_functions(lines)
+        .into_iter()
+        .map(|(name, body)| Function {
+            name,
+            blocks: split_blocks(body),
+        })
+        .collect();
+
+    let mut dot = String::new();
+    dot.push_str("digraph cfg {\n");
+    dot.push_str("    node [shape=box, fontname=monospace];\n");
+
+    for (f, function) in functions.iter().enumerate() {
+        let node = |b: usize| format!("f{}_b{}", f, b);
+        let exit = format!("f{}_exit", f);
+
+        // labels are scoped to their function, same as in codegen
+        let targets: HashMap<&str, usize> = function
+            .blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(b, block)| Some((block.label()?, b)))
+            .collect();
+
+        dot.push_str(&format!("\n    subgraph cluster_{} {{\n", f));
+        dot.push_str(&format!(
+            "        label=\"{}\";\n",
+            escape(&function.name)
+        ));
+
+        let mut edges = Vec::new();
+        let mut returns = false;
+        let mut missing = Vec::new();
+
+        for (b, block) in function.blocks.iter().enumerate() {
+            let text: String = block
+                .lines
+                .iter()
+                .map(|line| format!("{}\\l", escape(&line.to_string())))
+                .collect();
+            dot.push_str(&format!(
+                "        {} [label=\"{}\"];\n",
+                node(b),
+                text
+            ));
+
+            let next = (b + 1 < function.blocks.len()).then(|| node(b + 1));
+            let mut jump = |label: &str| match targets.get(label) {
+                Some(t) => node(*t),
+                None => {
+                    let m = format!("f{}_missing{}", f, missing.len());
+                    missing.push((m.clone(), label.to_string()));
+                    m
+                }
+            };
+
+            match block.lines.last() {
+                Some(Line::Branch(BranchToken::GoTo(label))) => {
+                    edges.push((node(b), jump(label), None));
+                }
+                Some(Line::Branch(BranchToken::IfGoTo(label))) => {
+                    edges.push((node(b), jump(label), Some("true")));
+                    if let Some(next) = next {
+                        edges.push((node(b), next, Some("false")));
+                    }
+                }
+                Some(Line::Function(FunctionToken::Return)) => {
+                    returns = true;
+                    edges.push((node(b), exit.clone(), None));
+                }
+                // the program stops there
+                Some(Line::Extension(ExtensionToken::Halt)) => {}
+                _ => {
+                    if let Some(next) = next {
+                        edges.push((node(b), next, None));
+                    }
+                }
+            }
+        }
+
+        if returns {
+            dot.push_str(&format!(
+                "        {} [label=\"return\", shape=oval];\n",
+                exit
+            ));
+        }
+        for (m, label) in &missing {
+            dot.push_str(&format!(
+                "        {} [label=\"{}?\", style=dashed];\n",
+                m,
+                escape(label)
+            ));
+        }
+        for (from, to, label) in edges {
+            match label {
+                Some(label) => dot.push_str(&format!(
+                    "        {} -> {} [label=\"{}\"];\n",
+                    from, to, label
+                )),
+                None => dot.push_str(&format!("        {} -> {};\n", from, to)),
+            }
+        }
+
+        dot.push_str("    }\n");
+    }
+
+    dot.push_str("}\n");
+    dot
+}
@@ -1,143 +1,685 @@
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+    fmt, mem,
+};
 
 use anyhow::{anyhow, Result};
 
-use crate::parser::{
-    BinaryToken, BranchToken, ComparisonToken, Line, Segment, StackToken,
-    UnaryToken,
+use crate::{
+    instruction::Instruction,
+    intern::intern_fmt,
+    parser::{
+        BinaryToken, BranchToken, ComparisonToken, ExtensionToken,
+        FunctionToken, Line, Segment, Span, StackToken, UnaryToken,
+    },
 };
 
+// A-instructions for segments, indices and symbols repeat throughout a
+// program, so they're interned rather than allocated each time
+fn at(target: impl fmt::Display) -> Instruction {
+    Instruction::A(Cow::Borrowed(intern_fmt(format_args!("{}", target))))
+}
+
+/// How scratch registers are named in the output: `@R13`, which the
+/// assembler predefines, or the bare address `@13`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Registers {
+    Symbolic,
+    Numeric,
+}
+
+impl Registers {
+    pub fn new(raw: &str) -> Result<Registers> {
+        match raw {
+            "symbolic" => Ok(Registers::Symbolic),
+            "numeric" => Ok(Registers::Numeric),
+            _ => Err(anyhow!("unexpected register style: {}", raw)),
+        }
+    }
+}
+
+// past this many pushes in a run, the longer reach above SP costs more
+// than moving SP for each
+const BATCH_MAX: u16 = 4;
+
+// past this index a push adds the index to the segment base, which takes
+// 5 instructions; stepping A there directly takes 3 plus the index
+const DIRECT_PUSH_MAX: u16 = 2;
+
+// past this index a pop stages its address in scratch, which takes 14
+// instructions; stepping A there directly takes 6 plus the index
+const DIRECT_POP_MAX: u16 = 7;
+
+/// Bumped whenever the same input and options give different asm, and
+/// written in the header, so that a diff between two versions' output is
+/// expected rather than a surprise.
+pub const CODEGEN_VERSION: u32 = 2;
+
+/// What starts the comment lines --explain writes under a block's own
+/// comment, to tell them apart from comments kept from the source.
+pub const EXPLAIN: &str = "//| ";
+
+// the widest an explanation line gets
+const EXPLAIN_WIDTH: usize = 76;
+
+/// From this many locals a function zeroes them in a loop, 9 instructions
+/// in all but 7 cycles a local against 5 unrolled.
+pub const INIT_LOOP: u16 = 8;
+
+/// The fewest locals a loop zeroes in fewer instructions than unrolling.
+pub const INIT_LOOP_SIZE: u16 = 2;
+
+/// Where a failed assert leaves its ID, counting the program's asserts
+/// from 1: the last word of the heap.
+pub const ASSERT_ADDRESS: u16 = 16383;
+
+/// Where halt leaves its exit code, counting the program's halts from 1,
+/// so a run that got to one reads nonzero.
+pub const HALT_ADDRESS: u16 = 16382;
+
+/// Which of the print row's 32 words the next print draws in.
+pub const PRINT_CURSOR: u16 = 16381;
+
+// print draws in the bottom 8 pixel rows of the screen, which it keeps to
+// itself
+const PRINT_ROW: u16 = 16384 + 248 * 32;
+
+/// Code emitted once per program and jumped to, rather than repeated
+/// wherever it's needed. Most of it only with `--shared-routines`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Routine {
+    Eq,
+    Gt,
+    Lt,
+    Call,
+    Return,
+    Assert,
+    Print,
+}
+
+impl Routine {
+    fn label(&self) -> &'static str {
+        match self {
+            Routine::Eq => "__VM_EQ",
+            Routine::Gt => "__VM_GT",
+            Routine::Lt => "__VM_LT",
+            Routine::Call => "__VM_CALL",
+            Routine::Return => "__VM_RETURN",
+            Routine::Assert => "__VM_ASSERT",
+            Routine::Print => "__VM_PRINT",
+        }
+    }
+}
+
+// the jump taken exactly when `jump` isn't
+fn invert(jump: &'static str) -> &'static str {
+    match jump {
+        "D; JEQ" => "D; JNE",
+        "D; JNE" => "D; JEQ",
+        "D; JGT" => "D; JLE",
+        "D; JLE" => "D; JGT",
+        "D; JLT" => "D; JGE",
+        "D; JGE" => "D; JLT",
+        _ => unreachable!("not a conditional jump: {}", jump),
+    }
+}
+
+/// The A-instruction for RAM `address` used as a register, in the style
+/// asked for; only R0 to R15 have symbols.
+pub fn register(registers: Registers, address: u16) -> Instruction {
+    match (registers, address) {
+        (Registers::Symbolic, r @ 0..=15) => at(format_args!("R{}", r)),
+        (_, r) => at(r),
+    }
+}
+
+/// The RAM the translator uses as scratch unless told otherwise.
+pub const SCRATCH: [u16; 3] = [13, 14, 15];
+
+/// Check a set of scratch registers: at least the two that codegen needs,
+/// none of them VM state (SP, LCL, ARG, THIS, THAT or temp) and no repeats.
+pub fn check_scratch(registers: &[u16]) -> Result<()> {
+    if registers.len() < 2 {
+        return Err(anyhow!("scratch needs at least two registers"));
+    }
+    for (i, register) in registers.iter().enumerate() {
+        if !(13..16384).contains(register) {
+            return Err(anyhow!(
+                "scratch register out of range: {} (expected 13 to 16383)",
+                register
+            ));
+        }
+        if registers[..i].contains(register) {
+            return Err(anyhow!("scratch register repeated: {}", register));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct Asm {
+    pub src: String,
+    pub bin: Vec<Instruction>,
+    pub span: Option<Span>,
+    pub function: Option<String>,
+    // the VM command word, for blocks generated from one
+    pub command: Option<&'static str>,
+    // what the block does in plain words, for --explain
+    pub explain: Option<String>,
+    // source comment and blank lines around the command, kept for
+    // --preserve-comments
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+impl Asm {
+    /// The explanation as comment lines, wrapped to fit in
+    /// `EXPLAIN_WIDTH` columns.
+    pub fn explain_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut line = EXPLAIN.to_string();
+        for word in self.explain.iter().flat_map(|text| text.split(' ')) {
+            if line.len() > EXPLAIN.len() {
+                if line.len() + 1 + word.len() > EXPLAIN_WIDTH {
+                    lines.push(mem::replace(&mut line, EXPLAIN.to_string()));
+                } else {
+                    line.push(' ');
+                }
+            }
+            line.push_str(word);
+        }
+        if line.len() > EXPLAIN.len() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    // labels don't occupy ROM
+    pub fn instructions(&self) -> usize {
+        self.bin.iter().filter(|line| !line.is_label()).count()
+    }
+}
+
 #[derive(Debug)]
 pub struct CodeGen {
-    jmps: u8,
-    vs: u8,
+    // the command being generated, counted from the start of its
+    // function, or of the file outside one, and the next to be
+    index: usize,
+    next_index: usize,
+    // labels made for it so far
+    labels: usize,
     statics: HashMap<u16, String>,
+    // the RAM address of the file's first static, when statics are given
+    // addresses here rather than left to the assembler
+    static_base: Option<u16>,
+    // and the static indices, in the order they're laid out from it
+    static_layout: Vec<u16>,
     filename: String,
+    function: Option<String>,
+    registers: Registers,
+    scratch: [u16; 2],
+    // 1 and up batch pushes and fuse commands with the one after
+    opt_level: u8,
+    // pushes written above SP but not yet added to it, and whether the
+    // next push joins them
+    pushed: u16,
+    more: bool,
+    // the command after this one, when optimizing, and what this one
+    // handed over to it
+    next: Next,
+    held: Held,
+    // the slot whose value D holds after the last block, if any
+    d: Option<(Segment, u16)>,
+    // jump to shared routines instead of inlining them, and which ones
+    // this file has jumped to
+    shared: bool,
+    routines: BTreeSet<Routine>,
+    // with a profile, the functions it found hot; those are inlined and
+    // everything else is built for size
+    hot: Option<BTreeSet<String>>,
+    // zero this many locals or more in a loop
+    init_loop: u16,
+    asserts: u16,
+    halts: u16,
+}
+
+// the commands a block can be fused with
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Next {
+    Other,
+    Push,
+    Binary(BinaryToken),
+    Not,
+    IfGoTo,
+}
+
+// what a block fused with the next leaves it instead of a stack top: a
+// pushed value in D, nothing at all for a constant 1 an add or sub can
+// apply itself, or a comparison as x - y in D and the jump taken when it
+// holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Held {
+    Nothing,
+    D,
+    One,
+    Jump(&'static str),
 }
 
 impl CodeGen {
     pub fn new(filename: String) -> CodeGen {
         CodeGen {
-            jmps: 0,
-            vs: 0,
+            index: 0,
+            next_index: 0,
+            labels: 0,
             statics: HashMap::new(),
+            static_base: None,
+            static_layout: Vec::new(),
             filename,
+            function: None,
+            registers: Registers::Symbolic,
+            scratch: [SCRATCH[0], SCRATCH[1]],
+            opt_level: 0,
+            pushed: 0,
+            more: false,
+            next: Next::Other,
+            held: Held::Nothing,
+            d: None,
+            shared: false,
+            routines: BTreeSet::new(),
+            hot: None,
+            init_loop: INIT_LOOP,
+            asserts: 1,
+            halts: 1,
         }
     }
 
-    fn get_jmp_token(&mut self) -> String {
-        let jmp_id = self.jmps;
-        self.jmps += 1;
-        format!("JMP_{}_{}", &self.filename, jmp_id)
+    /// Give statics addresses from `base` on, in the order of `layout`;
+    /// any index not in it goes after the rest as it's first used.
+    pub fn with_statics(mut self, base: u16, layout: Vec<u16>) -> CodeGen {
+        self.static_base = Some(base);
+        self.static_layout = layout;
+        self
     }
 
-    fn get_variable(&mut self) -> String {
-        let v_id = self.vs;
-        self.vs += 1;
-        format!("V_{}_{}", &self.filename, v_id)
+    pub fn with_registers(mut self, registers: Registers) -> CodeGen {
+        self.registers = registers;
+        self
     }
 
-    fn get_static_variable(&mut self, index: &u16) -> String {
-        match self.statics.get(index) {
-            Some(v) => v.to_string(),
-            None => {
-                let v = self.get_variable();
-                self.statics.insert(*index, v.to_string());
-                v
+    /// From 1, let consecutive pushes write above SP and move it once at
+    /// the end of the run, fuse pushes and binary ops with the binary op
+    /// after them, and jump on comparisons and nots feeding an if-goto
+    /// rather than make booleans.
+    /// SP and the stack don't match the VM between them, so this is for
+    /// optimized builds only.
+    pub fn with_opt_level(mut self, level: u8) -> CodeGen {
+        self.opt_level = level;
+        self
+    }
+
+    pub fn with_shared_routines(mut self, shared: bool) -> CodeGen {
+        self.shared = shared;
+        self
+    }
+
+    /// Inline routines and locals' zeroing in `hot` functions only, and
+    /// share and loop elsewhere, whatever the shared routines and init loop
+    /// settings.
+    pub fn with_hot(mut self, hot: Option<BTreeSet<String>>) -> CodeGen {
+        self.hot = hot;
+        self
+    }
+
+    // jump to shared routines from the code being generated
+    fn sharing(&self) -> bool {
+        match &self.hot {
+            Some(hot) => {
+                !self.function.as_ref().is_some_and(|f| hot.contains(f))
             }
+            None => self.shared,
+        }
+    }
+
+    // the fewest locals the current function zeroes in a loop
+    fn init_loop(&self) -> u16 {
+        match (&self.hot, &self.function) {
+            (Some(hot), Some(function)) if hot.contains(function) => u16::MAX,
+            (Some(_), _) => self.init_loop.min(INIT_LOOP_SIZE),
+            (None, _) => self.init_loop,
+        }
+    }
+
+    // the ID of this file's first assert, the ones before it having
+    // been counted in earlier files
+    pub fn with_assert_base(mut self, base: u16) -> CodeGen {
+        self.asserts = base;
+        self
+    }
+
+    // the ID the next assert gets
+    pub fn next_assert(&self) -> u16 {
+        self.asserts
+    }
+
+    // likewise for the exit codes of halts
+    pub fn with_halt_base(mut self, base: u16) -> CodeGen {
+        self.halts = base;
+        self
+    }
+
+    pub fn next_halt(&self) -> u16 {
+        self.halts
+    }
+
+    // at least 1, or there would be no locals to loop over
+    pub fn with_init_loop(mut self, locals: u16) -> CodeGen {
+        self.init_loop = locals.max(1);
+        self
+    }
+
+    // the shared routines this file's code jumps to
+    pub fn routines(&self) -> &BTreeSet<Routine> {
+        &self.routines
+    }
+
+    // only the first two registers of a checked set are needed
+    pub fn with_scratch(mut self, registers: &[u16]) -> CodeGen {
+        self.scratch = [registers[0], registers[1]];
+        self
+    }
+
+    // the nth scratch register
+    fn scratch(&self, n: usize) -> Instruction {
+        register(self.registers, self.scratch[n])
+    }
+
+    // generated names go by where their command is in its function, so
+    // that an edit elsewhere leaves them alone
+    fn scope(&self) -> &str {
+        self.function.as_deref().unwrap_or(&self.filename)
+    }
+
+    fn get_jmp_token(&mut self) -> String {
+        let label = self.labels;
+        self.labels += 1;
+        format!("{}$jmp.{}.{}", self.scope(), self.index, label)
+    }
+
+    // the name the book gives a file's statics
+    fn get_variable(&self, index: u16) -> String {
+        format!("{}.{}", &self.filename, index)
+    }
+
+    // move on to the next command, which a function starts counting from
+    fn next_command(&mut self, line: &Line) {
+        if let Line::Function(FunctionToken::Function { .. }) = line {
+            self.next_index = 0;
+        }
+        self.index = self.next_index;
+        self.next_index += 1;
+        self.labels = 0;
+    }
+
+    // how many distinct statics the file has used so far
+    pub fn statics(&self) -> usize {
+        self.statics.len()
+    }
+
+    // how many addresses the file's statics take, used or not
+    pub fn static_slots(&self) -> usize {
+        self.static_layout.len()
+    }
+
+    /// Each static index used so far with the symbol or address it was
+    /// given, in index order.
+    pub fn static_symbols(&self) -> Vec<(u16, &str)> {
+        let mut symbols: Vec<(u16, &str)> = self
+            .statics
+            .iter()
+            .map(|(index, symbol)| (*index, symbol.as_str()))
+            .collect();
+        symbols.sort();
+        symbols
+    }
+
+    // the function the last generated block belongs to
+    pub fn function(&self) -> Option<&str> {
+        self.function.as_deref()
+    }
+
+    fn get_return_label(&self) -> String {
+        format!("{}$ret.{}", self.scope(), self.index)
+    }
+
+    // labels are scoped to the function they are declared in
+    fn get_label(&self, label: &str) -> String {
+        match &self.function {
+            Some(f) => format!("{}${}", f, label),
+            None => label.to_string(),
         }
     }
 
-    fn get_address(
+    fn get_static_variable(&mut self, index: &u16) -> Result<String> {
+        if let Some(v) = self.statics.get(index) {
+            return Ok(v.to_string());
+        }
+        let v = match self.static_base {
+            // statics have RAM 16 to 255 to themselves
+            Some(base) => {
+                let slot =
+                    match self.static_layout.iter().position(|i| i == index) {
+                        Some(slot) => slot,
+                        None => {
+                            self.static_layout.push(*index);
+                            self.static_layout.len() - 1
+                        }
+                    };
+                let address = base as usize + slot;
+                if address > 255 {
+                    return Err(anyhow!(
+                        "out of static memory: static {} would be at RAM {}",
+                        index,
+                        address
+                    ));
+                }
+                address.to_string()
+            }
+            None => self.get_variable(*index),
+        };
+        self.statics.insert(*index, v.to_string());
+        Ok(v)
+    }
+
+    // load segment[index] into D
+    fn gen_load(
         &mut self,
         segment: &Segment,
         index: &u16,
-    ) -> Result<String> {
-        Ok(if let Segment::Static = segment {
-            self.get_static_variable(index)
-        } else {
-            segment.to_address()?.to_string()
-        })
+    ) -> Result<Vec<Instruction>> {
+        let mut asm = Vec::new();
+        match segment {
+            Segment::Constant => {
+                // use index directly
+                asm.push(at(index));
+                asm.push("D=A".into());
+            }
+            Segment::Static => {
+                // each static is a variable of its own
+                let variable = self.get_static_variable(index)?;
+                asm.push(at(variable));
+                asm.push("D=M".into());
+            }
+            // temp and pointers are fixed, so the address is known here
+            Segment::Temp | Segment::Pointer => {
+                let base: u16 = segment.to_address()?.parse()?;
+                asm.push(at(base + index));
+                asm.push("D=M".into());
+            }
+            // up to index 2, stepping A is no longer than adding
+            _ if *index <= DIRECT_PUSH_MAX => {
+                asm.push(at(segment.to_address()?));
+                asm.push("A=M".into());
+                for _ in 0..*index {
+                    asm.push("A=A+1".into());
+                }
+                asm.push("D=M".into());
+            }
+            _ => {
+                // offset segment by index
+                asm.push(at(index));
+                asm.push("D=A".into());
+                asm.push(at(segment.to_address()?));
+                asm.push("A=D+M".into());
+                asm.push("D=M".into());
+            }
+        };
+        Ok(asm)
     }
 
-    fn gen_stack_block(&mut self, token: &StackToken) -> Result<Vec<String>> {
+    // move SP past the pushes written above it, keeping D if asked
+    fn gen_sp_update(&mut self, keep_d: bool) -> Vec<Instruction> {
+        let mut asm = Vec::new();
+        match mem::replace(&mut self.pushed, 0) {
+            0 => {}
+            n if n > 3 && !keep_d => {
+                self.d = None;
+                asm.push(at(n));
+                asm.push("D=A".into());
+                asm.push("@SP".into());
+                asm.push("M=D+M".into());
+            }
+            n => {
+                asm.push("@SP".into());
+                for _ in 0..n {
+                    asm.push("M=M+1".into());
+                }
+            }
+        }
+        asm
+    }
+
+    fn gen_stack_block(
+        &mut self,
+        token: &StackToken,
+    ) -> Result<Vec<Instruction>> {
+        let known = self.d.take();
         match token {
             StackToken::Push { segment, index } => {
-                let mut asm = Vec::new();
+                // a push of what D already holds needs no load
+                let slot = (segment.clone(), *index);
+                let loaded = known.as_ref() == Some(&slot);
 
-                match segment {
-                    Segment::Constant => {
-                        // use index directly
-                        asm.push(format!("@{}", index));
-                        asm.push(format!("D=A"));
-                    }
-                    _ => {
-                        let address = self.get_address(segment, index)?;
-
-                        // offset segment by index
-                        asm.push(format!("@{}", index));
-                        asm.push(format!("D=A"));
-                        asm.push(format!("@{}", &address));
-
-                        // temp and pointers are fixed with no variables
-                        // but they behave like the other virtual memories
-                        match segment {
-                            Segment::Temp | Segment::Pointer => {
-                                asm.push(format!("A=D+A"));
-                            }
-                            _ => {
-                                asm.push(format!("A=D+M"));
-                            }
+                if let Next::Binary(op) = &self.next {
+                    let one = *segment == Segment::Constant
+                        && *index == 1
+                        && matches!(op, BinaryToken::Add | BinaryToken::Sub);
+                    let mut asm = match one || loaded {
+                        true => Vec::new(),
+                        false => self.gen_load(segment, index)?,
+                    };
+                    asm.extend(self.gen_sp_update(true));
+                    self.held = if one { Held::One } else { Held::D };
+                    return Ok(asm);
+                }
+
+                let mut asm = match loaded {
+                    true => Vec::new(),
+                    false => self.gen_load(segment, index)?,
+                };
+                if self.opt_level >= 1 {
+                    self.d = Some(slot);
+                }
+
+                // the kth push of a run lands k above SP
+                asm.push("@SP".into());
+                match self.pushed {
+                    0 => asm.push("A=M".into()),
+                    k => {
+                        asm.push("A=M+1".into());
+                        for _ in 1..k {
+                            asm.push("A=A+1".into());
                         }
-                        asm.push(format!("D=M"));
                     }
-                };
+                }
+                asm.push("M=D".into());
+                self.pushed += 1;
 
-                asm.push(format!("@SP"));
-                asm.push(format!("A=M"));
-                asm.push(format!("M=D"));
-                asm.push(format!("@SP"));
-                asm.push(format!("M=M+1"));
+                if !self.more {
+                    asm.extend(self.gen_sp_update(false));
+                }
 
                 Ok(asm)
             }
             StackToken::Pop { segment, index } => {
+                // every pop leaves the value it stored in D
+                if self.opt_level >= 1 && *segment != Segment::Constant {
+                    self.d = Some((segment.clone(), *index));
+                }
                 match segment {
                     Segment::Constant => Err(anyhow!("cannot pop constant")),
+                    Segment::Static => {
+                        let variable = self.get_static_variable(index)?;
+                        Ok(vec![
+                            "@SP".into(),
+                            "M=M-1".into(),
+                            "A=M".into(),
+                            "D=M".into(),
+                            at(variable),
+                            "M=D".into(),
+                        ])
+                    }
+                    // temp and pointers are fixed, so the address is
+                    // known here
+                    Segment::Temp | Segment::Pointer => {
+                        let base: u16 = segment.to_address()?.parse()?;
+                        Ok(vec![
+                            "@SP".into(),
+                            "AM=M-1".into(),
+                            "D=M".into(),
+                            at(base + index),
+                            "M=D".into(),
+                        ])
+                    }
+                    // stepping A up to a small index is shorter than
+                    // staging the address
+                    _ if *index <= DIRECT_POP_MAX => {
+                        let mut asm = vec![
+                            "@SP".into(),
+                            "AM=M-1".into(),
+                            "D=M".into(),
+                            at(segment.to_address()?),
+                            "A=M".into(),
+                        ];
+                        for _ in 0..*index {
+                            asm.push("A=A+1".into());
+                        }
+                        asm.push("M=D".into());
+                        Ok(asm)
+                    }
                     _ => {
                         let mut asm = Vec::new();
-                        let dest = self.get_variable();
-                        let address = self.get_address(segment, index)?;
-
-                        // get segment + index and load value into "dest"
-                        asm.push(format!("@{}", index));
-                        asm.push(format!("D=A"));
-                        asm.push(format!("@{}", address));
-
-                        // temp and pointers are fixed, there is no variable
-                        // to look up and load value from
-                        match segment {
-                            Segment::Temp | Segment::Pointer => {
-                                //  wink
-                            }
-                            _ => {
-                                asm.push(format!("A=M"));
-                            }
-                        }
-                        asm.push(format!("D=D+A"));
-                        asm.push(format!("@{}", dest));
-                        asm.push(format!("M=D"));
+                        let address = segment.to_address()?;
+
+                        // get segment + index and stage it in scratch
+                        asm.push(at(index));
+                        asm.push("D=A".into());
+                        asm.push(at(address));
+                        asm.push("A=M".into());
+                        asm.push("D=D+A".into());
+                        asm.push(self.scratch(0));
+                        asm.push("M=D".into());
 
                         // dec SP and load M into D
-                        asm.push(format!("@SP"));
-                        asm.push(format!("M=M-1"));
-                        asm.push(format!("A=M"));
-                        asm.push(format!("D=M"));
+                        asm.push("@SP".into());
+                        asm.push("M=M-1".into());
+                        asm.push("A=M".into());
+                        asm.push("D=M".into());
 
                         // set popped value to saved index
-                        asm.push(format!("@{}", dest));
-                        asm.push(format!("A=M"));
-                        asm.push(format!("M=D"));
+                        asm.push(self.scratch(0));
+                        asm.push("A=M".into());
+                        asm.push("M=D".into());
 
                         Ok(asm)
                     }
@@ -146,11 +688,35 @@ impl CodeGen {
         }
     }
 
-    fn gen_unary_block(&self, token: &UnaryToken) -> Result<Vec<String>> {
+    fn gen_unary_block(
+        &mut self,
+        token: &UnaryToken,
+    ) -> Result<Vec<Instruction>> {
+        if let UnaryToken::Not = token {
+            match mem::replace(&mut self.held, Held::Nothing) {
+                // negating a fused comparison negates its condition
+                Held::Jump(jump) if self.next == Next::IfGoTo => {
+                    self.held = Held::Jump(invert(jump));
+                    return Ok(Vec::new());
+                }
+                Held::Jump(jump) => return Ok(self.gen_boolean(invert(jump))),
+                // not is bitwise, so !x is true unless x is all ones
+                _ if self.next == Next::IfGoTo => {
+                    self.held = Held::Jump("D; JNE");
+                    return Ok(vec![
+                        "@SP".into(),
+                        "AM=M-1".into(),
+                        "D=M+1".into(),
+                    ]);
+                }
+                _ => {}
+            }
+        }
+
         let mut asm = Vec::new();
         if let UnaryToken::Neg = token {
-            asm.push(format!("@0"));
-            asm.push(format!("D=A"));
+            asm.push("@0".into());
+            asm.push("D=A".into());
         }
 
         let operation = match token {
@@ -158,116 +724,806 @@ impl CodeGen {
             UnaryToken::Not => "M=!M",
         };
 
-        asm.push(format!("@SP"));
-        asm.push(format!("A=M-1"));
-        asm.push(format!("{}", operation));
+        asm.push("@SP".into());
+        asm.push("A=M-1".into());
+        asm.push(operation.into());
 
         Ok(asm)
     }
 
-    fn gen_binary_block(&self, token: &BinaryToken) -> Result<Vec<String>> {
-        let operation = match token {
-            BinaryToken::Add => "M=D+M",
-            BinaryToken::Sub => "M=M-D",
-            BinaryToken::And => "M=D&M",
-            BinaryToken::Or => "M=D|M",
+    fn gen_binary_block(
+        &mut self,
+        token: &BinaryToken,
+    ) -> Result<Vec<Instruction>> {
+        // x is in M and y in D; the result goes back to M, or stays in D
+        // for a binary op straight after
+        let (operation, into_d) = match token {
+            BinaryToken::Add => ("M=D+M", "D=D+M"),
+            BinaryToken::Sub => ("M=M-D", "D=M-D"),
+            BinaryToken::And => ("M=D&M", "D=D&M"),
+            BinaryToken::Or => ("M=D|M", "D=D|M"),
+        };
+        let chained = matches!(self.next, Next::Binary(_));
+        let held = mem::replace(&mut self.held, Held::Nothing);
+        if chained {
+            self.held = Held::D;
+        }
+        let asm = match (held, chained) {
+            // fused with the push before, so y never went on the stack
+            (Held::D, false) => {
+                vec!["@SP".into(), "A=M-1".into(), operation.into()]
+            }
+            (Held::One, false) => {
+                let step = match token {
+                    BinaryToken::Add => "M=M+1",
+                    _ => "M=M-1",
+                };
+                vec!["@SP".into(), "A=M-1".into(), step.into()]
+            }
+            // and fused with the op after, so x comes off the stack too
+            (Held::D, true) => {
+                vec!["@SP".into(), "AM=M-1".into(), into_d.into()]
+            }
+            (Held::One, true) => {
+                let step = match token {
+                    BinaryToken::Add => "D=M+1",
+                    _ => "D=M-1",
+                };
+                vec!["@SP".into(), "AM=M-1".into(), step.into()]
+            }
+            (_, true) => vec![
+                "@SP".into(),
+                "AM=M-1".into(),
+                "D=M".into(),
+                "A=A-1".into(),
+                into_d.into(),
+                "@SP".into(),
+                "M=M-1".into(),
+            ],
+            (_, false) => vec![
+                "@SP".into(),
+                "M=M-1".into(),
+                "A=M".into(),
+                "D=M".into(),
+                "A=A-1".into(),
+                operation.into(),
+            ],
         };
-        let mut asm = Vec::new();
-        asm.push(format!("@SP"));
-        asm.push(format!("M=M-1"));
-        asm.push(format!("A=M"));
-        asm.push(format!("D=M"));
-        asm.push(format!("A=A-1"));
-        asm.push(format!("{}", operation));
         Ok(asm)
     }
 
     fn gen_comparison_block(
         &mut self,
         token: &ComparisonToken,
-    ) -> Result<Vec<String>> {
+    ) -> Result<Vec<Instruction>> {
         let cnd_jmp = match token {
-            ComparisonToken::Equal => "JEQ",
-            ComparisonToken::GreaterThan => "JGT",
-            ComparisonToken::LessThan => "JLT",
+            ComparisonToken::Equal => "D; JEQ",
+            ComparisonToken::GreaterThan => "D; JGT",
+            ComparisonToken::LessThan => "D; JLT",
         };
 
+        // the if-goto after jumps on x - y itself, so no boolean is made;
+        // a not in between flips the jump
+        if matches!(self.next, Next::IfGoTo | Next::Not) {
+            self.held = Held::Jump(cnd_jmp);
+            return Ok(vec![
+                "@SP".into(),
+                "AM=M-1".into(),
+                "D=M".into(),
+                "A=A-1".into(),
+                "D=M-D".into(),
+                "@SP".into(),
+                "M=M-1".into(),
+            ]);
+        }
+
+        // the routine returns to the address left in D
+        if self.sharing() {
+            let routine = match token {
+                ComparisonToken::Equal => Routine::Eq,
+                ComparisonToken::GreaterThan => Routine::Gt,
+                ComparisonToken::LessThan => Routine::Lt,
+            };
+            self.routines.insert(routine);
+            let ret = self.get_jmp_token();
+            return Ok(vec![
+                Instruction::A(ret.clone().into()),
+                "D=A".into(),
+                at(routine.label()),
+                "0; JMP".into(),
+                Instruction::Label(ret.into()),
+            ]);
+        }
+
         let if_match = self.get_jmp_token();
         let if_not_match = self.get_jmp_token();
         let done = self.get_jmp_token();
 
-        let mut asm = Vec::new();
-
-        // load 1st number into D
-        asm.push(format!("@SP"));
-        asm.push(format!("M=M-1"));
-        asm.push(format!("A=M"));
-        asm.push(format!("D=M"));
-
-        // load comparison with second numer into D
-        asm.push(format!("A=A-1"));
-        asm.push(format!("D=M-D"));
-
-        // branch from comparison outcome
-        asm.push(format!("@{}", if_match));
-        asm.push(format!("D; {}", cnd_jmp));
-        asm.push(format!("@{}", if_not_match));
-        asm.push(format!("0; JMP"));
-
-        // set D=-1 if numbers were equal
-        asm.push(format!("({})", if_match));
-        asm.push(format!("@0"));
-        asm.push(format!("D=A-1"));
-        asm.push(format!("@{}", done));
-        asm.push(format!("0; JMP"));
-
-        // set D=0 if numbers were not equal
-        asm.push(format!("({})", if_not_match));
-        asm.push(format!("@0"));
-        asm.push(format!("D=A"));
-
-        // set @SP-1 = D
-        asm.push(format!("({})", done));
-        asm.push(format!("@SP"));
-        asm.push(format!("A=M"));
-        asm.push(format!("A=A-1"));
-        asm.push(format!("M=D"));
+        Ok(vec![
+            // load 1st number into D
+            "@SP".into(),
+            "M=M-1".into(),
+            "A=M".into(),
+            "D=M".into(),
+            // load comparison with second numer into D
+            "A=A-1".into(),
+            "D=M-D".into(),
+            // branch from comparison outcome
+            Instruction::A(if_match.clone().into()),
+            cnd_jmp.into(),
+            Instruction::A(if_not_match.clone().into()),
+            "0; JMP".into(),
+            // set D=-1 if numbers were equal
+            Instruction::Label(if_match.into()),
+            "@0".into(),
+            "D=A-1".into(),
+            Instruction::A(done.clone().into()),
+            "0; JMP".into(),
+            // set D=0 if numbers were not equal
+            Instruction::Label(if_not_match.into()),
+            "@0".into(),
+            "D=A".into(),
+            // set @SP-1 = D
+            Instruction::Label(done.into()),
+            "@SP".into(),
+            "A=M".into(),
+            "A=A-1".into(),
+            "M=D".into(),
+        ])
+    }
 
-        Ok(asm)
+    // push -1 when `jump` is taken on D, else 0
+    fn gen_boolean(&mut self, jump: &'static str) -> Vec<Instruction> {
+        let if_true = self.get_jmp_token();
+        let done = self.get_jmp_token();
+        vec![
+            Instruction::A(if_true.clone().into()),
+            jump.into(),
+            "D=0".into(),
+            Instruction::A(done.clone().into()),
+            "0; JMP".into(),
+            Instruction::Label(if_true.into()),
+            "D=-1".into(),
+            Instruction::Label(done.into()),
+            "@SP".into(),
+            "A=M".into(),
+            "M=D".into(),
+            "@SP".into(),
+            "M=M+1".into(),
+        ]
     }
 
-    fn gen_branch_block(&mut self, token: &BranchToken) -> Result<Vec<String>> {
+    fn gen_branch_block(
+        &mut self,
+        token: &BranchToken,
+    ) -> Result<Vec<Instruction>> {
         let mut asm = Vec::new();
         match token {
             BranchToken::Label(label) => {
-                asm.push(format!("({})", label));
+                let label = self.get_label(label);
+                asm.push(Instruction::Label(label.into()));
             }
             BranchToken::GoTo(label) => {
-                asm.push(format!("@{}", label));
-                asm.push(format!("0; JMP"));
+                let label = self.get_label(label);
+                asm.push(Instruction::A(label.into()));
+                asm.push("0; JMP".into());
             }
             BranchToken::IfGoTo(label) => {
+                let label = self.get_label(label);
+
+                // fused with the comparison before
+                if let Held::Jump(jump) =
+                    mem::replace(&mut self.held, Held::Nothing)
+                {
+                    asm.push(Instruction::A(label.into()));
+                    asm.push(jump.into());
+                    return Ok(asm);
+                }
+
                 // pop value off stack
-                asm.push(format!("@SP"));
-                asm.push(format!("M=M-1"));
-                asm.push(format!("A=M"));
-                asm.push(format!("D=M"));
+                asm.push("@SP".into());
+                asm.push("M=M-1".into());
+                asm.push("A=M".into());
+                asm.push("D=M".into());
 
                 // jump if not {false, equal 0}
-                asm.push(format!("@{}", label));
-                asm.push(format!("D; JNE"));
+                asm.push(Instruction::A(label.into()));
+                asm.push("D; JNE".into());
+            }
+        };
+        Ok(asm)
+    }
+
+    fn gen_function_block(
+        &mut self,
+        token: &FunctionToken,
+    ) -> Result<Vec<Instruction>> {
+        let mut asm = Vec::new();
+        match token {
+            FunctionToken::Function { name, locals } => {
+                self.function = Some(name.to_string());
+                asm.push(Instruction::Label(name.clone().into()));
+
+                if *locals >= self.init_loop() {
+                    asm.extend(self.gen_locals_loop(*locals));
+                    return Ok(asm);
+                }
+
+                // zero out locals by pushing them onto the stack
+                for _ in 0..*locals {
+                    asm.push("@SP".into());
+                    asm.push("A=M".into());
+                    asm.push("M=0".into());
+                    asm.push("@SP".into());
+                    asm.push("M=M+1".into());
+                }
+            }
+            FunctionToken::Call { name, args } if self.sharing() => {
+                self.routines.insert(Routine::Call);
+                let ret = self.get_return_label();
+
+                // scratch 0 = callee, scratch 1 = frame size, D = return
+                asm.push(at(args + 5));
+                asm.push("D=A".into());
+                asm.push(self.scratch(1));
+                asm.push("M=D".into());
+                asm.push(at(name));
+                asm.push("D=A".into());
+                asm.push(self.scratch(0));
+                asm.push("M=D".into());
+                asm.push(Instruction::A(ret.clone().into()));
+                asm.push("D=A".into());
+                asm.push(at(Routine::Call.label()));
+                asm.push("0; JMP".into());
+                asm.push(Instruction::Label(ret.into()));
+            }
+            FunctionToken::Call { name, args } => {
+                let ret = self.get_return_label();
+
+                // push return address
+                asm.push(Instruction::A(ret.clone().into()));
+                asm.push("D=A".into());
+                asm.push("@SP".into());
+                asm.push("A=M".into());
+                asm.push("M=D".into());
+                asm.push("@SP".into());
+                asm.push("M=M+1".into());
+
+                // save caller frame
+                for pointer in &["LCL", "ARG", "THIS", "THAT"] {
+                    asm.push(at(pointer));
+                    asm.push("D=M".into());
+                    asm.push("@SP".into());
+                    asm.push("A=M".into());
+                    asm.push("M=D".into());
+                    asm.push("@SP".into());
+                    asm.push("M=M+1".into());
+                }
+
+                // ARG = SP - 5 - args
+                asm.push("@SP".into());
+                asm.push("D=M".into());
+                asm.push(at(args + 5));
+                asm.push("D=D-A".into());
+                asm.push("@ARG".into());
+                asm.push("M=D".into());
+
+                // LCL = SP
+                asm.push("@SP".into());
+                asm.push("D=M".into());
+                asm.push("@LCL".into());
+                asm.push("M=D".into());
+
+                // jump to callee and mark where it comes back to
+                asm.push(at(name));
+                asm.push("0; JMP".into());
+                asm.push(Instruction::Label(ret.into()));
+            }
+            FunctionToken::Return if self.sharing() => {
+                self.routines.insert(Routine::Return);
+                asm.push(at(Routine::Return.label()));
+                asm.push("0; JMP".into());
+            }
+            FunctionToken::Return => asm.extend(self.gen_return()),
+        };
+        Ok(asm)
+    }
+
+    fn gen_extension_block(
+        &mut self,
+        token: &ExtensionToken,
+    ) -> Result<Vec<Instruction>> {
+        match token {
+            ExtensionToken::Assert => {
+                self.routines.insert(Routine::Assert);
+                let id = self.asserts;
+                self.asserts += 1;
+                let ok = self.get_jmp_token();
+                Ok(vec![
+                    "@SP".into(),
+                    "AM=M-1".into(),
+                    "D=M".into(),
+                    at(&ok),
+                    "D; JNE".into(),
+                    at(id),
+                    "D=A".into(),
+                    at(Routine::Assert.label()),
+                    "0; JMP".into(),
+                    Instruction::Label(ok.into()),
+                ])
+            }
+            ExtensionToken::Print => {
+                self.routines.insert(Routine::Print);
+                let ret = self.get_return_label();
+                Ok(vec![
+                    Instruction::A(ret.clone().into()),
+                    "D=A".into(),
+                    at(Routine::Print.label()),
+                    "0; JMP".into(),
+                    Instruction::Label(ret.into()),
+                ])
+            }
+            ExtensionToken::Halt => {
+                let code = self.halts;
+                self.halts += 1;
+                let halt = format!("__VM_HALT_{}", code);
+                Ok(vec![
+                    at(code),
+                    "D=A".into(),
+                    at(HALT_ADDRESS),
+                    "M=D".into(),
+                    Instruction::Label(halt.clone().into()),
+                    at(halt),
+                    "0; JMP".into(),
+                ])
+            }
+        }
+    }
+
+    // zero locals by pushing them onto the stack, counting down in D
+    fn gen_locals_loop(&mut self, locals: u16) -> Vec<Instruction> {
+        let jmp = self.get_jmp_token();
+        vec![
+            at(locals),
+            "D=A".into(),
+            Instruction::Label(jmp.clone().into()),
+            "@SP".into(),
+            "AM=M+1".into(),
+            "A=A-1".into(),
+            "M=0".into(),
+            "D=D-1".into(),
+            at(jmp),
+            "D; JGT".into(),
+        ]
+    }
+
+    // the shared half of a call: push the return address in D and the
+    // caller's frame, then point ARG and LCL at the callee's and jump to
+    // it, with the callee in scratch 0 and args + 5 in scratch 1
+    fn gen_call_frame(&self) -> Vec<Instruction> {
+        let push_d = ["@SP", "AM=M+1", "A=A-1", "M=D"];
+        let mut asm: Vec<Instruction> =
+            push_d.iter().map(|&line| line.into()).collect();
+        for pointer in &["LCL", "ARG", "THIS", "THAT"] {
+            asm.push(at(pointer));
+            asm.push("D=M".into());
+            asm.extend(push_d.iter().map(|&line| line.into()));
+        }
+
+        // ARG = SP - 5 - args
+        asm.push(self.scratch(1));
+        asm.push("D=M".into());
+        asm.push("@SP".into());
+        asm.push("D=M-D".into());
+        asm.push("@ARG".into());
+        asm.push("M=D".into());
+
+        // LCL = SP
+        asm.push("@SP".into());
+        asm.push("D=M".into());
+        asm.push("@LCL".into());
+        asm.push("M=D".into());
+
+        asm.push(self.scratch(0));
+        asm.push("A=M".into());
+        asm.push("0; JMP".into());
+        asm
+    }
+
+    // draw the stack top as a word of pixels, bit 0 leftmost, 8 rows high
+    // at the cursor, then pop it and move the cursor on; the return
+    // address comes in D
+    fn gen_print(&self) -> Vec<Instruction> {
+        // scratch 0 = return address, scratch 1 = where the next row goes
+        let mut asm = vec![
+            self.scratch(0),
+            "M=D".into(),
+            at(PRINT_CURSOR),
+            "D=M".into(),
+            "@31".into(),
+            "D=D&A".into(),
+            at(PRINT_ROW),
+            "D=D+A".into(),
+            self.scratch(1),
+            "M=D".into(),
+        ];
+        for row in 0..8 {
+            asm.push("@SP".into());
+            asm.push("A=M-1".into());
+            asm.push("D=M".into());
+            asm.push(self.scratch(1));
+            asm.push("A=M".into());
+            asm.push("M=D".into());
+            if row < 7 {
+                asm.push("@32".into());
+                asm.push("D=A".into());
+                asm.push(self.scratch(1));
+                asm.push("M=D+M".into());
+            }
+        }
+
+        // the cursor wraps around at the end of the row
+        asm.push(at(PRINT_CURSOR));
+        asm.push("D=M+1".into());
+        asm.push("@31".into());
+        asm.push("D=D&A".into());
+        asm.push(at(PRINT_CURSOR));
+        asm.push("M=D".into());
+
+        asm.push("@SP".into());
+        asm.push("M=M-1".into());
+        asm.push(self.scratch(0));
+        asm.push("A=M".into());
+        asm.push("0; JMP".into());
+        asm
+    }
+
+    fn gen_return(&self) -> Vec<Instruction> {
+        // scratch 0 = frame, scratch 1 = return address
+        let mut asm = vec![
+            "@LCL".into(),
+            "D=M".into(),
+            self.scratch(0),
+            "M=D".into(),
+            "@5".into(),
+            "A=D-A".into(),
+            "D=M".into(),
+            self.scratch(1),
+            "M=D".into(),
+        ];
+
+        // move return value to where the caller expects it
+        asm.push("@SP".into());
+        asm.push("AM=M-1".into());
+        asm.push("D=M".into());
+        asm.push("@ARG".into());
+        asm.push("A=M".into());
+        asm.push("M=D".into());
+
+        // restore caller SP
+        asm.push("@ARG".into());
+        asm.push("D=M+1".into());
+        asm.push("@SP".into());
+        asm.push("M=D".into());
+
+        // restore caller frame, walking back from the saved LCL
+        for pointer in &["THAT", "THIS", "ARG", "LCL"] {
+            asm.push(self.scratch(0));
+            asm.push("AM=M-1".into());
+            asm.push("D=M".into());
+            asm.push(at(pointer));
+            asm.push("M=D".into());
+        }
+
+        // jump back to caller
+        asm.push(self.scratch(1));
+        asm.push("A=M".into());
+        asm.push("0; JMP".into());
+        asm
+    }
+
+    /// The code of a shared routine, under its label.
+    pub fn gen_routine(&self, routine: Routine) -> Vec<Instruction> {
+        let mut asm = vec![Instruction::Label(routine.label().into())];
+        let jump = match routine {
+            Routine::Eq => "D; JEQ",
+            Routine::Gt => "D; JGT",
+            Routine::Lt => "D; JLT",
+            Routine::Call => {
+                asm.extend(self.gen_call_frame());
+                return asm;
+            }
+            Routine::Return => {
+                asm.extend(self.gen_return());
+                return asm;
+            }
+            Routine::Print => {
+                asm.extend(self.gen_print());
+                return asm;
+            }
+            // record the ID in D and halt
+            Routine::Assert => {
+                asm.push(at(ASSERT_ADDRESS));
+                asm.push("M=D".into());
+                asm.push("(__VM_ASSERT_HALT)".into());
+                asm.push("@__VM_ASSERT_HALT".into());
+                asm.push("0; JMP".into());
+                return asm;
             }
         };
+        let done = format!("{}_DONE", routine.label());
+
+        // keep the return address, then leave x - y in D and assume true
+        asm.push(self.scratch(0));
+        asm.push("M=D".into());
+        asm.push("@SP".into());
+        asm.push("AM=M-1".into());
+        asm.push("D=M".into());
+        asm.push("A=A-1".into());
+        asm.push("D=M-D".into());
+        asm.push("M=-1".into());
+        asm.push(Instruction::A(done.clone().into()));
+        asm.push(jump.into());
+        asm.push("@SP".into());
+        asm.push("A=M-1".into());
+        asm.push("M=0".into());
+        asm.push(Instruction::Label(done.into()));
+        asm.push(self.scratch(0));
+        asm.push("A=M".into());
+        asm.push("0; JMP".into());
+        asm
+    }
+
+    // point SP at the base of the stack and hand control to Sys.init
+    pub fn gen_bootstrap(&mut self) -> Result<Vec<Instruction>> {
+        let mut asm =
+            vec!["@256".into(), "D=A".into(), "@SP".into(), "M=D".into()];
+        asm.extend(self.gen_function_block(&FunctionToken::Call {
+            name: "Sys.init".to_string(),
+            args: 0,
+        })?);
         Ok(asm)
     }
 
-    pub fn gen_block(&mut self, line: &Line) -> Result<Vec<String>> {
+    // park the CPU once the program is done, rather than letting it run
+    // off into uninitialised ROM
+    pub fn gen_end_loop() -> Vec<Instruction> {
+        vec!["(END)".into(), "@END".into(), "0; JMP".into()]
+    }
+
+    // a pop whose store is dead only has to drop the value
+    pub fn gen_discard(&mut self, line: &Line) -> Vec<Instruction> {
+        self.next_command(line);
+        self.d = None;
+        self.next = Next::Other;
+        self.more = false;
+        vec!["@SP".into(), "M=M-1".into()]
+    }
+
+    pub fn gen_block(&mut self, line: &Line) -> Result<Vec<Instruction>> {
+        self.gen_block_before(line, None)
+    }
+
+    /// Like `gen_block`, knowing the line that comes next, which lets an
+    /// optimizing generator carry a run of pushes over to it or fuse the
+    /// two.
+    pub fn gen_block_before(
+        &mut self,
+        line: &Line,
+        next: Option<&Line>,
+    ) -> Result<Vec<Instruction>> {
+        self.next_command(line);
+        self.next = match next {
+            _ if self.opt_level < 1 => Next::Other,
+            Some(Line::Stack(StackToken::Push { .. })) => Next::Push,
+            Some(Line::Binary(op)) => Next::Binary(op.clone()),
+            Some(Line::Unary(UnaryToken::Not)) => Next::Not,
+            Some(Line::Branch(BranchToken::IfGoTo(_))) => Next::IfGoTo,
+            _ => Next::Other,
+        };
+        self.more = matches!(line, Line::Stack(StackToken::Push { .. }))
+            && self.next == Next::Push
+            && self.pushed + 1 < BATCH_MAX;
+        if !matches!(line, Line::Stack(_)) {
+            self.d = None;
+        }
         match line {
             Line::Stack(token) => self.gen_stack_block(token),
             Line::Unary(token) => self.gen_unary_block(token),
             Line::Binary(token) => self.gen_binary_block(token),
             Line::Comparison(token) => self.gen_comparison_block(token),
             Line::Branch(token) => self.gen_branch_block(token),
+            Line::Function(token) => self.gen_function_block(token),
+            Line::Extension(token) => self.gen_extension_block(token),
         }
     }
+
+    /// What the block `gen_block` is about to write for `line` does, in
+    /// plain words. This follows unoptimized code: from -O1, blocks are
+    /// fused and reordered beyond what a sentence per command can say.
+    pub fn explain(&self, line: &Line) -> String {
+        let scratch = |n| match self.scratch(n) {
+            Instruction::A(register) => register.to_string(),
+            _ => unreachable!("scratch registers are A-instructions"),
+        };
+        match line {
+            Line::Stack(StackToken::Push { segment, index }) => format!(
+                "{}; write D on top of the stack; increment SP",
+                explain_load(segment, *index)
+            ),
+            Line::Stack(StackToken::Pop { segment, index }) => {
+                let pop = "decrement SP; read the top of the stack into D";
+                match segment {
+                    Segment::Constant => "constants cannot be popped".into(),
+                    Segment::Static => {
+                        format!("{}; store it in static {}", pop, index)
+                    }
+                    Segment::Temp | Segment::Pointer => format!(
+                        "{}; store it in RAM[{}]",
+                        pop,
+                        explain_address(segment, *index)
+                    ),
+                    _ if *index <= DIRECT_POP_MAX => format!(
+                        "{}; store it {}",
+                        pop,
+                        explain_word(segment, *index)
+                    ),
+                    _ => format!(
+                        "work out {} + {} and keep it in {}; {}; store it \
+                         where {} points",
+                        segment.to_address().unwrap_or_default(),
+                        index,
+                        scratch(0),
+                        pop,
+                        scratch(0)
+                    ),
+                }
+            }
+            Line::Binary(op) => {
+                let operation = match op {
+                    BinaryToken::Add => "add it to x",
+                    BinaryToken::Sub => "subtract it from x",
+                    BinaryToken::And => "and it into x",
+                    BinaryToken::Or => "or it into x",
+                };
+                format!("decrement SP; read y into D; {} in place", operation)
+            }
+            Line::Unary(UnaryToken::Neg) => {
+                "put 0 in D; replace the top of the stack with D minus it"
+                    .into()
+            }
+            Line::Unary(UnaryToken::Not) => {
+                "flip every bit of the top of the stack in place".into()
+            }
+            Line::Comparison(op) if self.sharing() => format!(
+                "put the return address in D; jump to the shared {} \
+                 routine, which replaces x and y with -1 (true) or 0 \
+                 (false) and jumps back",
+                op
+            ),
+            Line::Comparison(op) => {
+                let test = match op {
+                    ComparisonToken::Equal => "is 0",
+                    ComparisonToken::GreaterThan => "is above 0",
+                    ComparisonToken::LessThan => "is below 0",
+                };
+                format!(
+                    "decrement SP; read y into D; work out x - y; set D to \
+                     -1 (true) if that {}, else to 0 (false); write D over x",
+                    test
+                )
+            }
+            Line::Branch(BranchToken::Label(label)) => format!(
+                "mark {} as a place to jump to; this takes no ROM",
+                self.get_label(label)
+            ),
+            Line::Branch(BranchToken::GoTo(label)) => {
+                format!("jump to {}", self.get_label(label))
+            }
+            Line::Branch(BranchToken::IfGoTo(label)) => format!(
+                "decrement SP; read the condition into D; jump to {} unless \
+                 it is 0 (false)",
+                self.get_label(label)
+            ),
+            Line::Function(FunctionToken::Function { name, locals }) => {
+                let locals = match *locals {
+                    0 => "it has no locals to set up".to_string(),
+                    1 => "push a zero for its local".to_string(),
+                    n if n >= self.init_loop() => format!(
+                        "push {} zeros for its locals, counting down in D",
+                        n
+                    ),
+                    n => format!("push {} zeros for its locals", n),
+                };
+                format!("mark where {} starts; {}", name, locals)
+            }
+            Line::Function(FunctionToken::Call { name, args })
+                if self.sharing() =>
+            {
+                format!(
+                    "put {} in {}, the frame size ({} arguments + 5) in {} \
+                     and the return address in D; jump to the shared call \
+                     routine, which saves the caller's frame and jumps to \
+                     {}; mark where it returns to",
+                    name,
+                    scratch(0),
+                    args,
+                    scratch(1),
+                    name
+                )
+            }
+            Line::Function(FunctionToken::Call { name, args }) => format!(
+                "push the return address and the caller's LCL, ARG, THIS \
+                 and THAT; point ARG at the {} arguments below them and LCL \
+                 at the top of the stack; jump to {}; mark where it returns \
+                 to",
+                args, name
+            ),
+            Line::Function(FunctionToken::Return) => {
+                let steps = format!(
+                    "keep the frame (LCL) in {} and the return address \
+                     (LCL - 5) in {}; pop the return value into argument 0; \
+                     point SP just past it; restore THAT, THIS, ARG and LCL \
+                     from the frame; jump to the return address",
+                    scratch(0),
+                    scratch(1)
+                );
+                match self.sharing() {
+                    true => format!(
+                        "jump to the shared return routine, which does this: \
+                         {}",
+                        steps
+                    ),
+                    false => steps,
+                }
+            }
+            Line::Extension(ExtensionToken::Assert) => format!(
+                "decrement SP; read the condition into D; unless it is 0 \
+                 (false), skip ahead; otherwise put {} in D and jump to the \
+                 shared assert routine, which records it and halts",
+                self.asserts
+            ),
+            Line::Extension(ExtensionToken::Halt) => format!(
+                "record halt {} in RAM[{}]; loop here for good",
+                self.halts, HALT_ADDRESS
+            ),
+            Line::Extension(ExtensionToken::Print) => {
+                "put the return address in D; jump to the shared print \
+                 routine, which draws the top of the stack, pops it and \
+                 jumps back"
+                    .into()
+            }
+        }
+    }
+}
+
+// the first half of a push: the value to push, in D
+fn explain_load(segment: &Segment, index: u16) -> String {
+    match segment {
+        Segment::Constant => format!("put {} in D", index),
+        Segment::Static => format!("read static {} into D", index),
+        Segment::Temp | Segment::Pointer => {
+            format!("read RAM[{}] into D", explain_address(segment, index))
+        }
+        _ => format!("read the word {} into D", explain_word(segment, index)),
+    }
+}
+
+// where a word of a segment behind a pointer is
+fn explain_word(segment: &Segment, index: u16) -> String {
+    let pointer = segment.to_address().unwrap_or_default();
+    match index {
+        0 => format!("where {} points", pointer),
+        _ => format!("{} past where {} points", index, pointer),
+    }
+}
+
+// temp and pointer words sit at fixed addresses
+fn explain_address(segment: &Segment, index: u16) -> u16 {
+    let base: u16 = segment
+        .to_address()
+        .ok()
+        .and_then(|base| base.parse().ok())
+        .unwrap_or_default();
+    base + index
 }
@@ -0,0 +1,468 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+
+use crate::{
+    assembler,
+    debugger::{Breakpoint, Debugger, Stop},
+    emulator::Cpu,
+    lsp::{read_message, write_message},
+    parser::{FunctionToken, Line, Span},
+    translator::{Options, Translator},
+};
+
+// how long a launched program may run, unless launch says otherwise
+const CYCLES: u64 = 10_000_000;
+
+// the one thread there is
+const THREAD: u64 = 1;
+
+// each frame's scopes; the first four sit behind the frame's pointers, the
+// rest are the same for every frame and only shown with the innermost
+const SCOPES: [&str; 7] = [
+    "local",
+    "argument",
+    "this",
+    "that",
+    "temp",
+    "stack",
+    "registers",
+];
+
+// words of this and that shown, since nothing says how big they are
+const SHOWN: usize = 8;
+
+// where temp and the stack live
+const TEMP: usize = 5;
+const STACK: usize = 256;
+
+// calls deeper than this aren't walked by a stack trace
+const MAX_FRAMES: usize = 64;
+
+/// A Debug Adapter Protocol server over stdin and stdout, which runs a
+/// translated program in the emulator and maps it back to its .vm files.
+#[derive(Debug, Default)]
+pub struct Server {
+    session: Option<Session>,
+    // the ROM addresses of each source's breakpoints
+    breakpoints: BTreeMap<String, Vec<u16>>,
+    seq: u64,
+}
+
+#[derive(Debug)]
+struct Session {
+    translator: Translator,
+    cpu: Cpu,
+    debugger: Debugger,
+    symbols: HashMap<String, u16>,
+    cycles: u64,
+    stop_on_entry: bool,
+    // each function's declared locals
+    locals: HashMap<String, usize>,
+}
+
+// a function's activation, found by walking the saved frames from LCL
+#[derive(Debug)]
+struct Frame {
+    function: Option<String>,
+    span: Option<Span>,
+    // LCL, ARG, THIS and THAT
+    pointers: [usize; 4],
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server::default()
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        self.serve(&mut stdin.lock(), &mut stdout.lock())
+    }
+
+    /// Answer requests from `input` until it ends or the client
+    /// disconnects.
+    pub fn serve(
+        &mut self,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> Result<()> {
+        while let Some(msg) = read_message(input)? {
+            let command = msg["command"].as_str().unwrap_or("").to_string();
+            let args = &msg["arguments"];
+            let mut events = Vec::new();
+            let result = match command.as_str() {
+                "initialize" => Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                })),
+                // breakpoints can only be placed in a translated program, so
+                // configuration waits until there is one
+                "launch" => self.launch(args).map(|()| {
+                    events.push(event("initialized", json!({})));
+                    json!({})
+                }),
+                "setBreakpoints" => self.set_breakpoints(args),
+                "configurationDone" => self.session().map(|session| {
+                    events.push(match session.stop_on_entry {
+                        true => stopped("entry"),
+                        false => session.resume(Debugger::run),
+                    });
+                    json!({})
+                }),
+                "threads" => Ok(json!({
+                    "threads": [{ "id": THREAD, "name": "main" }],
+                })),
+                "stackTrace" => self.session().map(Session::stack_trace),
+                "scopes" => self.session().map(|session| session.scopes(args)),
+                "variables" => {
+                    self.session().map(|session| session.variables(args))
+                }
+                "continue" => self.session().map(|session| {
+                    events.push(session.resume(Debugger::run));
+                    json!({ "allThreadsContinued": true })
+                }),
+                "next" => self.session().map(|session| {
+                    events.push(session.resume(Debugger::step_over));
+                    json!({})
+                }),
+                "stepIn" => self.session().map(|session| {
+                    events.push(session.resume(Debugger::step_command));
+                    json!({})
+                }),
+                "stepOut" => self.session().map(|session| {
+                    events.push(session.resume(Debugger::step_out));
+                    json!({})
+                }),
+                // runs finish before the next request is read, so the
+                // program is already paused
+                "pause" => {
+                    events.push(stopped("pause"));
+                    Ok(json!({}))
+                }
+                "disconnect" | "terminate" => Ok(json!({})),
+                _ => Err(anyhow!("unsupported request: {}", command)),
+            };
+
+            let mut response = json!({
+                "type": "response",
+                "request_seq": msg["seq"],
+                "command": command,
+                "success": result.is_ok(),
+            });
+            match result {
+                Ok(body) => response["body"] = body,
+                Err(err) => response["message"] = json!(format!("{:#}", err)),
+            }
+            self.send(output, response)?;
+            for event in events {
+                self.send(output, event)?;
+            }
+            if command == "disconnect" {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, output: &mut impl Write, mut msg: Value) -> Result<()> {
+        self.seq += 1;
+        msg["seq"] = json!(self.seq);
+        write_message(output, &msg)
+    }
+
+    fn session(&mut self) -> Result<&mut Session> {
+        self.session
+            .as_mut()
+            .ok_or_else(|| anyhow!("no program has been launched"))
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<()> {
+        let program = args["program"]
+            .as_str()
+            .ok_or_else(|| anyhow!("launch needs a program"))?;
+        let options = Options {
+            retain_ir: true,
+            ..Options::default()
+        };
+        let mut translator = Translator::open(program, options)?;
+        translator.process()?;
+        let assembled = assembler::assemble(&translator.render())?;
+        let mut cpu = Cpu::new(assembled.words);
+        cpu.preset_segments();
+        let debugger =
+            Debugger::new(&[], &assembled.symbols, translator.origins())?;
+        let locals = translator
+            .lines()
+            .filter_map(|line| match line {
+                Line::Function(FunctionToken::Function { name, locals }) => {
+                    Some((name.clone(), *locals as usize))
+                }
+                _ => None,
+            })
+            .collect();
+        self.session = Some(Session {
+            translator,
+            cpu,
+            debugger,
+            symbols: assembled.symbols,
+            cycles: args["cycles"].as_u64().unwrap_or(CYCLES),
+            stop_on_entry: args["stopOnEntry"].as_bool().unwrap_or(false),
+            locals,
+        });
+        Ok(())
+    }
+
+    // breakpoints go on the first code of the VM command on each line; lines
+    // without one can't hold a breakpoint
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value> {
+        let path = args["source"]["path"]
+            .as_str()
+            .ok_or_else(|| anyhow!("setBreakpoints needs a source path"))?;
+        let path = canonical(path);
+        let lines: Vec<usize> = args["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|breakpoint| breakpoint["line"].as_u64())
+            .map(|line| line as usize)
+            .collect();
+
+        let session = self.session()?;
+        let asm = session.translator.asm();
+        let origins = session.translator.origins();
+        let mut roms = Vec::new();
+        let mut verified = Vec::new();
+        for &line in &lines {
+            let rom = asm.iter().zip(&origins).find_map(|(asm, origin)| {
+                let span = asm.span.as_ref()?;
+                let found = span.line == line && canonical(&span.file) == path;
+                found.then_some(origin.rom.start as u16)
+            });
+            roms.extend(rom);
+            verified.push(json!({ "verified": rom.is_some(), "line": line }));
+        }
+
+        self.breakpoints.insert(path, roms);
+        let breakpoints: Vec<Breakpoint> = self
+            .breakpoints
+            .values()
+            .flatten()
+            .map(|&rom| Breakpoint::Rom(rom))
+            .collect();
+        let session = self.session()?;
+        session.debugger = Debugger::new(
+            &breakpoints,
+            &session.symbols,
+            session.translator.origins(),
+        )?;
+        Ok(json!({ "breakpoints": verified }))
+    }
+}
+
+impl Session {
+    // run the program on from where it is, and say how it stopped
+    fn resume(
+        &mut self,
+        how: fn(&mut Debugger, &mut Cpu, u64) -> Option<Stop>,
+    ) -> Value {
+        match how(&mut self.debugger, &mut self.cpu, self.cycles) {
+            Some(Stop::Break(_)) => stopped("breakpoint"),
+            Some(Stop::Watch { .. }) => stopped("data breakpoint"),
+            Some(Stop::Step) => stopped("step"),
+            None => event("terminated", json!({})),
+        }
+    }
+
+    // the source of the VM command whose code holds `rom`
+    fn locate(&self, rom: u16) -> (Option<String>, Option<Span>) {
+        match self.debugger.origin(rom) {
+            Some(origin) => {
+                let asm = &self.translator.asm()[origin];
+                (asm.function.clone(), asm.span.clone())
+            }
+            None => (None, None),
+        }
+    }
+
+    // the innermost frame first; each saved frame below LCL holds the
+    // return address and the caller's pointers
+    fn frames(&self) -> Vec<Frame> {
+        let ram = &self.cpu.ram;
+        let word = |address: usize| ram.get(address).map_or(0, |&w| w.max(0));
+        let (function, span) = self.locate(self.cpu.pc);
+        let mut frames = vec![Frame {
+            function,
+            span,
+            pointers: [1, 2, 3, 4].map(|pointer| word(pointer) as usize),
+        }];
+        while frames.len() < MAX_FRAMES {
+            let lcl = frames[frames.len() - 1].pointers[0];
+            if lcl < STACK + 5 {
+                break;
+            }
+            // the return address follows the call's code
+            let ret = word(lcl - 5) as u16;
+            let call = self.debugger.origin(ret.wrapping_sub(1));
+            match call.map(|origin| &self.debugger.origins()[origin].command) {
+                Some(command) if command.starts_with("call ") => {}
+                _ => break,
+            }
+            let (function, span) = self.locate(ret - 1);
+            frames.push(Frame {
+                function,
+                span,
+                pointers: [4, 3, 2, 1]
+                    .map(|offset| word(lcl - offset) as usize),
+            });
+        }
+        frames
+    }
+
+    fn stack_trace(&mut self) -> Value {
+        let frames: Vec<Value> = self
+            .frames()
+            .into_iter()
+            .enumerate()
+            .map(|(id, frame)| {
+                let mut value = json!({
+                    "id": id,
+                    "name": frame.function.as_deref().unwrap_or("(bootstrap)"),
+                    "line": 0,
+                    "column": 0,
+                });
+                if let Some(span) = frame.span {
+                    value["line"] = json!(span.line);
+                    value["column"] = json!(1);
+                    value["source"] = json!({
+                        "name": Path::new(&*span.file)
+                            .file_name()
+                            .map(|name| name.to_string_lossy()),
+                        "path": canonical(&span.file),
+                    });
+                }
+                value
+            })
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn scopes(&mut self, args: &Value) -> Value {
+        let frame = args["frameId"].as_u64().unwrap_or(0) as usize;
+        let shown = match frame {
+            0 => SCOPES.len(),
+            _ => 4,
+        };
+        let scopes: Vec<Value> = SCOPES[..shown]
+            .iter()
+            .enumerate()
+            .map(|(scope, name)| {
+                json!({
+                    "name": name,
+                    "variablesReference": frame * SCOPES.len() + scope + 1,
+                    "expensive": false,
+                })
+            })
+            .collect();
+        json!({ "scopes": scopes })
+    }
+
+    fn variables(&mut self, args: &Value) -> Value {
+        let reference = args["variablesReference"].as_u64().unwrap_or(0);
+        let index = (reference as usize).saturating_sub(1);
+        let frames = self.frames();
+        let frame = frames.get(index / SCOPES.len());
+        let scope = SCOPES[index % SCOPES.len()];
+        let ram = &self.cpu.ram;
+        let values: Vec<(String, i64)> = match frame {
+            None => Vec::new(),
+            Some(_) if scope == "registers" => {
+                let cpu = &self.cpu;
+                let mut values = vec![
+                    ("PC".to_string(), cpu.pc as i64),
+                    ("A".to_string(), cpu.a as i64),
+                    ("D".to_string(), cpu.d as i64),
+                ];
+                values.extend(
+                    ["SP", "LCL", "ARG", "THIS", "THAT"]
+                        .iter()
+                        .zip(ram)
+                        .map(|(name, &value)| (name.to_string(), value as i64)),
+                );
+                values
+            }
+            Some(frame) => {
+                let (base, count) = self.extent(frame, scope);
+                ram.iter()
+                    .skip(base)
+                    .take(count)
+                    .enumerate()
+                    .map(|(i, &value)| (i.to_string(), value as i64))
+                    .collect()
+            }
+        };
+        let variables: Vec<Value> = values
+            .into_iter()
+            .map(|(name, value)| {
+                json!({
+                    "name": name,
+                    "value": value.to_string(),
+                    "variablesReference": 0,
+                })
+            })
+            .collect();
+        json!({ "variables": variables })
+    }
+
+    // where a segment of `frame` starts in RAM, and its words
+    fn extent(&self, frame: &Frame, scope: &str) -> (usize, usize) {
+        let [lcl, arg, this, that] = frame.pointers;
+        match scope {
+            "local" => {
+                let locals = frame
+                    .function
+                    .as_ref()
+                    .and_then(|function| self.locals.get(function));
+                (lcl, locals.copied().unwrap_or(0))
+            }
+            // the caller's saved frame sits right after the arguments
+            "argument" => (arg, lcl.saturating_sub(arg + 5)),
+            "this" => (this, SHOWN),
+            "that" => (that, SHOWN),
+            "temp" => (TEMP, 8),
+            "stack" => {
+                let sp = self.cpu.ram[0].max(0) as usize;
+                (STACK, sp.saturating_sub(STACK))
+            }
+            _ => (0, 0),
+        }
+    }
+}
+
+fn event(name: &str, body: Value) -> Value {
+    json!({ "type": "event", "event": name, "body": body })
+}
+
+fn stopped(reason: &str) -> Value {
+    event(
+        "stopped",
+        json!({
+            "reason": reason,
+            "threadId": THREAD,
+            "allThreadsStopped": true,
+        }),
+    )
+}
+
+// sources are matched by where they really are, however they were named
+fn canonical(path: &str) -> String {
+    fs::canonicalize(path)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.to_string())
+}
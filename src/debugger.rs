@@ -0,0 +1,225 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{emulator::Cpu, sourcemap::Origin};
+
+// SP, LCL, ARG, THIS and THAT, shown with every stop
+const POINTERS: [&str; 5] = ["SP", "LCL", "ARG", "THIS", "THAT"];
+
+// how much of the top of the stack a stop shows
+const STACK_SHOWN: usize = 8;
+
+/// Where emulation should pause.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    // an asm label, such as a function's name or `Fn$label`
+    Label(String),
+    Rom(u16),
+    // any change to this RAM address
+    Watch(usize),
+}
+
+/// Why emulation paused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    Break(u16),
+    Watch { address: usize, old: i16, new: i16 },
+    Step,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Break(rom) => write!(f, "breakpoint at ROM[{}]", rom),
+            Stop::Watch { address, old, new } => {
+                write!(f, "RAM[{}] changed: {} -> {}", address, old, new)
+            }
+            Stop::Step => write!(f, "stepped"),
+        }
+    }
+}
+
+/// Runs a CPU to its breakpoints and describes the machine, and the VM
+/// command it's in the middle of, where it stops.
+#[derive(Debug)]
+pub struct Debugger {
+    breaks: BTreeSet<u16>,
+    watches: Vec<usize>,
+    origins: Vec<Origin>,
+    // stop after the next instruction
+    pub stepping: bool,
+}
+
+impl Debugger {
+    /// Labels are looked up in the assembled program's `symbols`;
+    /// `origins` map ROM back to VM commands.
+    pub fn new(
+        breakpoints: &[Breakpoint],
+        symbols: &HashMap<String, u16>,
+        origins: Vec<Origin>,
+    ) -> Result<Debugger> {
+        let mut breaks = BTreeSet::new();
+        let mut watches = Vec::new();
+        for breakpoint in breakpoints {
+            match breakpoint {
+                Breakpoint::Label(label) => {
+                    let rom = symbols
+                        .get(label)
+                        .ok_or_else(|| anyhow!("no such label: {}", label))?;
+                    breaks.insert(*rom);
+                }
+                Breakpoint::Rom(rom) => {
+                    breaks.insert(*rom);
+                }
+                Breakpoint::Watch(address) => watches.push(*address),
+            }
+        }
+        Ok(Debugger {
+            breaks,
+            watches,
+            origins,
+            stepping: false,
+        })
+    }
+
+    /// Run until a breakpoint, or until `cycles` have passed in all or the
+    /// CPU halts, which give `None`. A CPU stopped at a breakpoint goes
+    /// past it when run again.
+    pub fn run(&mut self, cpu: &mut Cpu, cycles: u64) -> Option<Stop> {
+        let mut first = true;
+        while cpu.cycles < cycles && !cpu.halted() {
+            if !first && self.breaks.contains(&cpu.pc) {
+                return Some(Stop::Break(cpu.pc));
+            }
+            first = false;
+
+            let before: Vec<i16> =
+                self.watches.iter().map(|&a| cpu.ram[a]).collect();
+            cpu.step();
+            for (&address, &old) in self.watches.iter().zip(&before) {
+                let new = cpu.ram[address];
+                if new != old {
+                    return Some(Stop::Watch { address, old, new });
+                }
+            }
+            if self.stepping {
+                self.stepping = false;
+                return Some(Stop::Step);
+            }
+        }
+        None
+    }
+
+    /// Run until the CPU reaches the code of another VM command, or stops
+    /// sooner for a breakpoint or any of the reasons `run` gives `None`.
+    pub fn step_command(&mut self, cpu: &mut Cpu, cycles: u64) -> Option<Stop> {
+        let start = self.origin(cpu.pc);
+        let mut stop = None;
+        while cpu.cycles < cycles && !cpu.halted() {
+            self.stepping = true;
+            match self.run(cpu, cycles) {
+                Some(Stop::Step) if self.origin(cpu.pc) == start => {}
+                Some(Stop::Step) => {
+                    stop = Some(Stop::Step);
+                    break;
+                }
+                other => {
+                    stop = other;
+                    break;
+                }
+            }
+        }
+        self.stepping = false;
+        stop
+    }
+
+    /// Like `step_command`, but runs a call through to its return.
+    pub fn step_over(&mut self, cpu: &mut Cpu, cycles: u64) -> Option<Stop> {
+        let calling = self.command(cpu.pc).is_some_and(is_call);
+        match self.step_command(cpu, cycles) {
+            Some(Stop::Step) if calling => self.step_out(cpu, cycles),
+            stop => stop,
+        }
+    }
+
+    /// Run until the function running now has returned, stopping at the
+    /// VM command after its call.
+    pub fn step_out(&mut self, cpu: &mut Cpu, cycles: u64) -> Option<Stop> {
+        // calls made on the way that haven't returned yet
+        let mut depth = 0;
+        loop {
+            let command = self.command(cpu.pc);
+            let calling = command.is_some_and(is_call);
+            let returning = command == Some("return");
+            match self.step_command(cpu, cycles) {
+                Some(Stop::Step) if returning && depth == 0 => {
+                    return Some(Stop::Step)
+                }
+                Some(Stop::Step) => {
+                    depth += calling as usize;
+                    depth -= (returning && depth > 0) as usize;
+                }
+                stop => return stop,
+            }
+        }
+    }
+
+    pub fn origins(&self) -> &[Origin] {
+        &self.origins
+    }
+
+    /// Which of `origins()` holds the code at `rom`.
+    pub fn origin(&self, rom: u16) -> Option<usize> {
+        self.origins
+            .iter()
+            .position(|origin| origin.rom.contains(&(rom as usize)))
+    }
+
+    pub fn is_break(&self, rom: u16) -> bool {
+        self.breaks.contains(&rom)
+    }
+
+    // the VM command whose code holds `rom`
+    fn command(&self, rom: u16) -> Option<&str> {
+        self.origin(rom)
+            .map(|origin| self.origins[origin].command.as_str())
+    }
+
+    /// The registers, the VM's pointers and stack top, and the VM command
+    /// being run.
+    pub fn state(&self, cpu: &Cpu) -> String {
+        let mut buf = format!(
+            "PC={} A={} D={} cycle {}\n",
+            cpu.pc, cpu.a, cpu.d, cpu.cycles
+        );
+        if let Some(command) = self.command(cpu.pc) {
+            buf.push_str(&format!("in: {}\n", command));
+        }
+        let pointers: Vec<String> = POINTERS
+            .iter()
+            .enumerate()
+            .map(|(i, name)| format!("{}={}", name, cpu.ram[i]))
+            .collect();
+        buf.push_str(&pointers.join(" "));
+        buf.push('\n');
+
+        let sp = cpu.ram[0].clamp(0, 2048) as usize;
+        let bottom = sp.saturating_sub(STACK_SHOWN).max(256).min(sp);
+        let stack: Vec<String> =
+            cpu.ram[bottom..sp].iter().map(i16::to_string).collect();
+        buf.push_str(&format!(
+            "stack: {}[{}]\n",
+            if bottom > 256 { "... " } else { "" },
+            stack.join(", ")
+        ));
+        buf
+    }
+}
+
+fn is_call(command: &str) -> bool {
+    command.starts_with("call ")
+}
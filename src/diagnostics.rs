@@ -0,0 +1,252 @@
+use std::{env, error, fmt, io};
+
+use anyhow::{anyhow, Result};
+
+use crate::parser::Span;
+
+/// Which stage of a run failed. Each maps to its own exit code, in
+/// pipeline order, so scripts can tell them apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Class {
+    Usage,
+    Io,
+    Parse,
+    Semantic,
+    // the translator itself is at fault, not its input
+    Internal,
+}
+
+impl Class {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Class::Usage => 2,
+            Class::Io => 3,
+            Class::Parse => 4,
+            Class::Semantic => 5,
+            Class::Internal => 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// Whether terminal output gets ANSI colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+impl Color {
+    pub fn new(raw: &str) -> Result<Color> {
+        match raw {
+            "auto" => Ok(Color::Auto),
+            "always" => Ok(Color::Always),
+            "never" => Ok(Color::Never),
+            _ => Err(anyhow!("unexpected color choice: {}", raw)),
+        }
+    }
+
+    // auto colors terminals only, and honours NO_COLOR and TERM=dumb
+    pub fn enabled(&self, is_terminal: bool) -> bool {
+        match self {
+            Color::Always => true,
+            Color::Never => false,
+            Color::Auto => {
+                is_terminal
+                    && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && env::var_os("TERM").is_none_or(|t| t != "dumb")
+            }
+        }
+    }
+}
+
+/// Wrap `text` in an ANSI style when `color` is set, e.g. "1;31" for bold
+/// red.
+pub fn paint(text: &str, style: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[{}m{}\x1b[0m", style, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// One problem found in the input, located where possible.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub class: Class,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    // the lint that found it, by the name in lint::LINTS
+    pub lint: Option<&'static str>,
+}
+
+impl Diagnostic {
+    pub fn error(class: Class, message: impl fmt::Display) -> Diagnostic {
+        Diagnostic {
+            class,
+            severity: Severity::Error,
+            message: message.to_string(),
+            span: None,
+            lint: None,
+        }
+    }
+
+    pub fn warning(class: Class, message: impl fmt::Display) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(class, message)
+        }
+    }
+
+    /// A warning from the lint `name`.
+    pub fn lint(name: &'static str, message: impl fmt::Display) -> Diagnostic {
+        Diagnostic {
+            lint: Some(name),
+            ..Diagnostic::warning(Class::Semantic, message)
+        }
+    }
+
+    pub fn at(mut self, span: Span) -> Diagnostic {
+        self.span = Some(span);
+        self
+    }
+}
+
+impl Severity {
+    fn style(&self) -> &'static str {
+        match self {
+            Severity::Error => "1;31",
+            Severity::Warning => "1;33",
+        }
+    }
+}
+
+impl Diagnostic {
+    // file:line: error: message, as compilers print them
+    pub fn render(&self, color: bool) -> String {
+        let location = match &self.span {
+            Some(span) => {
+                paint(&format!("{}:{}:", span.file, span.line), "1", color)
+                    + " "
+            }
+            None => String::new(),
+        };
+        let severity = self.severity.name();
+        format!(
+            "{}{}: {}",
+            location,
+            paint(severity, self.severity.style(), color),
+            self.message
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(false))
+    }
+}
+
+impl error::Error for Diagnostic {}
+
+/// Everything wrong with a run's input, reported together rather than
+/// stopping at the first error.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub diagnostics: Vec<Diagnostic>,
+    pub files: usize,
+    // whether the error limit cut the run short
+    pub stopped: bool,
+}
+
+impl Report {
+    fn count(&self, severity: Severity) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == severity)
+            .count()
+    }
+
+    pub fn errors(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    // the earliest stage any error came from
+    pub fn class(&self) -> Option<Class> {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .map(|d| d.class)
+            .min()
+    }
+
+    /// e.g. "3 errors, 2 warnings in 4 files"
+    pub fn summary(&self) -> String {
+        let plural = |n: usize, what: &str| match n {
+            1 => format!("1 {}", what),
+            n => format!("{} {}s", n, what),
+        };
+        format!(
+            "{}, {} in {}",
+            plural(self.errors(), "error"),
+            plural(self.warnings(), "warning"),
+            plural(self.files, "file")
+        )
+    }
+
+    pub fn render(&self, color: bool) -> String {
+        let mut buf = String::new();
+        for diagnostic in &self.diagnostics {
+            buf.push_str(&diagnostic.render(color));
+            buf.push('\n');
+        }
+        if self.stopped {
+            buf.push_str("too many errors, stopping\n");
+        }
+        buf.push_str(&paint(&self.summary(), "1", color));
+        buf
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(false))
+    }
+}
+
+impl error::Error for Report {}
+
+/// The exit code for an error surfaced from a run: its class when it
+/// carries one, IO for failed file operations and 1 for anything else.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    if let Some(report) = err.downcast_ref::<Report>() {
+        return report.class().map_or(1, |class| class.exit_code());
+    }
+    if let Some(diagnostic) = err.downcast_ref::<Diagnostic>() {
+        return diagnostic.class.exit_code();
+    }
+    if err.chain().any(|cause| cause.is::<io::Error>()) {
+        return Class::Io.exit_code();
+    }
+    1
+}
@@ -0,0 +1,244 @@
+use crate::trace::{Entry, Trace};
+
+// 15 bit address space; RAM proper ends at the keyboard register
+pub(crate) const MEMORY: usize = 0x8000;
+
+/// The screen's memory map: 256 rows of 32 words, each word 16 pixels with
+/// bit 0 leftmost, a set bit black.
+pub const SCREEN: usize = 0x4000;
+pub const SCREEN_WIDTH: usize = 512;
+pub const SCREEN_HEIGHT: usize = 256;
+
+/// The keyboard register, holding the code of the key held down or 0. The
+/// program can only read it.
+pub const KBD: usize = 0x6000;
+
+// SP, LCL, ARG, THIS and THAT as the project 7 test scripts set them
+const SEGMENTS: [i16; 5] = [256, 300, 400, 3000, 3010];
+
+// R13-R15 and the variables from RAM[16] are named and used differently by
+// every translator, so they're left out when comparing two programs
+const SCRATCH: std::ops::Range<usize> = 13..256;
+
+// 0; JMP
+const JMP: u16 = 0b1110_1010_1000_0111;
+
+/// The Hack CPU: ROM, RAM and the A, D and PC registers.
+#[derive(Debug)]
+pub struct Cpu {
+    rom: Vec<u16>,
+    pub ram: Vec<i16>,
+    pub pc: u16,
+    pub a: u16,
+    pub d: i16,
+    pub cycles: u64,
+    // set whenever the program writes to the screen, for renderers to
+    // redraw on
+    screen_changed: bool,
+    // key presses still to come, last first
+    keys: Vec<(u64, i16)>,
+    // how many times each ROM address has run
+    executions: Vec<u64>,
+    // every step, once recording
+    trace: Option<Trace>,
+}
+
+impl Cpu {
+    pub fn new(rom: Vec<u16>) -> Cpu {
+        Cpu {
+            executions: vec![0; rom.len()],
+            rom,
+            ram: vec![0; MEMORY],
+            pc: 0,
+            a: 0,
+            d: 0,
+            cycles: 0,
+            screen_changed: false,
+            keys: Vec::new(),
+            trace: None,
+        }
+    }
+
+    pub fn rom(&self) -> &[u16] {
+        &self.rom
+    }
+
+    // how many times each ROM address has run since the last reset
+    pub fn executions(&self) -> &[u64] {
+        &self.executions
+    }
+
+    // clear registers and RAM, keeping the program
+    pub fn reset(&mut self) {
+        self.ram.iter_mut().for_each(|word| *word = 0);
+        self.pc = 0;
+        self.a = 0;
+        self.d = 0;
+        self.cycles = 0;
+        self.screen_changed = false;
+        self.executions.iter_mut().for_each(|count| *count = 0);
+    }
+
+    pub fn screen(&self) -> &[i16] {
+        &self.ram[SCREEN..KBD]
+    }
+
+    // whether the pixel at column x of row y is black
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let word = self.ram[SCREEN + y * SCREEN_WIDTH / 16 + x / 16];
+        word & (1 << (x % 16)) != 0
+    }
+
+    /// Whether the program has drawn anything since the last call.
+    pub fn take_screen_changed(&mut self) -> bool {
+        std::mem::take(&mut self.screen_changed)
+    }
+
+    // hold a key down, or let go of it with 0
+    pub fn press(&mut self, key: i16) {
+        self.ram[KBD] = key;
+    }
+
+    /// Press keys as the run reaches the given cycles, e.g. from
+    /// `keyboard::parse`. Events must be in cycle order.
+    pub fn schedule_keys(&mut self, events: Vec<(u64, i16)>) {
+        self.keys = events;
+        self.keys.reverse();
+    }
+
+    // point the segments somewhere sensible for programs without a
+    // bootstrap; one that has it sets them up again itself
+    pub fn preset_segments(&mut self) {
+        self.ram[..SEGMENTS.len()].copy_from_slice(&SEGMENTS);
+    }
+
+    /// Addresses whose value differs between two machines, ignoring
+    /// translator scratch space and variables.
+    pub fn divergence(&self, other: &Cpu) -> Vec<usize> {
+        (0..MEMORY)
+            .filter(|address| !SCRATCH.contains(address))
+            .filter(|address| self.ram[*address] != other.ram[*address])
+            .collect()
+    }
+
+    fn alu(comp: u16, x: i16, y: i16) -> i16 {
+        let (zx, nx, zy, ny, f, no) = (
+            comp & 0b100000 != 0,
+            comp & 0b010000 != 0,
+            comp & 0b001000 != 0,
+            comp & 0b000100 != 0,
+            comp & 0b000010 != 0,
+            comp & 0b000001 != 0,
+        );
+        let x = if zx { 0 } else { x };
+        let x = if nx { !x } else { x };
+        let y = if zy { 0 } else { y };
+        let y = if ny { !y } else { y };
+        let out = if f { x.wrapping_add(y) } else { x & y };
+        if no {
+            !out
+        } else {
+            out
+        }
+    }
+
+    /// Record every step from here on, replacing any trace so far.
+    pub fn record(&mut self) {
+        self.trace = Some(Trace::new(&self.ram));
+    }
+
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
+    /// Execute one instruction. ROM past the end of the program reads as
+    /// zero, which is harmless `@0`, just as on the real machine.
+    pub fn step(&mut self) {
+        while let Some(&(cycle, key)) = self.keys.last() {
+            if cycle > self.cycles {
+                break;
+            }
+            self.press(key);
+            self.keys.pop();
+        }
+        let instruction = self.rom.get(self.pc as usize).copied().unwrap_or(0);
+        self.cycles += 1;
+        if let Some(count) = self.executions.get_mut(self.pc as usize) {
+            *count += 1;
+        }
+        let pc = self.pc;
+        let write = self.execute(instruction);
+        if let Some(trace) = &mut self.trace {
+            let sp = self.ram[0];
+            let top = match sp {
+                1..=0x7fff => self.ram[sp as usize - 1],
+                _ => 0,
+            };
+            trace.entries.push(Entry { pc, sp, top, write });
+        }
+    }
+
+    // run an instruction, giving the address, old and new value of any RAM
+    // word written
+    fn execute(&mut self, instruction: u16) -> Option<(u16, i16, i16)> {
+        if instruction & 0x8000 == 0 {
+            self.a = instruction;
+            self.pc = self.pc.wrapping_add(1) & 0x7fff;
+            return None;
+        }
+
+        let address = (self.a & 0x7fff) as usize;
+        let y = if instruction & 0x1000 != 0 {
+            self.ram[address]
+        } else {
+            self.a as i16
+        };
+        let out = Cpu::alu((instruction >> 6) & 0b111111, self.d, y);
+
+        let dest = (instruction >> 3) & 0b111;
+        // nothing past the screen is writable, the keyboard included
+        let mut write = None;
+        if dest & 0b001 != 0 && address < KBD {
+            write = Some((address as u16, self.ram[address], out));
+            self.ram[address] = out;
+            self.screen_changed |= address >= SCREEN;
+        }
+        if dest & 0b010 != 0 {
+            self.d = out;
+        }
+        if dest & 0b100 != 0 {
+            self.a = out as u16;
+        }
+
+        let jump = instruction & 0b111;
+        let taken = (jump & 0b100 != 0 && out < 0)
+            || (jump & 0b010 != 0 && out == 0)
+            || (jump & 0b001 != 0 && out > 0);
+        self.pc = if taken {
+            self.a & 0x7fff
+        } else {
+            self.pc.wrapping_add(1) & 0x7fff
+        };
+        write
+    }
+
+    /// Whether the CPU is parked in a loop that jumps to itself,
+    /// `(L) @L 0; JMP`, as the end loop and the halt command leave it.
+    pub fn halted(&self) -> bool {
+        let pc = self.pc as usize;
+        [pc, pc.wrapping_sub(1)].iter().any(|&at| {
+            self.rom.get(at) == Some(&(at as u16))
+                && self.rom.get(at + 1) == Some(&JMP)
+        })
+    }
+
+    // run for up to `cycles`, stopping early once halted
+    pub fn run(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            if self.halted() {
+                return;
+            }
+            self.step();
+        }
+    }
+}
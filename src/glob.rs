@@ -0,0 +1,82 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use crate::project;
+
+/// Whether a source path is a pattern to expand here rather than a path,
+/// for shells such as Windows cmd that leave patterns as they are.
+pub fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?'])
+}
+
+/// The directory a pattern's matches are all under: the path up to its
+/// first component with a wildcard.
+pub fn base(pattern: &Path) -> PathBuf {
+    let base: PathBuf = pattern
+        .components()
+        .take_while(|component| !is_glob(Path::new(component)))
+        .collect();
+    match base.as_os_str().is_empty() {
+        true => PathBuf::from("."),
+        false => base,
+    }
+}
+
+/// The files a pattern matches, in order. Within a component `*` and `?`
+/// are as in `project::matches`, and neither matches a leading `.`; a
+/// component that's only `**` matches any number of directories.
+pub fn expand(pattern: &Path) -> Result<Vec<PathBuf>> {
+    let parts: Vec<String> = pattern
+        .components()
+        .skip_while(|component| !is_glob(Path::new(component)))
+        .map(|component| component.as_os_str().to_string_lossy().into())
+        .collect();
+    let mut found = Vec::new();
+    walk(&base(pattern), &parts, &mut found)?;
+    found.sort();
+    // **/** finds some files more than once
+    found.dedup();
+    Ok(found)
+}
+
+fn walk(dir: &Path, parts: &[String], found: &mut Vec<PathBuf>) -> Result<()> {
+    let (part, rest) = match parts.split_first() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("cannot read {}", dir.display()))?;
+    if part == "**" {
+        // a trailing ** stands for everything under it
+        let any = ["*".to_string()];
+        let rest = if rest.is_empty() { &any[..] } else { rest };
+        walk(dir, rest, found)?;
+        for entry in entries {
+            let entry = entry?;
+            // symlinks are left alone, as they can lead round in a circle
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if entry.file_type()?.is_dir() && !hidden {
+                walk(&entry.path(), parts, found)?;
+            }
+        }
+        return Ok(());
+    }
+    for entry in entries {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let hidden = name.starts_with('.') && !part.starts_with('.');
+        if hidden || !project::matches(part, &name) {
+            continue;
+        }
+        if rest.is_empty() && path.is_file() {
+            found.push(path);
+        } else if !rest.is_empty() && path.is_dir() {
+            walk(&path, rest, found)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use serde_json::json;
+
+use crate::{assembler, emulator::Cpu, script, translator::Translator};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Fail,
+    Error,
+}
+
+impl Status {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Pass => "pass",
+            Status::Fail => "fail",
+            Status::Error => "error",
+        }
+    }
+}
+
+/// The result of running one test against one submission.
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub submission: String,
+    pub test: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+#[derive(Debug)]
+struct Test {
+    name: String,
+    tst: PathBuf,
+}
+
+// every .tst under dir, skipping the VM emulator's *VME.tst scripts
+fn find_tests(dir: &Path, tests: &mut Vec<Test>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_tests(&path, tests)?;
+            continue;
+        }
+        let name = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(name) if !name.ends_with("VME") => name.to_string(),
+            _ => continue,
+        };
+        if path.extension().is_some_and(|ext| ext == "tst") {
+            tests.push(Test { name, tst: path });
+        }
+    }
+    Ok(())
+}
+
+// a submission provides test Foo as a Foo/ directory of .vm files or as a
+// single Foo.vm, anywhere inside it
+fn find_program(dir: &Path, name: &str) -> Option<PathBuf> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect();
+    entries.sort();
+
+    let vm = format!("{}.vm", name);
+    for path in &entries {
+        let file_name = path.file_name().and_then(|n| n.to_str());
+        if path.is_dir() && file_name == Some(name) {
+            return Some(path.clone());
+        }
+        if path.is_file() && file_name == Some(vm.as_str()) {
+            return Some(path.clone());
+        }
+    }
+    entries
+        .iter()
+        .filter(|path| path.is_dir())
+        .find_map(|path| find_program(path, name))
+}
+
+fn run(program: &Path, test: &Test) -> Result<Result<()>> {
+    let src = fs::read_to_string(&test.tst)?;
+    let script = script::Script::parse(&src)?;
+    let cmpname = script
+        .compare_to()
+        .ok_or_else(|| anyhow!("{} has no compare-to", test.tst.display()))?;
+    let cmp = fs::read_to_string(test.tst.with_file_name(cmpname))?;
+
+    let mut translator = Translator::new(program)?;
+    translator.process()?;
+    let assembled = assembler::assemble(&translator.render())?;
+
+    let mut cpu = Cpu::new(assembled.words);
+    let out = script.run(&mut cpu);
+    Ok(script::compare(&out, &cmp))
+}
+
+/// Translate every submission's programs and run them against each test
+/// script found under `tests`. Each subdirectory of `submissions` is one
+/// submission.
+pub fn grade(submissions: &Path, tests: &Path) -> Result<Vec<Outcome>> {
+    let mut found = Vec::new();
+    find_tests(tests, &mut found)?;
+    if found.is_empty() {
+        return Err(anyhow!("no test scripts in {}", tests.display()));
+    }
+
+    let mut dirs = fs::read_dir(submissions)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    dirs.retain(|path| path.is_dir());
+    dirs.sort();
+
+    let mut outcomes = Vec::new();
+    for dir in &dirs {
+        let submission = dir
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        for test in &found {
+            let (status, detail) = match find_program(dir, &test.name) {
+                None => (
+                    Status::Error,
+                    format!("no {0}.vm or {0}/ found", test.name),
+                ),
+                Some(program) => match run(&program, test) {
+                    Ok(Ok(())) => (Status::Pass, String::new()),
+                    Ok(Err(mismatch)) => (Status::Fail, mismatch.to_string()),
+                    Err(err) => (Status::Error, err.to_string()),
+                },
+            };
+            outcomes.push(Outcome {
+                submission: submission.clone(),
+                test: test.name.clone(),
+                status,
+                detail,
+            });
+        }
+    }
+    Ok(outcomes)
+}
+
+pub(crate) fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn to_csv(outcomes: &[Outcome]) -> String {
+    let mut buf = "submission,test,status,detail\n".to_string();
+    for outcome in outcomes {
+        buf.push_str(&format!(
+            "{},{},{},{}\n",
+            csv_field(&outcome.submission),
+            csv_field(&outcome.test),
+            outcome.status.name(),
+            csv_field(&outcome.detail),
+        ));
+    }
+    buf
+}
+
+pub fn to_json(outcomes: &[Outcome]) -> Result<String> {
+    let rows: Vec<_> = outcomes
+        .iter()
+        .map(|outcome| {
+            json!({
+                "submission": outcome.submission,
+                "test": outcome.test,
+                "status": outcome.status.name(),
+                "detail": outcome.detail,
+            })
+        })
+        .collect();
+    Ok(format!("{}\n", serde_json::to_string_pretty(&rows)?))
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use crate::translator::Translator;
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 1em; }
+.panes { display: flex; gap: 1em; height: 85vh; }
+.pane { flex: 1; overflow: auto; border: 1px solid #ccc; }
+pre { margin: 0; font-size: 13px; }
+h2 { font-size: 14px; margin: 0.5em; }
+.line, .block { display: block; padding: 0 0.5em; }
+[data-block] { cursor: pointer; }
+.count { color: #888; display: inline-block; width: 3em; text-align: right;
+  margin-right: 1em; }
+.no { color: #888; display: inline-block; width: 3em; }
+.comment { color: #080; }
+.hl { background: #ffef9e; }
+";
+
+// hovering either side highlights the other; clicking scrolls it into view
+const SCRIPT: &str = "\
+const all = id => document.querySelectorAll(`[data-block=\"${id}\"]`);
+for (const el of document.querySelectorAll('[data-block]')) {
+  const id = el.dataset.block;
+  el.addEventListener('mouseenter', () =>
+    all(id).forEach(e => e.classList.add('hl')));
+  el.addEventListener('mouseleave', () =>
+    all(id).forEach(e => e.classList.remove('hl')));
+  el.addEventListener('click', () => all(id).forEach(e => {
+    if (e !== el) e.scrollIntoView({ block: 'center' });
+  }));
+}
+";
+
+fn escape(text: &str) -> String {
+    let mut buf = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => buf.push_str("&amp;"),
+            '<' => buf.push_str("&lt;"),
+            '>' => buf.push_str("&gt;"),
+            '"' => buf.push_str("&quot;"),
+            c => buf.push(c),
+        }
+    }
+    buf
+}
+
+/// A standalone HTML page with the VM source and the generated asm side
+/// by side, each command linked to its block.
+pub fn report(title: &str, translator: &Translator) -> String {
+    let asm = translator.asm();
+
+    // which block each source line produced
+    let mut blocks: HashMap<(&str, usize), usize> = HashMap::new();
+    for (i, block) in asm.iter().enumerate() {
+        if let Some(span) = &block.span {
+            blocks.insert((&*span.file, span.line), i);
+        }
+    }
+
+    let mut vm = String::new();
+    for (path, text) in translator.sources() {
+        // spans name files the same way
+        let path = path.display().to_string();
+        vm.push_str(&format!("<h2>{}</h2>\n<pre>", escape(&path)));
+        for (n, raw) in text.lines().enumerate() {
+            let line = n + 1;
+            match blocks.get(&(path.as_str(), line)) {
+                Some(&i) => vm.push_str(&format!(
+                    "<span class=\"line\" data-block=\"{}\">\
+                     <span class=\"no\">{}</span>\
+                     <span class=\"count\">{}</span>{}</span>",
+                    i,
+                    line,
+                    asm[i].instructions(),
+                    escape(raw)
+                )),
+                None => vm.push_str(&format!(
+                    "<span class=\"line\"><span class=\"no\">{}</span>\
+                     <span class=\"count\"></span>{}</span>",
+                    line,
+                    escape(raw)
+                )),
+            }
+        }
+        vm.push_str("</pre>\n");
+    }
+
+    let mut bin = String::from("<pre>");
+    for (i, block) in asm.iter().enumerate() {
+        bin.push_str(&format!(
+            "<span class=\"block\" data-block=\"{}\">\
+             <span class=\"comment\">{} ({} instructions)</span>\n",
+            i,
+            escape(&block.src),
+            block.instructions()
+        ));
+        for line in block.explain_lines() {
+            bin.push_str(&format!(
+                "<span class=\"comment\">{}</span>\n",
+                escape(&line)
+            ));
+        }
+        for line in &block.bin {
+            bin.push_str(&escape(&line.to_string()));
+            bin.push('\n');
+        }
+        bin.push_str("</span>");
+    }
+    bin.push_str("</pre>\n");
+
+    let total: usize = asm.iter().map(|block| block.instructions()).sum();
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{title}</title>\n<style>\n{style}</style>\n</head>\n\
+         <body>\n<h1>{title}</h1>\n\
+         <p>{commands} commands, {total} instructions</p>\n\
+         <div class=\"panes\">\n<div class=\"pane\">\n{vm}</div>\n\
+         <div class=\"pane\">\n{bin}</div>\n</div>\n\
+         <script>\n{script}</script>\n</body>\n</html>\n",
+        title = escape(title),
+        style = STYLE,
+        commands = asm.len(),
+        total = total,
+        vm = vm,
+        bin = bin,
+        script = SCRIPT,
+    )
+}
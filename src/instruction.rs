@@ -0,0 +1,276 @@
+use std::{
+    borrow::Cow,
+    fmt::{self, Write},
+};
+
+use anyhow::{anyhow, Result};
+
+// each comp as the book spells it, with its a and c bits
+const COMPS: [(&str, u16); 28] = [
+    ("0", 0b0101010),
+    ("1", 0b0111111),
+    ("-1", 0b0111010),
+    ("D", 0b0001100),
+    ("A", 0b0110000),
+    ("!D", 0b0001101),
+    ("!A", 0b0110001),
+    ("-D", 0b0001111),
+    ("-A", 0b0110011),
+    ("D+1", 0b0011111),
+    ("A+1", 0b0110111),
+    ("D-1", 0b0001110),
+    ("A-1", 0b0110010),
+    ("D+A", 0b0000010),
+    ("D-A", 0b0010011),
+    ("A-D", 0b0000111),
+    ("D&A", 0b0000000),
+    ("D|A", 0b0010101),
+    ("M", 0b1110000),
+    ("!M", 0b1110001),
+    ("-M", 0b1110011),
+    ("M+1", 0b1110111),
+    ("M-1", 0b1110010),
+    ("D+M", 0b1000010),
+    ("D-M", 0b1010011),
+    ("M-D", 0b1000111),
+    ("D&M", 0b1000000),
+    ("D|M", 0b1010101),
+];
+
+// by their bits, in the order the book writes them
+const DESTS: [&str; 8] = ["", "M", "D", "MD", "A", "AM", "AD", "AMD"];
+
+// by their bits, none first
+const JUMPS: [&str; 8] = ["", "JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+
+/// One line of generated Hack assembly, kept in parts and only written
+/// out as text with the rest of the program.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// `@value`, an address or a symbol.
+    A(Cow<'static, str>),
+    /// `dest=comp; jump`, where only comp is always there.
+    C { dest: Dest, comp: Comp, jump: Jump },
+    /// `(name)`, which takes no ROM.
+    Label(Cow<'static, str>),
+}
+
+/// Where a C-instruction stores its result: A, D and M as bits 2, 1
+/// and 0, as they're encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Dest(u8);
+
+/// What a C-instruction computes, as its index in the book's table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Comp(u8);
+
+/// The condition a C-instruction jumps on, as its bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Jump(u8);
+
+impl Dest {
+    pub fn new(raw: &str) -> Option<Dest> {
+        let mut bits = 0;
+        for c in raw.chars() {
+            let bit = match c {
+                'A' => 0b100,
+                'D' => 0b010,
+                'M' => 0b001,
+                _ => return None,
+            };
+            if bits & bit != 0 {
+                return None;
+            }
+            bits |= bit;
+        }
+        Some(Dest(bits))
+    }
+
+    pub fn name(self) -> &'static str {
+        DESTS[self.0 as usize]
+    }
+
+    pub fn bits(self) -> u16 {
+        self.0 as u16
+    }
+
+    /// Whether it stores to `register`: 'A', 'D' or 'M'.
+    pub fn writes(self, register: char) -> bool {
+        let bit = match register {
+            'A' => 0b100,
+            'D' => 0b010,
+            'M' => 0b001,
+            _ => return false,
+        };
+        self.0 & bit != 0
+    }
+}
+
+impl Comp {
+    pub fn new(raw: &str) -> Option<Comp> {
+        let i = COMPS.iter().position(|(comp, _)| *comp == raw)?;
+        Some(Comp(i as u8))
+    }
+
+    pub fn name(self) -> &'static str {
+        COMPS[self.0 as usize].0
+    }
+
+    pub fn bits(self) -> u16 {
+        COMPS[self.0 as usize].1
+    }
+
+    /// Like `new`, also taking x+y, x&y and x|y written the other way
+    /// round, as assemblers do.
+    pub fn commuted(raw: &str) -> Option<Comp> {
+        Comp::new(raw).or_else(|| {
+            let op = raw.find(['+', '&', '|'])?;
+            let (x, y) = (&raw[..op], &raw[op + 1..]);
+            Comp::new(&format!("{}{}{}", y, &raw[op..=op], x))
+        })
+    }
+
+    /// Whether it reads `register`: 'A', 'D' or 'M'.
+    pub fn reads(self, register: char) -> bool {
+        self.name().contains(register)
+    }
+}
+
+impl Jump {
+    pub fn new(raw: &str) -> Option<Jump> {
+        let i = JUMPS.iter().position(|jump| *jump == raw)?;
+        Some(Jump(i as u8))
+    }
+
+    pub fn name(self) -> &'static str {
+        JUMPS[self.0 as usize]
+    }
+
+    pub fn bits(self) -> u16 {
+        self.0 as u16
+    }
+}
+
+impl Instruction {
+    /// Read one instruction, without comments, in the form codegen writes
+    /// it.
+    pub fn parse(raw: &str) -> Option<Instruction> {
+        if let Some(value) = raw.strip_prefix('@') {
+            return Some(Instruction::A(value.to_string().into()));
+        }
+        if let Some(label) = raw.strip_prefix('(') {
+            let label = label.strip_suffix(')')?;
+            return Some(Instruction::Label(label.to_string().into()));
+        }
+        Instruction::read_c(raw, false).ok()
+    }
+
+    /// Read `dest=comp; jump`, where dest and jump can be left out along
+    /// with their `=` or `;`, saying which part isn't a Hack mnemonic if
+    /// one isn't. Comps are spelled as the book does unless `commuted`.
+    pub fn read_c(raw: &str, commuted: bool) -> Result<Instruction> {
+        let (dest, rest) = match raw.split_once('=') {
+            Some((dest, rest)) => (Some(dest.trim()), rest),
+            None => (None, raw),
+        };
+        let (comp, jump) = match rest.split_once(';') {
+            Some((comp, jump)) => (comp.trim(), Some(jump.trim())),
+            None => (rest.trim(), None),
+        };
+        // an = or ; with nothing on its other side is no instruction
+        let dest = match dest {
+            Some(dest) => Dest::new(dest)
+                .filter(|bits| bits.0 != 0)
+                .ok_or_else(|| anyhow!("invalid dest: {}", dest))?,
+            None => Dest(0),
+        };
+        let comp = match commuted {
+            true => Comp::commuted(comp),
+            false => Comp::new(comp),
+        }
+        .ok_or_else(|| anyhow!("invalid comp: {}", comp))?;
+        let jump = match jump {
+            Some(jump) => Jump::new(jump)
+                .filter(|bits| bits.0 != 0)
+                .ok_or_else(|| anyhow!("invalid jump: {}", jump))?,
+            None => Jump(0),
+        };
+        Ok(Instruction::C { dest, comp, jump })
+    }
+
+    /// Whether it takes a word of ROM, which labels don't.
+    pub fn is_label(&self) -> bool {
+        matches!(self, Instruction::Label(_))
+    }
+
+    /// The machine word of a C-instruction; A-instructions need their
+    /// symbols resolved first.
+    pub fn word(&self) -> Option<u16> {
+        match self {
+            Instruction::C { dest, comp, jump } => Some(
+                0b111 << 13 | comp.bits() << 6 | dest.bits() << 3 | jump.bits(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// How long it is as text, for sizing the output up front.
+    pub fn text_len(&self) -> usize {
+        match self {
+            Instruction::A(value) => value.len() + 1,
+            Instruction::Label(label) => label.len() + 2,
+            Instruction::C { dest, comp, jump } => {
+                let dest = dest.0.count_ones() as usize;
+                let jump = jump.name().len();
+                comp.name().len()
+                    + if dest > 0 { dest + 1 } else { 0 }
+                    + if jump > 0 { jump + 2 } else { 0 }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::A(value) => {
+                f.write_char('@')?;
+                f.write_str(value)
+            }
+            Instruction::Label(label) => {
+                f.write_char('(')?;
+                f.write_str(label)?;
+                f.write_char(')')
+            }
+            Instruction::C { dest, comp, jump } => {
+                if dest.0 != 0 {
+                    f.write_str(dest.name())?;
+                    f.write_char('=')?;
+                }
+                f.write_str(comp.name())?;
+                match jump.0 {
+                    0 => Ok(()),
+                    _ => {
+                        f.write_str("; ")?;
+                        f.write_str(jump.name())
+                    }
+                }
+            }
+        }
+    }
+}
+
+// codegen's own instructions, written out as text in its source; one that
+// doesn't read is a bug there
+impl From<&'static str> for Instruction {
+    fn from(raw: &'static str) -> Instruction {
+        match raw.as_bytes().first() {
+            Some(b'@') => Instruction::A(Cow::Borrowed(&raw[1..])),
+            Some(b'(') => {
+                Instruction::Label(Cow::Borrowed(&raw[1..raw.len() - 1]))
+            }
+            _ => Instruction::parse(raw)
+                .unwrap_or_else(|| panic!("not an instruction: {}", raw)),
+        }
+    }
+}
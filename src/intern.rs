@@ -0,0 +1,39 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    fmt::{self, Write},
+    sync::Mutex,
+};
+
+// each distinct string is stored once and kept for the rest of the process,
+// which is what lets instructions borrow it as &'static str
+static STRINGS: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+thread_local! {
+    static BUF: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// The shared copy of `s`, stored on first use.
+pub fn intern(s: &str) -> &'static str {
+    let mut strings = STRINGS.lock().unwrap_or_else(|e| e.into_inner());
+    match strings.get(s) {
+        Some(interned) => interned,
+        None => {
+            let interned: &'static str = Box::leak(s.into());
+            strings.insert(interned);
+            interned
+        }
+    }
+}
+
+/// Like `intern(&format!(..))`, but formats into a reused buffer so a
+/// string that's already interned costs no allocation.
+pub fn intern_fmt(args: fmt::Arguments) -> &'static str {
+    BUF.with(|buf| {
+        let mut buf = buf.borrow_mut();
+        buf.clear();
+        // writing to a String can't fail
+        let _ = buf.write_fmt(args);
+        intern(&buf)
+    })
+}
@@ -0,0 +1,415 @@
+use std::{collections::HashMap, convert::TryFrom};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    emulator::MEMORY,
+    parser::{
+        BinaryToken, BranchToken, ComparisonToken, ExtensionToken,
+        FunctionToken, Line, Parser, Segment, Span, StackToken, UnaryToken,
+    },
+};
+
+pub const SP: usize = 0;
+pub const LCL: usize = 1;
+pub const ARG: usize = 2;
+pub const THIS: usize = 3;
+pub const THAT: usize = 4;
+pub const TEMP: usize = 5;
+pub const STACK: usize = 256;
+
+// where the course's test scripts point the segments of a program that
+// isn't started from Sys.init
+const SEGMENTS: [(usize, i16); 5] = [
+    (SP, 256),
+    (LCL, 300),
+    (ARG, 400),
+    (THIS, 3000),
+    (THAT, 3010),
+];
+
+/// One command of the program, with where it came from.
+#[derive(Debug, Clone)]
+pub struct Command {
+    pub line: Line,
+    pub file: usize,
+    // the source text, when the command was parsed from some
+    pub raw: Option<String>,
+    pub span: Option<Span>,
+    // what its labels are scoped to: its function, or its file outside one
+    scope: String,
+}
+
+/// A program's commands numbered in one run across its files, as the
+/// interpreter and the C backend both lay it out.
+#[derive(Debug)]
+pub(crate) struct Program<'a> {
+    pub commands: Vec<Linked<'a>>,
+    // where each label, by scope and name, and each function is
+    pub targets: HashMap<String, usize>,
+    // each file's first static
+    pub statics: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Linked<'a> {
+    pub line: &'a Line,
+    pub file: usize,
+    // where it is in its file
+    pub index: usize,
+    // what its labels are scoped to: its function, or its file outside one
+    pub scope: String,
+}
+
+// statics take RAM from 16, file after file, each file up to its highest
+// index
+pub(crate) fn link(parsers: &[Parser]) -> Program<'_> {
+    let mut program = Program {
+        commands: Vec::new(),
+        targets: HashMap::new(),
+        statics: Vec::new(),
+    };
+    let mut next_static = 16;
+    for (file, parser) in parsers.iter().enumerate() {
+        program.statics.push(next_static);
+        let mut scope = parser.filename().to_string();
+        for (index, line) in parser.lines().iter().enumerate() {
+            let here = program.commands.len();
+            match line {
+                Line::Function(FunctionToken::Function { name, .. }) => {
+                    scope = name.clone();
+                    program.targets.insert(name.clone(), here);
+                }
+                Line::Branch(BranchToken::Label(label)) => {
+                    program
+                        .targets
+                        .insert(format!("{}${}", scope, label), here);
+                }
+                Line::Stack(
+                    StackToken::Push {
+                        segment: Segment::Static,
+                        index,
+                    }
+                    | StackToken::Pop {
+                        segment: Segment::Static,
+                        index,
+                    },
+                ) => {
+                    next_static = next_static
+                        .max(program.statics[file] + *index as usize + 1)
+                }
+                _ => {}
+            }
+            program.commands.push(Linked {
+                line,
+                file,
+                index,
+                scope: scope.clone(),
+            });
+        }
+    }
+    program
+}
+
+/// A call that hasn't returned, for telling apart the words of its frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub function: String,
+    pub args: u16,
+    pub locals: u16,
+    // where the callee's locals start, above the saved frame
+    pub lcl: usize,
+}
+
+/// Runs VM commands directly, a command at a time, with the stack and
+/// segments in RAM where the translated program would keep them.
+#[derive(Debug)]
+pub struct Interpreter {
+    pub ram: Vec<i16>,
+    commands: Vec<Command>,
+    files: Vec<String>,
+    // each file's first static
+    statics: Vec<usize>,
+    // labels by scope and name, and functions by name
+    targets: HashMap<String, usize>,
+    pc: usize,
+    frames: Vec<Frame>,
+    pub steps: u64,
+    // why the program stopped, once it has
+    stopped: Option<String>,
+}
+
+impl Interpreter {
+    /// Load the program parsed by `parsers`. It starts at Sys.init with
+    /// SP at 256 when it defines one, and otherwise at its first command,
+    /// with the segments where the course's test scripts put them.
+    pub fn new(parsers: &[Parser]) -> Result<Interpreter> {
+        let program = link(parsers);
+        let commands = program
+            .commands
+            .into_iter()
+            .map(|linked| {
+                let parser = &parsers[linked.file];
+                Command {
+                    line: linked.line.clone(),
+                    file: linked.file,
+                    raw: parser.raws().get(linked.index).map(str::to_string),
+                    span: parser.spans().get(linked.index).cloned(),
+                    scope: linked.scope,
+                }
+            })
+            .collect();
+
+        let mut interpreter = Interpreter {
+            ram: vec![0; MEMORY],
+            commands,
+            files: parsers.iter().map(|p| p.filename().to_string()).collect(),
+            statics: program.statics,
+            targets: program.targets,
+            pc: 0,
+            frames: Vec::new(),
+            steps: 0,
+            stopped: None,
+        };
+        match interpreter.targets.get("Sys.init") {
+            Some(_) => {
+                interpreter.ram[SP] = STACK as i16;
+                // returning from Sys.init ends the program
+                let end = interpreter.commands.len();
+                interpreter.pc = interpreter.call("Sys.init", 0, end)?;
+            }
+            None => {
+                for (pointer, value) in SEGMENTS {
+                    interpreter.ram[pointer] = value;
+                }
+            }
+        }
+        Ok(interpreter)
+    }
+
+    /// The command that runs next, if the program hasn't stopped.
+    pub fn next_command(&self) -> Option<&Command> {
+        match self.stopped {
+            Some(_) => None,
+            None => self.commands.get(self.pc),
+        }
+    }
+
+    pub fn file(&self, command: &Command) -> &str {
+        &self.files[command.file]
+    }
+
+    /// Calls that haven't returned, outermost first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Where the statics of `file` start.
+    pub fn static_base(&self, file: usize) -> usize {
+        self.statics[file]
+    }
+
+    pub fn stopped(&self) -> Option<&str> {
+        self.stopped.as_deref()
+    }
+
+    /// Run the next command, returning false once the program has
+    /// stopped.
+    pub fn step(&mut self) -> Result<bool> {
+        if self.stopped.is_some() {
+            return Ok(false);
+        }
+        let command = match self.commands.get(self.pc) {
+            Some(command) => command.clone(),
+            None => {
+                self.stopped = Some("ran past the last command".to_string());
+                return Ok(false);
+            }
+        };
+        self.steps += 1;
+        let mut next = self.pc + 1;
+        match &command.line {
+            Line::Stack(StackToken::Push { segment, index }) => {
+                let value = match segment {
+                    Segment::Constant => *index as i16,
+                    _ => {
+                        let address =
+                            self.address(segment, *index, command.file)?;
+                        self.read(address)?
+                    }
+                };
+                self.push(value)?;
+            }
+            Line::Stack(StackToken::Pop { segment, index }) => {
+                let value = self.pop()?;
+                let address = self.address(segment, *index, command.file)?;
+                self.write(address, value)?;
+            }
+            Line::Binary(op) => {
+                let y = self.pop()?;
+                let x = self.pop()?;
+                self.push(match op {
+                    BinaryToken::Add => x.wrapping_add(y),
+                    BinaryToken::Sub => x.wrapping_sub(y),
+                    BinaryToken::And => x & y,
+                    BinaryToken::Or => x | y,
+                })?;
+            }
+            Line::Unary(op) => {
+                let x = self.pop()?;
+                self.push(match op {
+                    UnaryToken::Neg => x.wrapping_neg(),
+                    UnaryToken::Not => !x,
+                })?;
+            }
+            Line::Comparison(op) => {
+                let y = self.pop()?;
+                let x = self.pop()?;
+                // on the difference, as the generated code compares
+                let diff = x.wrapping_sub(y);
+                let holds = match op {
+                    ComparisonToken::Equal => diff == 0,
+                    ComparisonToken::GreaterThan => diff > 0,
+                    ComparisonToken::LessThan => diff < 0,
+                };
+                self.push(-(holds as i16))?;
+            }
+            Line::Branch(BranchToken::Label(_)) => {}
+            Line::Branch(BranchToken::GoTo(label)) => {
+                let target = self.label(&command.scope, label)?;
+                // a label straight before its own goto is a loop that
+                // never ends, the usual way to stop
+                if target + 1 == self.pc {
+                    self.stopped = Some("looping for good".to_string());
+                }
+                next = target;
+            }
+            Line::Branch(BranchToken::IfGoTo(label)) => {
+                if self.pop()? != 0 {
+                    next = self.label(&command.scope, label)?;
+                }
+            }
+            Line::Function(FunctionToken::Function { locals, .. }) => {
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.locals = *locals;
+                }
+                for _ in 0..*locals {
+                    self.push(0)?;
+                }
+            }
+            Line::Function(FunctionToken::Call { name, args }) => {
+                next = self.call(name, *args, self.pc + 1)?;
+            }
+            Line::Function(FunctionToken::Return) => {
+                next = self.ret()?;
+            }
+            Line::Extension(ExtensionToken::Assert) => {
+                if self.pop()? == 0 {
+                    self.stopped = Some("an assert failed".to_string());
+                }
+            }
+            Line::Extension(ExtensionToken::Halt) => {
+                self.stopped = Some("halted".to_string());
+            }
+            Line::Extension(ExtensionToken::Print) => {
+                self.pop()?;
+            }
+        }
+        self.pc = next;
+        if self.pc >= self.commands.len() && self.stopped.is_none() {
+            self.stopped = Some("ran past the last command".to_string());
+        }
+        Ok(self.stopped.is_none())
+    }
+
+    // push the frame and jump to `name`, returning where to go
+    fn call(&mut self, name: &str, args: u16, ret: usize) -> Result<usize> {
+        let target = *self
+            .targets
+            .get(name)
+            .ok_or_else(|| anyhow!("call to undefined function {}", name))?;
+        self.push(ret as i16)?;
+        for pointer in [LCL, ARG, THIS, THAT] {
+            self.push(self.ram[pointer])?;
+        }
+        let sp = self.ram[SP];
+        self.ram[ARG] = sp - 5 - args as i16;
+        self.ram[LCL] = sp;
+        self.frames.push(Frame {
+            function: name.to_string(),
+            args,
+            locals: 0,
+            lcl: sp as usize,
+        });
+        Ok(target)
+    }
+
+    fn ret(&mut self) -> Result<usize> {
+        let frame = self.ram[LCL] as i32;
+        let ret = self.read(frame - 5)?;
+        let value = self.pop()?;
+        let arg = self.ram[ARG] as i32;
+        self.write(arg, value)?;
+        self.ram[SP] = (arg + 1) as i16;
+        for (i, &pointer) in [THAT, THIS, ARG, LCL].iter().enumerate() {
+            self.ram[pointer] = self.read(frame - 1 - i as i32)?;
+        }
+        self.frames.pop();
+        Ok(ret as u16 as usize)
+    }
+
+    fn label(&self, scope: &str, label: &str) -> Result<usize> {
+        self.targets
+            .get(&format!("{}${}", scope, label))
+            .copied()
+            .ok_or_else(|| anyhow!("no label {} in {}", label, scope))
+    }
+
+    fn address(
+        &self,
+        segment: &Segment,
+        index: u16,
+        file: usize,
+    ) -> Result<i32> {
+        let index = index as i32;
+        Ok(match segment {
+            Segment::Constant => return Err(anyhow!("cannot pop constant")),
+            Segment::Local => self.ram[LCL] as i32 + index,
+            Segment::Argument => self.ram[ARG] as i32 + index,
+            Segment::This => self.ram[THIS] as i32 + index,
+            Segment::That => self.ram[THAT] as i32 + index,
+            Segment::Temp => TEMP as i32 + index,
+            Segment::Pointer => THIS as i32 + index,
+            Segment::Static => self.statics[file] as i32 + index,
+        })
+    }
+
+    fn read(&self, address: i32) -> Result<i16> {
+        usize::try_from(address)
+            .ok()
+            .and_then(|address| self.ram.get(address))
+            .copied()
+            .ok_or_else(|| anyhow!("RAM[{}] is out of range", address))
+    }
+
+    fn write(&mut self, address: i32, value: i16) -> Result<()> {
+        let word = usize::try_from(address)
+            .ok()
+            .and_then(|address| self.ram.get_mut(address))
+            .ok_or_else(|| anyhow!("RAM[{}] is out of range", address))?;
+        *word = value;
+        Ok(())
+    }
+
+    fn push(&mut self, value: i16) -> Result<()> {
+        let sp = self.ram[SP] as i32;
+        self.write(sp, value)?;
+        self.ram[SP] += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<i16> {
+        self.ram[SP] -= 1;
+        self.read(self.ram[SP] as i32)
+    }
+}
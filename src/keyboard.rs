@@ -0,0 +1,80 @@
+use anyhow::{anyhow, Result};
+
+/// How long a typed character is held down, and the gap after it, in
+/// cycles: long enough for a program polling the keyboard between other
+/// work to see each key come and go.
+pub const TYPING: u64 = 1000;
+
+/// Parse a key script into (cycle, key code) events, in cycle order. The
+/// script is a list of `cycle=key` events split by commas or newlines,
+/// where the key is a code, a single character, a key name such as
+/// `newline` or `f1`, `none` to let go, or text in double quotes, which is
+/// typed a key at a time from that cycle on.
+pub fn parse(script: &str) -> Result<Vec<(u64, i16)>> {
+    let mut events = Vec::new();
+    for event in script.split([',', '\n']).map(str::trim) {
+        if event.is_empty() || event.starts_with('#') {
+            continue;
+        }
+        let (cycle, key) = event
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected cycle=key: {}", event))?;
+        let cycle: u64 = cycle
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("unexpected cycle: {}", cycle))?;
+        let key = key.trim();
+        match key.strip_prefix('"').and_then(|key| key.strip_suffix('"')) {
+            Some(text) => {
+                for (i, c) in text.chars().enumerate() {
+                    let at = cycle + 2 * TYPING * i as u64;
+                    events.push((at, code(c)?));
+                    events.push((at + TYPING, 0));
+                }
+            }
+            None => events.push((cycle, key_code(key)?)),
+        }
+    }
+    // stable, so a release and a press on the same cycle keep their order
+    events.sort_by_key(|&(cycle, _)| cycle);
+    Ok(events)
+}
+
+fn code(c: char) -> Result<i16> {
+    match c {
+        ' '..='~' => Ok(c as i16),
+        _ => Err(anyhow!("no key for {:?}", c)),
+    }
+}
+
+// the Hack keyboard's codes, past ASCII
+fn key_code(key: &str) -> Result<i16> {
+    let named = match key {
+        "none" => 0,
+        "newline" => 128,
+        "backspace" => 129,
+        "left" => 130,
+        "up" => 131,
+        "right" => 132,
+        "down" => 133,
+        "home" => 134,
+        "end" => 135,
+        "pageup" => 136,
+        "pagedown" => 137,
+        "insert" => 138,
+        "delete" => 139,
+        "esc" => 140,
+        _ => match key.strip_prefix('f').map(str::parse::<i16>) {
+            Some(Ok(n @ 1..=12)) => 140 + n,
+            _ => {
+                let mut chars = key.chars();
+                return match (chars.next(), chars.next(), key.parse()) {
+                    (_, _, Ok(code)) => Ok(code),
+                    (Some(c), None, _) => code(c),
+                    _ => Err(anyhow!("unexpected key: {}", key)),
+                };
+            }
+        },
+    };
+    Ok(named)
+}
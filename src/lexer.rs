@@ -0,0 +1,108 @@
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Command,
+    Segment,
+    Index,
+    Label,
+    Function,
+    Comment,
+    Unknown,
+}
+
+impl TokenKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenKind::Command => "command",
+            TokenKind::Segment => "segment",
+            TokenKind::Index => "index",
+            TokenKind::Label => "label",
+            TokenKind::Function => "function",
+            TokenKind::Comment => "comment",
+            TokenKind::Unknown => "unknown",
+        }
+    }
+}
+
+/// A classified slice of VM source. `span` is a byte range into the text
+/// that was tokenized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    pub span: Range<usize>,
+}
+
+/// Split VM source into tokens. Words are classified by their position in
+/// the command rather than validated, so partially typed or invalid lines
+/// still highlight sensibly.
+pub fn tokenize(src: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut offset = 0;
+    for line in src.split_inclusive('\n') {
+        tokenize_line(line, offset, &mut tokens);
+        offset += line.len();
+    }
+    tokens
+}
+
+fn tokenize_line<'a>(
+    line: &'a str,
+    offset: usize,
+    tokens: &mut Vec<Token<'a>>,
+) {
+    let (code, comment) = match line.find("//") {
+        Some(i) => (&line[..i], Some(i)),
+        None => (line, None),
+    };
+
+    let mut cmd = "";
+    for (position, (start, word)) in words(code).into_iter().enumerate() {
+        let kind = match (position, cmd) {
+            (0, _) => TokenKind::Command,
+            (1, "push") | (1, "pop") => TokenKind::Segment,
+            (1, "label") | (1, "goto") | (1, "if-goto") => TokenKind::Label,
+            (1, "function") | (1, "call") => TokenKind::Function,
+            (2, "push") | (2, "pop") | (2, "function") | (2, "call") => {
+                TokenKind::Index
+            }
+            _ => TokenKind::Unknown,
+        };
+        if position == 0 {
+            cmd = word;
+        }
+
+        tokens.push(Token {
+            kind,
+            text: word,
+            span: offset + start..offset + start + word.len(),
+        });
+    }
+
+    if let Some(start) = comment {
+        let text = line[start..].trim_end();
+        tokens.push(Token {
+            kind: TokenKind::Comment,
+            text,
+            span: offset + start..offset + start + text.len(),
+        });
+    }
+}
+
+// whitespace-separated words with their starting byte
+fn words(raw: &str) -> Vec<(usize, &str)> {
+    let mut ws = Vec::new();
+    let mut start = None;
+    for (i, c) in raw.char_indices().chain(Some((raw.len(), ' '))) {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                ws.push((s, &raw[s..i]));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    ws
+}
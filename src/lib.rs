@@ -0,0 +1,39 @@
+pub mod arena;
+pub mod assembler;
+pub mod backend;
+pub mod builder;
+pub mod cfg;
+pub mod codegen;
+pub mod dap;
+pub mod debugger;
+pub mod diagnostics;
+pub mod emulator;
+pub mod glob;
+pub mod grade;
+pub mod html;
+pub mod instruction;
+pub mod intern;
+pub mod interpreter;
+pub mod keyboard;
+pub mod lexer;
+pub mod lint;
+pub mod lsp;
+pub mod optimize;
+pub mod parser;
+pub mod passes;
+pub mod pgo;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+pub mod project;
+pub mod refactor;
+pub mod screen;
+pub mod script;
+pub mod sourcemap;
+pub mod stats;
+pub mod style;
+pub mod teach;
+pub mod trace;
+pub mod translator;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod verify;
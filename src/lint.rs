@@ -0,0 +1,204 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+
+use crate::{
+    diagnostics::{Diagnostic, Severity},
+    parser::{FunctionToken, Line},
+};
+
+/// Every lint, by the name `--deny`, `--warn` and `--allow` take.
+pub const LINTS: [&str; 3] =
+    ["unreachable-code", "over-budget", "static-overflow"];
+
+/// What becomes of a lint's findings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    pub fn new(raw: &str) -> Result<Level> {
+        match raw {
+            "allow" => Ok(Level::Allow),
+            "warn" => Ok(Level::Warn),
+            "deny" => Ok(Level::Deny),
+            _ => Err(anyhow!("unexpected lint level: {}", raw)),
+        }
+    }
+}
+
+/// Check a lint name, where `warnings` also stands for every warning.
+pub fn check(name: &str) -> Result<()> {
+    known(name).map(drop)
+}
+
+/// The lint named `name`, or `warnings`, as the name lints are known by.
+pub fn known(name: &str) -> Result<&'static str> {
+    LINTS
+        .iter()
+        .chain(&["warnings"])
+        .find(|&&lint| lint == name)
+        .copied()
+        .ok_or_else(|| {
+            anyhow!(
+                "unknown lint: {} (expected warnings or one of {})",
+                name,
+                LINTS.join(", ")
+            )
+        })
+}
+
+/// Apply `levels` to a warning: dropped where its lint is allowed, an
+/// error where it's denied. A lint's own allow or deny comes first, then
+/// the level of `warnings`, which also covers warnings from no lint.
+pub fn apply(
+    levels: &BTreeMap<String, Level>,
+    mut warning: Diagnostic,
+) -> Option<Diagnostic> {
+    let level = warning
+        .lint
+        .and_then(|name| levels.get(name))
+        .filter(|&&level| level != Level::Warn)
+        .or_else(|| levels.get("warnings"));
+    match level {
+        Some(Level::Allow) => None,
+        Some(Level::Deny) => {
+            warning.severity = Severity::Error;
+            Some(warning)
+        }
+        _ => Some(warning),
+    }
+}
+
+/// Warnings already known about, which aren't reported again, so that a
+/// project can take up the lints a bit at a time. Each is told apart by
+/// its lint, its file's name and the code it points at, rather than by
+/// line, so edits elsewhere in the file don't bring it back.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Baseline {
+    // lint, file and code, the last two empty for a finding about the
+    // whole program
+    entries: BTreeSet<(String, String, String)>,
+}
+
+impl Baseline {
+    pub fn insert(&mut self, lint: &str, file: &str, code: &str) {
+        self.entries.insert((
+            lint.to_string(),
+            file.to_string(),
+            code.to_string(),
+        ));
+    }
+
+    pub fn contains(&self, lint: &str, file: &str, code: &str) -> bool {
+        // without allocating a key
+        self.entries
+            .iter()
+            .any(|(l, f, c)| l == lint && f == file && c == code)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// `{"warnings": [{"lint": "over-budget", "file": "Main.vm", "code":
+    /// "function Main.main 0"}, ...]}`
+    pub fn to_json(&self) -> String {
+        let warnings: Vec<Value> = self
+            .entries
+            .iter()
+            .map(|(lint, file, code)| {
+                json!({ "lint": lint, "file": file, "code": code })
+            })
+            .collect();
+        let mut json =
+            serde_json::to_string_pretty(&json!({ "warnings": warnings }))
+                .unwrap_or_default();
+        json.push('\n');
+        json
+    }
+
+    pub fn parse(json: &str) -> Result<Baseline> {
+        let value: Value = serde_json::from_str(json)?;
+        let warnings = value["warnings"]
+            .as_array()
+            .ok_or_else(|| anyhow!("expected an array of warnings"))?;
+        let mut baseline = Baseline::default();
+        for warning in warnings {
+            let field = |key: &str| {
+                warning[key].as_str().ok_or_else(|| {
+                    anyhow!("expected a {} for {}", key, warning)
+                })
+            };
+            let lint = field("lint")?;
+            check(lint)?;
+            baseline.insert(lint, field("file")?, field("code")?);
+        }
+        Ok(baseline)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Baseline> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("cannot read {}", path.display()))?;
+        Baseline::parse(&json)
+            .with_context(|| format!("cannot load {}", path.display()))
+    }
+}
+
+/// Functions that no chain of calls reaches from where the program starts,
+/// in the order they're defined. The program starts at Sys.init when it's
+/// bootstrapped and at its first line otherwise, so there the first
+/// function, which the code can run into, counts as reached too. Calls
+/// made outside any function always count.
+pub fn unreachable_functions<'a>(
+    lines: impl IntoIterator<Item = &'a Line>,
+    bootstrap: bool,
+) -> Vec<&'a str> {
+    let mut defined = Vec::new();
+    let mut calls: BTreeMap<Option<&str>, BTreeSet<&str>> = BTreeMap::new();
+    let mut current = None;
+    for line in lines {
+        match line {
+            Line::Function(FunctionToken::Function { name, .. }) => {
+                defined.push(name.as_str());
+                current = Some(name.as_str());
+            }
+            Line::Function(FunctionToken::Call { name, .. }) => {
+                calls.entry(current).or_default().insert(name);
+            }
+            _ => {}
+        }
+    }
+
+    let mut todo: Vec<Option<&str>> = vec![None];
+    match bootstrap {
+        true => todo.push(Some("Sys.init")),
+        false => todo.extend(defined.first().map(|&name| Some(name))),
+    }
+    let mut reached = BTreeSet::new();
+    while let Some(function) = todo.pop() {
+        if !reached.insert(function) {
+            continue;
+        }
+        if let Some(callees) = calls.get(&function) {
+            todo.extend(callees.iter().map(|&name| Some(name)));
+        }
+    }
+    defined
+        .into_iter()
+        .filter(|&name| !reached.contains(&Some(name)))
+        .collect()
+}
@@ -0,0 +1,562 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    str,
+};
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+
+use crate::{
+    codegen::CodeGen,
+    diagnostics::Diagnostic,
+    lexer::{self, TokenKind},
+    parser::Parser,
+};
+
+// lsp SymbolKind values
+const SYMBOL_FUNCTION: u8 = 12;
+const SYMBOL_KEY: u8 = 20;
+
+// lsp DiagnosticSeverity values
+const SEVERITY_ERROR: u8 = 1;
+
+#[derive(Debug, Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+    shutdown: bool,
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server::default()
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        self.serve(&mut stdin.lock(), &mut stdout.lock())
+    }
+
+    /// Answer messages from `input` until it ends or the client exits.
+    pub fn serve(
+        &mut self,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> Result<()> {
+        while let Some(msg) = read_message(input)? {
+            let method = msg["method"].as_str().unwrap_or("").to_string();
+            if method == "exit" {
+                break;
+            }
+
+            let id = msg.get("id").cloned();
+            let params = &msg["params"];
+            let result = match method.as_str() {
+                "initialize" => Some(self.initialize()),
+                "shutdown" => {
+                    self.shutdown = true;
+                    Some(Value::Null)
+                }
+                "textDocument/didOpen" => {
+                    let uri = params["textDocument"]["uri"].as_str();
+                    let text = params["textDocument"]["text"].as_str();
+                    if let (Some(uri), Some(text)) = (uri, text) {
+                        self.documents
+                            .insert(uri.to_string(), text.to_string());
+                        write_message(output, &self.publish(uri))?;
+                    }
+                    None
+                }
+                "textDocument/didChange" => {
+                    // full sync, so the last change holds the whole document
+                    let uri = params["textDocument"]["uri"].as_str();
+                    let text = params["contentChanges"]
+                        .as_array()
+                        .and_then(|changes| changes.last())
+                        .and_then(|change| change["text"].as_str());
+                    if let (Some(uri), Some(text)) = (uri, text) {
+                        self.documents
+                            .insert(uri.to_string(), text.to_string());
+                        write_message(output, &self.publish(uri))?;
+                    }
+                    None
+                }
+                "textDocument/didClose" => {
+                    if let Some(uri) = params["textDocument"]["uri"].as_str() {
+                        self.documents.remove(uri);
+                        let clear = notification(
+                            "textDocument/publishDiagnostics",
+                            json!({ "uri": uri, "diagnostics": [] }),
+                        );
+                        write_message(output, &clear)?;
+                    }
+                    None
+                }
+                "textDocument/hover" => Some(self.hover(params)),
+                "textDocument/documentSymbol" => Some(self.symbols(params)),
+                "textDocument/definition" => Some(self.definition(params)),
+                "textDocument/references" => Some(self.references(params)),
+                _ => None,
+            };
+
+            // requests always get an answer, notifications never do
+            if let Some(id) = id {
+                let response = match result {
+                    Some(result) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "result": result,
+                    }),
+                    None => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {
+                            "code": -32601,
+                            "message": format!("unsupported method: {}", method),
+                        },
+                    }),
+                };
+                write_message(output, &response)?;
+            }
+        }
+
+        if self.shutdown {
+            Ok(())
+        } else {
+            Err(anyhow!("exit received before shutdown"))
+        }
+    }
+
+    fn initialize(&self) -> Value {
+        json!({
+            "capabilities": {
+                "textDocumentSync": 1,
+                "hoverProvider": true,
+                "documentSymbolProvider": true,
+                "definitionProvider": true,
+                "referencesProvider": true,
+            },
+            "serverInfo": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        })
+    }
+
+    fn publish(&self, uri: &str) -> Value {
+        let text = self.documents.get(uri).map(String::as_str).unwrap_or("");
+        notification(
+            "textDocument/publishDiagnostics",
+            json!({ "uri": uri, "diagnostics": diagnose(uri, text) }),
+        )
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let text = params["textDocument"]["uri"]
+            .as_str()
+            .and_then(|uri| self.documents.get(uri));
+        let line = params["position"]["line"].as_u64();
+        let character = params["position"]["character"].as_u64();
+
+        let (text, line, character) = match (text, line, character) {
+            (Some(t), Some(l), Some(c)) => (t, l as usize, c as usize),
+            _ => return Value::Null,
+        };
+
+        let raw = match text.lines().nth(line) {
+            Some(raw) => raw,
+            None => return Value::Null,
+        };
+
+        let token = lexer::tokenize(raw).into_iter().find(|t| {
+            t.kind != TokenKind::Comment
+                && t.span.start <= character
+                && character <= t.span.end
+        });
+
+        match token.and_then(|t| Some((doc(t.text)?, t))) {
+            Some((doc, t)) => json!({
+                "contents": { "kind": "markdown", "value": doc },
+                "range": range(line, t.span.start, t.span.end),
+            }),
+            None => Value::Null,
+        }
+    }
+
+    fn symbols(&self, params: &Value) -> Value {
+        let text = match params["textDocument"]["uri"]
+            .as_str()
+            .and_then(|uri| self.documents.get(uri))
+        {
+            Some(text) => text,
+            None => return json!([]),
+        };
+
+        let mut functions: Vec<Value> = Vec::new();
+        let mut labels: Vec<Value> = Vec::new();
+
+        for (i, raw) in text.lines().enumerate() {
+            let tokens = lexer::tokenize(raw);
+            let (cmd, name) = match (tokens.first(), tokens.get(1)) {
+                (Some(cmd), Some(name)) if name.kind != TokenKind::Comment => {
+                    (cmd, name)
+                }
+                _ => continue,
+            };
+
+            let kind = match cmd.text {
+                "function" => SYMBOL_FUNCTION,
+                "label" => SYMBOL_KEY,
+                _ => continue,
+            };
+            let symbol = json!({
+                "name": name.text,
+                "kind": kind,
+                "range": range(i, 0, raw.len()),
+                "selectionRange": range(i, name.span.start, name.span.end),
+                "children": [],
+            });
+
+            if kind == SYMBOL_KEY {
+                // labels belong to the enclosing function, if any
+                match functions.last_mut() {
+                    Some(f) => {
+                        f["children"].as_array_mut().unwrap().push(symbol)
+                    }
+                    None => labels.push(symbol),
+                }
+            } else {
+                functions.push(symbol);
+            }
+        }
+
+        // stretch each function's range to cover its body
+        let ends: Vec<Value> = functions
+            .iter()
+            .skip(1)
+            .map(|f| f["range"]["start"].clone())
+            .collect();
+        let last = text.lines().count();
+        for (i, f) in functions.iter_mut().enumerate() {
+            f["range"]["end"] = match ends.get(i) {
+                Some(end) => end.clone(),
+                None => json!({ "line": last, "character": 0 }),
+            };
+        }
+
+        labels.extend(functions);
+        Value::Array(labels)
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let at = match self.name_at(params) {
+            Some(at) => at,
+            None => return Value::Null,
+        };
+        let found: Vec<Value> = self
+            .workspace(&at.uri)
+            .values()
+            .flat_map(|(uri, text)| names(uri, text))
+            .filter(|name| name.definition && name.refers_to(&at))
+            .map(|name| name.location())
+            .collect();
+        Value::Array(found)
+    }
+
+    fn references(&self, params: &Value) -> Value {
+        let at = match self.name_at(params) {
+            Some(at) => at,
+            None => return json!([]),
+        };
+        let declaration = params["context"]["includeDeclaration"]
+            .as_bool()
+            .unwrap_or(false);
+        let found: Vec<Value> = self
+            .workspace(&at.uri)
+            .values()
+            .flat_map(|(uri, text)| names(uri, text))
+            .filter(|name| {
+                (declaration || !name.definition) && name.refers_to(&at)
+            })
+            .map(|name| name.location())
+            .collect();
+        Value::Array(found)
+    }
+
+    // the function or label name under the cursor
+    fn name_at(&self, params: &Value) -> Option<Name> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let text = self.documents.get(uri)?;
+        let line = params["position"]["line"].as_u64()? as usize;
+        let character = params["position"]["character"].as_u64()? as usize;
+        names(uri, text).into_iter().find(|name| {
+            name.line == line
+                && name.start <= character
+                && character <= name.end
+        })
+    }
+
+    // the program `uri` is part of: every .vm file in its directory, as
+    // it's open or else as it is on disk, each by its path with its uri
+    // and text
+    fn workspace(&self, uri: &str) -> BTreeMap<String, (String, String)> {
+        let mut documents = BTreeMap::new();
+        let dir: Option<PathBuf> =
+            file_path(uri).and_then(|path| Some(path.parent()?.into()));
+        let entries = dir.as_deref().and_then(|dir| fs::read_dir(dir).ok());
+        for path in entries.into_iter().flatten().flatten().map(|e| e.path()) {
+            if path.extension().is_some_and(|ext| ext == "vm") {
+                if let Ok(text) = fs::read_to_string(&path) {
+                    let key = path.display().to_string();
+                    documents.insert(key, (file_uri(&path), text));
+                }
+            }
+        }
+
+        // open documents stand in for what's on disk; those that aren't
+        // files only have each other
+        let parent = uri.rsplit_once('/').map(|(parent, _)| parent);
+        for (open, text) in &self.documents {
+            let key = match (&dir, file_path(open)) {
+                (Some(dir), Some(path))
+                    if path.parent() == Some(dir.as_path()) =>
+                {
+                    path.display().to_string()
+                }
+                (None, None)
+                    if open.rsplit_once('/').map(|(p, _)| p) == parent =>
+                {
+                    open.clone()
+                }
+                _ => continue,
+            };
+            documents.insert(key, (open.clone(), text.clone()));
+        }
+        documents
+    }
+}
+
+// a function or label name where it's written in a document
+#[derive(Debug)]
+struct Name {
+    uri: String,
+    line: usize,
+    start: usize,
+    end: usize,
+    text: String,
+    // the function a label belongs to; none for function names
+    scope: Option<String>,
+    // a function or label command, rather than a call or jump
+    definition: bool,
+}
+
+impl Name {
+    // functions are found across the program, labels only within their
+    // function
+    fn refers_to(&self, other: &Name) -> bool {
+        self.text == other.text
+            && self.scope == other.scope
+            && (self.scope.is_none() || self.uri == other.uri)
+    }
+
+    fn location(&self) -> Value {
+        json!({
+            "uri": self.uri,
+            "range": range(self.line, self.start, self.end),
+        })
+    }
+}
+
+fn names(uri: &str, text: &str) -> Vec<Name> {
+    let mut names = Vec::new();
+    let mut function = String::new();
+    for (i, raw) in text.lines().enumerate() {
+        let tokens = lexer::tokenize(raw);
+        let (cmd, name) = match (tokens.first(), tokens.get(1)) {
+            (Some(cmd), Some(name))
+                if matches!(
+                    name.kind,
+                    TokenKind::Label | TokenKind::Function
+                ) =>
+            {
+                (cmd, name)
+            }
+            _ => continue,
+        };
+        if cmd.text == "function" {
+            function = name.text.to_string();
+        }
+        names.push(Name {
+            uri: uri.to_string(),
+            line: i,
+            start: name.span.start,
+            end: name.span.end,
+            text: name.text.to_string(),
+            scope: match name.kind {
+                TokenKind::Label => Some(function.clone()),
+                _ => None,
+            },
+            definition: matches!(cmd.text, "function" | "label"),
+        });
+    }
+    names
+}
+
+// run every line through the parser (and codegen) exactly as translation
+// would, reporting the first error on each line
+fn diagnose(uri: &str, text: &str) -> Vec<Value> {
+    let filename = uri
+        .rsplit('/')
+        .next()
+        .and_then(|name| name.strip_suffix(".vm"))
+        .unwrap_or("lsp");
+    let mut parser = Parser::new(filename.to_string(), uri.to_string());
+    let mut cg = CodeGen::new(filename.to_string());
+
+    let mut diagnostics = Vec::new();
+    for (i, raw) in text.lines().enumerate() {
+        let result = match parser.parse_line(raw) {
+            Ok(Some(line)) => cg.gen_block(line).map(|_| ()),
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            let start = raw.len() - raw.trim_start().len();
+            let end = raw.trim_end().len();
+            diagnostics.push(json!({
+                "range": range(i, start, end),
+                "severity": SEVERITY_ERROR,
+                "source": env!("CARGO_PKG_NAME"),
+                "message": match err.downcast_ref::<Diagnostic>() {
+                    Some(diagnostic) => diagnostic.message.to_string(),
+                    None => err.to_string(),
+                },
+            }));
+        }
+    }
+    diagnostics
+}
+
+fn doc(word: &str) -> Option<&'static str> {
+    match word {
+        "push" => Some("**push** *segment* *index*\n\nPush the value of `segment[index]` onto the stack."),
+        "pop" => Some("**pop** *segment* *index*\n\nPop the top of the stack and store it in `segment[index]`."),
+        "add" => Some("**add**\n\nPop `y` and `x`, push `x + y`."),
+        "sub" => Some("**sub**\n\nPop `y` and `x`, push `x - y`."),
+        "neg" => Some("**neg**\n\nPop `y`, push `-y`."),
+        "and" => Some("**and**\n\nPop `y` and `x`, push bitwise `x & y`."),
+        "or" => Some("**or**\n\nPop `y` and `x`, push bitwise `x | y`."),
+        "not" => Some("**not**\n\nPop `y`, push bitwise `!y`."),
+        "eq" => Some("**eq**\n\nPop `y` and `x`, push true (-1) if `x == y`, else false (0)."),
+        "gt" => Some("**gt**\n\nPop `y` and `x`, push true (-1) if `x > y`, else false (0)."),
+        "lt" => Some("**lt**\n\nPop `y` and `x`, push true (-1) if `x < y`, else false (0)."),
+        "label" => Some("**label** *name*\n\nMark a jump target, scoped to the enclosing function."),
+        "goto" => Some("**goto** *label*\n\nJump unconditionally to `label`."),
+        "if-goto" => Some("**if-goto** *label*\n\nPop the top of the stack and jump to `label` if it is not false (0)."),
+        "function" => Some("**function** *name* *nLocals*\n\nDeclare a function with `nLocals` local variables, all initialised to 0."),
+        "call" => Some("**call** *name* *nArgs*\n\nCall `name`, whose `nArgs` arguments have already been pushed."),
+        "return" => Some("**return**\n\nReturn the top of the stack to the caller."),
+        "assert" => Some("**assert** (extension)\n\nPop the top of the stack and, if it is false (0), halt with the assert's number, counting from 1, in `RAM[16383]`."),
+        "halt" => Some("**halt** (extension)\n\nStop the program, leaving the halt's number, counting from 1, in `RAM[16382]`."),
+        "print" => Some("**print** (extension)\n\nPop the top of the stack and draw its 16 bits, bit 0 leftmost, in the next word of the bottom 8 pixel rows of the screen."),
+        "constant" => Some("**constant**\n\nVirtual segment holding the constants 0..32767. Push only."),
+        "local" => Some("**local**\n\nThe current function's local variables, based at `LCL`."),
+        "argument" => Some("**argument**\n\nThe current function's arguments, based at `ARG`."),
+        "this" => Some("**this**\n\nSegment based at `THIS`, set through `pointer 0`."),
+        "that" => Some("**that**\n\nSegment based at `THAT`, set through `pointer 1`."),
+        "temp" => Some("**temp**\n\nEight shared registers, RAM[5..12]."),
+        "pointer" => Some("**pointer**\n\n`pointer 0` is `THIS`, `pointer 1` is `THAT`."),
+        "static" => Some("**static**\n\nVariables shared by all functions in the file."),
+        _ => None,
+    }
+}
+
+// the path a file: uri names, percent-decoded
+fn file_path(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    // past the authority, which is usually empty or localhost
+    let encoded = &rest[rest.find('/')?..];
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut raw = encoded.bytes();
+    while let Some(b) = raw.next() {
+        bytes.push(match b {
+            b'%' => {
+                let hex = [raw.next()?, raw.next()?];
+                u8::from_str_radix(str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            _ => b,
+        });
+    }
+    let path = String::from_utf8(bytes).ok()?;
+    // file:///c:/dir is c:/dir
+    match path.as_bytes() {
+        [b'/', _, b':', ..] if cfg!(windows) => Some(path[1..].into()),
+        _ => Some(path.into()),
+    }
+}
+
+// a file: uri for `path`, percent-encoded the way editors write them
+fn file_uri(path: &Path) -> String {
+    let mut path = path.to_string_lossy().into_owned();
+    if cfg!(windows) {
+        path = path.replace('\\', "/");
+    }
+    let mut uri = String::from("file://");
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => uri.push(b as char),
+            b'-' | b'.' | b'_' | b'~' | b'/' => uri.push(b as char),
+            _ => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+fn range(line: usize, start: usize, end: usize) -> Value {
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": end },
+    })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+pub(crate) fn read_message(input: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+
+    let length = length.ok_or_else(|| anyhow!("missing Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+pub(crate) fn write_message(
+    output: &mut impl Write,
+    msg: &Value,
+) -> Result<()> {
+    let body = msg.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()?;
+    Ok(())
+}
@@ -1,42 +1,1634 @@
-use std::env;
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    process::{self, Command},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use serde_json::json;
 
-mod codegen;
-mod parser;
-mod translator;
+use n2t_vm_translator::{
+    assembler,
+    backend::{self, Backend},
+    cfg,
+    codegen::{self, Asm, CodeGen, Registers},
+    dap,
+    debugger::{Breakpoint, Debugger},
+    diagnostics::{self, paint, Class, Color, Diagnostic, Report},
+    emulator::Cpu,
+    glob, grade, keyboard, lexer,
+    lint::{self, Baseline, Level},
+    lsp,
+    parser::{IrFormat, Line},
+    pgo::Profile,
+    project::{self, Project},
+    refactor, screen,
+    script::Dump,
+    sourcemap,
+    style::{Spacing, MAX_BLANK_LINES},
+    teach,
+    trace::{Replay, Trace},
+    translator::{
+        Addresses, Backup, Comments, Newline, Options, Order, Translator,
+    },
+};
 
-use translator::Translator;
+const USAGE: &str = "usage: n2t-vm-translator \
+    [--emit asm,hack,ir,map,mem,html|tokens|cfg-dot] \
+    [--stop-after parse] [--dump-ir] [--dump-after <pass|all>] \
+    [--ir-format text|json] \
+    [--emit-end-loop] [--preserve-comments] [--minify] [--explain] [--diff] \
+    [--backup[=simple|numbered]] [--target hack,c] \
+    [--static-addresses] [--shared-routines] [--outline] [--extensions] \
+    [--strict] \
+    [--init-loop N] [--split N] [--max-errors N] [--verify-ir] \
+    [--validate-labels] [--mmap] [--jobs N] \
+    [--profile <name>] [--budget [<function>=]N]... [--plugin <lib>]... \
+    [--exclude <pattern>]... [--order input|alpha|sys-first|manifest] \
+    [--deny|--warn|--allow <lint|warnings>]... [--baseline <file.json>] \
+    [--write-baseline <file.json>] \
+    [--[no-]bootstrap] [-O0|-O1|-O2|-Os] [--comments source|canonical|none] \
+    [--rom-addresses off|block|instruction] [--newline lf|crlf|native] \
+    [--registers symbolic|numeric] [--scratch <addr,addr,...>] \
+    [--dest-spacing tight|spaced] [--jump-spacing tight|spaced] \
+    [--[no-]indent-labels] [--blank-lines N] \
+    [--color auto|always|never] \
+    [--compare-with <cmd>] [--cycles N] [--dump <RAM[a..b],...>] \
+    [--screen <file.pbm|file.png>] [--keys <cycle=key,...|file>] \
+    [--break <label>] [--break-rom <addr>] [--watch-ram <addr>] [--tui] \
+    [--trace <file>] [--assembler-cmd <cmd>] \
+    [--jack-compiler <cmd>] [--report <file.md|file.csv|xref>] \
+    [--out-dir <dir>] \
+    [--timing <file.md|file.csv>] [--static-timing <file.md|file.csv>] \
+    [--coverage <file>] [--annotate <dir>] \
+    [--pgo <profile.json>] [--pgo-generate <profile.json>] \
+    <file[.vm]|dir|glob> \
+    | lsp | dap | explain <vm command> | explain-asm <file.asm> \
+    | replay <file> \
+    | grade <submissions> <tests> [--format csv|json] \
+    | teach <file[.vm]|dir> [--steps N] \
+    | refactor rename <Old.name> <New.name> [<file[.vm]|dir|glob>]";
+
+#[derive(Debug, PartialEq)]
+enum Emit {
+    Asm,
+    Hack,
+    Ir,
+    Map,
+    Mem,
+    Html,
+    Tokens,
+    CfgDot,
+}
+
+impl Emit {
+    fn new(raw: &str) -> Result<Emit> {
+        match raw {
+            "asm" => Ok(Emit::Asm),
+            "hack" => Ok(Emit::Hack),
+            "ir" => Ok(Emit::Ir),
+            "map" => Ok(Emit::Map),
+            "mem" => Ok(Emit::Mem),
+            "html" => Ok(Emit::Html),
+            "tokens" => Ok(Emit::Tokens),
+            "cfg-dot" => Ok(Emit::CfgDot),
+            _ => Err(anyhow!("unexpected emit kind: {}", raw)),
+        }
+    }
+
+    // artifacts can be combined freely, but tokens and cfg-dot print to
+    // stdout and stand alone
+    fn list(raw: &str) -> Result<Vec<Emit>> {
+        let mut emit = Vec::new();
+        for kind in raw.split(',') {
+            let kind = Emit::new(kind.trim())?;
+            if !emit.contains(&kind) {
+                emit.push(kind);
+            }
+        }
+        let standalone = emit
+            .iter()
+            .any(|kind| matches!(kind, Emit::Tokens | Emit::CfgDot));
+        if standalone && emit.len() > 1 {
+            return Err(anyhow!(
+                "tokens and cfg-dot cannot be combined with other emit kinds"
+            ));
+        }
+        Ok(emit)
+    }
+}
 
 #[derive(Debug)]
 struct Config {
-    srcname: String,
-    binname: String,
+    srcname: PathBuf,
+    binname: PathBuf,
+    // where binname would be without --out-dir, which is where a reference
+    // translator writes
+    sibling: PathBuf,
+    emit: Vec<Emit>,
+    // hack, whose artifacts --emit picks, and any others
+    targets: Vec<Box<dyn Backend>>,
+    stop_after_parse: bool,
+    dump_ir: bool,
+    ir_format: IrFormat,
+    options: Options,
+    split: Option<usize>,
+    compare_with: Option<String>,
+    steps: u64,
+    assembler_cmd: Option<String>,
+    jack_compiler: Option<String>,
+    report: Option<PathBuf>,
+    // write where functions are defined and called, and statics used
+    xref: bool,
+    // compare the asm with what's already there instead of writing it
+    diff: bool,
+    // keep the asm that's already there before writing over it
+    backup: Option<Backup>,
+    // run the program and report where its cycles went
+    timing: Option<PathBuf>,
+    // estimate that without a run
+    static_timing: Option<PathBuf>,
+    // run the program and save what it drew
+    screen: Option<PathBuf>,
+    // typed into the keyboard during that run
+    keys: Vec<(u64, i16)>,
+    // run the program and print this state after
+    dump: Option<Dump>,
+    // run the program, pausing at these
+    breakpoints: Vec<Breakpoint>,
+    // run the program a step at a time in the terminal UI
+    tui: bool,
+    // run the program and record each step
+    trace: Option<PathBuf>,
+    // run the program and report the commands it never ran
+    coverage: Option<PathBuf>,
+    // and write annotated copies of the sources here
+    annotate: Option<PathBuf>,
+    // run the program and write a profile for --pgo
+    pgo_generate: Option<PathBuf>,
+    write_baseline: Option<PathBuf>,
 }
 
 impl Config {
-    fn parse(args: Vec<String>) -> Result<Config> {
-        if args.len() < 2 {
-            return Err(anyhow!("not enough arguments"));
+    fn parse(args: Vec<OsString>) -> Result<Config> {
+        let mut srcname = None;
+        let mut emit = vec![Emit::Asm];
+        let mut targets = backend::targets("hack")?;
+        let mut stop_after_parse = false;
+        let mut dump_ir = false;
+        let mut dump_after = None;
+        let mut ir_format = IrFormat::Text;
+        // kept apart from the options until the project file is read, so
+        // that flags override it
+        let mut end_loop = false;
+        let mut static_addresses = false;
+        let mut shared_routines = false;
+        let mut outline = false;
+        let mut extensions = false;
+        let mut mmap = false;
+        let mut verify_ir = false;
+        let mut validate_labels = false;
+        let mut strict = false;
+        let mut max_errors = None;
+        let mut jobs = None;
+        let mut bootstrap = None;
+        let mut opt_level = None;
+        let mut optimize_size = None;
+        let mut init_loop = None;
+        let mut budget = None;
+        let mut budgets = Vec::new();
+        let mut plugins = Vec::new();
+        let mut exclude = Vec::new();
+        let mut order = None;
+        let mut lints = Vec::new();
+        let mut comments = None;
+        let mut profile = None;
+        let mut addresses = None;
+        let mut newline = None;
+        let mut dest_spacing = None;
+        let mut jump_spacing = None;
+        let mut indent_labels = None;
+        let mut blank_lines = None;
+        let mut registers = None;
+        let mut scratch = None;
+        let mut preserve_comments = false;
+        let mut minify = false;
+        let mut explain = false;
+        let mut diff = false;
+        let mut backup = None;
+        let mut split = None;
+        let mut compare_with = None;
+        let mut steps = 100_000;
+        let mut assembler_cmd = None;
+        let mut jack_compiler = None;
+        let mut report = None;
+        let mut xref = false;
+        let mut timing = None;
+        let mut static_timing = None;
+        let mut screen = None;
+        let mut keys = Vec::new();
+        let mut dump = None;
+        let mut breakpoints = Vec::new();
+        let mut tui = false;
+        let mut trace = None;
+        let mut coverage = None;
+        let mut annotate = None;
+        let mut pgo_generate = None;
+        let mut baseline = None;
+        let mut write_baseline = None;
+        let mut hot = None;
+        let mut out_dir = None;
+
+        let mut args = args.into_iter().skip(1);
+        while let Some(arg) = args.next() {
+            let (flag, inline) = split_flag(&arg);
+            match flag.as_str() {
+                "--emit" => {
+                    emit = Emit::list(&value(&flag, inline, &mut args)?)?
+                }
+                "--target" => {
+                    targets =
+                        backend::targets(&value(&flag, inline, &mut args)?)?
+                }
+                "--stop-after" => match value(&flag, inline, &mut args)? {
+                    stage if stage == "parse" => stop_after_parse = true,
+                    stage => {
+                        return Err(anyhow!("unexpected stage: {}", stage))
+                    }
+                },
+                "--dump-ir" => dump_ir = true,
+                "--dump-after" => {
+                    dump_after = Some(value(&flag, inline, &mut args)?)
+                }
+                "--ir-format" => {
+                    ir_format =
+                        IrFormat::new(&value(&flag, inline, &mut args)?)?
+                }
+                "--emit-end-loop" => end_loop = true,
+                "--static-addresses" => static_addresses = true,
+                "--shared-routines" => shared_routines = true,
+                "--outline" => outline = true,
+                "--extensions" => extensions = true,
+                "--strict" => strict = true,
+                "--split" => match value(&flag, inline, &mut args)?.parse() {
+                    Ok(parts) if parts > 0 => split = Some(parts),
+                    _ => return Err(anyhow!("--split expects a count > 0")),
+                },
+                "--init-loop" => {
+                    match value(&flag, inline, &mut args)?.parse() {
+                        Ok(n) if n > 0 => init_loop = Some(n),
+                        _ => {
+                            return Err(anyhow!(
+                                "--init-loop expects a count > 0"
+                            ))
+                        }
+                    }
+                }
+                // N for every function, or Name=N for one
+                "--budget" => {
+                    let raw = value(&flag, inline, &mut args)?;
+                    let (name, count) = match raw.split_once('=') {
+                        Some((name, count)) => (Some(name), count),
+                        None => (None, raw.as_str()),
+                    };
+                    let count = count.parse().map_err(|_| {
+                        anyhow!("--budget expects a count: {}", raw)
+                    })?;
+                    match name {
+                        Some(name) => budgets.push((name.to_string(), count)),
+                        None => budget = Some(count),
+                    }
+                }
+                "--mmap" => mmap = true,
+                // check the IR after every pass
+                "--verify-ir" => verify_ir = true,
+                "--validate-labels" => validate_labels = true,
+                "--exclude" => exclude.push(value(&flag, inline, &mut args)?),
+                "--deny" | "--warn" | "--allow" => {
+                    let name = value(&flag, inline, &mut args)?;
+                    lint::check(&name)?;
+                    lints.push((name, Level::new(&flag[2..])?));
+                }
+                "--order" => {
+                    order = Some(Order::new(&value(&flag, inline, &mut args)?)?)
+                }
+                "--plugin" if cfg!(feature = "plugins") => plugins
+                    .push(PathBuf::from(value_os(&flag, inline, &mut args)?)),
+                "--plugin" => {
+                    return Err(anyhow!(
+                        "--plugin needs a build with the plugins feature"
+                    ))
+                }
+                "--tui" if cfg!(feature = "tui") => tui = true,
+                "--tui" => {
+                    return Err(anyhow!(
+                        "--tui needs a build with the tui feature"
+                    ))
+                }
+                // already picked out by color_choice, only checked here
+                "--color" => {
+                    Color::new(&value(&flag, inline, &mut args)?)?;
+                }
+                "--max-errors" => {
+                    match value(&flag, inline, &mut args)?.parse() {
+                        Ok(n) => max_errors = Some(n),
+                        _ => {
+                            return Err(anyhow!("--max-errors expects a count"))
+                        }
+                    }
+                }
+                "--jobs" => match value(&flag, inline, &mut args)?.parse() {
+                    Ok(n) if n > 0 => jobs = Some(n),
+                    _ => return Err(anyhow!("--jobs expects a count > 0")),
+                },
+                "--profile" => profile = Some(value(&flag, inline, &mut args)?),
+                "--rom-addresses" => {
+                    addresses =
+                        Some(Addresses::new(&value(&flag, inline, &mut args)?)?)
+                }
+                "--preserve-comments" => preserve_comments = true,
+                "--minify" => minify = true,
+                "--explain" => explain = true,
+                "--diff" => diff = true,
+                // simple unless told otherwise, and only ever inline
+                "--backup" => {
+                    backup = Some(match inline {
+                        Some(style) => Backup::new(&style)?,
+                        None => Backup::Simple,
+                    })
+                }
+                "--registers" => {
+                    registers =
+                        Some(Registers::new(&value(&flag, inline, &mut args)?)?)
+                }
+                "--scratch" => {
+                    let list = value(&flag, inline, &mut args)?;
+                    let registers = list
+                        .split(',')
+                        .map(|r| {
+                            r.trim().parse().map_err(|_| {
+                                anyhow!("unexpected register: {}", r)
+                            })
+                        })
+                        .collect::<Result<Vec<u16>>>()?;
+                    codegen::check_scratch(&registers)?;
+                    scratch = Some(registers);
+                }
+                "--newline" => {
+                    newline =
+                        Some(Newline::new(&value(&flag, inline, &mut args)?)?)
+                }
+                "--dest-spacing" => {
+                    dest_spacing =
+                        Some(Spacing::new(&value(&flag, inline, &mut args)?)?)
+                }
+                "--jump-spacing" => {
+                    jump_spacing =
+                        Some(Spacing::new(&value(&flag, inline, &mut args)?)?)
+                }
+                "--indent-labels" => indent_labels = Some(true),
+                "--no-indent-labels" => indent_labels = Some(false),
+                "--blank-lines" => {
+                    match value(&flag, inline, &mut args)?.parse() {
+                        Ok(n) if n <= MAX_BLANK_LINES => blank_lines = Some(n),
+                        _ => {
+                            return Err(anyhow!(
+                                "--blank-lines expects a count up to {}",
+                                MAX_BLANK_LINES
+                            ))
+                        }
+                    }
+                }
+                "--bootstrap" => bootstrap = Some(true),
+                "--no-bootstrap" => bootstrap = Some(false),
+                "--comments" => {
+                    comments =
+                        Some(Comments::new(&value(&flag, inline, &mut args)?)?)
+                }
+                "-Os" => {
+                    opt_level = Some(2);
+                    optimize_size = Some(true);
+                }
+                _ if flag.starts_with("-O") => match flag[2..].parse() {
+                    Ok(level) => {
+                        opt_level = Some(project::opt_level(level)?);
+                        optimize_size = Some(false);
+                    }
+                    _ => return Err(anyhow!("unexpected flag: {}", flag)),
+                },
+                "--compare-with" => {
+                    compare_with = Some(value(&flag, inline, &mut args)?)
+                }
+                "--assembler-cmd" => {
+                    assembler_cmd = Some(value(&flag, inline, &mut args)?)
+                }
+                "--jack-compiler" => {
+                    jack_compiler = Some(value(&flag, inline, &mut args)?)
+                }
+                "--out-dir" => {
+                    out_dir =
+                        Some(PathBuf::from(value_os(&flag, inline, &mut args)?))
+                }
+                "--screen" => {
+                    screen =
+                        Some(PathBuf::from(value_os(&flag, inline, &mut args)?))
+                }
+                // a script inline, or the file holding one
+                "--keys" => {
+                    let raw = value(&flag, inline, &mut args)?;
+                    let script = match Path::new(&raw).is_file() {
+                        true => fs::read_to_string(&raw)?,
+                        false => raw,
+                    };
+                    keys = keyboard::parse(&script)?;
+                }
+                "--report" => {
+                    let value = value_os(&flag, inline, &mut args)?;
+                    match value.to_str() {
+                        Some("xref") => xref = true,
+                        _ => report = Some(PathBuf::from(value)),
+                    }
+                }
+                "--trace" => {
+                    trace =
+                        Some(PathBuf::from(value_os(&flag, inline, &mut args)?))
+                }
+                "--coverage" => {
+                    coverage =
+                        Some(PathBuf::from(value_os(&flag, inline, &mut args)?))
+                }
+                "--annotate" => {
+                    annotate =
+                        Some(PathBuf::from(value_os(&flag, inline, &mut args)?))
+                }
+                "--pgo" => {
+                    let name =
+                        PathBuf::from(value_os(&flag, inline, &mut args)?);
+                    hot = Some(Profile::load(name)?.hot());
+                }
+                "--baseline" => {
+                    let name =
+                        PathBuf::from(value_os(&flag, inline, &mut args)?);
+                    baseline = Some(Baseline::load(name)?);
+                }
+                "--write-baseline" => {
+                    write_baseline =
+                        Some(PathBuf::from(value_os(&flag, inline, &mut args)?))
+                }
+                "--pgo-generate" => {
+                    pgo_generate =
+                        Some(PathBuf::from(value_os(&flag, inline, &mut args)?))
+                }
+                "--timing" => {
+                    timing =
+                        Some(PathBuf::from(value_os(&flag, inline, &mut args)?))
+                }
+                "--static-timing" => {
+                    static_timing =
+                        Some(PathBuf::from(value_os(&flag, inline, &mut args)?))
+                }
+                // --steps is the older name
+                "--cycles" | "--steps" => {
+                    match value(&flag, inline, &mut args)?.parse() {
+                        Ok(n) => steps = n,
+                        _ => return Err(anyhow!("{} expects a count", flag)),
+                    }
+                }
+                "--break" => breakpoints
+                    .push(Breakpoint::Label(value(&flag, inline, &mut args)?)),
+                "--break-rom" | "--watch-ram" => {
+                    let raw = value(&flag, inline, &mut args)?;
+                    let address = match raw.parse() {
+                        Ok(address) if address < 0x8000 => address,
+                        _ => {
+                            return Err(anyhow!(
+                                "{} expects an address: {}",
+                                flag,
+                                raw
+                            ))
+                        }
+                    };
+                    breakpoints.push(match flag.as_str() {
+                        "--break-rom" => Breakpoint::Rom(address),
+                        _ => Breakpoint::Watch(address as usize),
+                    });
+                }
+                "--dump" => {
+                    dump = Some(Dump::parse(&value(&flag, inline, &mut args)?)?)
+                }
+                _ if flag.starts_with("--") => {
+                    return Err(anyhow!(
+                        "unexpected flag: {} ({})",
+                        flag,
+                        USAGE
+                    ));
+                }
+                _ => srcname = Some(PathBuf::from(arg)),
+            }
+        }
+
+        let srcname = resolve(
+            srcname
+                .ok_or_else(|| anyhow!("not enough arguments ({})", USAGE))?,
+        );
+        // a pattern's output goes where its directory's would
+        let base = glob::base(&srcname);
+        let path = match glob::is_glob(&srcname) {
+            true if !base.is_dir() => {
+                return Err(anyhow!("no directory {}", base.display()))
+            }
+            true => base.as_path(),
+            false => srcname.as_path(),
+        };
+        let sibling = if path.is_dir() {
+            // Foo/ translates to Foo/Foo.asm; . and .. are named after the
+            // directory they stand for
+            let mut name = match path.file_name() {
+                Some(name) => name.to_os_string(),
+                None => fs::canonicalize(path)?
+                    .file_name()
+                    .ok_or_else(|| {
+                        anyhow!("invalid directory: {}", path.display())
+                    })?
+                    .to_os_string(),
+            };
+            name.push(".asm");
+            path.join(name)
+        } else if path.extension().is_some_and(|ext| ext == "vm") {
+            path.with_extension("asm")
+        } else {
+            return Err(anyhow!(
+                "file must be vm file or directory. (provided: {}, no {}.vm)",
+                path.display(),
+                path.display(),
+            ));
+        };
+
+        // sibling always ends in a file name, derived above
+        let binname = match &out_dir {
+            Some(dir) => dir.join(sibling.file_name().unwrap()),
+            None => sibling.clone(),
+        };
+
+        if !keys.is_empty()
+            && screen.is_none()
+            && dump.is_none()
+            && timing.is_none()
+            && breakpoints.is_empty()
+            && !tui
+            && trace.is_none()
+            && coverage.is_none()
+            && annotate.is_none()
+            && pgo_generate.is_none()
+        {
+            return Err(anyhow!("--keys needs an emulator run"));
+        }
+        if jack_compiler.is_some() && !srcname.is_dir() {
+            return Err(anyhow!("--jack-compiler expects a directory"));
+        }
+        if assembler_cmd.is_some()
+            && (split.is_some() || !emit.contains(&Emit::Asm))
+        {
+            return Err(anyhow!(
+                "--assembler-cmd needs a single .asm file to be emitted"
+            ));
+        }
+
+        let mut options = Options::default();
+        let project = Project::discover(path)?
+            .map(|(_, project)| project)
+            .unwrap_or_default();
+        project.apply(&mut options, profile.as_deref())?;
+        options.end_loop |= end_loop;
+        options.preserve_comments |= preserve_comments;
+        options.minify |= minify;
+        options.static_addresses |= static_addresses;
+        options.shared_routines |= shared_routines;
+        options.outline |= outline;
+        options.extensions |= extensions;
+        if !targets.iter().any(|target| target.name() == "hack")
+            && (emit != [Emit::Asm] || split.is_some() || diff)
+        {
+            return Err(anyhow!(
+                "--emit, --split and --diff need --target hack"
+            ));
+        }
+        if diff && (emit != [Emit::Asm] || split.is_some()) {
+            return Err(anyhow!("--diff compares a single .asm file alone"));
+        }
+        if emit.contains(&Emit::Mem) && !options.static_addresses {
+            return Err(anyhow!("--emit mem needs --static-addresses"));
+        }
+        options.mmap = mmap;
+        options.verify_ir = verify_ir;
+        options.validate_labels = validate_labels;
+        if strict && options.extensions {
+            return Err(anyhow!("--strict rejects the extensions"));
+        }
+        options.strict = strict;
+        options.dump_after = dump_after.map(|pass| (pass, ir_format));
+        if let Some(n) = max_errors {
+            options.max_errors = n;
+        }
+        if jobs.is_some() {
+            options.jobs = jobs;
+        }
+        if bootstrap.is_some() {
+            options.bootstrap = bootstrap;
+        }
+        if let Some(level) = opt_level {
+            options.opt_level = level;
+        }
+        if let Some(size) = optimize_size {
+            options.optimize_size = size;
+        }
+        if init_loop.is_some() {
+            options.init_loop = init_loop;
+        }
+        if budget.is_some() {
+            options.budget = budget;
+        }
+        options.budgets.extend(budgets);
+        // on top of the project file's
+        options.exclude.extend(exclude);
+        if let Some(order) = order {
+            options.order = order;
+        }
+        // the last level given for a lint wins, over the project file's too
+        options.lints.extend(lints);
+        if let Some(baseline) = baseline {
+            options.baseline = baseline;
+        }
+        // a new baseline takes in everything the lints find
+        if write_baseline.is_some() {
+            options.lints.clear();
+            options.baseline = Baseline::default();
+        }
+        options.plugins = plugins;
+        if hot.is_some() {
+            options.hot = hot;
+        }
+        if let Some(comments) = comments {
+            options.comments = comments;
+        }
+        if let Some(addresses) = addresses {
+            options.addresses = addresses;
+        }
+        // the explanations are comments, and describe unoptimized code
+        if explain && options.comments == Comments::None {
+            return Err(anyhow!(
+                "--explain cannot be used with --comments none"
+            ));
+        }
+        if explain && options.minify {
+            return Err(anyhow!("--explain cannot be used with --minify"));
+        }
+        if explain && options.opt_level > 0 {
+            return Err(anyhow!(
+                "--explain cannot be used with -O1, -O2 or -Os"
+            ));
+        }
+        options.explain = explain;
+        if let Some(newline) = newline {
+            options.newline = newline;
+        }
+        if let Some(spacing) = dest_spacing {
+            options.style.dest = spacing;
+        }
+        if let Some(spacing) = jump_spacing {
+            options.style.jump = spacing;
+        }
+        if let Some(indent) = indent_labels {
+            options.style.indent = indent;
+        }
+        if blank_lines.is_some() {
+            options.style.blank_lines = blank_lines;
+        }
+        if let Some(registers) = registers {
+            options.registers = registers;
+        }
+        if let Some(scratch) = scratch {
+            options.scratch = scratch;
+        }
+
+        Ok(Config {
+            srcname,
+            binname,
+            sibling,
+            emit,
+            targets,
+            stop_after_parse,
+            dump_ir,
+            ir_format,
+            options,
+            split,
+            compare_with,
+            steps,
+            assembler_cmd,
+            jack_compiler,
+            report,
+            xref,
+            diff,
+            backup,
+            timing,
+            static_timing,
+            screen,
+            keys,
+            dump,
+            breakpoints,
+            tui,
+            trace,
+            coverage,
+            annotate,
+            pgo_generate,
+            write_baseline,
+        })
+    }
+}
+
+// flags take their value either inline (--flag=value) or as the following
+// argument; an argument that isn't UTF-8 can only be a path
+fn split_flag(arg: &OsStr) -> (String, Option<String>) {
+    let arg = arg.to_string_lossy();
+    match arg.split_once('=') {
+        Some((flag, value)) if arg.starts_with("--") => {
+            (flag.to_string(), Some(value.to_string()))
         }
-        let srcname = args[1].clone();
-        if !srcname.ends_with(".vm") {
-            return Err(anyhow!("file must be vm file. (provided: {})", srcname,));
+        _ => (arg.into_owned(), None),
+    }
+}
+
+// a flag's value as given, for paths
+fn value_os(
+    flag: &str,
+    inline: Option<String>,
+    args: &mut impl Iterator<Item = OsString>,
+) -> Result<OsString> {
+    inline
+        .map(OsString::from)
+        .or_else(|| args.next())
+        .ok_or_else(|| anyhow!("{} expects a value", flag))
+}
+
+fn value(
+    flag: &str,
+    inline: Option<String>,
+    args: &mut impl Iterator<Item = OsString>,
+) -> Result<String> {
+    value_os(flag, inline, args)?.into_string().map_err(|raw| {
+        anyhow!("{} expects UTF-8: {}", flag, raw.to_string_lossy())
+    })
+}
+
+// Foo stands for Foo.vm when there's one, and otherwise for the directory
+fn resolve(srcname: PathBuf) -> PathBuf {
+    if srcname.extension().is_some_and(|ext| ext == "vm") {
+        return srcname;
+    }
+    let mut file = srcname.clone().into_os_string();
+    file.push(".vm");
+    match Path::new(&file).is_file() {
+        true => PathBuf::from(file),
+        false => srcname,
+    }
+}
+
+fn emit_tokens(srcname: &Path) -> Result<()> {
+    if srcname.is_dir() || glob::is_glob(srcname) {
+        return Err(anyhow!("tokens are emitted for a single vm file"));
+    }
+    let src = fs::read_to_string(srcname)?;
+    let tokens: Vec<_> = lexer::tokenize(&src)
+        .into_iter()
+        .map(|t| {
+            json!({
+                "kind": t.kind.name(),
+                "text": t.text,
+                "start": t.span.start,
+                "end": t.span.end,
+            })
+        })
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&tokens)?);
+    Ok(())
+}
+
+// the asm a single VM command becomes, as if it were alone in Main.vm,
+// and what that asm does
+fn explain(command: Option<&OsString>) -> Result<()> {
+    let raw = command
+        .and_then(|command| command.to_str())
+        .ok_or_else(|| usage(anyhow!("not enough arguments ({})", USAGE)))?;
+    let line = Line::new(raw).map_err(|e| {
+        Diagnostic::error(Class::Parse, format!("{}: {}", raw.trim(), e))
+    })?;
+
+    let mut cg = CodeGen::new("Main".to_string());
+    let explain = cg.explain(&line);
+    let asm = Asm {
+        src: format!("// {}", line),
+        bin: cg.gen_block(&line)?,
+        span: None,
+        function: None,
+        command: Some(line.name()),
+        explain: Some(explain),
+        before: Vec::new(),
+        after: Vec::new(),
+    };
+    println!("{}", asm.src);
+    for line in asm.explain_lines() {
+        println!("{}", line);
+    }
+    for line in &asm.bin {
+        println!("{}", line);
+    }
+    println!("// {} instructions", asm.instructions());
+    Ok(())
+}
+
+fn explain_asm(binname: Option<&OsString>) -> Result<()> {
+    let binname = binname
+        .ok_or_else(|| usage(anyhow!("not enough arguments ({})", USAGE)))?;
+    let asm = fs::read_to_string(binname)?;
+
+    println!("{:<12}{:<12}vm command", "rom", "asm lines");
+    for origin in sourcemap::from_asm(&asm) {
+        let rom = match origin.rom.len() {
+            0 => "-".to_string(),
+            _ => format!("{}-{}", origin.rom.start, origin.rom.end - 1),
+        };
+        let lines = format!("{}-{}", origin.lines.start, origin.lines.end - 1);
+        let command = match origin.command.as_str() {
+            "" => "(no vm command)",
+            command => command,
+        };
+        println!("{:<12}{:<12}{}", rom, lines, command);
+    }
+    Ok(())
+}
+
+// move through a recorded run as told on stdin, a command a line, to find
+// when memory went wrong
+fn replay(tracename: Option<&OsString>) -> Result<()> {
+    let tracename = tracename
+        .ok_or_else(|| usage(anyhow!("not enough arguments ({})", USAGE)))?;
+    let trace = Trace::load(tracename)?;
+    let mut replay = Replay::new(&trace);
+    let interactive = io::stdin().is_terminal();
+    println!("{} steps", trace.entries.len());
+    loop {
+        if interactive {
+            eprint!("[f]orward N, [b]ack N, [g]oto N, [r]am A, [w]rites A, ");
+            eprint!("[q]uit> ");
+        }
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            return Ok(());
         }
+        let mut words = answer.split_whitespace();
+        let command = words.next().unwrap_or("f");
+        let n = match words.next().map(str::parse::<usize>) {
+            Some(Ok(n)) => Some(n),
+            None => None,
+            Some(Err(_)) => {
+                eprintln!("expected a number: {}", answer.trim());
+                continue;
+            }
+        };
+        match (command, n) {
+            ("f", n) => replay.forward(n.unwrap_or(1)),
+            ("b", n) => replay.back(n.unwrap_or(1)),
+            ("g", Some(n)) => replay.seek(n),
+            ("r", Some(address)) => {
+                match replay.ram().get(address) {
+                    Some(value) => println!("RAM[{}] = {}", address, value),
+                    None => eprintln!("no such address: {}", address),
+                }
+                continue;
+            }
+            // step n is the one that leaves the replay at n
+            ("w", Some(address)) => {
+                for (step, entry) in trace.changes(address as u16) {
+                    if let Some((_, old, new)) = entry.write {
+                        println!(
+                            "step {}: PC={} {} -> {}",
+                            step + 1,
+                            entry.pc,
+                            old,
+                            new
+                        );
+                    }
+                }
+                continue;
+            }
+            ("q", _) => return Ok(()),
+            _ => {
+                eprintln!("unexpected command: {}", answer.trim());
+                continue;
+            }
+        }
+        match replay.entry() {
+            Some(entry) => println!(
+                "step {}: PC={} SP={} top={}",
+                replay.at(),
+                entry.pc,
+                entry.sp,
+                entry.top
+            ),
+            None => println!("step 0"),
+        }
+    }
+}
+
+// run the reference translator on the same input and both programs side
+// by side in the emulator, reporting memory that ends up different
+fn compare_with(
+    cmd: &str,
+    srcname: &Path,
+    binname: &Path,
+    steps: u64,
+    translator: &Translator,
+) -> Result<()> {
+    let mut words = cmd.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| anyhow!("--compare-with expects a command"))?;
 
-        let binname = srcname.replace(".vm", ".asm");
-        Ok(Config { srcname, binname })
+    // reference translators write Foo.asm next to the input like we do,
+    // some print it instead; a file left over from before doesn't count
+    let modified = |name: &Path| fs::metadata(name).and_then(|m| m.modified());
+    let before = modified(binname).ok();
+    let output = Command::new(program).args(words).arg(srcname).output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed: {}",
+            cmd,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
     }
+    let reference = match modified(binname).ok() {
+        Some(after) if Some(after) != before => fs::read_to_string(binname)?,
+        _ => String::from_utf8_lossy(&output.stdout).into_owned(),
+    };
+
+    let mut ours = Cpu::new(assembler::assemble(&translator.render())?.words);
+    let mut theirs = Cpu::new(
+        assembler::assemble(&reference)
+            .map_err(|e| anyhow!("reference output: {}", e))?
+            .words,
+    );
+    for cpu in [&mut ours, &mut theirs] {
+        cpu.preset_segments();
+        cpu.run(steps);
+    }
+
+    let diverged = ours.divergence(&theirs);
+    if diverged.is_empty() {
+        println!("matches {} after {} steps", cmd, steps);
+        return Ok(());
+    }
+    println!("{:<10}{:>8}{:>8}", "address", "ours", "theirs");
+    for address in &diverged {
+        println!(
+            "{:<10}{:>8}{:>8}",
+            format!("RAM[{}]", address),
+            ours.ram[*address],
+            theirs.ram[*address]
+        );
+    }
+    Err(anyhow!(
+        "{} addresses diverge from {} after {} steps",
+        diverged.len(),
+        cmd,
+        steps
+    ))
 }
 
-fn main() -> Result<()> {
-    let config = Config::parse(env::args().collect())?;
-    let mut translator = Translator::new(&config.srcname)?;
+// removed along with everything in it once translation is done
+struct TempDir(PathBuf);
+
+impl TempDir {
+    // made here and now, so never one someone else had already put there
+    fn new() -> Result<TempDir> {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.subsec_nanos());
+        for attempt in 0..100u32 {
+            let dir = env::temp_dir().join(format!(
+                "{}-{}-{:08x}",
+                env!("CARGO_PKG_NAME"),
+                process::id(),
+                seed.wrapping_add(attempt)
+            ));
+            match fs::create_dir(&dir) {
+                Ok(()) => return Ok(TempDir(dir)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("cannot create {}", dir.display())
+                    })
+                }
+            }
+        }
+        Err(anyhow!(
+            "cannot create a directory in {}",
+            env::temp_dir().display()
+        ))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
 
-    println!("translating {}", &config.srcname);
+// compile a directory of .jack files with an external compiler, leaving
+// the .vm files in a temporary directory rather than the source tree
+fn compile_jack(cmd: &str, srcname: &Path) -> Result<TempDir> {
+    let mut words = cmd.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| anyhow!("--jack-compiler expects a command"))?;
+
+    let dir = TempDir::new()?;
+    let mut jack = 0;
+    for entry in fs::read_dir(srcname)? {
+        let path = entry?.path();
+        if let (Some(name), true) = (
+            path.file_name(),
+            path.extension().is_some_and(|ext| ext == "jack"),
+        ) {
+            fs::copy(&path, dir.0.join(name))?;
+            jack += 1;
+        }
+    }
+    if jack == 0 {
+        return Err(anyhow!("no jack files in {}", srcname.display()));
+    }
+
+    // compilers write Foo.vm next to each Foo.jack
+    let output = Command::new(program).args(words).arg(&dir.0).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        return Err(match (stderr.trim(), stdout.trim()) {
+            ("", "") => anyhow!("{} failed", cmd),
+            ("", message) | (message, _) => {
+                anyhow!("{} failed: {}", cmd, message)
+            }
+        });
+    }
+    Ok(dir)
+}
+
+// the asm line an assembler's message refers to: "line 12", "Line: 12" or
+// "Foo.asm:12"
+fn reported_line(message: &str) -> Option<usize> {
+    let lower = message.to_lowercase();
+    let after = lower
+        .find("line")
+        .map(|i| &lower[i + 4..])
+        .or_else(|| lower.find(".asm:").map(|i| &lower[i + 5..]))?;
+    let digits: String = after
+        .trim_start_matches([' ', ':', '#'])
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().ok()
+}
+
+// run an external assembler on the written .asm, pointing whatever lines it
+// complains about back at the VM commands they came from
+fn run_assembler(
+    cmd: &str,
+    binname: &Path,
+    translator: &Translator,
+) -> Result<()> {
+    let mut words = cmd.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| anyhow!("--assembler-cmd expects a command"))?;
+    let output = Command::new(program).args(words).arg(binname).output()?;
+
+    let origins = translator.origins();
+    let messages = String::from_utf8_lossy(&output.stdout).into_owned()
+        + &String::from_utf8_lossy(&output.stderr);
+    for message in messages.lines() {
+        println!("{}", message);
+        let found = reported_line(message).and_then(|line| {
+            origins
+                .iter()
+                .position(|origin| origin.lines.contains(&line))
+        });
+        if let Some(i) = found {
+            let asm = &translator.asm()[i];
+            let location = match &asm.span {
+                Some(span) => format!("{}:{}: ", span.file, span.line),
+                None => String::new(),
+            };
+            println!("  --> {}{}", location, origins[i].command);
+        }
+    }
+
+    if !output.status.success() {
+        return Err(anyhow!("{} failed on {}", cmd, binname.display()));
+    }
+    Ok(())
+}
+
+fn grade_submissions(args: &[OsString]) -> Result<()> {
+    let mut dirs = Vec::new();
+    let mut json = false;
+    let mut args = args.iter().skip(2).cloned();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = split_flag(&arg);
+        match flag.as_str() {
+            "--format" => match value(&flag, inline, &mut args)
+                .map_err(usage)?
+                .as_str()
+            {
+                "csv" => json = false,
+                "json" => json = true,
+                format => {
+                    return Err(usage(anyhow!("unexpected format: {}", format)))
+                }
+            },
+            _ if flag.starts_with("--") => {
+                return Err(usage(anyhow!(
+                    "unexpected flag: {} ({})",
+                    flag,
+                    USAGE
+                )))
+            }
+            _ => dirs.push(PathBuf::from(arg)),
+        }
+    }
+    let (submissions, tests) = match dirs.as_slice() {
+        [submissions, tests] => (submissions, tests),
+        _ => return Err(usage(anyhow!("not enough arguments ({})", USAGE))),
+    };
+
+    let outcomes = grade::grade(submissions, tests)?;
+    if json {
+        print!("{}", grade::to_json(&outcomes)?);
+    } else {
+        print!("{}", grade::to_csv(&outcomes));
+    }
+
+    // the table goes to stdout, the tally to stderr
+    let count = |status| outcomes.iter().filter(|o| o.status == status).count();
+    eprintln!(
+        "{} passed, {} failed, {} errors",
+        count(grade::Status::Pass),
+        count(grade::Status::Fail),
+        count(grade::Status::Error)
+    );
+    Ok(())
+}
+
+// draw the stack and segments after each command of a program, run
+// directly rather than translated
+fn teach(args: &[OsString]) -> Result<()> {
+    let mut srcname = None;
+    let mut steps = 1000;
+    let mut args = args.iter().skip(2).cloned();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = split_flag(&arg);
+        match flag.as_str() {
+            "--steps" => {
+                let raw = value(&flag, inline, &mut args).map_err(usage)?;
+                steps = raw.parse().map_err(|_| {
+                    usage(anyhow!("invalid step count: {}", raw))
+                })?;
+            }
+            _ if flag.starts_with("--") => {
+                return Err(usage(anyhow!(
+                    "unexpected flag: {} ({})",
+                    flag,
+                    USAGE
+                )))
+            }
+            _ => srcname = Some(PathBuf::from(arg)),
+        }
+    }
+    let srcname =
+        resolve(srcname.ok_or_else(|| {
+            usage(anyhow!("not enough arguments ({})", USAGE))
+        })?);
+
+    // the interpreter runs extensions too
+    let options = Options {
+        extensions: true,
+        ..Options::default()
+    };
+    let mut translator = Translator::open(&srcname, options)?;
+    translator.parse()?;
+    teach::run(translator.parsers(), steps, &mut io::stdout().lock())
+}
+
+// rewrite the program's sources in place; the current directory unless
+// told otherwise
+fn refactor(args: &[OsString], color: Color) -> Result<()> {
+    let args: Vec<&str> = args
+        .iter()
+        .skip(2)
+        .map(|arg| arg.to_str().ok_or_else(|| anyhow!("invalid argument")))
+        .collect::<Result<_>>()?;
+    let (old, new, srcname) = match args.as_slice() {
+        ["rename", old, new] => (*old, *new, "."),
+        ["rename", old, new, srcname] => (*old, *new, *srcname),
+        [] | ["rename", ..] => {
+            return Err(usage(anyhow!("not enough arguments ({})", USAGE)))
+        }
+        [refactoring, ..] => {
+            return Err(usage(anyhow!("unknown refactoring: {}", refactoring)))
+        }
+    };
+
+    // every command has to parse to be found, extensions included
+    let options = Options {
+        extensions: true,
+        ..Options::default()
+    };
+    let mut translator =
+        Translator::open(resolve(PathBuf::from(srcname)), options)?;
+    translator.parse()?;
+    let color = color.enabled(io::stdout().is_terminal());
+    for (name, text) in refactor::rename(&translator, old, new)? {
+        fs::write(&name, text)
+            .with_context(|| format!("cannot write {}", name.display()))?;
+        println!("{} {}", paint("written to", "1;32", color), name.display());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "tui")]
+fn run_tui(
+    cpu: &mut Cpu,
+    debugger: &mut Debugger,
+    listing: &[String],
+    steps: u64,
+) -> Result<()> {
+    n2t_vm_translator::tui::run(cpu, debugger, listing, steps)
+}
+
+// --tui is refused before it gets here
+#[cfg(not(feature = "tui"))]
+fn run_tui(_: &mut Cpu, _: &mut Debugger, _: &[String], _: u64) -> Result<()> {
+    unreachable!()
+}
+
+// stop at each breakpoint, and ask what next when there's someone to ask
+fn debug(debugger: &mut Debugger, cpu: &mut Cpu, steps: u64) -> Result<()> {
+    let interactive = io::stdin().is_terminal();
+    while let Some(stop) = debugger.run(cpu, steps) {
+        eprintln!("{}", stop);
+        eprint!("{}", debugger.state(cpu));
+        if !interactive {
+            continue;
+        }
+        loop {
+            eprint!("[c]ontinue, [s]tep, [q]uit> ");
+            let mut answer = String::new();
+            if io::stdin().read_line(&mut answer)? == 0 {
+                return Ok(());
+            }
+            match answer.trim() {
+                "" | "c" => break,
+                "s" => {
+                    debugger.stepping = true;
+                    break;
+                }
+                "q" => return Ok(()),
+                _ => continue,
+            }
+        }
+    }
+    Ok(())
+}
+
+// errors in the command line itself, as opposed to the input
+fn usage(err: anyhow::Error) -> anyhow::Error {
+    Diagnostic::error(Class::Usage, err).into()
+}
+
+// --color is needed to print errors in the command line itself, so it's
+// picked out ahead of the rest; Config::parse rejects bad values
+fn color_choice(args: &[OsString]) -> Color {
+    let mut choice = Color::Auto;
+    let mut args = args.iter().map(|arg| arg.to_str());
+    while let Some(arg) = args.next() {
+        let raw = match arg.and_then(|arg| arg.strip_prefix("--color")) {
+            Some(inline) if inline.starts_with('=') => Some(&inline[1..]),
+            Some("") => args.next().flatten(),
+            _ => continue,
+        };
+        if let Some(Ok(color)) = raw.map(Color::new) {
+            choice = color;
+        }
+    }
+    choice
+}
+
+fn main() {
+    let args: Vec<OsString> = env::args_os().collect();
+    let color = color_choice(&args);
+    if let Err(err) = run(args, color) {
+        let color = color.enabled(io::stderr().is_terminal());
+        if let Some(report) = err.downcast_ref::<Report>() {
+            eprintln!("{}", report.render(color));
+        } else if let Some(diagnostic) = err.downcast_ref::<Diagnostic>() {
+            eprintln!("{}", diagnostic.render(color));
+        } else {
+            eprintln!("{}: {:#}", paint("error", "1;31", color), err);
+        }
+        process::exit(diagnostics::exit_code(&err));
+    }
+}
+
+fn run(args: Vec<OsString>, color: Color) -> Result<()> {
+    match args.get(1).and_then(|arg| arg.to_str()) {
+        Some("lsp") => return lsp::Server::new().run(),
+        Some("dap") => return dap::Server::new().run(),
+        Some("explain") => return explain(args.get(2)),
+        Some("explain-asm") => return explain_asm(args.get(2)),
+        Some("replay") => return replay(args.get(2)),
+        Some("grade") => return grade_submissions(&args),
+        Some("teach") => return teach(&args),
+        Some("refactor") => return refactor(&args, color),
+        _ => {}
+    }
+
+    let config = Config::parse(args).map_err(usage)?;
+    if config.emit == [Emit::Tokens] {
+        return emit_tokens(&config.srcname);
+    }
+
+    let mut options = config.options;
+    options.retain_ir = config.dump_ir
+        || config.emit.contains(&Emit::Ir)
+        || config.xref
+        || config.targets.iter().any(|target| target.name() != "hack");
+    // held until the end of the run, when the compiled .vm files go
+    let compiled = match &config.jack_compiler {
+        Some(cmd) => Some(compile_jack(cmd, &config.srcname)?),
+        None => None,
+    };
+    let mut translator = match &compiled {
+        Some(dir) => Translator::open(&dir.0, options)?,
+        None => Translator::open(&config.srcname, options)?,
+    };
+
+    // the IR goes to stdout, so keep it free of progress messages
+    if config.emit == [Emit::CfgDot] {
+        translator.parse()?;
+        print!("{}", cfg::to_dot(translator.lines()));
+        return Ok(());
+    }
+    if config.stop_after_parse {
+        translator.parse()?;
+        print!("{}", translator.dump_ir(&config.ir_format)?);
+        return Ok(());
+    }
+
+    let warning_color = color.enabled(io::stderr().is_terminal());
+    let color = color.enabled(io::stdout().is_terminal());
+    println!(
+        "{} {}",
+        paint("translating", "1;32", color),
+        config.srcname.display()
+    );
     translator.process()?;
-    translator.write_bin(&config.binname)?;
-    println!("written to {}", &config.binname);
+    for warning in translator.warnings() {
+        eprintln!("{}", warning.render(warning_color));
+    }
+    for snapshot in translator.snapshots() {
+        print!("{}", snapshot);
+    }
+    if config.dump_ir {
+        print!("{}", translator.dump_ir(&config.ir_format)?);
+    }
+    // before writing anything, since the reference may write to the same
+    // place
+    if let Some(cmd) = &config.compare_with {
+        compare_with(
+            cmd,
+            &config.srcname,
+            &config.sibling,
+            config.steps,
+            &translator,
+        )?;
+    }
+
+    // every artifact shares the one parse and codegen run above
+    let output = config.binname.as_path();
+    let written = |name: &Path| {
+        println!("{} {}", paint("written to", "1;32", color), name.display())
+    };
+    let hack = config.targets.iter().any(|target| target.name() == "hack");
+    for kind in config.emit.iter().filter(|_| hack) {
+        let name = match (kind, config.split) {
+            (Emit::Asm, Some(parts)) => {
+                for name in translator.write_split(output, parts)? {
+                    written(&name);
+                }
+                continue;
+            }
+            (Emit::Asm, None) if config.diff => {
+                let diff = translator.diff(output)?;
+                if !diff.is_empty() {
+                    print!("{}", diff);
+                    return Err(anyhow!(
+                        "{} differs from the generated asm",
+                        output.display()
+                    ));
+                }
+                println!(
+                    "{} {}",
+                    paint("no differences", "1;32", color),
+                    output.display()
+                );
+                continue;
+            }
+            // left alone, mtime and all, when nothing it depends on changed
+            (Emit::Asm, None) if translator.up_to_date(output) => {
+                println!(
+                    "{} {}",
+                    paint("up to date", "1;32", color),
+                    output.display()
+                );
+                continue;
+            }
+            (Emit::Asm, None) => {
+                if let Some(backup) = &config.backup {
+                    if let Some(name) = backup.keep(output)? {
+                        println!(
+                            "{} {}",
+                            paint("backed up to", "1;32", color),
+                            name.display()
+                        );
+                    }
+                }
+                translator.write_bin(output)?;
+                output.to_path_buf()
+            }
+            (Emit::Hack, _) => {
+                let name = output.with_extension("hack");
+                translator.write_hack(&name)?;
+                name
+            }
+            (Emit::Ir, _) => {
+                let ext = match config.ir_format {
+                    IrFormat::Text => "ir",
+                    IrFormat::Json => "ir.json",
+                };
+                let name = output.with_extension(ext);
+                translator.write_ir(&name, &config.ir_format)?;
+                name
+            }
+            (Emit::Map, _) => {
+                let name = output.with_extension("map");
+                translator.write_map(&name)?;
+                name
+            }
+            (Emit::Mem, _) => {
+                let name = output.with_extension("mem");
+                translator.write_mem(&name)?;
+                name
+            }
+            (Emit::Html, _) => {
+                let name = output.with_extension("html");
+                let title = config.srcname.display().to_string();
+                translator.write_html(&name, &title)?;
+                name
+            }
+            (Emit::Tokens, _) | (Emit::CfgDot, _) => unreachable!(),
+        };
+        written(&name);
+    }
+    for target in &config.targets {
+        if target.name() == "hack" {
+            continue;
+        }
+        let name = output.with_extension(target.extension());
+        if let Some(backup) = &config.backup {
+            if let Some(kept) = backup.keep(&name)? {
+                println!(
+                    "{} {}",
+                    paint("backed up to", "1;32", color),
+                    kept.display()
+                );
+            }
+        }
+        fs::write(&name, target.generate(&translator)?)
+            .with_context(|| format!("cannot write {}", name.display()))?;
+        written(&name);
+    }
+
+    if let Some(name) = &config.report {
+        translator.write_report(name)?;
+        written(name);
+    }
+    if config.xref {
+        let name = output.with_extension("xref.md");
+        translator.write_xref(&name)?;
+        written(&name);
+    }
+    if let Some(name) = &config.static_timing {
+        translator.write_timing(name, None)?;
+        written(name);
+    }
+    if let Some(name) = &config.write_baseline {
+        fs::write(name, translator.baseline().to_json())
+            .with_context(|| format!("cannot write {}", name.display()))?;
+        written(name);
+    }
+
+    // one run serves them all
+    if config.screen.is_some()
+        || config.dump.is_some()
+        || config.timing.is_some()
+        || !config.breakpoints.is_empty()
+        || config.tui
+        || config.trace.is_some()
+        || config.coverage.is_some()
+        || config.annotate.is_some()
+        || config.pgo_generate.is_some()
+    {
+        let assembled = assembler::assemble(&translator.render())?;
+        let mut cpu = Cpu::new(assembled.words);
+        cpu.preset_segments();
+        cpu.schedule_keys(config.keys);
+        if config.trace.is_some() {
+            cpu.record();
+        }
+        match config.breakpoints.as_slice() {
+            breakpoints if config.tui => {
+                let mut debugger = Debugger::new(
+                    breakpoints,
+                    &assembled.symbols,
+                    translator.origins(),
+                )?;
+                run_tui(
+                    &mut cpu,
+                    &mut debugger,
+                    &assembled.source,
+                    config.steps,
+                )?;
+            }
+            [] => cpu.run(config.steps),
+            breakpoints => {
+                let mut debugger = Debugger::new(
+                    breakpoints,
+                    &assembled.symbols,
+                    translator.origins(),
+                )?;
+                debug(&mut debugger, &mut cpu, config.steps)?;
+            }
+        }
+        if let Some(name) = &config.screen {
+            fs::write(name, screen::render(&cpu, name)?)
+                .with_context(|| format!("cannot write {}", name.display()))?;
+            written(name);
+        }
+        if let Some(dump) = &config.dump {
+            print!("{}", dump.render(&cpu));
+        }
+        if let (Some(name), Some(trace)) = (&config.trace, cpu.trace()) {
+            trace.save(name)?;
+            written(name);
+        }
+        if let Some(name) = &config.coverage {
+            translator.write_coverage(name, cpu.executions(), cpu.pc)?;
+            written(name);
+        }
+        if let Some(dir) = &config.annotate {
+            for name in
+                translator.write_annotated(dir, cpu.executions(), cpu.pc)?
+            {
+                written(&name);
+            }
+        }
+        if let Some(name) = &config.pgo_generate {
+            let timing = translator.timing(Some(cpu.executions()));
+            fs::write(name, Profile::from_timing(&timing).to_json())
+                .with_context(|| format!("cannot write {}", name.display()))?;
+            written(name);
+        }
+        if let Some(name) = &config.timing {
+            translator.write_timing(name, Some(cpu.executions()))?;
+            written(name);
+        }
+    }
+
+    if let Some(cmd) = &config.assembler_cmd {
+        run_assembler(cmd, &config.binname, &translator)?;
+    }
 
     Ok(())
 }
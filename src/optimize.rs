@@ -0,0 +1,166 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    codegen::Asm,
+    instruction::Instruction,
+    parser::{Line, Segment, StackToken},
+};
+
+// the jump to a shared block: the way back in D, and the jump
+const CALL_SIZE: usize = 4;
+// around a shared block: the way back stored, and then taken
+const ROUTINE_SIZE: usize = 5;
+
+/// Indices of the pops whose store is overwritten before anything reads
+/// it: a pop into a local, temp or static slot that is popped into again
+/// with no push from it, and no label, jump, call or extension command, in
+/// between. Such a pop only has to drop the value.
+pub fn dead_stores(lines: &[Line]) -> Vec<usize> {
+    let mut dead = Vec::new();
+    // slots stored to in this stretch of straight-line code, not yet read
+    let mut stored: Vec<(&Segment, u16, usize)> = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        match line {
+            Line::Stack(StackToken::Pop { segment, index }) => {
+                if !tracked(segment) {
+                    continue;
+                }
+                let slot = stored
+                    .iter()
+                    .position(|(s, n, _)| *s == segment && n == index);
+                if let Some(slot) = slot {
+                    dead.push(stored.remove(slot).2);
+                }
+                stored.push((segment, *index, i));
+            }
+            Line::Stack(StackToken::Push { segment, index }) => {
+                match segment {
+                    // this and that can point anywhere, slots included
+                    Segment::This | Segment::That => stored.clear(),
+                    _ => stored
+                        .retain(|(s, n, _)| !(*s == segment && n == index)),
+                }
+            }
+            // an assert can halt the program with the stores as they are
+            Line::Branch(_) | Line::Function(_) | Line::Extension(_) => {
+                stored.clear()
+            }
+            _ => {}
+        }
+    }
+    dead.sort_unstable();
+    dead
+}
+
+fn tracked(segment: &Segment) -> bool {
+    matches!(segment, Segment::Local | Segment::Temp | Segment::Static)
+}
+
+/// Replace each block of code repeated often enough to pay for it with a
+/// jump to one shared copy, returning the shared copies. Each copy keeps
+/// the way back in `register`, which nothing else may use. Blocks of `hot`
+/// functions are left where they are.
+pub fn outline(
+    blocks: &mut [Asm],
+    register: Instruction,
+    explain: bool,
+    hot: Option<&BTreeSet<String>>,
+) -> Vec<Instruction> {
+    // identical blocks, in the order they first appear
+    let mut sites: HashMap<&[Instruction], Vec<usize>> = HashMap::new();
+    let mut order = Vec::new();
+    for (i, asm) in blocks.iter().enumerate() {
+        let is_hot = match (hot, &asm.function) {
+            (Some(hot), Some(function)) => hot.contains(function),
+            _ => false,
+        };
+        if is_hot || !movable(&asm.bin) || !next_sets_a(&blocks[i + 1..]) {
+            continue;
+        }
+        let same = sites.entry(&asm.bin).or_insert_with(|| {
+            order.push(i);
+            Vec::new()
+        });
+        same.push(i);
+    }
+    let mut groups: Vec<(Vec<Instruction>, Vec<usize>)> = order
+        .into_iter()
+        .map(|first| {
+            let bin = blocks[first].bin.clone();
+            let same = sites.remove(bin.as_slice()).unwrap_or_default();
+            (bin, same)
+        })
+        .filter(|(bin, same)| {
+            let before = bin.len() * same.len();
+            let after = CALL_SIZE * same.len() + ROUTINE_SIZE + bin.len();
+            after < before
+        })
+        .collect();
+
+    let mut shared = Vec::new();
+    for (k, (bin, same)) in groups.iter_mut().enumerate() {
+        let name = format!("__VM_OUTLINED_{}", k);
+        for (n, &i) in same.iter().enumerate() {
+            let ret = format!("{}$ret.{}", name, n);
+            blocks[i].bin = vec![
+                Instruction::A(ret.clone().into()),
+                "D=A".into(),
+                Instruction::A(name.clone().into()),
+                "0; JMP".into(),
+                Instruction::Label(ret.into()),
+            ];
+            if explain {
+                blocks[i].explain = Some(format!(
+                    "the same code as {} other blocks, kept once at {}: \
+                     jump there with the way back in D",
+                    same.len() - 1,
+                    name
+                ));
+            }
+        }
+        shared.push(Instruction::Label(name.into()));
+        shared.push(register.clone());
+        shared.push("M=D".into());
+        shared.append(bin);
+        shared.push(register.clone());
+        shared.push("A=M".into());
+        shared.push("0; JMP".into());
+    }
+    shared
+}
+
+// whether a block works the same wherever it is: nothing jumps into it,
+// it sets A and D before it reads them and D before it jumps, and it
+// leaves D set, so that the D and A the jump to it needs are nobody
+// else's
+fn movable(bin: &[Instruction]) -> bool {
+    let (mut a, mut d) = (false, false);
+    for instruction in bin {
+        match instruction {
+            Instruction::Label(_) => return false,
+            Instruction::A(_) => a = true,
+            Instruction::C { dest, comp, jump } => {
+                let reads_a = comp.reads('A') || comp.reads('M');
+                let writes_m = dest.writes('M');
+                let jumps = jump.bits() != 0;
+                if (!a && (reads_a || writes_m || jumps))
+                    || (!d && (comp.reads('D') || jumps))
+                {
+                    return false;
+                }
+                a |= dest.writes('A');
+                d |= dest.writes('D');
+            }
+        }
+    }
+    d
+}
+
+// coming back from a shared block leaves A changed, which is fine when
+// the code after it sets A before anything else
+fn next_sets_a(after: &[Asm]) -> bool {
+    match after.iter().flat_map(|asm| &asm.bin).next() {
+        Some(Instruction::A(_)) | Some(Instruction::Label(_)) | None => true,
+        Some(Instruction::C { .. }) => false,
+    }
+}
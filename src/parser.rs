@@ -1,8 +1,20 @@
+use std::{fmt, iter, mem, ops::Range, sync::Arc};
+
 use anyhow::{anyhow, Result};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
-use crate::codegen::CodeGen;
+use crate::{
+    arena::Arena,
+    diagnostics::{Class, Diagnostic},
+    lint,
+};
 
-#[derive(Debug)]
+/// A virtual memory segment addressed by push and pop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Segment {
     Constant,
     Local,
@@ -15,7 +27,7 @@ pub enum Segment {
 }
 
 impl Segment {
-    fn new(raw: &str) -> Result<Segment> {
+    pub fn new(raw: &str) -> Result<Segment> {
         match raw {
             "constant" => Ok(Segment::Constant),
             "local" => Ok(Segment::Local),
@@ -29,6 +41,19 @@ impl Segment {
         }
     }
 
+    pub fn name(&self) -> &'static str {
+        match self {
+            Segment::Constant => "constant",
+            Segment::Local => "local",
+            Segment::Argument => "argument",
+            Segment::This => "this",
+            Segment::That => "that",
+            Segment::Temp => "temp",
+            Segment::Pointer => "pointer",
+            Segment::Static => "static",
+        }
+    }
+
     pub fn to_address<'a>(&self) -> Result<&'a str> {
         match self {
             Segment::Local => Ok("LCL"),
@@ -47,7 +72,10 @@ impl Segment {
     }
 }
 
-#[derive(Debug)]
+/// Arithmetic and logical commands that pop two values and push one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "command", rename_all = "lowercase"))]
 pub enum BinaryToken {
     Add,
     Sub,
@@ -55,20 +83,62 @@ pub enum BinaryToken {
     Or,
 }
 
-#[derive(Debug)]
+impl BinaryToken {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BinaryToken::Add => "add",
+            BinaryToken::Sub => "sub",
+            BinaryToken::And => "and",
+            BinaryToken::Or => "or",
+        }
+    }
+}
+
+/// Commands that replace the top of the stack in place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "command", rename_all = "lowercase"))]
 pub enum UnaryToken {
     Neg,
     Not,
 }
 
-#[derive(Debug)]
+impl UnaryToken {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UnaryToken::Neg => "neg",
+            UnaryToken::Not => "not",
+        }
+    }
+}
+
+/// Commands that pop two values and push true (-1) or false (0).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "command"))]
 pub enum ComparisonToken {
+    #[cfg_attr(feature = "serde", serde(rename = "eq"))]
     Equal,
+    #[cfg_attr(feature = "serde", serde(rename = "lt"))]
     LessThan,
+    #[cfg_attr(feature = "serde", serde(rename = "gt"))]
     GreaterThan,
 }
 
-#[derive(Debug)]
+impl ComparisonToken {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ComparisonToken::Equal => "eq",
+            ComparisonToken::LessThan => "lt",
+            ComparisonToken::GreaterThan => "gt",
+        }
+    }
+}
+
+/// Memory access commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "command", rename_all = "lowercase"))]
 pub enum StackToken {
     Push { segment: Segment, index: u16 },
     Pop { segment: Segment, index: u16 },
@@ -79,11 +149,16 @@ impl StackToken {
         let ts = raw.split_whitespace();
         let tokens: Vec<&str> = ts.collect();
 
-        let cmd = tokens.get(0).unwrap().trim();
-        let segment = Segment::new(tokens.get(1).unwrap().trim())?;
-        let index = tokens.get(2).unwrap().parse()?;
+        let cmd = tokens.first().ok_or_else(|| anyhow!("missing command"))?;
+        let segment = Segment::new(
+            tokens.get(1).ok_or_else(|| anyhow!("missing segment"))?,
+        )?;
+        let index = tokens
+            .get(2)
+            .ok_or_else(|| anyhow!("missing index"))?
+            .parse()?;
 
-        match cmd {
+        match *cmd {
             "push" => Ok(StackToken::Push { segment, index }),
             "pop" => Ok(StackToken::Pop { segment, index }),
             _ => Err(anyhow!("unsupported stack cmd: {}", cmd)),
@@ -91,10 +166,16 @@ impl StackToken {
     }
 }
 
-#[derive(Debug)]
+/// Program flow commands. Labels are scoped to the enclosing function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "command", content = "label"))]
 pub enum BranchToken {
+    #[cfg_attr(feature = "serde", serde(rename = "label"))]
     Label(String),
+    #[cfg_attr(feature = "serde", serde(rename = "goto"))]
     GoTo(String),
+    #[cfg_attr(feature = "serde", serde(rename = "if-goto"))]
     IfGoTo(String),
 }
 
@@ -103,10 +184,13 @@ impl BranchToken {
         let ts = raw.split_whitespace();
         let tokens: Vec<&str> = ts.collect();
 
-        let cmd = tokens.get(0).unwrap().trim();
-        let label = tokens.get(1).unwrap().trim().to_string();
+        let cmd = tokens.first().ok_or_else(|| anyhow!("missing command"))?;
+        let label = tokens
+            .get(1)
+            .ok_or_else(|| anyhow!("missing label"))?
+            .to_string();
 
-        match cmd {
+        match *cmd {
             "label" => Ok(BranchToken::Label(label)),
             "goto" => Ok(BranchToken::GoTo(label)),
             "if-goto" => Ok(BranchToken::IfGoTo(label)),
@@ -115,17 +199,178 @@ impl BranchToken {
     }
 }
 
-#[derive(Debug)]
+/// Function declaration, call and return commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "command", rename_all = "lowercase"))]
+pub enum FunctionToken {
+    Function { name: String, locals: u16 },
+    Call { name: String, args: u16 },
+    Return,
+}
+
+impl FunctionToken {
+    fn new(raw: &str) -> Result<FunctionToken> {
+        let ts = raw.split_whitespace();
+        let tokens: Vec<&str> = ts.collect();
+
+        let cmd = tokens.first().ok_or_else(|| anyhow!("missing command"))?;
+        if *cmd == "return" {
+            return Ok(FunctionToken::Return);
+        }
+
+        let name = tokens
+            .get(1)
+            .ok_or_else(|| anyhow!("missing function name"))?
+            .to_string();
+        let count = tokens
+            .get(2)
+            .ok_or_else(|| anyhow!("missing count"))?
+            .parse()?;
+
+        match *cmd {
+            "function" => Ok(FunctionToken::Function {
+                name,
+                locals: count,
+            }),
+            "call" => Ok(FunctionToken::Call { name, args: count }),
+            _ => Err(anyhow!("unsupported function cmd: {}", cmd)),
+        }
+    }
+}
+
+/// Commands beyond the VM spec, accepted only with extensions turned on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "command", rename_all = "lowercase"))]
+pub enum ExtensionToken {
+    // pop a value and halt the program, recording which assert it was,
+    // when it's false
+    Assert,
+    // stop the program for good, recording which halt it was
+    Halt,
+    // pop a value and draw its bits on the screen
+    Print,
+}
+
+impl ExtensionToken {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExtensionToken::Assert => "assert",
+            ExtensionToken::Halt => "halt",
+            ExtensionToken::Print => "print",
+        }
+    }
+}
+
+/// One VM command, the unit of the IR.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
 pub enum Line {
     Stack(StackToken),
     Binary(BinaryToken),
     Unary(UnaryToken),
     Comparison(ComparisonToken),
     Branch(BranchToken),
+    Function(FunctionToken),
+    Extension(ExtensionToken),
 }
 
 impl Line {
+    /// Parse a single line of VM source (without comments or blank lines).
     pub fn new(raw: &str) -> Result<Line> {
+        let line = Line::parse(raw)?;
+        line.validate()?;
+        Ok(line)
+    }
+
+    pub fn push(segment: Segment, index: u16) -> Result<Line> {
+        Line::Stack(StackToken::Push { segment, index }).validated()
+    }
+
+    pub fn pop(segment: Segment, index: u16) -> Result<Line> {
+        Line::Stack(StackToken::Pop { segment, index }).validated()
+    }
+
+    pub fn label(label: &str) -> Result<Line> {
+        Line::Branch(BranchToken::Label(label.to_string())).validated()
+    }
+
+    pub fn goto(label: &str) -> Result<Line> {
+        Line::Branch(BranchToken::GoTo(label.to_string())).validated()
+    }
+
+    pub fn if_goto(label: &str) -> Result<Line> {
+        Line::Branch(BranchToken::IfGoTo(label.to_string())).validated()
+    }
+
+    pub fn function(name: &str, locals: u16) -> Result<Line> {
+        let name = name.to_string();
+        Line::Function(FunctionToken::Function { name, locals }).validated()
+    }
+
+    pub fn call(name: &str, args: u16) -> Result<Line> {
+        let name = name.to_string();
+        Line::Function(FunctionToken::Call { name, args }).validated()
+    }
+
+    pub fn ret() -> Line {
+        Line::Function(FunctionToken::Return)
+    }
+
+    fn validated(self) -> Result<Line> {
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Check the constraints the VM spec puts on operands: index ranges of
+    /// the fixed segments, constants that fit an A-instruction, and symbol
+    /// syntax for labels and function names.
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Line::Stack(StackToken::Pop {
+                segment: Segment::Constant,
+                ..
+            }) => Err(anyhow!("cannot pop constant")),
+            Line::Stack(StackToken::Push { segment, index })
+            | Line::Stack(StackToken::Pop { segment, index }) => {
+                // indices are loaded with an A-instruction, so none can
+                // exceed 15 bits
+                let max = match segment {
+                    Segment::Temp => 7,
+                    Segment::Pointer => 1,
+                    _ => 32767,
+                };
+                if *index > max {
+                    return Err(anyhow!(
+                        "{} index out of range: {} (max {})",
+                        segment.name(),
+                        index,
+                        max
+                    ));
+                }
+                Ok(())
+            }
+            // the frame takes 5 words below the arguments and ARG has to
+            // stay addressable
+            Line::Function(FunctionToken::Call { args, .. })
+                if *args > 32767 - 5 =>
+            {
+                Err(anyhow!("too many arguments: {} (max {})", args, 32767 - 5))
+            }
+            Line::Branch(BranchToken::Label(name))
+            | Line::Branch(BranchToken::GoTo(name))
+            | Line::Branch(BranchToken::IfGoTo(name))
+            | Line::Function(FunctionToken::Function { name, .. })
+            | Line::Function(FunctionToken::Call { name, .. }) => {
+                validate_symbol(name)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn parse(raw: &str) -> Result<Line> {
         match raw.split_whitespace().next() {
             Some(t) => match t.trim() {
                 "push" | "pop" => Ok(Line::Stack(StackToken::new(raw)?)),
@@ -141,52 +386,544 @@ impl Line {
                 "label" | "goto" | "if-goto" => {
                     Ok(Line::Branch(BranchToken::new(raw)?))
                 }
+                "function" | "call" | "return" => {
+                    Ok(Line::Function(FunctionToken::new(raw)?))
+                }
+                "assert" => Ok(Line::Extension(ExtensionToken::Assert)),
+                "halt" => Ok(Line::Extension(ExtensionToken::Halt)),
+                "print" => Ok(Line::Extension(ExtensionToken::Print)),
                 _ => Err(anyhow!("unexpected token: {}", t)),
             },
             None => Err(anyhow!("token cannot be null")),
         }
     }
+
+    // the command word, e.g. "push" or "if-goto"
+    pub fn name(&self) -> &'static str {
+        match self {
+            Line::Stack(StackToken::Push { .. }) => "push",
+            Line::Stack(StackToken::Pop { .. }) => "pop",
+            Line::Unary(token) => token.name(),
+            Line::Binary(token) => token.name(),
+            Line::Comparison(token) => token.name(),
+            Line::Branch(BranchToken::Label(_)) => "label",
+            Line::Branch(BranchToken::GoTo(_)) => "goto",
+            Line::Branch(BranchToken::IfGoTo(_)) => "if-goto",
+            Line::Function(FunctionToken::Function { .. }) => "function",
+            Line::Function(FunctionToken::Call { .. }) => "call",
+            Line::Function(FunctionToken::Return) => "return",
+            Line::Extension(token) => token.name(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            Line::Stack(StackToken::Push { segment, index }) => json!({
+                "command": "push",
+                "segment": segment.name(),
+                "index": index,
+            }),
+            Line::Stack(StackToken::Pop { segment, index }) => json!({
+                "command": "pop",
+                "segment": segment.name(),
+                "index": index,
+            }),
+            Line::Unary(token) => json!({ "command": token.name() }),
+            Line::Binary(token) => json!({ "command": token.name() }),
+            Line::Comparison(token) => json!({ "command": token.name() }),
+            Line::Branch(token) => {
+                let (command, label) = match token {
+                    BranchToken::Label(label) => ("label", label),
+                    BranchToken::GoTo(label) => ("goto", label),
+                    BranchToken::IfGoTo(label) => ("if-goto", label),
+                };
+                json!({ "command": command, "label": label })
+            }
+            Line::Function(FunctionToken::Function { name, locals }) => {
+                json!({ "command": "function", "name": name, "locals": locals })
+            }
+            Line::Function(FunctionToken::Call { name, args }) => {
+                json!({ "command": "call", "name": name, "args": args })
+            }
+            Line::Function(FunctionToken::Return) => {
+                json!({ "command": "return" })
+            }
+            Line::Extension(token) => json!({ "command": token.name() }),
+        }
+    }
+
+    /// Read back a command written by `to_json`.
+    pub fn from_json(json: &Value) -> Result<Line> {
+        // the fields, in the order the command spells them out
+        let mut words = Vec::new();
+        for key in ["command", "segment", "index", "label", "name", "locals"] {
+            match &json[key] {
+                Value::Null => {}
+                Value::String(word) => words.push(word.clone()),
+                word => words.push(word.to_string()),
+            }
+        }
+        if let Some(args) = json["args"].as_u64() {
+            words.push(args.to_string());
+        }
+        Line::new(&words.join(" "))
+            .map_err(|err| anyhow!("invalid command {}: {}", json, err))
+    }
 }
 
-#[derive(Debug)]
-pub struct Asm {
-    pub src: String,
-    pub bin: Vec<String>,
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for BinaryToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for UnaryToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for ComparisonToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for ExtensionToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for StackToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StackToken::Push { segment, index } => {
+                write!(f, "push {} {}", segment, index)
+            }
+            StackToken::Pop { segment, index } => {
+                write!(f, "pop {} {}", segment, index)
+            }
+        }
+    }
+}
+
+impl fmt::Display for BranchToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BranchToken::Label(label) => write!(f, "label {}", label),
+            BranchToken::GoTo(label) => write!(f, "goto {}", label),
+            BranchToken::IfGoTo(label) => write!(f, "if-goto {}", label),
+        }
+    }
+}
+
+impl fmt::Display for FunctionToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FunctionToken::Function { name, locals } => {
+                write!(f, "function {} {}", name, locals)
+            }
+            FunctionToken::Call { name, args } => {
+                write!(f, "call {} {}", name, args)
+            }
+            FunctionToken::Return => write!(f, "return"),
+        }
+    }
+}
+
+// canonical VM syntax: single spaces, no comments, parses back to the
+// same line
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Stack(token) => write!(f, "{}", token),
+            Line::Binary(token) => write!(f, "{}", token),
+            Line::Unary(token) => write!(f, "{}", token),
+            Line::Comparison(token) => write!(f, "{}", token),
+            Line::Branch(token) => write!(f, "{}", token),
+            Line::Function(token) => write!(f, "{}", token),
+            Line::Extension(token) => write!(f, "{}", token),
+        }
+    }
+}
+
+// the lints a comment allows, by `vm-lint: allow(<lint>, ...)`
+fn allowed_lints(comment: &str) -> Result<Vec<&'static str>> {
+    let directive = match comment.trim().strip_prefix("vm-lint:") {
+        Some(directive) => directive.trim(),
+        None => return Ok(Vec::new()),
+    };
+    directive
+        .strip_prefix("allow(")
+        .and_then(|names| names.strip_suffix(')'))
+        .ok_or_else(|| anyhow!("expected vm-lint: allow(<lint>, ...)"))?
+        .split(',')
+        .map(|name| lint::known(name.trim()))
+        .collect()
+}
+
+// what the VM spec allows beyond what the parser would accept anyway:
+// words split by spaces alone, exactly as many as the command takes, and
+// numbers written as plain digits. Around the command, as the course's own
+// files have it, any whitespace goes.
+fn check_strict(code: &str) -> Result<()> {
+    let code = code.trim();
+    if let Some(c) = code.chars().find(|c| c.is_whitespace() && *c != ' ') {
+        return Err(anyhow!(
+            "only spaces separate words in the VM spec, not {:?}",
+            c
+        ));
+    }
+    let words: Vec<&str> = code.split_whitespace().collect();
+    let command = match words.first() {
+        Some(command) => *command,
+        None => return Ok(()),
+    };
+    let expected = match command {
+        "push" | "pop" | "function" | "call" => 3,
+        "label" | "goto" | "if-goto" => 2,
+        "add" | "sub" | "neg" | "eq" | "gt" | "lt" | "and" | "or" | "not"
+        | "return" => 1,
+        _ => return Err(anyhow!("{} is outside the VM spec", command)),
+    };
+    if words.len() != expected {
+        return Err(anyhow!(
+            "{} takes {} arguments, not {}",
+            command,
+            expected - 1,
+            words.len() - 1
+        ));
+    }
+    if expected == 3 {
+        let number = words[2];
+        if number.starts_with('-') {
+            return Err(anyhow!("negative numbers are outside the VM spec"));
+        }
+        if !number.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(anyhow!("{} is not a plain number", number));
+        }
+    }
+    Ok(())
+}
+
+// symbols may use letters, digits, '_', '.', '$' and ':', but can't start
+// with a digit
+fn validate_symbol(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_.$:".contains(c));
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("invalid symbol: {}", name))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum IrFormat {
+    Text,
+    Json,
+}
+
+impl IrFormat {
+    pub fn new(raw: &str) -> Result<IrFormat> {
+        match raw {
+            "text" => Ok(IrFormat::Text),
+            "json" => Ok(IrFormat::Json),
+            _ => Err(anyhow!("unexpected ir format: {}", raw)),
+        }
+    }
+}
+
+/// Where a command came from: the source file, its 1-based line number and
+/// the byte range of the command (without indentation or trailing comment)
+/// within the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    // shared by every span of the file
+    pub file: Arc<str>,
+    pub line: usize,
+    pub bytes: Range<usize>,
 }
 
 #[derive(Debug)]
 pub struct Parser {
     lines: Vec<Line>,
-    pub asm: Vec<Asm>,
-    cg: CodeGen,
+    raws: Arena,
+    spans: Vec<Span>,
+    // comment and blank lines before each command, and those not yet
+    // followed by one
+    notes: Vec<Vec<String>>,
+    skipped: Vec<String>,
+    // lints allowed by vm-lint comments, at the line of the command each
+    // applies to, and those waiting for the next command
+    allows: Vec<(usize, &'static str)>,
+    pending: Vec<&'static str>,
     filename: String,
+    path: Arc<str>,
+    line: usize,
+    offset: usize,
+    // accept extension commands
+    extensions: bool,
+    // accept nothing the VM spec doesn't
+    strict: bool,
 }
 
 impl Parser {
-    pub fn new(filename: String) -> Parser {
+    pub fn new(filename: String, path: String) -> Parser {
         Parser {
             lines: Vec::new(),
-            asm: Vec::new(),
-            cg: CodeGen::new(filename.clone()),
+            raws: Arena::new(),
+            spans: Vec::new(),
+            notes: Vec::new(),
+            skipped: Vec::new(),
+            allows: Vec::new(),
+            pending: Vec::new(),
             filename,
+            path: path.into(),
+            line: 0,
+            offset: 0,
+            extensions: false,
+            strict: false,
         }
     }
 
-    pub fn process_line(&mut self, raw: &str) -> Result<()> {
-        if !raw.starts_with("//") && raw != "" {
-            let line = Line::new(raw)?;
-            let src = format!("// {}", raw);
-            let bin = self.cg.gen_block(&line)?;
-            self.lines.push(line);
-            self.asm.push(Asm { src, bin });
+    /// A parser holding IR that was built directly rather than parsed.
+    pub fn from_lines(filename: String, lines: Vec<Line>) -> Parser {
+        Parser {
+            lines,
+            raws: Arena::new(),
+            spans: Vec::new(),
+            notes: Vec::new(),
+            skipped: Vec::new(),
+            allows: Vec::new(),
+            pending: Vec::new(),
+            path: filename.as_str().into(),
+            filename,
+            line: 0,
+            offset: 0,
+            extensions: false,
+            strict: false,
         }
-        Ok(())
     }
 
-    pub fn debug(&self) {
-        println!("***  LINES  ***\n");
+    pub fn with_extensions(mut self, extensions: bool) -> Parser {
+        self.extensions = extensions;
+        self
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Parser {
+        self.strict = strict;
+        self
+    }
+
+    // lines are fed in order; `raw` may keep its line terminator, which
+    // keeps byte spans exact for \r\n files
+    pub fn parse_line(&mut self, raw: &str) -> Result<Option<&Line>> {
+        let start = self.offset;
+        self.line += 1;
+        self.offset += raw.len();
+
+        let raw = raw.trim_end_matches(['\n', '\r']);
+        let comment = raw.find("//").map(|i| &raw[i + 2..]);
+        let allowed = comment.map(allowed_lints).transpose().map_err(|e| {
+            Diagnostic::error(Class::Parse, e).at(Span {
+                file: self.path.clone(),
+                line: self.line,
+                bytes: start..start + raw.len(),
+            })
+        })?;
+        // blank and comment-only lines, indented or not, aren't commands
+        if raw.trim_start().starts_with("//") || raw.trim().is_empty() {
+            self.pending.extend(allowed.into_iter().flatten());
+            self.skipped.push(raw.trim_end().to_string());
+            return Ok(None);
+        }
+        let code = match raw.find("//") {
+            Some(i) => &raw[..i],
+            None => raw,
+        };
+        let indent = code.len() - code.trim_start().len();
+        let span = Span {
+            file: self.path.clone(),
+            line: self.line,
+            bytes: start + indent..start + code.trim_end().len(),
+        };
+        for lint in self.pending.drain(..).chain(allowed.into_iter().flatten())
+        {
+            self.allows.push((self.line, lint));
+        }
+
+        if self.strict {
+            check_strict(code).map_err(|e| {
+                Diagnostic::error(Class::Parse, e).at(span.clone())
+            })?;
+        }
+        // malformed lines are parse errors, well-formed ones the VM can't
+        // run are semantic errors
+        let line = Line::parse(raw)
+            .map_err(|e| Diagnostic::error(Class::Parse, e).at(span.clone()))?;
+        line.validate().map_err(|e| {
+            Diagnostic::error(Class::Semantic, e).at(span.clone())
+        })?;
+        if matches!(line, Line::Extension(_)) && !self.extensions {
+            return Err(Diagnostic::error(
+                Class::Parse,
+                format!("{} is an extension; enable with --extensions", line),
+            )
+            .at(span)
+            .into());
+        }
+
+        self.lines.push(line);
+        self.raws.push(raw);
+        self.spans.push(span);
+        self.notes.push(mem::take(&mut self.skipped));
+
+        Ok(self.lines.last())
+    }
+
+    /// Hand over the IR, leaving the parser empty. Each line comes with
+    /// its span when it was parsed from text; take the source text first
+    /// with `take_raws` to keep it.
+    pub fn drain(&mut self) -> impl Iterator<Item = (Line, Option<Span>)> {
+        self.raws = Arena::new();
+        let spans = mem::take(&mut self.spans)
+            .into_iter()
+            .map(Some)
+            .chain(iter::repeat_with(|| None));
+        mem::take(&mut self.lines).into_iter().zip(spans)
+    }
+
+    pub fn take_raws(&mut self) -> Arena {
+        mem::take(&mut self.raws)
+    }
+
+    /// Replace the IR with `commands`, each paired with the index of the
+    /// command it stands for, in order. Each takes that command's source
+    /// text and span, and the comments before any command it replaced.
+    pub fn rewrite(&mut self, commands: Vec<(Line, usize)>) {
+        let mut raws = Arena::new();
+        let mut spans = Vec::new();
+        let mut notes = Vec::new();
+        let mut old_notes = mem::take(&mut self.notes).into_iter();
+        let mut next = 0;
+        for (_, at) in &commands {
+            if let Some(raw) = self.raws.get(*at) {
+                raws.push(raw);
+            }
+            spans.extend(self.spans.get(*at).cloned());
+            let mut before = Vec::new();
+            while next <= *at {
+                before.extend(old_notes.next().unwrap_or_default());
+                next += 1;
+            }
+            notes.push(before);
+        }
+        // comments before commands that are gone now trail the file
+        let mut trailing: Vec<String> = old_notes.flatten().collect();
+        trailing.append(&mut self.skipped);
+        self.skipped = trailing;
+        self.lines = commands.into_iter().map(|(line, _)| line).collect();
+        self.raws = raws;
+        self.spans = spans;
+        self.notes = notes;
+    }
+
+    /// The lints `// vm-lint: allow(<name>, ...)` comments allow, each at
+    /// the line of the command it's on or, on a line of its own, of the
+    /// next command.
+    pub fn allows(&self) -> &[(usize, &'static str)] {
+        &self.allows
+    }
+
+    /// Hand over the comment and blank lines skipped before each command,
+    /// plus those after the last one.
+    pub fn take_notes(&mut self) -> (Vec<Vec<String>>, Vec<String>) {
+        (mem::take(&mut self.notes), mem::take(&mut self.skipped))
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    // the source text each line was parsed from
+    pub fn raws(&self) -> &Arena {
+        &self.raws
+    }
+
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    /// The IR as VM code, one command per line, under the file's name.
+    pub fn dump_text(&self) -> String {
+        let mut buf = format!("// {}\n", self.filename);
         for line in &self.lines {
-            println!("{:?}", line);
+            buf.push_str(&format!("{}\n", line));
+        }
+        buf
+    }
+
+    pub fn to_json(&self) -> Value {
+        let lines: Vec<Value> = self
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let mut json = line.to_json();
+                if let Some(span) = self.spans.get(i) {
+                    json["line"] = json!(span.line);
+                    json["bytes"] = json!([span.bytes.start, span.bytes.end]);
+                }
+                json
+            })
+            .collect();
+        json!({ "file": self.filename, "path": *self.path, "lines": lines })
+    }
+
+    /// Read back a file written by `to_json`, keeping the source lines of
+    /// the commands that have one.
+    pub fn from_json(json: &Value) -> Result<Parser> {
+        let filename = json["file"]
+            .as_str()
+            .ok_or_else(|| anyhow!("expected a file name"))?;
+        let path = json["path"].as_str().unwrap_or(filename);
+        let lines = json["lines"]
+            .as_array()
+            .ok_or_else(|| anyhow!("expected the lines of {}", filename))?;
+        let mut parser = Parser::new(filename.to_string(), path.to_string());
+        for line in lines {
+            parser.lines.push(Line::from_json(line)?);
+            let number = line["line"].as_u64();
+            let start = line["bytes"][0].as_u64();
+            let end = line["bytes"][1].as_u64();
+            if let (Some(number), Some(start), Some(end)) = (number, start, end)
+            {
+                parser.spans.push(Span {
+                    file: parser.path.clone(),
+                    line: number as usize,
+                    bytes: start as usize..end as usize,
+                });
+            }
+        }
+        // spans are kept only when every command has one
+        if parser.spans.len() != parser.lines.len() {
+            parser.spans.clear();
         }
+        Ok(parser)
     }
 }
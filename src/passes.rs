@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Result};
+
+use crate::parser::{
+    BinaryToken, BranchToken, ComparisonToken, Line, Segment, StackToken,
+    UnaryToken,
+};
+
+/// The IR passes, by the names a project file's `passes` lists them under.
+pub const PASSES: [&str; 2] = ["fold-constants", "peephole"];
+
+/// The passes run when a project doesn't list its own: none at -O0, and
+/// all of them from -O1.
+pub fn default_pipeline(opt_level: u8) -> Vec<String> {
+    match opt_level {
+        0 => Vec::new(),
+        _ => PASSES.iter().map(|pass| pass.to_string()).collect(),
+    }
+}
+
+pub fn check(names: &[String]) -> Result<()> {
+    for name in names {
+        if !PASSES.contains(&name.as_str()) {
+            return Err(anyhow!(
+                "unknown pass: {} (expected one of {})",
+                name,
+                PASSES.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Run the pass `name` over one file's commands. Each command it returns
+/// comes with the index of the command it stands for, in order.
+pub fn run(name: &str, lines: &[Line]) -> Result<Vec<(Line, usize)>> {
+    match name {
+        "fold-constants" => Ok(fold_constants(lines)),
+        "peephole" => Ok(peephole(lines)),
+        _ => Err(anyhow!("unknown pass: {}", name)),
+    }
+}
+
+// arithmetic on constants is done here rather than at run time
+fn fold_constants(lines: &[Line]) -> Vec<(Line, usize)> {
+    let mut out: Vec<(Line, usize)> = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let folded = match line {
+            Line::Unary(op) => constant(&out).map(|(x, n)| {
+                out.truncate(out.len() - n);
+                match op {
+                    UnaryToken::Neg => x.wrapping_neg(),
+                    UnaryToken::Not => !x,
+                }
+            }),
+            Line::Binary(_) | Line::Comparison(_) => {
+                match constant(&out).and_then(|(y, ny)| {
+                    constant(&out[..out.len() - ny])
+                        .map(|(x, nx)| (x, y, nx + ny))
+                }) {
+                    Some((x, y, n)) => {
+                        out.truncate(out.len() - n);
+                        Some(apply(line, x, y))
+                    }
+                    None => None,
+                }
+            }
+            _ => None,
+        };
+        match folded {
+            Some(value) => {
+                out.extend(push_value(value).into_iter().map(|l| (l, i)))
+            }
+            None => out.push((line.clone(), i)),
+        }
+    }
+    out
+}
+
+// the value the commands at the end of `out` push, and how many commands
+// that takes: push constant k, optionally followed by neg or not
+fn constant(out: &[(Line, usize)]) -> Option<(i16, usize)> {
+    let value = |line: &Line| match line {
+        Line::Stack(StackToken::Push {
+            segment: Segment::Constant,
+            index,
+        }) => Some(*index as i16),
+        _ => None,
+    };
+    match out {
+        [.., (before, _), (Line::Unary(op), _)] => {
+            value(before).map(|x| match op {
+                UnaryToken::Neg => (x.wrapping_neg(), 2),
+                UnaryToken::Not => (!x, 2),
+            })
+        }
+        [.., (last, _)] => value(last).map(|x| (x, 1)),
+        [] => None,
+    }
+}
+
+// as the generated code computes it, comparisons on the difference
+fn apply(line: &Line, x: i16, y: i16) -> i16 {
+    let diff = x.wrapping_sub(y);
+    match line {
+        Line::Binary(BinaryToken::Add) => x.wrapping_add(y),
+        Line::Binary(BinaryToken::Sub) => diff,
+        Line::Binary(BinaryToken::And) => x & y,
+        Line::Binary(BinaryToken::Or) => x | y,
+        Line::Comparison(ComparisonToken::Equal) => -((diff == 0) as i16),
+        Line::Comparison(ComparisonToken::GreaterThan) => -((diff > 0) as i16),
+        Line::Comparison(ComparisonToken::LessThan) => -((diff < 0) as i16),
+        _ => unreachable!("only arithmetic is folded"),
+    }
+}
+
+// constants only go up to 32767; anything below 0 is the not of one
+fn push_value(value: i16) -> Vec<Line> {
+    let push = |index: i16| {
+        Line::Stack(StackToken::Push {
+            segment: Segment::Constant,
+            index: index as u16,
+        })
+    };
+    match value {
+        0.. => vec![push(value)],
+        _ => vec![push(!value), Line::Unary(UnaryToken::Not)],
+    }
+}
+
+// pairs of commands that undo each other or do nothing, until none are
+// left
+fn peephole(lines: &[Line]) -> Vec<(Line, usize)> {
+    let mut out: Vec<(Line, usize)> = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        out.push((line.clone(), i));
+        while let [.., (first, _), (second, at)] = out.as_slice() {
+            let at = *at;
+            let replaced = match (first, second) {
+                (Line::Unary(a), Line::Unary(b)) if a == b => Vec::new(),
+                // a value stored back where it was read from
+                (
+                    Line::Stack(StackToken::Push { segment, index }),
+                    Line::Stack(StackToken::Pop {
+                        segment: popped,
+                        index: popped_index,
+                    }),
+                ) if segment == popped && index == popped_index => Vec::new(),
+                // a jump to the very next command
+                (
+                    Line::Branch(BranchToken::GoTo(to)),
+                    Line::Branch(BranchToken::Label(label)),
+                ) if to == label => vec![second.clone()],
+                // a branch that's always or never taken
+                (
+                    Line::Stack(StackToken::Push {
+                        segment: Segment::Constant,
+                        index,
+                    }),
+                    Line::Branch(BranchToken::IfGoTo(label)),
+                ) => match index {
+                    0 => Vec::new(),
+                    _ => vec![Line::Branch(BranchToken::GoTo(label.clone()))],
+                },
+                _ => break,
+            };
+            out.truncate(out.len() - 2);
+            out.extend(replaced.into_iter().map(|line| (line, at)));
+        }
+    }
+    out
+}
@@ -0,0 +1,97 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+
+use crate::stats::Timing;
+
+// the share of a run's cycles that its hot functions account for between
+// them
+const HOT_SHARE: f64 = 0.9;
+
+/// How often each function was called in an emulator run and the cycles
+/// spent in its own code, for a later build to be optimized by.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub functions: BTreeMap<String, (u64, u64)>,
+}
+
+impl Profile {
+    pub fn from_timing(timing: &Timing) -> Profile {
+        Profile {
+            functions: timing.functions.clone(),
+        }
+    }
+
+    /// `{"functions": {"Main.main": {"calls": 1, "cycles": 120}, ...}}`
+    pub fn to_json(&self) -> String {
+        let functions: serde_json::Map<String, Value> = self
+            .functions
+            .iter()
+            .map(|(name, (calls, cycles))| {
+                (name.clone(), json!({ "calls": calls, "cycles": cycles }))
+            })
+            .collect();
+        let mut json =
+            serde_json::to_string_pretty(&json!({ "functions": functions }))
+                .unwrap_or_default();
+        json.push('\n');
+        json
+    }
+
+    pub fn parse(json: &str) -> Result<Profile> {
+        let value: Value = serde_json::from_str(json)?;
+        let functions = value["functions"]
+            .as_object()
+            .ok_or_else(|| anyhow!("expected an object of functions"))?;
+        let mut profile = Profile::default();
+        for (name, counts) in functions {
+            let count = |key: &str| {
+                counts[key].as_u64().ok_or_else(|| {
+                    anyhow!("expected a count of {} for {}", key, name)
+                })
+            };
+            profile
+                .functions
+                .insert(name.clone(), (count("calls")?, count("cycles")?));
+        }
+        Ok(profile)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Profile> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("cannot read {}", path.display()))?;
+        Profile::parse(&json)
+            .with_context(|| format!("cannot load {}", path.display()))
+    }
+
+    /// The busiest functions that between them took most of the run's
+    /// cycles. Everything else, including functions the run never called,
+    /// is cold.
+    pub fn hot(&self) -> BTreeSet<String> {
+        let mut functions: Vec<(&String, u64)> = self
+            .functions
+            .iter()
+            .map(|(name, &(_, cycles))| (name, cycles))
+            .filter(|&(_, cycles)| cycles > 0)
+            .collect();
+        functions.sort_by_key(|&(_, cycles)| std::cmp::Reverse(cycles));
+
+        let total: u64 = functions.iter().map(|&(_, cycles)| cycles).sum();
+        let mut hot = BTreeSet::new();
+        let mut covered = 0;
+        for (name, cycles) in functions {
+            if covered as f64 >= total as f64 * HOT_SHARE {
+                break;
+            }
+            hot.insert(name.clone());
+            covered += cycles;
+        }
+        hot
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    ffi::{c_char, CStr, CString},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use libloading::{Library, Symbol};
+use serde_json::Value;
+
+use crate::{
+    diagnostics::{Class, Diagnostic},
+    parser::{Parser, Span},
+};
+
+// takes the IR as JSON and returns the result as JSON, or null on failure
+type Pass = unsafe extern "C" fn(*const c_char) -> *mut c_char;
+// frees what the pass returned
+type Free = unsafe extern "C" fn(*mut c_char);
+
+/// An IR pass loaded from a dynamic library. The library exports
+///
+/// ```c
+/// char *n2t_pass(const char *ir);
+/// void n2t_free(char *result);
+/// ```
+///
+/// `ir` is the program as `--dump-ir --ir-format json` prints it. The
+/// result is `{"files": [...], "diagnostics": [...]}`: the transformed IR
+/// in the same form, and anything the pass found, each as
+/// `{"severity": "warning" | "error", "message": ..., "file": ...,
+/// "line": ...}` with the location optional.
+#[derive(Debug)]
+pub struct Plugin {
+    name: String,
+    library: Library,
+}
+
+impl Plugin {
+    pub fn load(path: impl AsRef<Path>) -> Result<Plugin> {
+        let path = path.as_ref();
+        // loading runs the library's initialisers, which is trusting it
+        // as much as running it
+        let library = unsafe { Library::new(path) }
+            .with_context(|| format!("cannot load {}", path.display()))?;
+        // missing symbols are found now rather than mid-translation
+        unsafe {
+            library.get::<Pass>(b"n2t_pass\0")?;
+            library.get::<Free>(b"n2t_free\0")?;
+        }
+        // libfold.so is the fold pass
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = stem.strip_prefix("lib").unwrap_or(&stem).to_string();
+        Ok(Plugin { name, library })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Run the pass over the IR, returning the new IR and what it found.
+    pub fn run(
+        &self,
+        parsers: &[Parser],
+    ) -> Result<(Vec<Parser>, Vec<Diagnostic>)> {
+        let files: Vec<Value> = parsers.iter().map(Parser::to_json).collect();
+        let ir = CString::new(serde_json::to_string(&files)?)?;
+        let output = unsafe {
+            let pass: Symbol<Pass> = self.library.get(b"n2t_pass\0")?;
+            let free: Symbol<Free> = self.library.get(b"n2t_free\0")?;
+            let result = pass(ir.as_ptr());
+            if result.is_null() {
+                return Err(anyhow!("plugin {} failed", self.name));
+            }
+            let output = CStr::from_ptr(result).to_string_lossy().into_owned();
+            free(result);
+            output
+        };
+        parse_output(&output)
+            .with_context(|| format!("plugin {} returned bad IR", self.name))
+    }
+}
+
+fn parse_output(output: &str) -> Result<(Vec<Parser>, Vec<Diagnostic>)> {
+    let output: Value = serde_json::from_str(output)?;
+    let parsers = output["files"]
+        .as_array()
+        .ok_or_else(|| anyhow!("expected an array of files"))?
+        .iter()
+        .map(Parser::from_json)
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut diagnostics = Vec::new();
+    for found in output["diagnostics"].as_array().into_iter().flatten() {
+        let message = found["message"]
+            .as_str()
+            .ok_or_else(|| anyhow!("expected a message: {}", found))?;
+        let diagnostic = match found["severity"].as_str() {
+            Some("error") => Diagnostic::error(Class::Semantic, message),
+            Some("warning") => Diagnostic::warning(Class::Semantic, message),
+            _ => return Err(anyhow!("expected a severity: {}", found)),
+        };
+        let file = found["file"].as_str();
+        let line = found["line"].as_u64().map(|line| line as usize);
+        diagnostics.push(match (file, line) {
+            // at the command on that line, when there is one
+            (Some(file), Some(line)) => {
+                let span = parsers
+                    .iter()
+                    .flat_map(Parser::spans)
+                    .find(|span| *span.file == *file && span.line == line)
+                    .cloned()
+                    .unwrap_or(Span {
+                        file: file.into(),
+                        line,
+                        bytes: 0..0,
+                    });
+                diagnostic.at(span)
+            }
+            _ => diagnostic,
+        });
+    }
+    Ok((parsers, diagnostics))
+}
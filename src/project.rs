@@ -0,0 +1,451 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use toml::{Table, Value};
+
+use crate::{
+    codegen::{self, Registers},
+    lint::{self, Level},
+    passes,
+    style::{Spacing, MAX_BLANK_LINES},
+    translator::{Addresses, Comments, Newline, Options, Order},
+};
+
+pub const FILENAME: &str = "vm-translator.toml";
+
+/// Translation options a project file can set. Anything left out keeps
+/// its default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Settings {
+    pub bootstrap: Option<bool>,
+    pub opt_level: Option<u8>,
+    pub optimize_size: Option<bool>,
+    pub init_loop: Option<u16>,
+    pub comments: Option<Comments>,
+    pub addresses: Option<Addresses>,
+    pub newline: Option<Newline>,
+    pub dest_spacing: Option<Spacing>,
+    pub jump_spacing: Option<Spacing>,
+    pub indent_labels: Option<bool>,
+    pub blank_lines: Option<usize>,
+    pub registers: Option<Registers>,
+    pub scratch: Option<Vec<u16>>,
+    pub end_loop: Option<bool>,
+    pub preserve_comments: Option<bool>,
+    pub minify: Option<bool>,
+    pub static_addresses: Option<bool>,
+    pub shared_routines: Option<bool>,
+    pub outline: Option<bool>,
+    pub extensions: Option<bool>,
+    pub max_errors: Option<usize>,
+    pub budget: Option<usize>,
+    // per function, over budget
+    pub budgets: BTreeMap<String, usize>,
+    // file name patterns, with * and ?, left out of a directory's program
+    pub exclude: Vec<String>,
+    pub order: Option<Order>,
+    // each lint's level, by name or as warnings
+    pub lints: BTreeMap<String, Level>,
+    // file names in the order they go into ROM, for order = "manifest"
+    pub manifest: Option<Vec<String>>,
+    // the IR passes to run, in order, in place of the opt level's
+    pub passes: Option<Vec<String>>,
+}
+
+impl Settings {
+    // keys other than settings are left to the caller
+    fn set(&mut self, key: &str, value: &Value) -> Result<bool> {
+        match key {
+            "bootstrap" => self.bootstrap = Some(boolean(key, value)?),
+            // "s" as in -Os
+            "opt-level" if value.as_str() == Some("s") => {
+                self.opt_level = Some(2);
+                self.optimize_size = Some(true);
+            }
+            "opt-level" => {
+                self.opt_level = Some(opt_level(integer(key, value)?)?);
+                self.optimize_size = Some(false);
+            }
+            "init-loop" => match integer(key, value)? {
+                n @ 1..=32767 => self.init_loop = Some(n as u16),
+                _ => return Err(anyhow!("init-loop expects a count > 0")),
+            },
+            "comments" => {
+                self.comments = Some(Comments::new(string(key, value)?)?)
+            }
+            "rom-addresses" => {
+                self.addresses = Some(Addresses::new(string(key, value)?)?)
+            }
+            "newline" => {
+                self.newline = Some(Newline::new(string(key, value)?)?)
+            }
+            "dest-spacing" => {
+                self.dest_spacing = Some(Spacing::new(string(key, value)?)?)
+            }
+            "jump-spacing" => {
+                self.jump_spacing = Some(Spacing::new(string(key, value)?)?)
+            }
+            "indent-labels" => self.indent_labels = Some(boolean(key, value)?),
+            "blank-lines" => match integer(key, value)? as u64 {
+                n if n <= MAX_BLANK_LINES as u64 => {
+                    self.blank_lines = Some(n as usize)
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "blank-lines expects a count up to {}",
+                        MAX_BLANK_LINES
+                    ))
+                }
+            },
+            "registers" => {
+                self.registers = Some(Registers::new(string(key, value)?)?)
+            }
+            "scratch" => {
+                let registers = value
+                    .as_array()
+                    .ok_or_else(|| anyhow!("scratch expects a list"))?
+                    .iter()
+                    .map(|v| register(integer(key, v)?))
+                    .collect::<Result<Vec<_>>>()?;
+                codegen::check_scratch(&registers)?;
+                self.scratch = Some(registers);
+            }
+            "end-loop" => self.end_loop = Some(boolean(key, value)?),
+            "preserve-comments" => {
+                self.preserve_comments = Some(boolean(key, value)?)
+            }
+            "minify" => self.minify = Some(boolean(key, value)?),
+            "static-addresses" => {
+                self.static_addresses = Some(boolean(key, value)?)
+            }
+            "shared-routines" => {
+                self.shared_routines = Some(boolean(key, value)?)
+            }
+            "outline" => self.outline = Some(boolean(key, value)?),
+            "extensions" => self.extensions = Some(boolean(key, value)?),
+            "max-errors" => {
+                self.max_errors = Some(integer(key, value)? as usize)
+            }
+            "budget" => self.budget = Some(integer(key, value)? as usize),
+            "budgets" => {
+                let table = value
+                    .as_table()
+                    .ok_or_else(|| anyhow!("budgets expects a table"))?;
+                for (name, value) in table {
+                    let count = integer(&format!("budgets.{}", name), value)?;
+                    self.budgets.insert(name.to_string(), count as usize);
+                }
+            }
+            "exclude" => {
+                self.exclude = value
+                    .as_array()
+                    .ok_or_else(|| anyhow!("exclude expects a list"))?
+                    .iter()
+                    .map(|v| string(key, v).map(str::to_string))
+                    .collect::<Result<_>>()?
+            }
+            "lints" => {
+                let table = value
+                    .as_table()
+                    .ok_or_else(|| anyhow!("lints expects a table"))?;
+                for (name, value) in table {
+                    lint::check(name)?;
+                    let level = string(&format!("lints.{}", name), value)?;
+                    self.lints.insert(name.to_string(), Level::new(level)?);
+                }
+            }
+            "order" => self.order = Some(Order::new(string(key, value)?)?),
+            "manifest" => {
+                self.manifest = Some(
+                    value
+                        .as_array()
+                        .ok_or_else(|| anyhow!("manifest expects a list"))?
+                        .iter()
+                        .map(|v| string(key, v).map(str::to_string))
+                        .collect::<Result<_>>()?,
+                )
+            }
+            "passes" => {
+                let passes = value
+                    .as_array()
+                    .ok_or_else(|| anyhow!("passes expects a list"))?
+                    .iter()
+                    .map(|v| string(key, v).map(str::to_string))
+                    .collect::<Result<Vec<_>>>()?;
+                passes::check(&passes)?;
+                self.passes = Some(passes);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    pub fn apply(&self, options: &mut Options) {
+        if self.bootstrap.is_some() {
+            options.bootstrap = self.bootstrap;
+        }
+        if let Some(level) = self.opt_level {
+            options.opt_level = level;
+        }
+        if let Some(size) = self.optimize_size {
+            options.optimize_size = size;
+        }
+        if self.init_loop.is_some() {
+            options.init_loop = self.init_loop;
+        }
+        if let Some(comments) = self.comments {
+            options.comments = comments;
+        }
+        if let Some(addresses) = self.addresses {
+            options.addresses = addresses;
+        }
+        if let Some(newline) = self.newline {
+            options.newline = newline;
+        }
+        if let Some(spacing) = self.dest_spacing {
+            options.style.dest = spacing;
+        }
+        if let Some(spacing) = self.jump_spacing {
+            options.style.jump = spacing;
+        }
+        if let Some(indent) = self.indent_labels {
+            options.style.indent = indent;
+        }
+        if self.blank_lines.is_some() {
+            options.style.blank_lines = self.blank_lines;
+        }
+        if let Some(registers) = self.registers {
+            options.registers = registers;
+        }
+        if let Some(scratch) = &self.scratch {
+            options.scratch = scratch.clone();
+        }
+        if let Some(end_loop) = self.end_loop {
+            options.end_loop = end_loop;
+        }
+        if let Some(preserve) = self.preserve_comments {
+            options.preserve_comments = preserve;
+        }
+        if let Some(minify) = self.minify {
+            options.minify = minify;
+        }
+        if let Some(addresses) = self.static_addresses {
+            options.static_addresses = addresses;
+        }
+        if let Some(shared) = self.shared_routines {
+            options.shared_routines = shared;
+        }
+        if let Some(outline) = self.outline {
+            options.outline = outline;
+        }
+        if let Some(extensions) = self.extensions {
+            options.extensions = extensions;
+        }
+        if let Some(max_errors) = self.max_errors {
+            options.max_errors = max_errors;
+        }
+        if self.budget.is_some() {
+            options.budget = self.budget;
+        }
+        options.budgets.extend(self.budgets.clone());
+        options.exclude.extend(self.exclude.iter().cloned());
+        if let Some(order) = self.order {
+            options.order = order;
+        }
+        options.lints.extend(self.lints.clone());
+        if let Some(manifest) = &self.manifest {
+            options.manifest = manifest.clone();
+        }
+        if self.passes.is_some() {
+            options.passes = self.passes.clone();
+        }
+    }
+}
+
+/// Per-project defaults read from a `vm-translator.toml` beside the
+/// sources, plus named profiles declared as `[profile.<name>]` tables.
+/// Command line flags override all of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Project {
+    pub settings: Settings,
+    pub profiles: BTreeMap<String, Settings>,
+}
+
+impl Default for Project {
+    // debug and release exist even without a project file, which can
+    // redefine them
+    fn default() -> Project {
+        let mut profiles = BTreeMap::new();
+        profiles.insert(
+            "debug".to_string(),
+            Settings {
+                opt_level: Some(0),
+                optimize_size: Some(false),
+                comments: Some(Comments::Source),
+                ..Settings::default()
+            },
+        );
+        profiles.insert(
+            "release".to_string(),
+            Settings {
+                opt_level: Some(2),
+                optimize_size: Some(false),
+                comments: Some(Comments::None),
+                ..Settings::default()
+            },
+        );
+        Project {
+            settings: Settings::default(),
+            profiles,
+        }
+    }
+}
+
+impl Project {
+    pub fn parse(text: &str) -> Result<Project> {
+        let table: Table = text.parse()?;
+        let mut project = Project::default();
+        for (key, value) in &table {
+            if key == "profile" {
+                let profiles = value
+                    .as_table()
+                    .ok_or_else(|| anyhow!("profile expects tables"))?;
+                for (name, table) in profiles {
+                    let table = table.as_table().ok_or_else(|| {
+                        anyhow!("profile.{} expects a table", name)
+                    })?;
+                    let mut settings = Settings::default();
+                    for (key, value) in table {
+                        if !settings.set(key, value)? {
+                            return Err(anyhow!(
+                                "unknown key: profile.{}.{}",
+                                name,
+                                key
+                            ));
+                        }
+                    }
+                    project.profiles.insert(name.to_string(), settings);
+                }
+            } else if !project.settings.set(key, value)? {
+                return Err(anyhow!("unknown key: {}", key));
+            }
+        }
+        Ok(project)
+    }
+
+    /// The project file in `input`'s directory (or in `input` itself, when
+    /// it is one), if there is one.
+    pub fn discover(input: &Path) -> Result<Option<(PathBuf, Project)>> {
+        let dir = if input.is_dir() {
+            input
+        } else {
+            match input.parent() {
+                Some(parent) => parent,
+                None => return Ok(None),
+            }
+        };
+        let path = dir.join(FILENAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("cannot read {}", path.display()))?;
+        let project = Project::parse(&text)
+            .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        Ok(Some((path, project)))
+    }
+
+    /// Apply the project's defaults, then the named profile's on top.
+    pub fn apply(
+        &self,
+        options: &mut Options,
+        profile: Option<&str>,
+    ) -> Result<()> {
+        self.settings.apply(options);
+        if let Some(name) = profile {
+            let names: Vec<&str> =
+                self.profiles.keys().map(String::as_str).collect();
+            self.profiles
+                .get(name)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "unknown profile: {} (expected one of {})",
+                        name,
+                        names.join(", ")
+                    )
+                })?
+                .apply(options);
+        }
+        Ok(())
+    }
+}
+
+pub fn opt_level(level: i64) -> Result<u8> {
+    match level {
+        0..=2 => Ok(level as u8),
+        _ => Err(anyhow!("unexpected optimization level: {}", level)),
+    }
+}
+
+fn register(address: i64) -> Result<u16> {
+    match address {
+        0..=32767 => Ok(address as u16),
+        _ => Err(anyhow!("unexpected register: {}", address)),
+    }
+}
+
+fn boolean(key: &str, value: &Value) -> Result<bool> {
+    value
+        .as_bool()
+        .ok_or_else(|| anyhow!("{} expects true or false", key))
+}
+
+fn integer(key: &str, value: &Value) -> Result<i64> {
+    match value.as_integer() {
+        Some(n) if n >= 0 => Ok(n),
+        _ => Err(anyhow!("{} expects a count", key)),
+    }
+}
+
+fn string<'a>(key: &str, value: &'a Value) -> Result<&'a str> {
+    value
+        .as_str()
+        .ok_or_else(|| anyhow!("{} expects a string", key))
+}
+
+/// Match a file name against a pattern where `*` stands for any run of
+/// characters and `?` for any one.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // where the last * started, and how much of the name it has taken
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // let the * swallow one more character and retry
+                Some((sp, sn)) => {
+                    star = Some((sp, sn + 1));
+                    p = sp + 1;
+                    n = sn + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
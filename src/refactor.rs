@@ -0,0 +1,76 @@
+use std::{collections::BTreeMap, ops::Range, path::PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{
+    parser::{FunctionToken, Line},
+    translator::Translator,
+};
+
+/// What renaming the function `old` to `new` across a parsed program
+/// changes: each file that declares or calls it, with its new text. Only
+/// the names are rewritten, so indentation, spacing and comments stay as
+/// they were.
+pub fn rename(
+    translator: &Translator,
+    old: &str,
+    new: &str,
+) -> Result<Vec<(PathBuf, String)>> {
+    Line::function(new, 0)
+        .with_context(|| format!("cannot rename {} to {}", old, new))?;
+
+    // each file's commands that name the function, by where they are
+    let mut found: BTreeMap<&str, Vec<Range<usize>>> = BTreeMap::new();
+    for parser in translator.parsers() {
+        for (line, span) in parser.lines().iter().zip(parser.spans()) {
+            let name = match line {
+                Line::Function(FunctionToken::Function { name, .. }) => {
+                    if name == new {
+                        return Err(anyhow!(
+                            "cannot rename {} to {}: {}:{} already defines it",
+                            old,
+                            new,
+                            span.file,
+                            span.line
+                        ));
+                    }
+                    name
+                }
+                Line::Function(FunctionToken::Call { name, .. }) => name,
+                _ => continue,
+            };
+            if name == old {
+                found
+                    .entry(&span.file)
+                    .or_default()
+                    .push(span.bytes.clone());
+            }
+        }
+    }
+    if found.is_empty() {
+        return Err(anyhow!("no function or call named {}", old));
+    }
+
+    let mut changes = Vec::new();
+    for (path, text) in translator.sources() {
+        let commands = match found.get(path.display().to_string().as_str()) {
+            Some(commands) => commands,
+            None => continue,
+        };
+        let mut renamed = String::with_capacity(text.len());
+        let mut copied = 0;
+        for command in commands {
+            // the name is the command's second word
+            let code = &text[command.clone()];
+            let word = code.find(char::is_whitespace).unwrap_or(code.len());
+            let start =
+                command.start + code.len() - code[word..].trim_start().len();
+            renamed.push_str(&text[copied..start]);
+            renamed.push_str(new);
+            copied = start + old.len();
+        }
+        renamed.push_str(&text[copied..]);
+        changes.push((path.to_path_buf(), renamed));
+    }
+    Ok(changes)
+}
@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+use crate::emulator::{Cpu, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// The screen as a binary PBM, black pixels set.
+pub fn pbm(cpu: &Cpu) -> Vec<u8> {
+    let mut image =
+        format!("P4\n{} {}\n", SCREEN_WIDTH, SCREEN_HEIGHT).into_bytes();
+    image.extend(rows(cpu, true).concat());
+    image
+}
+
+/// The screen as a 1-bit grayscale PNG.
+pub fn png(cpu: &Cpu) -> Vec<u8> {
+    // each row leads with its filter, none
+    let mut raw = Vec::new();
+    for row in rows(cpu, false) {
+        raw.push(0);
+        raw.extend(row);
+    }
+
+    let mut header = Vec::new();
+    header.extend((SCREEN_WIDTH as u32).to_be_bytes());
+    header.extend((SCREEN_HEIGHT as u32).to_be_bytes());
+    // bit depth 1, grayscale, then default compression, filter and no
+    // interlacing
+    header.extend([1, 0, 0, 0, 0]);
+
+    let mut image = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut image, b"IHDR", &header);
+    chunk(&mut image, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut image, b"IEND", &[]);
+    image
+}
+
+/// The screen in the format `path`'s extension names, .pbm or .png.
+pub fn render(cpu: &Cpu, path: &Path) -> Result<Vec<u8>> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("pbm") => Ok(pbm(cpu)),
+        Some("png") => Ok(png(cpu)),
+        _ => Err(anyhow!(
+            "screen image must be .pbm or .png (provided: {})",
+            path.display()
+        )),
+    }
+}
+
+// pixels 8 to a byte, leftmost in the high bit, set for black or for white
+fn rows(cpu: &Cpu, black: bool) -> Vec<Vec<u8>> {
+    (0..SCREEN_HEIGHT)
+        .map(|y| {
+            (0..SCREEN_WIDTH / 8)
+                .map(|byte| {
+                    (0..8).fold(0, |bits, bit| {
+                        let set = cpu.pixel(byte * 8 + bit, y) == black;
+                        bits | (set as u8) << (7 - bit)
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn chunk(image: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    image.extend((data.len() as u32).to_be_bytes());
+    let start = image.len();
+    image.extend(kind);
+    image.extend(data);
+    let crc = crc32(&image[start..]);
+    image.extend(crc.to_be_bytes());
+}
+
+// zlib framing around deflate's stored blocks, which are as good as any
+// for images this small
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(0xffff).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        out.push((i + 1 == blocks.len()) as u8);
+        let len = block.len() as u16;
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(*block);
+    }
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
@@ -0,0 +1,401 @@
+use anyhow::{anyhow, Result};
+
+use crate::emulator::Cpu;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Ram(usize),
+    Pc,
+    A,
+    D,
+}
+
+impl Target {
+    fn new(raw: &str) -> Result<Target> {
+        match raw {
+            "PC" => Ok(Target::Pc),
+            "A" => Ok(Target::A),
+            "D" => Ok(Target::D),
+            _ => raw
+                .strip_prefix("RAM[")
+                .and_then(|rest| rest.strip_suffix(']'))
+                .and_then(|address| address.parse().ok())
+                .filter(|address| *address < 0x8000)
+                .map(Target::Ram)
+                .ok_or_else(|| anyhow!("unsupported variable: {}", raw)),
+        }
+    }
+
+    fn get(&self, cpu: &Cpu) -> i16 {
+        match self {
+            Target::Ram(address) => cpu.ram[*address],
+            Target::Pc => cpu.pc as i16,
+            Target::A => cpu.a as i16,
+            Target::D => cpu.d,
+        }
+    }
+
+    fn set(&self, cpu: &mut Cpu, value: i16) {
+        match self {
+            Target::Ram(address) => cpu.ram[*address] = value,
+            Target::Pc => cpu.pc = value as u16 & 0x7fff,
+            Target::A => cpu.a = value as u16,
+            Target::D => cpu.d = value,
+        }
+    }
+}
+
+// one output-list entry, e.g. RAM[256]%D2.6.2
+#[derive(Debug, Clone)]
+struct Column {
+    name: String,
+    target: Target,
+    format: char,
+    left: usize,
+    width: usize,
+    right: usize,
+}
+
+impl Column {
+    fn new(raw: &str) -> Result<Column> {
+        let (name, format) = raw.split_once('%').unwrap_or((raw, "D1.6.1"));
+        let mut chars = format.chars();
+        let kind = chars.next().unwrap_or('D');
+        let padding: Vec<usize> = chars
+            .as_str()
+            .split('.')
+            .map(|n| n.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| anyhow!("invalid output format: {}", raw))?;
+        let (left, width, right) = match padding.as_slice() {
+            [left, width, right] => (*left, *width, *right),
+            _ => return Err(anyhow!("invalid output format: {}", raw)),
+        };
+        if !matches!(kind, 'D' | 'X' | 'B') {
+            return Err(anyhow!("unsupported output format: {}", raw));
+        }
+        Ok(Column {
+            name: name.to_string(),
+            target: Target::new(name)?,
+            format: kind,
+            left,
+            width,
+            right,
+        })
+    }
+
+    fn header(&self) -> String {
+        let total = self.left + self.width + self.right;
+        let name: String = self.name.chars().take(total).collect();
+        let before = (total - name.len()) / 2;
+        let after = total - name.len() - before;
+        format!("{}{}{}", " ".repeat(before), name, " ".repeat(after))
+    }
+
+    fn cell(&self, cpu: &Cpu) -> String {
+        let value = self.target.get(cpu);
+        let value = match self.format {
+            'X' => format!("{:04X}", value as u16),
+            'B' => format!("{:016b}", value as u16),
+            _ => value.to_string(),
+        };
+        format!(
+            "{}{:>width$}{}",
+            " ".repeat(self.left),
+            value,
+            " ".repeat(self.right),
+            width = self.width
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Command {
+    OutputList(Vec<Column>),
+    Set(Target, i16),
+    Repeat(u64, Vec<Command>),
+    Tick,
+    Tock,
+    Output,
+    // load, output-file, echo and friends don't change the result
+    Ignore,
+}
+
+/// A CPU emulator test script (.tst), restricted to what the VM
+/// translator tests use: set, repeat, ticktock and output.
+#[derive(Debug, Clone)]
+pub struct Script {
+    commands: Vec<Command>,
+    compare_to: Option<String>,
+}
+
+fn strip_comments(src: &str) -> String {
+    let mut out = String::new();
+    let mut rest = src;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("//") {
+            rest = after.find('\n').map_or("", |end| &after[end..]);
+        } else if let Some(after) = rest.strip_prefix("/*") {
+            rest = after.find("*/").map_or("", |end| &after[end + 2..]);
+            out.push(' ');
+        } else {
+            let c = rest.chars().next().unwrap();
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    out
+}
+
+// words, with the punctuation that ends statements and blocks kept as
+// tokens of its own
+fn tokenize(src: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    for c in strip_comments(src).chars() {
+        if c.is_whitespace() || matches!(c, ',' | ';' | '{' | '}') {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            if !c.is_whitespace() {
+                tokens.push(c.to_string());
+            }
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+fn parse_block(
+    tokens: &mut std::vec::IntoIter<String>,
+    compare_to: &mut Option<String>,
+    nested: bool,
+) -> Result<Vec<Command>> {
+    let mut commands = Vec::new();
+    loop {
+        // one statement: words up to a terminator
+        let mut words = Vec::new();
+        let terminator = loop {
+            match tokens.next() {
+                Some(t) if matches!(t.as_str(), "," | ";" | "{" | "}") => {
+                    break Some(t)
+                }
+                Some(t) => words.push(t),
+                None => break None,
+            }
+        };
+
+        match (words.first().map(String::as_str), terminator.as_deref()) {
+            (None, None) if !nested => return Ok(commands),
+            (None, None) => return Err(anyhow!("unclosed repeat block")),
+            (None, Some("}")) if nested => return Ok(commands),
+            (None, Some("}")) => return Err(anyhow!("unexpected }}")),
+            (None, _) => continue,
+            (Some("repeat"), Some("{")) => {
+                let count = words
+                    .get(1)
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(|| anyhow!("repeat expects a count"))?;
+                let body = parse_block(tokens, compare_to, true)?;
+                commands.push(Command::Repeat(count, body));
+            }
+            (Some(word), Some("{")) => {
+                return Err(anyhow!("unsupported block: {}", word))
+            }
+            (Some(_), terminator) => {
+                commands.push(parse_command(&words, compare_to)?);
+                match terminator {
+                    Some("}") if nested => return Ok(commands),
+                    Some("}") => return Err(anyhow!("unexpected }}")),
+                    None if nested => {
+                        return Err(anyhow!("unclosed repeat block"))
+                    }
+                    None => return Ok(commands),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+fn parse_command(
+    words: &[String],
+    compare_to: &mut Option<String>,
+) -> Result<Command> {
+    Ok(match words[0].as_str() {
+        "output-list" => Command::OutputList(
+            words[1..]
+                .iter()
+                .map(|w| Column::new(w))
+                .collect::<Result<_>>()?,
+        ),
+        "set" => {
+            let (target, value) = match words {
+                [_, target, value] => (target, value),
+                _ => return Err(anyhow!("set expects a variable and value")),
+            };
+            let value = value
+                .parse::<i32>()
+                .ok()
+                .filter(|v| (-32768..=65535).contains(v))
+                .ok_or_else(|| anyhow!("invalid value: {}", value))?;
+            Command::Set(Target::new(target)?, value as i16)
+        }
+        "compare-to" => {
+            *compare_to = words.get(1).cloned();
+            Command::Ignore
+        }
+        "ticktock" => Command::Repeat(1, vec![Command::Tick, Command::Tock]),
+        "tick" => Command::Tick,
+        "tock" => Command::Tock,
+        "output" => Command::Output,
+        "load" | "output-file" | "echo" | "clear-echo" | "breakpoint"
+        | "clear-breakpoints" => Command::Ignore,
+        word => return Err(anyhow!("unsupported command: {}", word)),
+    })
+}
+
+fn run_block(
+    commands: &[Command],
+    cpu: &mut Cpu,
+    columns: &mut Vec<Column>,
+    out: &mut String,
+) {
+    for command in commands {
+        match command {
+            Command::OutputList(list) => {
+                *columns = list.clone();
+                let header: Vec<String> =
+                    columns.iter().map(Column::header).collect();
+                out.push_str(&format!("|{}|\n", header.join("|")));
+            }
+            Command::Set(target, value) => target.set(cpu, *value),
+            Command::Repeat(count, body) => {
+                for _ in 0..*count {
+                    run_block(body, cpu, columns, out);
+                }
+            }
+            // the clock's rising edge changes nothing visible
+            Command::Tick => {}
+            Command::Tock => cpu.step(),
+            Command::Output => {
+                let cells: Vec<String> =
+                    columns.iter().map(|c| c.cell(cpu)).collect();
+                out.push_str(&format!("|{}|\n", cells.join("|")));
+            }
+            Command::Ignore => {}
+        }
+    }
+}
+
+impl Script {
+    pub fn parse(src: &str) -> Result<Script> {
+        let mut tokens = tokenize(src).into_iter();
+        let mut compare_to = None;
+        let commands = parse_block(&mut tokens, &mut compare_to, false)?;
+        Ok(Script {
+            commands,
+            compare_to,
+        })
+    }
+
+    // the .cmp file named by compare-to, if any
+    pub fn compare_to(&self) -> Option<&str> {
+        self.compare_to.as_deref()
+    }
+
+    /// Run the script against `cpu`, returning what it would write to its
+    /// .out file.
+    pub fn run(&self, cpu: &mut Cpu) -> String {
+        let mut out = String::new();
+        run_block(&self.commands, cpu, &mut Vec::new(), &mut out);
+        out
+    }
+}
+
+/// Machine state to print after a run, laid out as a script's output
+/// would be: a list such as `RAM[0..16],RAM[256..260],PC`, where ranges
+/// leave out their end and each entry can take a format, e.g.
+/// `RAM[256..258]%D2.6.2`.
+#[derive(Debug, Clone)]
+pub struct Dump {
+    columns: Vec<Column>,
+}
+
+impl Dump {
+    pub fn parse(list: &str) -> Result<Dump> {
+        let mut columns = Vec::new();
+        for entry in list.split(',').map(str::trim) {
+            let (name, format) = match entry.split_once('%') {
+                Some((name, format)) => (name, format),
+                None => (entry, "D1.6.1"),
+            };
+            let range = name
+                .strip_prefix("RAM[")
+                .and_then(|rest| rest.strip_suffix(']'))
+                .and_then(|range| range.split_once(".."));
+            let names = match range {
+                Some((start, end)) => {
+                    let start: usize = start.parse().map_err(|_| {
+                        anyhow!("unsupported variable: {}", name)
+                    })?;
+                    let end: usize = end.parse().map_err(|_| {
+                        anyhow!("unsupported variable: {}", name)
+                    })?;
+                    if start >= end {
+                        return Err(anyhow!("empty range: {}", name));
+                    }
+                    (start..end).map(|a| format!("RAM[{}]", a)).collect()
+                }
+                None => vec![name.to_string()],
+            };
+            for name in names {
+                columns.push(Column::new(&format!("{}%{}", name, format))?);
+            }
+        }
+        Ok(Dump { columns })
+    }
+
+    /// The header and a row of values, as a .out file has them.
+    pub fn render(&self, cpu: &Cpu) -> String {
+        let header: Vec<String> =
+            self.columns.iter().map(Column::header).collect();
+        let cells: Vec<String> =
+            self.columns.iter().map(|c| c.cell(cpu)).collect();
+        format!("|{}|\n|{}|\n", header.join("|"), cells.join("|"))
+    }
+}
+
+/// Compare script output against a .cmp file cell by cell, ignoring
+/// padding. The first differing line is reported as the error.
+pub fn compare(out: &str, cmp: &str) -> Result<()> {
+    let cells = |line: &str| -> Vec<String> {
+        line.trim()
+            .split('|')
+            .map(|cell| cell.trim().to_string())
+            .collect()
+    };
+    let mut out_lines = out.lines();
+    for (i, expected) in cmp.lines().enumerate() {
+        if expected.trim().is_empty() {
+            continue;
+        }
+        match out_lines.next() {
+            Some(actual) if cells(actual) == cells(expected) => {}
+            Some(actual) => {
+                return Err(anyhow!(
+                    "line {}: expected {} got {}",
+                    i + 1,
+                    expected.trim(),
+                    actual.trim()
+                ))
+            }
+            None => return Err(anyhow!("line {}: missing output", i + 1)),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use std::ops::Range;
+
+use crate::codegen::EXPLAIN;
+
+/// The stretch of generated asm that came from one VM command. `lines` are
+/// 1-based line numbers in the .asm file, `rom` the instruction addresses
+/// those lines occupy once assembled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    pub command: String,
+    pub lines: Range<usize>,
+    pub rom: Range<usize>,
+}
+
+/// Recover which VM command each part of a generated .asm file came from,
+/// using the `// <command>` marker the translator writes above each block.
+pub fn from_asm(asm: &str) -> Vec<Origin> {
+    let mut origins: Vec<Origin> = Vec::new();
+    let mut rom = 0;
+    let mut header = true;
+
+    for (i, raw) in asm.lines().enumerate() {
+        let line = i + 1;
+        let raw = raw.trim();
+
+        if raw.is_empty() {
+            header = false;
+            continue;
+        }
+
+        if let Some(comment) = raw.strip_prefix("//") {
+            // --explain lines belong to the marker above them
+            if raw.starts_with(EXPLAIN.trim_end()) {
+                if let Some(origin) = origins.last_mut() {
+                    origin.lines.end = line + 1;
+                }
+                continue;
+            }
+            // the banner at the top of the file isn't a command
+            if !header {
+                origins.push(Origin {
+                    command: comment.trim().to_string(),
+                    lines: line..line + 1,
+                    rom: rom..rom,
+                });
+            }
+            continue;
+        }
+        header = false;
+
+        // anything ahead of the first marker was not written for a command
+        if origins.is_empty() {
+            origins.push(Origin {
+                command: String::new(),
+                lines: line..line,
+                rom: rom..rom,
+            });
+        }
+
+        // labels don't take up an address
+        if !raw.starts_with('(') {
+            rom += 1;
+        }
+
+        let origin = origins.last_mut().unwrap();
+        origin.lines.end = line + 1;
+        origin.rom.end = rom;
+    }
+
+    origins
+}
@@ -0,0 +1,396 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    grade::csv_field,
+    instruction::Instruction,
+    parser::{FunctionToken, Line, Segment, StackToken},
+};
+
+// words of ROM on the Hack platform
+const ROM_SIZE: usize = 32768;
+
+/// What translating one file produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStats {
+    pub file: String,
+    pub commands: usize,
+    pub instructions: usize,
+    // distinct static indices used
+    pub statics: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub files: Vec<FileStats>,
+    // every instruction written, the bootstrap and end loop included
+    pub rom: usize,
+    // how often each VM command appears
+    pub commands: BTreeMap<&'static str, usize>,
+    // how often each form of Hack instruction was generated; see `forms`
+    pub forms: BTreeMap<&'static str, usize>,
+}
+
+/// The forms one line of generated asm counts towards. A C-instruction
+/// can count towards several, e.g. `M=M+1` both reads and writes memory.
+pub fn forms(line: &str) -> Vec<&'static str> {
+    let code = line.split("//").next().unwrap_or("").trim();
+    if code.starts_with('(') {
+        return vec!["label"];
+    }
+    if let Some(value) = code.strip_prefix('@') {
+        return match value.starts_with(|c: char| c.is_ascii_digit()) {
+            true => vec!["A-instruction (constant)"],
+            false => vec!["A-instruction (symbol)"],
+        };
+    }
+
+    let mut forms = vec!["C-instruction"];
+    let (dest, rest) = code.split_once('=').unwrap_or(("", code));
+    let (comp, jump) = rest.split_once(';').unwrap_or((rest, ""));
+    if comp.contains('M') {
+        forms.push("memory read");
+    }
+    if dest.contains('M') {
+        forms.push("memory write");
+    }
+    if !jump.trim().is_empty() {
+        forms.push("jump");
+    }
+    forms
+}
+
+/// Like `forms`, for an instruction codegen made.
+pub fn instruction_forms(instruction: &Instruction) -> Vec<&'static str> {
+    match instruction {
+        Instruction::Label(_) => vec!["label"],
+        Instruction::A(value) => {
+            match value.starts_with(|c: char| c.is_ascii_digit()) {
+                true => vec!["A-instruction (constant)"],
+                false => vec!["A-instruction (symbol)"],
+            }
+        }
+        Instruction::C { dest, comp, jump } => {
+            let mut forms = vec!["C-instruction"];
+            if comp.reads('M') {
+                forms.push("memory read");
+            }
+            if dest.writes('M') {
+                forms.push("memory write");
+            }
+            if jump.bits() != 0 {
+                forms.push("jump");
+            }
+            forms
+        }
+    }
+}
+
+fn histogram_csv(what: &str, counts: &BTreeMap<&str, usize>) -> String {
+    let mut buf = format!("{},count\n", what);
+    for (name, count) in counts {
+        buf.push_str(&format!("{},{}\n", csv_field(name), count));
+    }
+    buf
+}
+
+fn histogram_markdown(what: &str, counts: &BTreeMap<&str, usize>) -> String {
+    let mut buf = format!("| {} | count |\n| --- | ---: |\n", what);
+    for (name, count) in counts {
+        buf.push_str(&format!("| {} | {} |\n", name, count));
+    }
+    buf
+}
+
+impl Stats {
+    pub fn to_csv(&self) -> String {
+        let mut buf = "file,commands,instructions,statics\n".to_string();
+        for file in &self.files {
+            buf.push_str(&format!(
+                "{},{},{},{}\n",
+                csv_field(&file.file),
+                file.commands,
+                file.instructions,
+                file.statics
+            ));
+        }
+        buf.push_str(&format!("rom,,{},\n", self.rom));
+        // one table after another, each with its own header
+        buf.push('\n');
+        buf.push_str(&histogram_csv("command", &self.commands));
+        buf.push('\n');
+        buf.push_str(&histogram_csv("instruction", &self.forms));
+        buf
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut buf = "| file | commands | instructions | statics |\n\
+                       | --- | ---: | ---: | ---: |\n"
+            .to_string();
+        for file in &self.files {
+            buf.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                file.file.replace('|', "\\|"),
+                file.commands,
+                file.instructions,
+                file.statics
+            ));
+        }
+        buf.push_str(&format!(
+            "\nROM: {} of {} words ({:.1}%)\n",
+            self.rom,
+            ROM_SIZE,
+            self.rom as f64 * 100.0 / ROM_SIZE as f64
+        ));
+        buf.push('\n');
+        buf.push_str(&histogram_markdown("command", &self.commands));
+        buf.push('\n');
+        buf.push_str(&histogram_markdown("instruction", &self.forms));
+        buf
+    }
+}
+
+/// Where the cycles of an emulator run went, at one cycle per instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timing {
+    pub cycles: u64,
+    // for each VM command word and each function, how many times its code
+    // was entered and the cycles spent in its own code
+    pub commands: BTreeMap<&'static str, (u64, u64)>,
+    pub functions: BTreeMap<String, (u64, u64)>,
+    // the costliest commands, where they are in the source and the cycles
+    // spent in them, most first
+    pub hottest: Vec<(String, u64)>,
+}
+
+// the costliest commands a timing report lists
+pub const HOTTEST: usize = 10;
+
+// a name, its runs and its cycles
+type Row<'a> = (&'a str, u64, u64);
+
+fn timing_csv(what: &str, rows: &[Row]) -> String {
+    let mut buf = format!("{},runs,cycles,average\n", what);
+    for (name, runs, cycles) in rows {
+        buf.push_str(&format!(
+            "{},{},{},{:.1}\n",
+            csv_field(name),
+            runs,
+            cycles,
+            average(*runs, *cycles)
+        ));
+    }
+    buf
+}
+
+fn timing_markdown(what: &str, rows: &[Row]) -> String {
+    let mut buf = format!(
+        "| {} | runs | cycles | average |\n| --- | ---: | ---: | ---: |\n",
+        what
+    );
+    for (name, runs, cycles) in rows {
+        buf.push_str(&format!(
+            "| {} | {} | {} | {:.1} |\n",
+            name.replace('|', "\\|"),
+            runs,
+            cycles,
+            average(*runs, *cycles)
+        ));
+    }
+    buf
+}
+
+fn average(runs: u64, cycles: u64) -> f64 {
+    match runs {
+        0 => 0.0,
+        _ => cycles as f64 / runs as f64,
+    }
+}
+
+impl Timing {
+    // commands and functions as (name, runs, cycles) rows
+    fn tables(&self) -> [(&'static str, Vec<Row<'_>>); 2] {
+        let commands = self
+            .commands
+            .iter()
+            .map(|(name, (runs, cycles))| (*name, *runs, *cycles))
+            .collect();
+        let functions = self
+            .functions
+            .iter()
+            .map(|(name, (runs, cycles))| (name.as_str(), *runs, *cycles))
+            .collect();
+        [("command", commands), ("function", functions)]
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut buf = format!("cycles\n{}\n", self.cycles);
+        for (what, rows) in &self.tables() {
+            buf.push('\n');
+            buf.push_str(&timing_csv(what, rows));
+        }
+        buf.push_str("\nlocation,cycles\n");
+        for (location, cycles) in &self.hottest {
+            buf.push_str(&format!("{},{}\n", csv_field(location), cycles));
+        }
+        buf
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut buf = format!("{} cycles\n", self.cycles);
+        for (what, rows) in &self.tables() {
+            buf.push('\n');
+            buf.push_str(&timing_markdown(what, rows));
+        }
+        buf.push_str("\n| location | cycles |\n| --- | ---: |\n");
+        for (location, cycles) in &self.hottest {
+            buf.push_str(&format!(
+                "| {} | {} |\n",
+                location.replace('|', "\\|"),
+                cycles
+            ));
+        }
+        buf
+    }
+}
+
+/// Which VM commands an emulator run reached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coverage {
+    // file, line, command and how many times it ran, in source order
+    pub commands: Vec<(String, usize, String, u64)>,
+}
+
+impl Coverage {
+    /// How many of the commands ran at all.
+    pub fn covered(&self) -> usize {
+        self.commands.iter().filter(|command| command.3 > 0).count()
+    }
+
+    /// A summary, then every command that never ran.
+    pub fn to_text(&self) -> String {
+        let total = self.commands.len();
+        let mut buf = format!(
+            "{} of {} VM commands run ({:.1}%)\n",
+            self.covered(),
+            total,
+            match total {
+                0 => 100.0,
+                _ => self.covered() as f64 * 100.0 / total as f64,
+            }
+        );
+        for (file, line, command, runs) in &self.commands {
+            if *runs == 0 {
+                buf.push_str(&format!(
+                    "{}:{}: never run: {}\n",
+                    file, line, command
+                ));
+            }
+        }
+        buf
+    }
+
+    /// `file`'s `text` with each line led by how many times its command
+    /// ran, `#####` for a command that never ran and `-` for a line
+    /// without one, as gcov does.
+    pub fn annotate(&self, file: &str, text: &str) -> String {
+        let mut runs = BTreeMap::new();
+        for (_, line, _, count) in
+            self.commands.iter().filter(|command| command.0 == file)
+        {
+            runs.insert(*line, *count);
+        }
+        let mut buf = String::new();
+        for (i, line) in text.lines().enumerate() {
+            let count = match runs.get(&(i + 1)) {
+                Some(0) => "#####".to_string(),
+                Some(count) => count.to_string(),
+                None => "-".to_string(),
+            };
+            buf.push_str(&format!("{:>9}:{:>5}:{}\n", count, i + 1, line));
+        }
+        buf
+    }
+}
+
+/// Where each function is defined and called, and which functions use
+/// each static.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Xref {
+    // by name, where it's defined and where it's called, as file:line
+    pub functions: BTreeMap<String, (Vec<String>, Vec<String>)>,
+    // by file and index, the functions that read it and those that write it
+    pub statics: BTreeMap<(String, u16), (BTreeSet<String>, BTreeSet<String>)>,
+}
+
+// what commands before the first function are counted under
+const TOP_LEVEL: &str = "(top level)";
+
+impl Xref {
+    /// Count `line`, at `location` and inside `function`, if it defines,
+    /// calls or uses anything.
+    pub fn add(
+        &mut self,
+        file: &str,
+        function: Option<&str>,
+        line: &Line,
+        location: Option<String>,
+    ) {
+        let function = function.unwrap_or(TOP_LEVEL).to_string();
+        match line {
+            Line::Function(FunctionToken::Function { name, .. }) => {
+                let entry = self.functions.entry(name.clone()).or_default();
+                entry.0.extend(location);
+            }
+            Line::Function(FunctionToken::Call { name, .. }) => {
+                let entry = self.functions.entry(name.clone()).or_default();
+                entry.1.extend(location);
+            }
+            Line::Stack(StackToken::Push {
+                segment: Segment::Static,
+                index,
+            }) => {
+                let key = (file.to_string(), *index);
+                self.statics.entry(key).or_default().0.insert(function);
+            }
+            Line::Stack(StackToken::Pop {
+                segment: Segment::Static,
+                index,
+            }) => {
+                let key = (file.to_string(), *index);
+                self.statics.entry(key).or_default().1.insert(function);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let cell = |items: Vec<&str>| match items.is_empty() {
+            true => "-".to_string(),
+            false => items.join(", ").replace('|', "\\|"),
+        };
+        let mut buf = "| function | defined | called from |\n\
+                       | --- | --- | --- |\n"
+            .to_string();
+        for (name, (defined, called)) in &self.functions {
+            buf.push_str(&format!(
+                "| {} | {} | {} |\n",
+                name.replace('|', "\\|"),
+                cell(defined.iter().map(String::as_str).collect()),
+                cell(called.iter().map(String::as_str).collect()),
+            ));
+        }
+        buf.push_str(
+            "\n| static | read by | written by |\n| --- | --- | --- |\n",
+        );
+        for ((file, index), (read, written)) in &self.statics {
+            buf.push_str(&format!(
+                "| {}.{} | {} | {} |\n",
+                file.replace('|', "\\|"),
+                index,
+                cell(read.iter().map(String::as_str).collect()),
+                cell(written.iter().map(String::as_str).collect()),
+            ));
+        }
+        buf
+    }
+}
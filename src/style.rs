@@ -0,0 +1,100 @@
+use std::fmt::Write;
+
+use anyhow::{anyhow, Result};
+
+use crate::instruction::Instruction;
+
+/// Whether a separator in a C-instruction has spaces around it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spacing {
+    Tight,
+    Spaced,
+}
+
+impl Spacing {
+    pub fn new(raw: &str) -> Result<Spacing> {
+        match raw {
+            "tight" => Ok(Spacing::Tight),
+            "spaced" => Ok(Spacing::Spaced),
+            _ => Err(anyhow!("unexpected spacing: {}", raw)),
+        }
+    }
+}
+
+/// The most blank lines there can be between blocks.
+pub const MAX_BLANK_LINES: usize = 8;
+
+/// How the generated asm is laid out as text, which the assembler doesn't
+/// care about. All of the output's instructions are written through here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    // around the = of D=M
+    pub dest: Spacing,
+    // after the ; of 0; JMP
+    pub jump: Spacing,
+    // instructions indented under the labels between them
+    pub indent: bool,
+    // between blocks; by default two when they're commented and none
+    // otherwise
+    pub blank_lines: Option<usize>,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style {
+            dest: Spacing::Tight,
+            jump: Spacing::Spaced,
+            indent: false,
+            blank_lines: None,
+        }
+    }
+}
+
+impl Style {
+    /// As short as the instructions can be written.
+    pub const MINIFIED: Style = Style {
+        dest: Spacing::Tight,
+        jump: Spacing::Tight,
+        indent: false,
+        blank_lines: Some(0),
+    };
+
+    pub fn blank_lines(&self, commented: bool) -> usize {
+        self.blank_lines.unwrap_or(if commented { 2 } else { 0 })
+    }
+
+    /// What goes before the instruction on its line.
+    pub fn indent(&self, instruction: &Instruction) -> &'static str {
+        match self.indent && !instruction.is_label() {
+            true => "    ",
+            false => "",
+        }
+    }
+
+    /// Write the instruction itself, without its indentation.
+    pub fn write(&self, buf: &mut String, instruction: &Instruction) {
+        let (dest, comp, jump) = match instruction {
+            Instruction::C { dest, comp, jump } => (dest, comp, jump),
+            // writing to a String can't fail
+            _ => {
+                let _ = write!(buf, "{}", instruction);
+                return;
+            }
+        };
+        if dest.bits() != 0 {
+            buf.push_str(dest.name());
+            buf.push_str(match self.dest {
+                Spacing::Tight => "=",
+                Spacing::Spaced => " = ",
+            });
+        }
+        buf.push_str(comp.name());
+        if jump.bits() != 0 {
+            buf.push_str(match self.jump {
+                Spacing::Tight => ";",
+                Spacing::Spaced => "; ",
+            });
+            buf.push_str(jump.name());
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::{collections::HashMap, io::Write};
+
+use anyhow::Result;
+
+use crate::{
+    interpreter::{Interpreter, ARG, LCL, SP, STACK, TEMP, THAT, THIS},
+    parser::{Line, Parser, Segment, StackToken},
+};
+
+// how much of the top of the stack a diagram shows
+const STACK_SHOWN: usize = 12;
+
+// the words of a saved frame, from the lowest
+const SAVED: [&str; 5] = [
+    "return address",
+    "saved LCL",
+    "saved ARG",
+    "saved THIS",
+    "saved THAT",
+];
+
+/// Run a program a command at a time, up to `steps` commands, drawing the
+/// stack and segments after each as plain text for pasting into a
+/// write-up.
+pub fn run(parsers: &[Parser], steps: u64, out: &mut impl Write) -> Result<()> {
+    let used = used(parsers);
+    let mut vm = Interpreter::new(parsers)?;
+
+    writeln!(out, "start")?;
+    diagram(&vm, &used, 0, out)?;
+    while vm.steps < steps {
+        let command = match vm.next_command() {
+            Some(command) => command.clone(),
+            None => break,
+        };
+        vm.step()?;
+        let text = match &command.raw {
+            Some(raw) => raw.trim().to_string(),
+            None => command.line.to_string(),
+        };
+        match &command.span {
+            Some(span) => writeln!(
+                out,
+                "\n{}. {}  ({}:{})",
+                vm.steps, text, span.file, span.line
+            )?,
+            None => writeln!(out, "\n{}. {}", vm.steps, text)?,
+        }
+        diagram(&vm, &used, command.file, out)?;
+    }
+
+    match vm.stopped() {
+        Some(why) => writeln!(out, "\nstopped: {}", why)?,
+        None => writeln!(out, "\nstopped after {} commands", steps)?,
+    }
+    Ok(())
+}
+
+// the words of each segment the program touches, and of each file's
+// statics: one past the highest index used
+fn used(parsers: &[Parser]) -> HashMap<(&'static str, usize), usize> {
+    let mut used = HashMap::new();
+    for (file, parser) in parsers.iter().enumerate() {
+        for line in parser.lines() {
+            let (segment, index) = match line {
+                Line::Stack(StackToken::Push { segment, index })
+                | Line::Stack(StackToken::Pop { segment, index }) => {
+                    (segment, *index as usize)
+                }
+                _ => continue,
+            };
+            // only statics differ from file to file
+            let file = match segment {
+                Segment::Static => file,
+                _ => 0,
+            };
+            let words = used.entry((segment.name(), file)).or_insert(0);
+            *words = (*words).max(index + 1);
+        }
+    }
+    used
+}
+
+fn diagram(
+    vm: &Interpreter,
+    used: &HashMap<(&'static str, usize), usize>,
+    file: usize,
+    out: &mut impl Write,
+) -> Result<()> {
+    let ram = &vm.ram;
+    let sp = (ram[SP].max(0) as usize).min(ram.len());
+
+    // what each stack word is, when it's more than a value
+    let mut notes: HashMap<usize, String> = HashMap::new();
+    for frame in vm.frames() {
+        for (i, saved) in SAVED.iter().enumerate() {
+            let address = frame.lcl - SAVED.len() + i;
+            notes.insert(address, format!("{} ({})", saved, frame.function));
+        }
+    }
+    for &(pointer, name) in &[(ARG, "ARG"), (LCL, "LCL")] {
+        let address = ram[pointer].max(0) as usize;
+        let note = notes.entry(address).or_default();
+        *note = match note.is_empty() {
+            true => format!("<- {}", name),
+            false => format!("<- {} {}", name, note),
+        };
+    }
+
+    writeln!(out, "        +--------+")?;
+    let bottom = STACK.max(sp.saturating_sub(STACK_SHOWN));
+    for address in (bottom..sp).rev() {
+        let note = notes.get(&address).map(String::as_str).unwrap_or("");
+        let line = format!("  {:>5} | {:>6} | {}", address, ram[address], note);
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    if bottom > STACK {
+        writeln!(out, "    ... |    ... |")?;
+    }
+    writeln!(out, "        +--------+")?;
+    writeln!(
+        out,
+        "  SP={} LCL={} ARG={} THIS={} THAT={}",
+        ram[SP], ram[LCL], ram[ARG], ram[THIS], ram[THAT]
+    )?;
+
+    // the current call's own locals and arguments, where it's known
+    let frame = vm.frames().last();
+    let segments = [
+        (
+            "local",
+            ram[LCL].max(0) as usize,
+            frame.map(|f| f.locals as usize),
+        ),
+        (
+            "argument",
+            ram[ARG].max(0) as usize,
+            frame.map(|f| f.args as usize),
+        ),
+        ("this", ram[THIS].max(0) as usize, None),
+        ("that", ram[THAT].max(0) as usize, None),
+        ("temp", TEMP, None),
+        ("static", vm.static_base(file), None),
+    ];
+    for (name, base, words) in segments.iter().copied() {
+        let key = (name, if name == "static" { file } else { 0 });
+        let words =
+            words.unwrap_or_else(|| used.get(&key).copied().unwrap_or(0));
+        // this and that mean nothing until they're pointed somewhere
+        if words == 0 || (base == 0 && matches!(name, "this" | "that")) {
+            continue;
+        }
+        let values: Vec<String> = ram
+            .iter()
+            .skip(base)
+            .take(words)
+            .map(i16::to_string)
+            .collect();
+        writeln!(out, "  {:<8} {:>5} [{}]", name, base, values.join(", "))?;
+    }
+    Ok(())
+}
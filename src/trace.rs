@@ -0,0 +1,202 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::emulator::MEMORY;
+
+// the start of every trace file
+const MAGIC: &[u8; 4] = b"N2TT";
+
+// bytes per step in a trace file
+const ENTRY: usize = 12;
+
+// the address written by a step that wrote nothing
+const NO_WRITE: u16 = 0xffff;
+
+/// One executed instruction: where it was, the stack after it, and the
+/// RAM word it wrote, if any.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub pc: u16,
+    pub sp: i16,
+    // the word below SP
+    pub top: i16,
+    // address, old value, new value
+    pub write: Option<(u16, i16, i16)>,
+}
+
+/// Every step of a run, with the RAM it started from, so that RAM can be
+/// rebuilt at any point of the run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    // the words of RAM that weren't zero when recording began
+    initial: Vec<(u16, i16)>,
+    pub entries: Vec<Entry>,
+}
+
+impl Trace {
+    pub fn new(ram: &[i16]) -> Trace {
+        let initial = ram
+            .iter()
+            .enumerate()
+            .filter(|&(_, &value)| value != 0)
+            .map(|(address, &value)| (address as u16, value))
+            .collect();
+        Trace {
+            initial,
+            entries: Vec::new(),
+        }
+    }
+
+    /// The steps that changed `address`, by their index.
+    pub fn changes(
+        &self,
+        address: u16,
+    ) -> impl Iterator<Item = (usize, &Entry)> + '_ {
+        self.entries.iter().enumerate().filter(move |(_, entry)| {
+            match entry.write {
+                Some((at, old, new)) => at == address && old != new,
+                None => false,
+            }
+        })
+    }
+
+    // a count and that many (address, value) pairs, then the steps, all
+    // little-endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend((self.initial.len() as u32).to_le_bytes());
+        for &(address, value) in &self.initial {
+            bytes.extend(address.to_le_bytes());
+            bytes.extend(value.to_le_bytes());
+        }
+        for entry in &self.entries {
+            let (address, old, new) = entry.write.unwrap_or((NO_WRITE, 0, 0));
+            bytes.extend(entry.pc.to_le_bytes());
+            bytes.extend(entry.sp.to_le_bytes());
+            bytes.extend(entry.top.to_le_bytes());
+            bytes.extend(address.to_le_bytes());
+            bytes.extend(old.to_le_bytes());
+            bytes.extend(new.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Trace> {
+        let rest = bytes
+            .strip_prefix(MAGIC)
+            .ok_or_else(|| anyhow!("not a trace"))?;
+        let word = |at: &[u8], i: usize| u16::from_le_bytes([at[i], at[i + 1]]);
+        let count = match rest.get(..4) {
+            Some(&[a, b, c, d]) => u32::from_le_bytes([a, b, c, d]) as usize,
+            _ => return Err(anyhow!("truncated trace")),
+        };
+        let (initial, steps) = rest[4..]
+            .split_at_checked(count * 4)
+            .ok_or_else(|| anyhow!("truncated trace"))?;
+        if steps.len() % ENTRY != 0 {
+            return Err(anyhow!("truncated trace"));
+        }
+
+        let initial = initial
+            .chunks(4)
+            .map(|pair| (word(pair, 0), word(pair, 2) as i16))
+            .collect();
+        let entries = steps
+            .chunks(ENTRY)
+            .map(|step| Entry {
+                pc: word(step, 0),
+                sp: word(step, 2) as i16,
+                top: word(step, 4) as i16,
+                write: match word(step, 6) {
+                    NO_WRITE => None,
+                    address => Some((
+                        address,
+                        word(step, 8) as i16,
+                        word(step, 10) as i16,
+                    )),
+                },
+            })
+            .collect();
+        Ok(Trace { initial, entries })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Trace> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)
+            .with_context(|| format!("cannot read {}", path.display()))?;
+        Trace::from_bytes(&bytes)
+            .with_context(|| format!("cannot load {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        fs::write(path, self.to_bytes())
+            .with_context(|| format!("cannot write {}", path.display()))
+    }
+}
+
+/// A position in a trace that moves a step at a time either way, with
+/// RAM as it was at that point.
+#[derive(Debug)]
+pub struct Replay<'a> {
+    trace: &'a Trace,
+    ram: Vec<i16>,
+    // steps run so far
+    at: usize,
+}
+
+impl<'a> Replay<'a> {
+    /// Start before the first step.
+    pub fn new(trace: &'a Trace) -> Replay<'a> {
+        let mut ram = vec![0; MEMORY];
+        for &(address, value) in &trace.initial {
+            ram[address as usize] = value;
+        }
+        Replay { trace, ram, at: 0 }
+    }
+
+    pub fn at(&self) -> usize {
+        self.at
+    }
+
+    pub fn ram(&self) -> &[i16] {
+        &self.ram
+    }
+
+    /// The step run last, if any has been.
+    pub fn entry(&self) -> Option<&Entry> {
+        self.at.checked_sub(1).map(|at| &self.trace.entries[at])
+    }
+
+    /// Run up to `steps` further, stopping at the end of the trace.
+    pub fn forward(&mut self, steps: usize) {
+        let end = (self.at + steps).min(self.trace.entries.len());
+        for entry in &self.trace.entries[self.at..end] {
+            if let Some((address, _, new)) = entry.write {
+                self.ram[address as usize] = new;
+            }
+        }
+        self.at = end;
+    }
+
+    /// Undo up to `steps`, stopping at the start of the trace.
+    pub fn back(&mut self, steps: usize) {
+        let start = self.at.saturating_sub(steps);
+        for entry in self.trace.entries[start..self.at].iter().rev() {
+            if let Some((address, old, _)) = entry.write {
+                self.ram[address as usize] = old;
+            }
+        }
+        self.at = start;
+    }
+
+    /// Go to where `at` steps have run.
+    pub fn seek(&mut self, at: usize) {
+        if at < self.at {
+            self.back(self.at - at);
+        } else {
+            self.forward(at - self.at);
+        }
+    }
+}
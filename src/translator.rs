@@ -1,61 +1,2137 @@
 use std::{
-    env,
-    fs::{self, File},
-    io::{BufRead, BufReader},
-    path::Path,
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsString,
+    fmt::Write,
+    fs, io,
+    ops::Range,
+    panic,
+    path::{Path, PathBuf},
+    process, str,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
 };
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+use memmap2::Mmap;
+use serde_json::{json, Value};
+use similar::TextDiff;
 
-use crate::parser::Parser;
+use crate::{
+    assembler,
+    codegen::{self, Asm, CodeGen, Registers, Routine},
+    diagnostics::{Class, Diagnostic, Report},
+    glob, html,
+    instruction::Instruction,
+    lint::{self, Baseline, Level},
+    optimize,
+    parser::{
+        ExtensionToken, FunctionToken, IrFormat, Line, Parser, Segment, Span,
+        StackToken,
+    },
+    passes, project,
+    sourcemap::Origin,
+    stats::{self, Coverage, FileStats, Stats, Timing, Xref},
+    style::Style,
+    verify,
+};
+#[cfg(feature = "plugins")]
+use crate::{diagnostics::Severity, plugin::Plugin};
+
+const HEADER: &str = "\
+// Hack ASM (for nand2tetris book) generated from VM code
+// by Brent Hamilton <github.com/distrill/n2t-vm-translator>
+";
+
+/// The fewest commands a file has for its codegen to be split between
+/// threads; below this, starting them costs more than they save.
+pub const PARALLEL_MIN: usize = 4096;
+
+// source text is either read into memory or mapped straight from the file
+#[derive(Debug)]
+enum Text {
+    Read(String),
+    Mapped(Mmap),
+}
+
+#[derive(Debug)]
+struct Source {
+    name: String,
+    path: PathBuf,
+    text: Text,
+}
+
+impl Source {
+    fn new(path: &Path, mmap: bool) -> Result<Source> {
+        let text = if mmap {
+            let file = fs::File::open(path)
+                .with_context(|| format!("cannot read {}", path.display()))?;
+            // the map is only valid while nobody truncates the file under us,
+            // which is the usual caveat of reading a file being edited
+            Text::Mapped(unsafe { Mmap::map(&file)? })
+        } else {
+            Text::Read(
+                fs::read_to_string(path).with_context(|| {
+                    format!("cannot read {}", path.display())
+                })?,
+            )
+        };
+
+        // the stem namespaces statics, so it has to make a valid symbol
+        // whatever the file is called
+        let stem = path
+            .file_stem()
+            .ok_or_else(|| anyhow!("not a file: {}", path.display()))?;
+        let name = stem
+            .to_string_lossy()
+            .chars()
+            .map(|c| match c {
+                c if c.is_ascii_alphanumeric() || "_.$:".contains(c) => c,
+                _ => '_',
+            })
+            .collect();
+
+        Ok(Source {
+            name,
+            path: path.to_path_buf(),
+            text,
+        })
+    }
+
+    // for diagnostics and spans
+    fn display(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    // mapped bytes are checked for UTF-8 but not copied
+    fn text(&self) -> Result<&str> {
+        match &self.text {
+            Text::Read(text) => Ok(text),
+            Text::Mapped(map) => str::from_utf8(map).map_err(|e| {
+                anyhow!("{}: invalid UTF-8: {}", self.path.display(), e)
+            }),
+        }
+    }
+}
+
+/// What goes in the comment above each block of generated asm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comments {
+    // the VM command as written
+    Source,
+    // the VM command as the parser understood it
+    Canonical,
+    // no comments, header or blank lines at all
+    None,
+}
+
+impl Comments {
+    pub fn new(raw: &str) -> Result<Comments> {
+        match raw {
+            "source" => Ok(Comments::Source),
+            "canonical" => Ok(Comments::Canonical),
+            "none" => Ok(Comments::None),
+            _ => Err(anyhow!("unexpected comment style: {}", raw)),
+        }
+    }
+}
+
+/// Whether generated asm is annotated with ROM addresses, for setting
+/// breakpoints in the CPU emulator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Addresses {
+    Off,
+    // after each block's comment
+    Block,
+    // after every instruction; labels take no address
+    Instruction,
+}
+
+impl Addresses {
+    pub fn new(raw: &str) -> Result<Addresses> {
+        match raw {
+            "off" => Ok(Addresses::Off),
+            "block" => Ok(Addresses::Block),
+            "instruction" => Ok(Addresses::Instruction),
+            _ => Err(anyhow!("unexpected address style: {}", raw)),
+        }
+    }
+}
+
+/// Line endings of the generated asm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Newline {
+    Lf,
+    Crlf,
+}
+
+impl Newline {
+    // native is whatever the platform this runs on uses
+    pub fn new(raw: &str) -> Result<Newline> {
+        match raw {
+            "lf" => Ok(Newline::Lf),
+            "crlf" => Ok(Newline::Crlf),
+            "native" if cfg!(windows) => Ok(Newline::Crlf),
+            "native" => Ok(Newline::Lf),
+            _ => Err(anyhow!("unexpected newline style: {}", raw)),
+        }
+    }
+}
+
+/// The order a program's files, and so their functions, go into ROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    // as the source gives them: a directory's files by name, a pattern's
+    // by path
+    Input,
+    // by file name, whichever directory they're in
+    Alpha,
+    // Sys.vm, then the rest by file name
+    SysFirst,
+    // as a project file's manifest lists them, then any it leaves out
+    Manifest,
+}
+
+impl Order {
+    pub fn new(raw: &str) -> Result<Order> {
+        match raw {
+            "input" => Ok(Order::Input),
+            "alpha" => Ok(Order::Alpha),
+            "sys-first" => Ok(Order::SysFirst),
+            "manifest" => Ok(Order::Manifest),
+            _ => Err(anyhow!("unexpected file order: {}", raw)),
+        }
+    }
+
+    /// Put the paths of a program's files in this order; `manifest` lists
+    /// file names.
+    pub fn sort(
+        &self,
+        paths: &mut [PathBuf],
+        manifest: &[String],
+    ) -> Result<()> {
+        let name =
+            |path: &PathBuf| path.file_name().unwrap_or_default().to_owned();
+        match self {
+            Order::Input => {}
+            Order::Alpha => paths.sort_by_key(name),
+            Order::SysFirst => {
+                paths.sort_by_key(name);
+                paths.sort_by_key(|path| name(path) != "Sys.vm");
+            }
+            Order::Manifest => {
+                if manifest.is_empty() {
+                    return Err(anyhow!(
+                        "ordering by manifest needs a manifest in the \
+                         project file"
+                    ));
+                }
+                for listed in manifest {
+                    if !paths.iter().any(|path| name(path) == listed.as_str()) {
+                        return Err(anyhow!(
+                            "the manifest lists {}, which isn't in the program",
+                            listed
+                        ));
+                    }
+                }
+                paths.sort_by_key(|path| {
+                    let name = name(path);
+                    manifest
+                        .iter()
+                        .position(|listed| name == listed.as_str())
+                        .unwrap_or(manifest.len())
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How an existing output is kept before it's overwritten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backup {
+    // Foo.asm.bak, replacing any earlier one
+    Simple,
+    // Foo.asm.~1~, Foo.asm.~2~ and so on, as GNU cp numbers them
+    Numbered,
+}
+
+impl Backup {
+    pub fn new(raw: &str) -> Result<Backup> {
+        match raw {
+            "simple" => Ok(Backup::Simple),
+            "numbered" => Ok(Backup::Numbered),
+            _ => Err(anyhow!("unexpected backup style: {}", raw)),
+        }
+    }
+
+    /// Rename `path` out of the way, if it exists, returning where it went.
+    pub fn keep(&self, path: impl AsRef<Path>) -> Result<Option<PathBuf>> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let named = |suffix: String| {
+            let mut name = path.as_os_str().to_owned();
+            name.push(suffix);
+            PathBuf::from(name)
+        };
+        let backup = match self {
+            Backup::Simple => named(".bak".to_string()),
+            Backup::Numbered => (1..)
+                .map(|n| named(format!(".~{}~", n)))
+                .find(|name| !name.exists())
+                .expect("a free backup name"),
+        };
+        fs::rename(path, &backup).with_context(|| {
+            format!("cannot back up {} to {}", path.display(), backup.display())
+        })?;
+        Ok(Some(backup))
+    }
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub end_loop: bool,
+    // write the bootstrap or not; by default only when Sys.init is defined
+    pub bootstrap: Option<bool>,
+    // 0 to 2; from 1, runs of pushes move SP once and neighbouring
+    // commands are fused; from 2, dead stores are dropped too
+    pub opt_level: u8,
+    // -Os: trade speed for size, sharing routines and initializing locals
+    // in a loop, on top of what the opt level does
+    pub optimize_size: bool,
+    // zero locals in a loop from this many on; by default from 8, or
+    // whenever it's shorter under -Os
+    pub init_loop: Option<u16>,
+    pub comments: Comments,
+    pub addresses: Addresses,
+    pub newline: Newline,
+    pub registers: Registers,
+    // RAM the generated code may use as scratch; R13 to R15 by default
+    pub scratch: Vec<u16>,
+    // give statics RAM addresses here instead of leaving it to the assembler
+    pub static_addresses: bool,
+    // echo the source's comment and blank lines into the asm
+    pub preserve_comments: bool,
+    // nothing but the instructions, each as short as it's written: no
+    // comments of any kind, blank lines or spacing, whatever the style
+    pub minify: bool,
+    pub style: Style,
+    // say under each command's comment what its block does
+    pub explain: bool,
+    // emit comparisons, the call frame and return once and jump to them,
+    // for size
+    pub shared_routines: bool,
+    // keep one copy of blocks of code that repeat, each site jumping to it
+    // and back, for size
+    pub outline: bool,
+    // accept commands beyond the VM spec, such as assert
+    pub extensions: bool,
+    // reject anything beyond the VM spec, down to how lines are spaced
+    pub strict: bool,
+    // memory-map sources instead of reading them
+    pub mmap: bool,
+    // file name patterns left out when translating a directory
+    pub exclude: Vec<String>,
+    // lints allowed, warned about or denied, by name or as "warnings";
+    // those left out warn
+    pub lints: BTreeMap<String, Level>,
+    // warnings known about already, which aren't reported
+    pub baseline: Baseline,
+    pub order: Order,
+    // file names in order, for Order::Manifest
+    pub manifest: Vec<String>,
+    // keep the IR after codegen, for dumping it; otherwise each line is
+    // dropped once its asm is generated
+    pub retain_ir: bool,
+    // stop parsing after this many errors; 0 for no limit
+    pub max_errors: usize,
+    // warn about functions taking more instructions than this, or than
+    // their own entry in budgets
+    pub budget: Option<usize>,
+    pub budgets: BTreeMap<String, usize>,
+    // the functions a --pgo profile found hot, to be inlined, with the rest
+    // built for size
+    pub hot: Option<BTreeSet<String>>,
+    // the IR passes to run, in order; by default those of the opt level
+    pub passes: Option<Vec<String>>,
+    // check the IR after each of them, for debugging the optimizer
+    pub verify_ir: bool,
+    // check that every symbol the output refers to is defined, for
+    // debugging codegen
+    pub validate_labels: bool,
+    // keep a dump of the IR after the named pass, or after each with
+    // "all"; see Translator::snapshots
+    pub dump_after: Option<(String, IrFormat)>,
+    // dynamic libraries of IR passes, run in order after those; see
+    // plugin::Plugin
+    pub plugins: Vec<PathBuf>,
+    // threads to generate a large file's code on; by default one per core
+    pub jobs: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            end_loop: false,
+            bootstrap: None,
+            opt_level: 0,
+            optimize_size: false,
+            init_loop: None,
+            comments: Comments::Source,
+            addresses: Addresses::Off,
+            newline: Newline::Lf,
+            registers: Registers::Symbolic,
+            scratch: codegen::SCRATCH.to_vec(),
+            static_addresses: false,
+            preserve_comments: false,
+            minify: false,
+            style: Style::default(),
+            explain: false,
+            shared_routines: false,
+            outline: false,
+            extensions: false,
+            strict: false,
+            mmap: false,
+            exclude: Vec::new(),
+            lints: BTreeMap::new(),
+            baseline: Baseline::default(),
+            order: Order::Input,
+            manifest: Vec::new(),
+            retain_ir: false,
+            max_errors: 20,
+            budget: None,
+            budgets: BTreeMap::new(),
+            hot: None,
+            passes: None,
+            verify_ir: false,
+            validate_labels: false,
+            dump_after: None,
+            plugins: Vec::new(),
+            jobs: None,
+        }
+    }
+}
+
+impl Options {
+    /// The IR passes run between parse and codegen, in order.
+    pub fn passes(&self) -> Vec<String> {
+        match &self.passes {
+            Some(passes) => passes.clone(),
+            None => passes::default_pipeline(self.opt_level),
+        }
+    }
+
+    /// How many threads codegen may use.
+    pub fn jobs(&self) -> usize {
+        match self.jobs {
+            Some(jobs) => jobs.max(1),
+            None => thread::available_parallelism().map_or(1, usize::from),
+        }
+    }
+
+    /// The fewest locals a function zeroes in a loop.
+    pub fn init_loop(&self) -> u16 {
+        match self.init_loop {
+            Some(locals) => locals,
+            None if self.optimize_size => codegen::INIT_LOOP_SIZE,
+            None => codegen::INIT_LOOP,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Translator {
-    src: Vec<String>,
-    parser: Parser,
+    sources: Vec<Source>,
+    parsers: Vec<Parser>,
+    asm: Vec<Asm>,
+    options: Options,
+    ir_dropped: bool,
+    files: Vec<FileStats>,
+    // each static's file, index and RAM address, when they're assigned here
+    statics: Vec<(String, u16, u16)>,
+    // each file's static indices as parsed, which passes don't get to move
+    static_layout: Vec<Vec<u16>>,
+    // hash of everything the output depends on, written in its header
+    fingerprint: u64,
+    // problems found that don't stop the translation
+    warnings: Vec<Diagnostic>,
+    // and those of them found by plugins, kept apart from the lints
+    plugin_warnings: Vec<Diagnostic>,
+    // the IR dumped after passes, by Options::dump_after
+    snapshots: Vec<String>,
+    // each file, line and lint a vm-lint comment allows
+    allows: BTreeSet<(String, usize, &'static str)>,
 }
 
 impl Translator {
-    pub fn new(filename: &str) -> Result<Translator> {
-        let file = File::open(filename)?;
-        let buf = BufReader::new(file);
-        let src = buf
-            .lines()
-            .map(|l| l.expect("Could not parse line"))
+    // a single .vm file, or a directory whose .vm files make up one program
+    pub fn new(filename: impl AsRef<Path>) -> Result<Translator> {
+        Translator::open(filename, Options::default())
+    }
+
+    /// Like `new`, but memory-maps each file and parses lines straight out
+    /// of the mapping instead of reading the file into memory first.
+    pub fn mapped(filename: impl AsRef<Path>) -> Result<Translator> {
+        let options = Options {
+            mmap: true,
+            ..Options::default()
+        };
+        Translator::open(filename, options)
+    }
+
+    /// Like `new`, with options that also decide how sources are found and
+    /// read.
+    pub fn open(
+        filename: impl AsRef<Path>,
+        options: Options,
+    ) -> Result<Translator> {
+        let path = filename.as_ref();
+        let wanted = |entry: &Path| {
+            let excluded = entry
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    options
+                        .exclude
+                        .iter()
+                        .any(|pattern| project::matches(pattern, name))
+                });
+            entry.extension().is_some_and(|ext| ext == "vm") && !excluded
+        };
+        let mut paths = Vec::new();
+        if path.is_dir() {
+            for entry in fs::read_dir(path)? {
+                let entry = entry?.path();
+                if wanted(&entry) {
+                    paths.push(entry);
+                }
+            }
+            paths.sort();
+            if paths.is_empty() {
+                return Err(anyhow!("no vm files in {}", path.display()));
+            }
+        } else if glob::is_glob(path) {
+            paths = glob::expand(path)?;
+            paths.retain(|entry| wanted(entry));
+            if paths.is_empty() {
+                return Err(anyhow!("no vm files match {}", path.display()));
+            }
+        } else {
+            paths.push(path.to_path_buf());
+        }
+        options.order.sort(&mut paths, &options.manifest)?;
+
+        let sources = paths
+            .iter()
+            .map(|p| Source::new(p, options.mmap))
+            .collect::<Result<Vec<Source>>>()?;
+
+        Ok(Translator {
+            sources,
+            parsers: Vec::new(),
+            asm: Vec::new(),
+            options,
+            ir_dropped: false,
+            files: Vec::new(),
+            statics: Vec::new(),
+            fingerprint: 0,
+            warnings: Vec::new(),
+            plugin_warnings: Vec::new(),
+            snapshots: Vec::new(),
+            static_layout: Vec::new(),
+            allows: BTreeSet::new(),
+        })
+    }
+
+    // a single file's source text that isn't read from disk; `name`
+    // namespaces statics as a file stem would
+    pub fn from_source(name: &str, text: &str) -> Translator {
+        Translator {
+            sources: vec![Source {
+                name: name.to_string(),
+                path: PathBuf::from(name),
+                text: Text::Read(text.to_string()),
+            }],
+            parsers: Vec::new(),
+            asm: Vec::new(),
+            options: Options::default(),
+            ir_dropped: false,
+            files: Vec::new(),
+            statics: Vec::new(),
+            fingerprint: 0,
+            warnings: Vec::new(),
+            plugin_warnings: Vec::new(),
+            snapshots: Vec::new(),
+            static_layout: Vec::new(),
+            allows: BTreeSet::new(),
+        }
+    }
+
+    /// A translator over IR built in code, one `(filename, lines)` entry per
+    /// file. The filename namespaces statics, as a .vm file's stem would.
+    pub fn from_ir(files: Vec<(String, Vec<Line>)>) -> Translator {
+        let parsers = files
+            .into_iter()
+            .map(|(filename, lines)| Parser::from_lines(filename, lines))
+            .collect();
+        Translator {
+            sources: Vec::new(),
+            parsers,
+            asm: Vec::new(),
+            options: Options::default(),
+            ir_dropped: false,
+            files: Vec::new(),
+            statics: Vec::new(),
+            fingerprint: 0,
+            warnings: Vec::new(),
+            plugin_warnings: Vec::new(),
+            snapshots: Vec::new(),
+            static_layout: Vec::new(),
+            allows: BTreeSet::new(),
+        }
+    }
+
+    pub fn with_options(mut self, options: Options) -> Translator {
+        self.options = options;
+        self
+    }
+
+    // first pass: build the IR for every file
+    pub fn parse(&mut self) -> Result<()> {
+        // translators built from IR have nothing to parse
+        if self.sources.is_empty() {
+            return Ok(());
+        }
+
+        self.parsers.clear();
+        self.static_layout.clear();
+        self.allows.clear();
+        self.ir_dropped = false;
+
+        // keep going past bad lines so every error is reported at once
+        let mut report = Report {
+            files: self.sources.len(),
+            ..Report::default()
+        };
+        let max_errors = self.options.max_errors;
+        'sources: for source in &self.sources {
+            let mut parser =
+                Parser::new(source.name.to_string(), source.display())
+                    .with_extensions(self.options.extensions)
+                    .with_strict(self.options.strict);
+            let text = match source.text() {
+                Ok(text) => text,
+                Err(err) => {
+                    report.diagnostics.push(Diagnostic::error(Class::Io, err));
+                    continue;
+                }
+            };
+            for line in text.split_inclusive('\n') {
+                if let Err(err) = parser.parse_line(line) {
+                    report.diagnostics.push(match err.downcast() {
+                        Ok(diagnostic) => diagnostic,
+                        Err(err) => Diagnostic::error(Class::Parse, err),
+                    });
+                    // a file that isn't VM code at all would error on
+                    // every line
+                    if max_errors > 0 && report.errors() >= max_errors {
+                        report.stopped = true;
+                        break 'sources;
+                    }
+                }
+            }
+            for &(line, lint) in parser.allows() {
+                self.allows.insert((source.display(), line, lint));
+            }
+            self.parsers.push(parser);
+        }
+
+        if report.errors() > 0 {
+            return Err(report.into());
+        }
+        Ok(())
+    }
+
+    // second pass: generate asm from the complete IR
+    pub fn generate(&mut self) -> Result<()> {
+        self.asm.clear();
+        self.fingerprint = self.fingerprint();
+        codegen::check_scratch(&self.options.scratch)?;
+        // the first two are codegen's
+        let outline_register =
+            match (self.options.outline, &self.options.scratch[..]) {
+                (false, _) => None,
+                (true, [_, _, register, ..]) => {
+                    Some(codegen::register(self.options.registers, *register))
+                }
+                (true, _) => {
+                    return Err(anyhow!(
+                        "--outline needs a third scratch register"
+                    ))
+                }
+            };
+
+        let bootstrap = self
+            .options
+            .bootstrap
+            .unwrap_or_else(|| self.defines("Sys.init"));
+        // before codegen, which may drop the IR
+        let unreachable: Vec<String> =
+            lint::unreachable_functions(self.lines(), bootstrap)
+                .into_iter()
+                .map(str::to_string)
+                .collect();
+        if bootstrap {
+            let bin = CodeGen::new("Bootstrap".to_string())
+                .with_registers(self.options.registers)
+                .with_scratch(&self.options.scratch)
+                .gen_bootstrap()?;
+            self.asm.push(Asm {
+                src: "// bootstrap".to_string(),
+                bin,
+                span: None,
+                function: None,
+                command: None,
+                explain: self.options.explain.then(|| {
+                    "point SP at 256; call Sys.init like any other function"
+                        .to_string()
+                }),
+                before: Vec::new(),
+                after: Vec::new(),
+            });
+        }
+
+        self.files.clear();
+        self.statics.clear();
+        // statics are laid out file after file from RAM 16
+        let mut next_static = 16;
+        // and asserts and halts numbered across the program
+        let mut next_assert = 1;
+        let mut next_halt = 1;
+        let mut routines: BTreeSet<Routine> = BTreeSet::new();
+        // each file's blocks
+        let mut ranges = Vec::new();
+        // the symbols statics were given, which aren't labels
+        let mut variables = BTreeSet::new();
+        for (i, parser) in self.parsers.iter_mut().enumerate() {
+            let options = &self.options;
+            let filename = parser.filename().to_string();
+            let new_cg = || {
+                CodeGen::new(filename.clone())
+                    .with_registers(options.registers)
+                    .with_scratch(&options.scratch)
+                    .with_opt_level(options.opt_level)
+                    .with_shared_routines(
+                        options.shared_routines || options.optimize_size,
+                    )
+                    .with_init_loop(options.init_loop())
+                    .with_hot(options.hot.clone())
+            };
+            let mut cg = new_cg()
+                .with_assert_base(next_assert)
+                .with_halt_base(next_halt);
+            if options.static_addresses {
+                // in index order, from the IR before any pass dropped uses
+                let layout = match self.static_layout.get(i) {
+                    Some(layout) => layout.clone(),
+                    None => static_indices(parser.lines()),
+                };
+                cg = cg.with_statics(next_static, layout);
+            }
+            let first = self.asm.len();
+            let (notes, trailing) = parser.take_notes();
+            let dead = match options.opt_level {
+                0 | 1 => Vec::new(),
+                _ => optimize::dead_stores(parser.lines()),
+            };
+            let jobs = options.jobs();
+            // statics given addresses are numbered as the file uses them,
+            // which only one generator going through it in order can do
+            let cgs = if jobs > 1
+                && !options.static_addresses
+                && parser.lines().len() >= PARALLEL_MIN
+            {
+                let (blocks, cgs) =
+                    gen_chunks(parser, &dead, &new_cg, &cg, options, jobs)?;
+                self.asm.extend(blocks);
+                if !options.retain_ir {
+                    parser.drain().for_each(drop);
+                }
+                cgs
+            } else {
+                gen_file(parser, &dead, &mut cg, options, &mut self.asm)?;
+                vec![cg]
+            };
+            self.ir_dropped |= !options.retain_ir;
+
+            if self.options.preserve_comments && !self.options.minify {
+                let blocks = &mut self.asm[first..];
+                for (asm, before) in blocks.iter_mut().zip(notes) {
+                    asm.before = before;
+                }
+                if let Some(last) = blocks.last_mut() {
+                    last.after = trailing;
+                }
+            }
+
+            ranges.push(first..self.asm.len());
+            let blocks = &self.asm[first..];
+            let statics: BTreeSet<u16> = cgs
+                .iter()
+                .flat_map(CodeGen::static_symbols)
+                .map(|(index, symbol)| {
+                    variables.insert(symbol.to_string());
+                    index
+                })
+                .collect();
+            self.files.push(FileStats {
+                file: parser.filename().to_string(),
+                commands: blocks.len(),
+                instructions: blocks.iter().map(Asm::instructions).sum(),
+                statics: statics.len(),
+            });
+            if self.options.static_addresses {
+                for (index, address) in cgs[0].static_symbols() {
+                    let address = address.parse().expect("a static address");
+                    self.statics.push((
+                        parser.filename().to_string(),
+                        index,
+                        address,
+                    ));
+                }
+                next_static += cgs[0].static_slots() as u16;
+            }
+            for cg in &cgs {
+                routines.extend(cg.routines());
+            }
+            let last = cgs.last().expect("a code generator");
+            next_assert = last.next_assert();
+            next_halt = last.next_halt();
+        }
+
+        let outlined = match outline_register {
+            Some(register) => {
+                let outlined = optimize::outline(
+                    &mut self.asm,
+                    register,
+                    self.options.explain,
+                    self.options.hot.as_ref(),
+                );
+                for (file, range) in self.files.iter_mut().zip(ranges) {
+                    file.instructions =
+                        self.asm[range].iter().map(Asm::instructions).sum();
+                }
+                outlined
+            }
+            None => Vec::new(),
+        };
+        if self.options.end_loop {
+            self.asm.push(Asm {
+                src: "// end".to_string(),
+                bin: CodeGen::gen_end_loop(),
+                span: None,
+                function: None,
+                command: None,
+                explain: self.options.explain.then(|| {
+                    "loop here for good once the program is done".to_string()
+                }),
+                before: Vec::new(),
+                after: Vec::new(),
+            });
+        }
+        if !routines.is_empty() || !outlined.is_empty() {
+            // out of the way of a program that runs off its end
+            let mut bin = match self.options.end_loop {
+                true => Vec::new(),
+                false => vec![
+                    "(__VM_HALT)".into(),
+                    "@__VM_HALT".into(),
+                    "0; JMP".into(),
+                ],
+            };
+            let cg = CodeGen::new("Routines".to_string())
+                .with_registers(self.options.registers)
+                .with_scratch(&self.options.scratch);
+            for routine in routines {
+                bin.extend(cg.gen_routine(routine));
+            }
+            bin.extend(outlined);
+            self.asm.push(Asm {
+                src: "// shared routines".to_string(),
+                bin,
+                span: None,
+                function: None,
+                command: None,
+                explain: self.options.explain.then(|| {
+                    "code that blocks above jump to and back from, kept \
+                     where the program never runs into it"
+                        .to_string()
+                }),
+                before: Vec::new(),
+                after: Vec::new(),
+            });
+        }
+        let levels = &self.options.lints;
+        self.warnings = self
+            .lint(&unreachable)
+            .into_iter()
+            .filter(|warning| !self.allowed(warning))
+            .chain(self.plugin_warnings.iter().cloned())
+            .filter_map(|warning| lint::apply(levels, warning))
+            .collect();
+        self.verify()?;
+        if self.options.validate_labels {
+            self.validate_labels(&variables)?;
+        }
+        // denied lints fail the run, after the output's been checked
+        let report = Report {
+            files: self.parsers.len(),
+            diagnostics: self.warnings.clone(),
+            ..Report::default()
+        };
+        if report.errors() > 0 {
+            return Err(report.into());
+        }
+        Ok(())
+    }
+
+    // each function's share of the ROM and where it's declared, in order
+    fn functions(&self) -> Vec<(&str, usize, Option<&Span>)> {
+        let mut functions: Vec<(&str, usize, Option<&Span>)> = Vec::new();
+        for asm in &self.asm {
+            let name = match &asm.function {
+                Some(name) => name.as_str(),
+                None => continue,
+            };
+            match functions.last_mut() {
+                Some(last) if last.0 == name => last.1 += asm.instructions(),
+                _ => functions.push((
+                    name,
+                    asm.instructions(),
+                    asm.span.as_ref(),
+                )),
+            }
+        }
+        functions
+    }
+
+    // warnings about functions, at their declarations
+    fn lint(&self, unreachable: &[String]) -> Vec<Diagnostic> {
+        let mut warnings = Vec::new();
+        for (name, size, span) in self.functions() {
+            let mut found = Vec::new();
+            if unreachable.iter().any(|n| n == name) {
+                found.push(Diagnostic::lint(
+                    "unreachable-code",
+                    format!(
+                        "function {} is never called ({} instructions)",
+                        name, size
+                    ),
+                ));
+            }
+            match self
+                .options
+                .budgets
+                .get(name)
+                .or(self.options.budget.as_ref())
+            {
+                Some(&budget) if size > budget => found.push(Diagnostic::lint(
+                    "over-budget",
+                    format!(
+                        "function {} is {} instructions, over its budget \
+                             of {}",
+                        name, size, budget
+                    ),
+                )),
+                _ => {}
+            }
+            for warning in found {
+                warnings.push(match span {
+                    Some(span) => warning.at(span.clone()),
+                    None => warning,
+                });
+            }
+        }
+        // the assembler gives statics RAM from 16 on, into the stack at 256
+        // (--static-addresses refuses to)
+        let statics: usize = self.files.iter().map(|file| file.statics).sum();
+        if !self.options.static_addresses && statics > 240 {
+            warnings.push(Diagnostic::lint(
+                "static-overflow",
+                format!(
+                    "the program has {} statics, more than fit in RAM 16 to \
+                     255, so the last of them share the stack's RAM",
+                    statics
+                ),
+            ));
+        }
+        warnings
+    }
+
+    // allowed where it points, or known about already
+    fn allowed(&self, warning: &Diagnostic) -> bool {
+        let lint = match warning.lint {
+            Some(lint) => lint,
+            None => return false,
+        };
+        if let Some(span) = &warning.span {
+            let at = |name| {
+                self.allows
+                    .contains(&(span.file.to_string(), span.line, name))
+            };
+            if at(lint) || at("warnings") {
+                return true;
+            }
+        }
+        let (file, code) = self.location(warning);
+        self.options.baseline.contains(lint, &file, &code)
+    }
+
+    // the name of the file a warning is in and the code it points at, by
+    // which a baseline knows it wherever the program is translated from
+    fn location(&self, warning: &Diagnostic) -> (String, String) {
+        let span = match &warning.span {
+            Some(span) => span,
+            None => return (String::new(), String::new()),
+        };
+        let code = self
+            .sources
+            .iter()
+            .find(|source| source.display() == *span.file)
+            .and_then(|source| source.text().ok())
+            .and_then(|text| text.get(span.bytes.clone()))
+            .unwrap_or_default();
+        let file = Path::new(&*span.file).file_name().unwrap_or_default();
+        (file.to_string_lossy().into_owned(), code.to_string())
+    }
+
+    /// The lint warnings of the last translation, as a baseline for later
+    /// ones.
+    pub fn baseline(&self) -> Baseline {
+        let mut baseline = Baseline::default();
+        for warning in &self.warnings {
+            if let Some(lint) = warning.lint {
+                let (file, code) = self.location(warning);
+                baseline.insert(lint, &file, &code);
+            }
+        }
+        baseline
+    }
+
+    /// What the last translation found worth pointing out but could
+    /// translate anyway.
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
+    // catch codegen bugs here, against the command that caused them, rather
+    // than as a confusing failure in whatever assembles the output
+    fn verify(&self) -> Result<()> {
+        let mut report = Report {
+            files: self.parsers.len(),
+            ..Report::default()
+        };
+        for asm in &self.asm {
+            for line in &asm.bin {
+                if let Err(err) = verify::check_instruction(line) {
+                    let diagnostic = Diagnostic::error(
+                        Class::Internal,
+                        format!(
+                            "internal error: {} `{}` generated for `{}`",
+                            err,
+                            line,
+                            asm.src.trim_start_matches("//").trim()
+                        ),
+                    );
+                    report.diagnostics.push(match &asm.span {
+                        Some(span) => diagnostic.at(span.clone()),
+                        None => diagnostic,
+                    });
+                }
+            }
+        }
+        if report.errors() > 0 {
+            return Err(report.into());
+        }
+        Ok(())
+    }
+
+    // a symbol that's no label, register or static is a jump to nowhere,
+    // or the address of nothing, that the assembler would quietly make a
+    // variable of
+    fn validate_labels(&self, variables: &BTreeSet<String>) -> Result<()> {
+        let blocks: Vec<&[Instruction]> =
+            self.asm.iter().map(|asm| &asm.bin[..]).collect();
+        let mut report = Report {
+            files: self.parsers.len(),
+            ..Report::default()
+        };
+        for (i, symbol) in verify::undefined_symbols(&blocks, variables) {
+            let asm = &self.asm[i];
+            let diagnostic = Diagnostic::error(
+                Class::Internal,
+                format!(
+                    "internal error: `@{}` generated for `{}` has no label",
+                    symbol,
+                    asm.src.trim_start_matches("//").trim()
+                ),
+            );
+            report.diagnostics.push(match &asm.span {
+                Some(span) => diagnostic.at(span.clone()),
+                None => diagnostic,
+            });
+        }
+        if report.errors() > 0 {
+            return Err(report.into());
+        }
+        Ok(())
+    }
+
+    /// Run each plugin in `Options::plugins` over the IR in turn. Errors
+    /// they report stop the translation; warnings join the lints'.
+    #[cfg(feature = "plugins")]
+    fn run_plugins(&mut self, before: &BTreeSet<String>) -> Result<()> {
+        self.plugin_warnings.clear();
+        for path in self.options.plugins.clone() {
+            let plugin = Plugin::load(path)?;
+            let (parsers, diagnostics) = plugin.run(&self.parsers)?;
+            let report = Report {
+                files: parsers.len(),
+                diagnostics: diagnostics
+                    .iter()
+                    .filter(|d| d.severity == Severity::Error)
+                    .cloned()
+                    .collect(),
+                ..Report::default()
+            };
+            if report.errors() > 0 {
+                return Err(report.into());
+            }
+            self.parsers = parsers;
+            self.plugin_warnings.extend(diagnostics);
+            self.verify_ir(plugin.name(), before)?;
+            self.snapshot(plugin.name())?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "plugins"))]
+    fn run_plugins(&mut self, _: &BTreeSet<String>) -> Result<()> {
+        match self.options.plugins.is_empty() {
+            true => Ok(()),
+            false => {
+                Err(anyhow!("plugins need a build with the plugins feature"))
+            }
+        }
+    }
+
+    /// Run the IR passes, then any plugins, over the parsed program.
+    pub fn optimize(&mut self) -> Result<()> {
+        // whatever was wrong with the input isn't a pass's fault
+        let before = match self.options.verify_ir {
+            true => verify::check_ir(&self.parsers),
+            false => BTreeSet::new(),
+        };
+        self.snapshots.clear();
+        self.static_layout = self
+            .parsers
+            .iter()
+            .map(|p| static_indices(p.lines()))
             .collect();
+        for pass in self.options.passes() {
+            for parser in &mut self.parsers {
+                let commands = passes::run(&pass, parser.lines())?;
+                parser.rewrite(commands);
+            }
+            self.verify_ir(&pass, &before)?;
+            self.snapshot(&pass)?;
+        }
+        self.run_plugins(&before)?;
+
+        match &self.options.dump_after {
+            Some((name, _)) if name != "all" && self.snapshots.is_empty() => {
+                let mut ran = self.options.passes();
+                ran.extend(self.options.plugins.iter().map(|path| {
+                    path.file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into()
+                }));
+                Err(anyhow!(
+                    "no pass named {} ran (ran: {})",
+                    name,
+                    match ran.is_empty() {
+                        true => "none".to_string(),
+                        false => ran.join(", "),
+                    }
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // keep the IR as it is after `pass`, if it's to be dumped
+    fn snapshot(&mut self, pass: &str) -> Result<()> {
+        let format = match &self.options.dump_after {
+            Some((name, format)) if name == "all" || name == pass => format,
+            _ => return Ok(()),
+        };
+        let dump = match format {
+            IrFormat::Text => {
+                let files: String =
+                    self.parsers.iter().map(Parser::dump_text).collect();
+                format!("// after {}\n{}", pass, files)
+            }
+            IrFormat::Json => {
+                let files: Vec<_> =
+                    self.parsers.iter().map(Parser::to_json).collect();
+                let dump = json!({ "after": pass, "files": files });
+                format!("{}\n", serde_json::to_string_pretty(&dump)?)
+            }
+        };
+        self.snapshots.push(dump);
+        Ok(())
+    }
 
-        let stemmed = Path::new(filename).file_stem().unwrap();
-        let trimmed = Path::new(stemmed).file_name().unwrap();
-        let parser = Parser::new(format!("{}", trimmed.to_str().unwrap()));
+    /// The IR after each pass `Options::dump_after` picked, in the order
+    /// they ran.
+    pub fn snapshots(&self) -> &[String] {
+        &self.snapshots
+    }
 
-        Ok(Translator { src, parser })
+    // catch a pass that broke the IR as soon as it has, rather than as
+    // wrong output; only under Options::verify_ir
+    fn verify_ir(&self, pass: &str, before: &BTreeSet<String>) -> Result<()> {
+        if !self.options.verify_ir {
+            return Ok(());
+        }
+        let report = Report {
+            files: self.parsers.len(),
+            diagnostics: verify::check_ir(&self.parsers)
+                .difference(before)
+                .map(|problem| {
+                    Diagnostic::error(
+                        Class::Internal,
+                        format!("internal error: after {}, {}", pass, problem),
+                    )
+                })
+                .collect(),
+            ..Report::default()
+        };
+        if report.errors() > 0 {
+            return Err(report.into());
+        }
+        Ok(())
     }
 
     pub fn process(&mut self) -> Result<()> {
-        for line in &self.src {
-            self.parser.process_line(line)?;
+        self.parse()?;
+        self.optimize()?;
+        self.generate()
+    }
+
+    // the sources (or the IR built in code) and the options that change
+    // the output, along with the translator's own version
+    fn fingerprint(&self) -> u64 {
+        let options = &self.options;
+        let settings = format!(
+            "{} {:?} {:?} {} {} {} {} {:?} {:?} {:?} {:?} {:?} {} {}",
+            env!("CARGO_PKG_VERSION"),
+            options.bootstrap,
+            options.comments,
+            options.end_loop,
+            options.preserve_comments,
+            options.static_addresses,
+            options.shared_routines,
+            options.addresses,
+            options.newline,
+            options.registers,
+            options.scratch,
+            options.opt_level,
+            options.optimize_size,
+            options.init_loop(),
+        );
+        let mut hash = fnv1a(FNV_OFFSET, settings.as_bytes());
+        if let Some(hot) = &options.hot {
+            hash = fnv1a(hash, format!("{:?}", hot).as_bytes());
+        }
+        if options.explain {
+            hash = fnv1a(hash, b"explain");
+        }
+        if options.outline {
+            hash = fnv1a(hash, b"outline");
+        }
+        if options.minify {
+            hash = fnv1a(hash, b"minify");
+        }
+        if options.style != Style::default() {
+            hash = fnv1a(hash, format!("{:?}", options.style).as_bytes());
+        }
+        if let Some(passes) = &options.passes {
+            hash = fnv1a(hash, format!("passes {:?}", passes).as_bytes());
+        }
+        // a rebuilt plugin can change the output as much as the sources
+        for path in &options.plugins {
+            hash = fnv1a(hash, path.to_string_lossy().as_bytes());
+            if let Ok(bytes) = fs::read(path) {
+                hash = fnv1a(hash, &bytes);
+            }
+        }
+        for source in &self.sources {
+            hash = fnv1a(hash, source.name.as_bytes());
+            if let Ok(text) = source.text() {
+                hash = fnv1a(hash, text.as_bytes());
+            }
+        }
+        if self.sources.is_empty() {
+            for parser in &self.parsers {
+                hash = fnv1a(hash, parser.filename().as_bytes());
+                for line in parser.lines() {
+                    hash = fnv1a(hash, line.to_string().as_bytes());
+                }
+            }
+        }
+        hash
+    }
+
+    /// Whether `binname` already holds exactly what would be written there.
+    pub fn up_to_date(&self, binname: impl AsRef<Path>) -> bool {
+        // the header alone would miss hand edits below it
+        fs::read_to_string(binname)
+            .is_ok_and(|existing| existing == self.render())
+    }
+
+    fn defines(&self, function: &str) -> bool {
+        self.lines().any(|line| match line {
+            Line::Function(FunctionToken::Function { name, .. }) => {
+                name == function
+            }
+            _ => false,
+        })
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &Line> {
+        self.parsers.iter().flat_map(|p| p.lines())
+    }
+
+    // each file's IR, in translation order
+    pub fn parsers(&self) -> &[Parser] {
+        &self.parsers
+    }
+
+    /// Each file's IR, or an error once codegen has dropped it.
+    pub fn ir(&self) -> Result<&[Parser]> {
+        match self.ir_dropped {
+            true => Err(anyhow!(
+                "the IR was dropped after codegen; set Options::retain_ir"
+            )),
+            false => Ok(&self.parsers),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut commands = BTreeMap::new();
+        let mut forms = BTreeMap::new();
+        for asm in &self.asm {
+            if let Some(command) = asm.command {
+                *commands.entry(command).or_insert(0) += 1;
+            }
+            for line in &asm.bin {
+                for form in stats::instruction_forms(line) {
+                    *forms.entry(form).or_insert(0) += 1;
+                }
+            }
+        }
+        Stats {
+            files: self.files.clone(),
+            rom: self.asm.iter().map(Asm::instructions).sum(),
+            commands,
+            forms,
         }
-        match env::var("DEBUG") {
-            Ok(_) => &self.parser.debug(),
-            Err(_) => &{},
+    }
+
+    pub fn asm(&self) -> &[Asm] {
+        &self.asm
+    }
+
+    // each source's path and text, skipping any that isn't UTF-8
+    pub fn sources(&self) -> impl Iterator<Item = (&Path, &str)> {
+        self.sources.iter().filter_map(|source| {
+            source.text().ok().map(|text| (source.path.as_path(), text))
+        })
+    }
+
+    pub fn dump_ir(&self, format: &IrFormat) -> Result<String> {
+        if self.ir_dropped {
+            return Err(anyhow!(
+                "the IR was dropped after codegen; set Options::retain_ir"
+            ));
+        }
+        Ok(match format {
+            IrFormat::Text => {
+                self.parsers.iter().map(Parser::dump_text).collect()
+            }
+            IrFormat::Json => {
+                let files: Vec<_> =
+                    self.parsers.iter().map(Parser::to_json).collect();
+                format!("{}\n", serde_json::to_string_pretty(&files)?)
+            }
+        })
+    }
+
+    pub fn render(&self) -> String {
+        render_blocks(
+            &self.asm.iter().collect::<Vec<_>>(),
+            &self.options,
+            self.fingerprint,
+            0,
+        )
+    }
+
+    /// Partition the generated blocks into at most `parts` groups of
+    /// roughly equal instruction count, splitting only where one function
+    /// ends and the next begins.
+    pub fn split(&self, parts: usize) -> Vec<Vec<&Asm>> {
+        // runs of blocks belonging to the same function
+        let mut functions: Vec<Vec<&Asm>> = Vec::new();
+        for asm in &self.asm {
+            match functions.last_mut() {
+                Some(run) if run[0].function == asm.function => run.push(asm),
+                _ => functions.push(vec![asm]),
+            }
+        }
+
+        let total: usize = self.asm.iter().map(Asm::instructions).sum();
+        let target = total.div_ceil(parts.max(1));
+
+        let mut split: Vec<Vec<&Asm>> = vec![Vec::new()];
+        let mut size = 0;
+        for run in functions {
+            let run_size: usize =
+                run.iter().map(|asm| asm.instructions()).sum();
+            if size > 0 && size + run_size > target && split.len() < parts {
+                split.push(Vec::new());
+                size = 0;
+            }
+            size += run_size;
+            split.last_mut().unwrap().extend(run);
+        }
+        split
+    }
+
+    pub fn write_bin(&self, binname: impl AsRef<Path>) -> Result<()> {
+        write(binname, self.render())?;
+
+        Ok(())
+    }
+
+    /// A unified diff from what `binname` holds to what would be written
+    /// there, empty when they're the same. A missing file diffs as empty.
+    pub fn diff(&self, binname: impl AsRef<Path>) -> Result<String> {
+        let binname = binname.as_ref();
+        let existing = match fs::read_to_string(binname) {
+            Ok(existing) => existing,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("cannot read {}", binname.display())
+                })
+            }
         };
+        let rendered = self.render();
+        if existing == rendered {
+            return Ok(String::new());
+        }
+        let name = binname.display().to_string();
+        Ok(TextDiff::from_lines(&existing, &rendered)
+            .unified_diff()
+            .header(&name, &format!("{} (generated)", name))
+            .to_string())
+    }
+
+    // Foo.asm becomes Foo.1.asm .. Foo.N.asm, described by
+    // Foo.manifest.json; returns the names of everything written
+    pub fn write_split(
+        &self,
+        binname: impl AsRef<Path>,
+        parts: usize,
+    ) -> Result<Vec<PathBuf>> {
+        let path = binname.as_ref();
+        let stem = path
+            .file_stem()
+            .ok_or_else(|| anyhow!("not a file: {}", path.display()))?;
+        let named = |suffix: &str| {
+            let mut name = stem.to_os_string();
+            name.push(suffix);
+            path.with_file_name(name)
+        };
+
+        let mut written = Vec::new();
+        let mut manifest = Vec::new();
+        let mut rom = 0;
+        for (i, blocks) in self.split(parts).iter().enumerate() {
+            let name = named(&format!(".{}.asm", i + 1));
+            write(
+                &name,
+                render_blocks(blocks, &self.options, self.fingerprint, rom),
+            )?;
+
+            let mut functions: Vec<&str> = Vec::new();
+            for asm in blocks {
+                if let Some(f) = asm.function.as_deref() {
+                    if !functions.contains(&f) {
+                        functions.push(f);
+                    }
+                }
+            }
+            let size: usize = blocks.iter().map(|asm| asm.instructions()).sum();
+            manifest.push(json!({
+                "file": name.display().to_string(),
+                "functions": functions,
+                "instructions": size,
+                "rom": [rom, rom + size],
+            }));
+            rom += size;
+            written.push(name);
+        }
+
+        let name = named(".manifest.json");
+        let manifest = json!({ "instructions": rom, "parts": manifest });
+        write(
+            &name,
+            format!("{}\n", serde_json::to_string_pretty(&manifest)?),
+        )?;
+        written.push(name);
+
+        Ok(written)
+    }
+
+    pub fn write_hack(&self, hackname: impl AsRef<Path>) -> Result<()> {
+        let assembled = assembler::assemble(&self.render())?;
+        write(hackname, assembled.to_hack())?;
+
         Ok(())
     }
 
-    pub fn write_bin(&self, binname: &String) -> Result<()> {
-        let mut buf = "".to_string();
+    pub fn write_ir(
+        &self,
+        irname: impl AsRef<Path>,
+        format: &IrFormat,
+    ) -> Result<()> {
+        write(irname, self.dump_ir(format)?)?;
+
+        Ok(())
+    }
+
+    // every block's position in the .asm and in ROM, alongside the VM
+    // command it was generated from
+    pub fn render_map(&self) -> Result<String> {
+        let origins = self.origins();
+        let entries: Vec<Value> = origins
+            .iter()
+            .zip(&self.asm)
+            .map(|(origin, asm)| {
+                let mut entry = json!({
+                    "command": origin.command,
+                    "asm_lines": [origin.lines.start, origin.lines.end],
+                    "rom": [origin.rom.start, origin.rom.end],
+                });
+                if let Some(span) = &asm.span {
+                    entry["file"] = json!(*span.file);
+                    entry["line"] = json!(span.line);
+                    entry["bytes"] = json!([span.bytes.start, span.bytes.end]);
+                }
+                entry
+            })
+            .collect();
+        Ok(format!("{}\n", serde_json::to_string_pretty(&entries)?))
+    }
+
+    /// Where `render` puts each block, one entry per block of `asm()`.
+    // worked out from the layout rather than recovered from comments that
+    // may not be written
+    pub fn origins(&self) -> Vec<Origin> {
+        let comments = commented(&self.options);
+        let blank_lines = style(&self.options).blank_lines(comments);
+        let mut line = match comments {
+            true => header(self.fingerprint).lines().count() + 1,
+            false => 1,
+        };
+        let mut rom = 0;
+        let mut origins = Vec::new();
+        for (i, asm) in self.asm.iter().enumerate() {
+            // blank lines and any preserved source lines, then the comment
+            // and any explanation
+            let start = match comments || i > 0 {
+                true => line + blank_lines,
+                false => line,
+            } + asm.before.len();
+            let explain = match comments {
+                true => asm.explain_lines().len(),
+                false => 0,
+            };
+            let end = start + comments as usize + explain + asm.bin.len();
+            line = end + asm.after.len();
+            let size = asm.instructions();
+            origins.push(Origin {
+                command: asm.src.trim_start_matches("//").trim().to_string(),
+                lines: start..end,
+                rom: rom..rom + size,
+            });
+            rom += size;
+        }
+        origins
+    }
+
+    pub fn write_html(
+        &self,
+        htmlname: impl AsRef<Path>,
+        title: &str,
+    ) -> Result<()> {
+        write(htmlname, html::report(title, self))?;
 
-        buf.push_str("// Hack ASM (for nand2tetris book) generated from VM code\n");
-        buf.push_str("// by Brent Hamilton <github.com/distrill/n2t-vm-translator>\n");
-        for asm in &self.parser.asm {
-            buf.push_str(format!("\n\n{}\n", &asm.src).as_str());
-            for binline in &asm.bin {
-                buf.push_str(format!("{}\n", binline).as_str());
+        Ok(())
+    }
+
+    // markdown, or CSV for a .csv path
+    pub fn write_report(&self, reportname: impl AsRef<Path>) -> Result<()> {
+        let reportname = reportname.as_ref();
+        let stats = self.stats();
+        let report = if reportname.extension().is_some_and(|ext| ext == "csv") {
+            stats.to_csv()
+        } else {
+            stats.to_markdown()
+        };
+        write(reportname, report)?;
+
+        Ok(())
+    }
+
+    /// Where the cycles of an emulator run went, from how many times it ran
+    /// each ROM address. Without a run, the estimate is for running every
+    /// instruction once.
+    pub fn timing(&self, executions: Option<&[u64]>) -> Timing {
+        let once;
+        let executions = match executions {
+            Some(executions) => executions,
+            None => {
+                once = vec![1; self.asm.iter().map(Asm::instructions).sum()];
+                &once
+            }
+        };
+        let mut commands = BTreeMap::new();
+        let mut functions: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+        let mut hottest = Vec::new();
+        for (asm, origin) in self.asm.iter().zip(self.origins()) {
+            // a block without instructions, such as a label's, runs as
+            // often as the instruction it leads into
+            let runs = executions.get(origin.rom.start).copied().unwrap_or(0);
+            let counts = executions.get(origin.rom).unwrap_or(&[]);
+            let cycles: u64 = counts.iter().sum();
+            if let Some(command) = asm.command {
+                let entry = commands.entry(command).or_insert((0, 0));
+                entry.0 += runs;
+                entry.1 += cycles;
+                // a function is entered through its declaration's code
+                if let Some(function) = &asm.function {
+                    let entry =
+                        functions.entry(function.to_string()).or_default();
+                    if command == "function" {
+                        entry.0 += runs;
+                    }
+                    entry.1 += cycles;
+                }
+            }
+            if cycles > 0 {
+                let location = match &asm.span {
+                    Some(span) => format!(
+                        "{}:{} {}",
+                        span.file, span.line, origin.command
+                    ),
+                    None => origin.command,
+                };
+                hottest.push((location, cycles));
+            }
+        }
+        hottest.sort_by_key(|&(_, cycles)| Reverse(cycles));
+        hottest.truncate(stats::HOTTEST);
+        Timing {
+            cycles: executions.iter().sum(),
+            commands,
+            functions,
+            hottest,
+        }
+    }
+
+    pub fn write_timing(
+        &self,
+        timingname: impl AsRef<Path>,
+        executions: Option<&[u64]>,
+    ) -> Result<()> {
+        let timingname = timingname.as_ref();
+        let timing = self.timing(executions);
+        let report = if timingname.extension().is_some_and(|ext| ext == "csv") {
+            timing.to_csv()
+        } else {
+            timing.to_markdown()
+        };
+        write(timingname, report)
+    }
+
+    /// Where every function is defined and called, and which functions
+    /// read and write each static, in the program as translated.
+    pub fn xref(&self) -> Result<Xref> {
+        let mut xref = Xref::default();
+        for parser in self.ir()? {
+            let mut function = None;
+            for (i, line) in parser.lines().iter().enumerate() {
+                if let Line::Function(FunctionToken::Function {
+                    name, ..
+                }) = line
+                {
+                    function = Some(name.as_str());
+                }
+                let location = parser
+                    .spans()
+                    .get(i)
+                    .map(|span| format!("{}:{}", span.file, span.line));
+                xref.add(parser.filename(), function, line, location);
             }
         }
+        Ok(xref)
+    }
+
+    pub fn write_xref(&self, xrefname: impl AsRef<Path>) -> Result<()> {
+        write(xrefname.as_ref(), self.xref()?.to_markdown())
+    }
 
-        fs::write(binname, buf)?;
+    /// Which commands from the sources an emulator run reached, from how
+    /// many times it ran each ROM address. The run ended at `pc`, which
+    /// counts as reached: a run stops at the loop it halts in before
+    /// running it.
+    pub fn coverage(&self, executions: &[u64], pc: u16) -> Coverage {
+        let pc = pc as usize;
+        let commands = self
+            .asm
+            .iter()
+            .zip(self.origins())
+            .filter(|(asm, _)| asm.command.is_some())
+            .filter_map(|(asm, origin)| {
+                let span = asm.span.as_ref()?;
+                // as often as the instruction it leads into, for a command
+                // without code of its own
+                let mut runs =
+                    executions.get(origin.rom.start).copied().unwrap_or(0);
+                if origin.rom.start == pc || origin.rom.contains(&pc) {
+                    runs = runs.max(1);
+                }
+                Some((span.file.to_string(), span.line, origin.command, runs))
+            })
+            .collect();
+        Coverage { commands }
+    }
+
+    pub fn write_coverage(
+        &self,
+        coveragename: impl AsRef<Path>,
+        executions: &[u64],
+        pc: u16,
+    ) -> Result<()> {
+        write(coveragename, self.coverage(executions, pc).to_text())
+    }
+
+    /// Write a copy of each source to `dir`, named for it with `.cov`
+    /// added, with each line led by how often it ran.
+    pub fn write_annotated(
+        &self,
+        dir: impl AsRef<Path>,
+        executions: &[u64],
+        pc: u16,
+    ) -> Result<Vec<PathBuf>> {
+        let coverage = self.coverage(executions, pc);
+        let mut written = Vec::new();
+        for (path, text) in self.sources() {
+            let mut name = path.file_name().unwrap_or_default().to_os_string();
+            name.push(".cov");
+            let name = dir.as_ref().join(name);
+            let file = path.display().to_string();
+            write(&name, coverage.annotate(&file, text))?;
+            written.push(name);
+        }
+        Ok(written)
+    }
+
+    /// The RAM address given to each static, one `address file.index` per
+    /// line in address order.
+    pub fn render_mem(&self) -> Result<String> {
+        if !self.options.static_addresses {
+            return Err(anyhow!(
+                "statics only have addresses with --static-addresses"
+            ));
+        }
+        let mut statics: Vec<_> = self.statics.iter().collect();
+        statics.sort_by_key(|(_, _, address)| *address);
+        let mut buf = String::new();
+        for (file, index, address) in statics {
+            buf.push_str(&format!("{} {}.{}\n", address, file, index));
+        }
+        Ok(buf)
+    }
+
+    pub fn write_mem(&self, memname: impl AsRef<Path>) -> Result<()> {
+        write(memname, self.render_mem()?)?;
 
         Ok(())
     }
+
+    pub fn write_map(&self, mapname: impl AsRef<Path>) -> Result<()> {
+        write(mapname, self.render_map()?)?;
+
+        Ok(())
+    }
+}
+
+// a file's blocks, in order, on the one code generator
+fn gen_file(
+    parser: &mut Parser,
+    dead: &[usize],
+    cg: &mut CodeGen,
+    options: &Options,
+    asm: &mut Vec<Asm>,
+) -> Result<()> {
+    let mut dead = dead.iter().copied().peekable();
+    let mut is_dead = |i: usize| dead.next_if_eq(&i).is_some();
+    if options.retain_ir {
+        let lines = parser.lines();
+        for (i, line) in lines.iter().enumerate() {
+            let raw = parser.raws().get(i);
+            let span = parser.spans().get(i).cloned();
+            asm.push(gen_asm(
+                cg,
+                line,
+                lines.get(i + 1),
+                is_dead(i),
+                raw,
+                span,
+                options,
+            )?);
+        }
+    } else {
+        // each line is dropped as soon as its block exists
+        let raws = parser.take_raws();
+        let mut lines = parser.drain().enumerate().peekable();
+        while let Some((i, (line, span))) = lines.next() {
+            let next = lines.peek().map(|(_, (next, _))| next);
+            asm.push(gen_asm(
+                cg,
+                &line,
+                next,
+                is_dead(i),
+                raws.get(i),
+                span,
+                options,
+            )?);
+        }
+    }
+    Ok(())
+}
+
+// a file's blocks, a run of whole functions at a time on a generator of
+// its own, spread over `jobs` threads. A function's code depends on
+// nothing before it but how many asserts and halts came first, which
+// each run is told up front.
+fn gen_chunks(
+    parser: &Parser,
+    dead: &[usize],
+    new_cg: &(impl Fn() -> CodeGen + Sync),
+    first: &CodeGen,
+    options: &Options,
+    jobs: usize,
+) -> Result<(Vec<Asm>, Vec<CodeGen>)> {
+    let lines = parser.lines();
+    let chunks = chunks(lines, jobs * 4);
+    let mut bases = Vec::new();
+    let (mut asserts, mut halts) = (first.next_assert(), first.next_halt());
+    for chunk in &chunks {
+        bases.push((asserts, halts));
+        for line in &lines[chunk.clone()] {
+            match line {
+                Line::Extension(ExtensionToken::Assert) => asserts += 1,
+                Line::Extension(ExtensionToken::Halt) => halts += 1,
+                _ => {}
+            }
+        }
+    }
+
+    let gen_chunk = |k: usize| -> Result<(Vec<Asm>, CodeGen)> {
+        let (asserts, halts) = bases[k];
+        let mut cg = new_cg().with_assert_base(asserts).with_halt_base(halts);
+        let blocks = chunks[k]
+            .clone()
+            .map(|i| {
+                gen_asm(
+                    &mut cg,
+                    &lines[i],
+                    lines.get(i + 1),
+                    dead.binary_search(&i).is_ok(),
+                    parser.raws().get(i),
+                    parser.spans().get(i).cloned(),
+                    options,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok((blocks, cg))
+    };
+    // each thread takes the next chunk nobody has yet
+    let next = AtomicUsize::new(0);
+    let mut done: Vec<_> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.min(chunks.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let k = next.fetch_add(1, Ordering::Relaxed);
+                        if k >= chunks.len() {
+                            return done;
+                        }
+                        done.push((k, gen_chunk(k)));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker.join().unwrap_or_else(|e| panic::resume_unwind(e))
+            })
+            .collect()
+    });
+    done.sort_by_key(|(k, _)| *k);
+
+    let mut asm = Vec::with_capacity(lines.len());
+    let mut cgs = Vec::with_capacity(chunks.len());
+    for (_, chunk) in done {
+        let (blocks, cg) = chunk?;
+        asm.extend(blocks);
+        cgs.push(cg);
+    }
+    Ok((asm, cgs))
+}
+
+// about `n` runs of whole functions, the first taking whatever comes
+// before the first function
+fn chunks(lines: &[Line], n: usize) -> Vec<Range<usize>> {
+    let size = lines.len() / n + 1;
+    let mut starts = vec![0];
+    for (i, line) in lines.iter().enumerate() {
+        let last = starts[starts.len() - 1];
+        if let Line::Function(FunctionToken::Function { .. }) = line {
+            if i - last >= size {
+                starts.push(i);
+            }
+        }
+    }
+    starts.push(lines.len());
+    starts.windows(2).map(|run| run[0]..run[1]).collect()
+}
+
+fn gen_asm(
+    cg: &mut CodeGen,
+    line: &Line,
+    next: Option<&Line>,
+    dead: bool,
+    raw: Option<&str>,
+    span: Option<Span>,
+    options: &Options,
+) -> Result<Asm> {
+    // IR built without source falls back to the canonical text
+    let src = match (raw, options.comments) {
+        (Some(raw), Comments::Source) => format!("// {}", raw),
+        _ => format!("// {}", line),
+    };
+    let explain = options.explain.then(|| match dead {
+        true => "the value is never read: drop it".to_string(),
+        false => cg.explain(line),
+    });
+    let bin = match dead {
+        true => cg.gen_discard(line),
+        false => cg.gen_block_before(line, next).map_err(|e| {
+            let diagnostic = Diagnostic::error(Class::Semantic, e);
+            match &span {
+                Some(span) => diagnostic.at(span.clone()),
+                None => diagnostic,
+            }
+        })?,
+    };
+    Ok(Asm {
+        src,
+        bin,
+        span,
+        function: cg.function().map(str::to_string),
+        command: Some(line.name()),
+        explain,
+        before: Vec::new(),
+        after: Vec::new(),
+    })
+}
+
+// output paths can name directories that don't exist yet; the contents go
+// to a temporary file beside the output first, then replace it whole, so
+// an interrupted run never leaves half a file behind
+fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("cannot create {}", parent.display()))?;
+    }
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{}.tmp", process::id()));
+    let temp = path.with_file_name(name);
+    fs::write(&temp, contents)
+        .and_then(|_| fs::rename(&temp, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temp);
+        })
+        .with_context(|| format!("cannot write {}", path.display()))
+}
+
+// the distinct static indices `lines` use, in order
+fn static_indices(lines: &[Line]) -> Vec<u16> {
+    let indices: BTreeSet<u16> = lines
+        .iter()
+        .filter_map(|line| match line {
+            Line::Stack(
+                StackToken::Push {
+                    segment: Segment::Static,
+                    index,
+                }
+                | StackToken::Pop {
+                    segment: Segment::Static,
+                    index,
+                },
+            ) => Some(*index),
+            _ => None,
+        })
+        .collect();
+    indices.into_iter().collect()
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+// FNV-1a, which unlike std's hasher is the same from one build to the next
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn header(fingerprint: u64) -> String {
+    format!(
+        "{}// codegen version: {}\n// input hash: {:016x}\n",
+        HEADER,
+        codegen::CODEGEN_VERSION,
+        fingerprint
+    )
+}
+
+// whether blocks get their comment, and the output its header
+fn commented(options: &Options) -> bool {
+    options.comments != Comments::None && !options.minify
+}
+
+fn style(options: &Options) -> Style {
+    match options.minify {
+        true => Style::MINIFIED,
+        false => options.style,
+    }
+}
+
+fn render_blocks(
+    blocks: &[&Asm],
+    options: &Options,
+    fingerprint: u64,
+    mut rom: usize,
+) -> String {
+    let header = header(fingerprint);
+    let comments = commented(options);
+    let style = style(options);
+    let blank_lines = style.blank_lines(comments);
+    let addresses = match options.minify {
+        true => Addresses::Off,
+        false => options.addresses,
+    };
+
+    // sized up front so the buffer is allocated once
+    let size: usize = blocks
+        .iter()
+        .map(|asm| {
+            asm.src.len()
+                + 3
+                + asm.bin.iter().map(|b| b.text_len() + 1).sum::<usize>()
+        })
+        .sum();
+    let mut buf = String::with_capacity(header.len() + size);
+
+    if comments {
+        buf.push_str(&header);
+    }
+    for (i, asm) in blocks.iter().enumerate() {
+        // after the header, or between blocks
+        if comments || i > 0 {
+            for _ in 0..blank_lines {
+                buf.push('\n');
+            }
+        }
+        for note in &asm.before {
+            buf.push_str(note);
+            buf.push('\n');
+        }
+        if comments {
+            buf.push_str(&asm.src);
+            if addresses == Addresses::Block {
+                buf.push_str(&format!(" (ROM {})", rom));
+            }
+            buf.push('\n');
+            for line in asm.explain_lines() {
+                buf.push_str(&line);
+                buf.push('\n');
+            }
+        }
+        for (j, binline) in asm.bin.iter().enumerate() {
+            let label = binline.is_label();
+            let annotate = match addresses {
+                Addresses::Off => false,
+                // without a comment line, the block's first line carries it
+                Addresses::Block => !comments && j == 0,
+                Addresses::Instruction => !label,
+            };
+            buf.push_str(style.indent(binline));
+            let start = buf.len();
+            style.write(&mut buf, binline);
+            if annotate {
+                let width = buf.len() - start;
+                buf.extend((width..15).map(|_| ' '));
+                // writing to a String can't fail
+                let _ = write!(buf, " // {}", rom);
+            }
+            buf.push('\n');
+            if !label {
+                rom += 1;
+            }
+        }
+        for note in &asm.after {
+            buf.push_str(note);
+            buf.push('\n');
+        }
+    }
+
+    match options.newline {
+        Newline::Lf => buf,
+        Newline::Crlf => buf.replace('\n', "\r\n"),
+    }
 }
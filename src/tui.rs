@@ -0,0 +1,187 @@
+use anyhow::Result;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, Paragraph},
+    DefaultTerminal, Frame,
+};
+
+use crate::{debugger::Debugger, emulator::Cpu};
+
+// the segments that sit behind a pointer, by the pointer's address
+const SEGMENTS: [(&str, usize); 4] =
+    [("local", 1), ("argument", 2), ("this", 3), ("that", 4)];
+
+// where temp and the stack live
+const TEMP: usize = 5;
+const STACK: usize = 256;
+
+// words of each segment shown
+const SHOWN: usize = 8;
+
+const KEYS: &str = "[s]tep [n]ext command [c]ontinue [q]uit";
+
+/// Step a CPU in a full-screen view of the ROM around the PC, the VM
+/// command being run, the stack and the segments, which are redrawn after
+/// each step. `listing` has the instruction for each ROM address.
+pub fn run(
+    cpu: &mut Cpu,
+    debugger: &mut Debugger,
+    listing: &[String],
+    cycles: u64,
+) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = interact(&mut terminal, cpu, debugger, listing, cycles);
+    ratatui::restore();
+    result
+}
+
+fn interact(
+    terminal: &mut DefaultTerminal,
+    cpu: &mut Cpu,
+    debugger: &mut Debugger,
+    listing: &[String],
+    cycles: u64,
+) -> Result<()> {
+    let mut status = String::new();
+    loop {
+        terminal.draw(|frame| draw(frame, cpu, debugger, listing, &status))?;
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        let stop = match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Char('s') => {
+                debugger.stepping = true;
+                debugger.run(cpu, cycles)
+            }
+            KeyCode::Char('n') | KeyCode::Enter => {
+                debugger.step_command(cpu, cycles)
+            }
+            KeyCode::Char('c') => debugger.run(cpu, cycles),
+            _ => continue,
+        };
+        status = match stop {
+            Some(stop) => stop.to_string(),
+            None if cpu.halted() => "halted".to_string(),
+            None => format!("stopped after {} cycles", cycles),
+        };
+    }
+}
+
+fn draw(
+    frame: &mut Frame,
+    cpu: &Cpu,
+    debugger: &Debugger,
+    listing: &[String],
+    status: &str,
+) {
+    let [top, segments, footer] = Layout::vertical([
+        Constraint::Min(6),
+        Constraint::Length(SEGMENTS.len() as u16 + 5),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [rom, vm, stack] = Layout::horizontal([
+        Constraint::Percentage(35),
+        Constraint::Percentage(40),
+        Constraint::Percentage(25),
+    ])
+    .areas(top);
+
+    let pc = cpu.pc as usize;
+    let lines = listing
+        .iter()
+        .enumerate()
+        .map(|(address, code)| {
+            let mark = if debugger.is_break(address as u16) {
+                '*'
+            } else {
+                ' '
+            };
+            format!("{}{:5} {}", mark, address, code)
+        })
+        .collect();
+    panel(frame, rom, "ROM", lines, Some(pc));
+
+    let lines = debugger
+        .origins()
+        .iter()
+        .map(|origin| origin.command.clone())
+        .collect();
+    panel(frame, vm, "VM", lines, debugger.origin(cpu.pc));
+
+    // top of the stack first
+    let sp = (cpu.ram[0].max(0) as usize).clamp(STACK, cpu.ram.len());
+    let lines = (STACK..sp)
+        .rev()
+        .map(|address| format!("{:5} {}", address, cpu.ram[address]))
+        .collect();
+    panel(frame, stack, "Stack", lines, None);
+
+    let mut lines = vec![
+        format!("PC={} A={} D={} cycle {}", cpu.pc, cpu.a, cpu.d, cpu.cycles),
+        format!(
+            "SP={} LCL={} ARG={} THIS={} THAT={}",
+            cpu.ram[0], cpu.ram[1], cpu.ram[2], cpu.ram[3], cpu.ram[4]
+        ),
+    ];
+    for &(name, pointer) in &SEGMENTS {
+        let base = cpu.ram[pointer].max(0) as usize;
+        lines.push(words(cpu, name, base));
+    }
+    lines.push(words(cpu, "temp", TEMP));
+    panel(frame, segments, "Segments", lines, None);
+
+    let help = match status {
+        "" => KEYS.to_string(),
+        _ => format!("{}  {}", status, KEYS),
+    };
+    frame.render_widget(Paragraph::new(help), footer);
+}
+
+// a bordered list, scrolled to keep `selected` near the middle and showing
+// it highlighted
+fn panel(
+    frame: &mut Frame,
+    area: Rect,
+    title: &str,
+    lines: Vec<String>,
+    selected: Option<usize>,
+) {
+    let height = area.height.saturating_sub(2) as usize;
+    let start = match selected {
+        Some(selected) => selected
+            .saturating_sub(height / 2)
+            .min(lines.len().saturating_sub(height)),
+        None => 0,
+    };
+    let lines: Vec<Line> = lines
+        .into_iter()
+        .enumerate()
+        .skip(start)
+        .take(height)
+        .map(|(i, text)| match Some(i) == selected {
+            true => Line::from(text)
+                .style(Style::new().add_modifier(Modifier::REVERSED)),
+            false => Line::from(text),
+        })
+        .collect();
+    let block = Block::bordered().title(title);
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+// the first words of a segment, with where it starts
+fn words(cpu: &Cpu, name: &str, base: usize) -> String {
+    let words: Vec<String> = cpu
+        .ram
+        .iter()
+        .skip(base)
+        .take(SHOWN)
+        .map(i16::to_string)
+        .collect();
+    format!("{:8} {:5} [{}]", name, base, words.join(", "))
+}
@@ -0,0 +1,179 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    assembler,
+    instruction::Instruction,
+    parser::{BranchToken, FunctionToken, Line, Parser},
+};
+
+fn symbol(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_.$:".contains(c))
+}
+
+/// Check one line of generated asm against the Hack assembly grammar as
+/// the course's assembler reads it, stricter than our own assembler: comp
+/// mnemonics must be spelled as in the spec and addresses must fit 15
+/// bits.
+pub fn check_line(line: &str) -> Result<()> {
+    let code = line.split("//").next().unwrap_or("").trim();
+    // only jumps are written with inner spaces, so most lines are borrowed
+    let code: Cow<str> = if code.contains(char::is_whitespace) {
+        code.chars().filter(|c| !c.is_whitespace()).collect()
+    } else {
+        code.into()
+    };
+    if code.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(label) = code.strip_prefix('(') {
+        return match label.strip_suffix(')') {
+            Some(label) => check_label(label),
+            None => Err(anyhow!("invalid label")),
+        };
+    }
+
+    if let Some(value) = code.strip_prefix('@') {
+        return check_address(value);
+    }
+
+    let instruction = Instruction::read_c(&code, false)?;
+    // with the dest's letters in the spec's order, too
+    match (instruction, code.split_once('=')) {
+        (Instruction::C { dest, .. }, Some((written, _)))
+            if written != dest.name() =>
+        {
+            Err(anyhow!("invalid dest: {}", written))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Like `check_line`, for an instruction codegen made. Its C-instructions
+/// were checked as they were made, so only names and numbers are left.
+pub fn check_instruction(instruction: &Instruction) -> Result<()> {
+    match instruction {
+        Instruction::A(value) => check_address(value),
+        Instruction::Label(label) => check_label(label),
+        Instruction::C { .. } => Ok(()),
+    }
+}
+
+fn check_label(label: &str) -> Result<()> {
+    match symbol(label) {
+        true => Ok(()),
+        false => Err(anyhow!("invalid label")),
+    }
+}
+
+fn check_address(value: &str) -> Result<()> {
+    if value.starts_with(|c: char| c.is_ascii_digit()) {
+        return match value.parse::<u16>() {
+            Ok(v) if v < 0x8000 => Ok(()),
+            _ => Err(anyhow!("invalid address")),
+        };
+    }
+    match symbol(value) {
+        true => Ok(()),
+        false => Err(anyhow!("invalid symbol")),
+    }
+}
+
+/// What's wrong with a program's IR: commands out of range, labels defined
+/// more than once in a scope or jumped to without being defined there, and
+/// calls to functions defined nowhere in the program.
+pub fn check_ir(parsers: &[Parser]) -> BTreeSet<String> {
+    let mut problems = BTreeSet::new();
+    // labels by scope, which is the function or, outside one, the file
+    let mut labels: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+    let mut jumps = Vec::new();
+    let mut functions = BTreeSet::new();
+    let mut calls = BTreeSet::new();
+    for parser in parsers {
+        let mut scope = parser.filename().to_string();
+        for line in parser.lines() {
+            if let Err(err) = line.validate() {
+                problems.insert(format!("{}: `{}`: {}", scope, line, err));
+            }
+            match line {
+                Line::Function(FunctionToken::Function { name, .. }) => {
+                    scope = name.clone();
+                    if !functions.insert(name.as_str()) {
+                        problems.insert(format!("{} is defined twice", name));
+                    }
+                }
+                Line::Function(FunctionToken::Call { name, .. }) => {
+                    calls.insert(name.as_str());
+                }
+                Line::Branch(BranchToken::Label(label)) => {
+                    let scoped = labels.entry(scope.clone()).or_default();
+                    if !scoped.insert(label) {
+                        problems.insert(format!(
+                            "{}: label {} is defined twice",
+                            scope, label
+                        ));
+                    }
+                }
+                Line::Branch(
+                    BranchToken::GoTo(label) | BranchToken::IfGoTo(label),
+                ) => jumps.push((scope.clone(), label.as_str())),
+                _ => {}
+            }
+        }
+    }
+    for (scope, label) in jumps {
+        if !labels.get(&scope).is_some_and(|l| l.contains(label)) {
+            problems
+                .insert(format!("{}: no label {} to jump to", scope, label));
+        }
+    }
+    for name in calls.difference(&functions) {
+        problems.insert(format!("call to undefined function {}", name));
+    }
+    problems
+}
+
+/// Each symbol the blocks of instructions refer to that's neither a label
+/// among them, a predefined symbol nor one of `variables`, with the index
+/// of the block that refers to it.
+pub fn undefined_symbols<'a>(
+    blocks: &[&'a [Instruction]],
+    variables: &BTreeSet<String>,
+) -> Vec<(usize, &'a str)> {
+    let predefined = assembler::predefined();
+    let labels: BTreeSet<&str> = blocks
+        .iter()
+        .flat_map(|bin| bin.iter())
+        .filter_map(|instruction| match instruction {
+            Instruction::Label(label) => Some(label.as_ref()),
+            _ => None,
+        })
+        .collect();
+    let mut undefined = Vec::new();
+    for (i, bin) in blocks.iter().enumerate() {
+        for instruction in bin.iter() {
+            let value = match instruction {
+                Instruction::A(value) => value.as_ref(),
+                _ => continue,
+            };
+            if value.starts_with(|c: char| c.is_ascii_digit())
+                || labels.contains(value)
+                || predefined.contains_key(value)
+                || variables.contains(value)
+            {
+                continue;
+            }
+            undefined.push((i, value));
+        }
+    }
+    undefined
+}
@@ -0,0 +1,25 @@
+use n2t_vm_translator::{arena::Arena, translator::Translator};
+
+#[test]
+fn strings_come_back_as_pushed() {
+    let mut arena = Arena::new();
+    for s in ["push constant 7", "", "add  // sum"] {
+        arena.push(s);
+    }
+    assert_eq!(arena.len(), 3);
+    assert_eq!(arena.get(0), Some("push constant 7"));
+    assert_eq!(arena.get(1), Some(""));
+    assert_eq!(arena.get(2), Some("add  // sum"));
+    assert_eq!(arena.get(3), None);
+    assert_eq!(arena.iter().count(), 3);
+}
+
+#[test]
+fn source_text_survives_codegen() {
+    let source = "push constant 7  // seven\n\n  push constant 8\nadd\n";
+    let mut translator = Translator::from_source("Main", source);
+    translator.process().unwrap();
+    let asm = translator.render();
+    assert!(asm.contains("// push constant 7  // seven\n"));
+    assert!(asm.contains("//   push constant 8\n"));
+}
@@ -0,0 +1,53 @@
+use n2t_vm_translator::{
+    backend::{self, Backend, Hack, C},
+    translator::{Options, Translator},
+};
+
+const PROGRAM: &str = "\
+function Sys.init 0
+push constant 3
+call Main.double 1
+pop static 0
+label END
+goto END
+function Main.double 1
+push argument 0
+push argument 0
+add
+return
+";
+
+fn translate(retain_ir: bool) -> Translator {
+    let options = Options {
+        retain_ir,
+        ..Options::default()
+    };
+    let mut translator =
+        Translator::from_source("Sys", PROGRAM).with_options(options);
+    translator.process().unwrap();
+    translator
+}
+
+#[test]
+fn targets_from_one_run() {
+    let translator = translate(true);
+    let targets = backend::targets("hack,c,hack").unwrap();
+    let names: Vec<_> = targets.iter().map(|t| t.extension()).collect();
+    assert_eq!(names, ["asm", "c"]);
+    assert_eq!(Hack.generate(&translator).unwrap(), translator.render());
+
+    let c = C.generate(&translator).unwrap();
+    // the bootstrap call returns past the last command, ending the run
+    assert!(c.contains("PUSH(11); PUSH(ram[1])"));
+    assert!(c.contains("case 6:\n            /* function Main.double 1 */"));
+    assert!(c.contains("x = POP(); ram[16] = x;"));
+    assert!(c.contains("/* goto END */\n            return 0;"));
+
+    assert!(backend::targets("hack,arm").is_err());
+}
+
+#[test]
+fn c_needs_the_ir() {
+    let translator = translate(false);
+    assert!(C.generate(&translator).is_err());
+}
@@ -0,0 +1,31 @@
+use std::{fs, path::Path};
+
+use n2t_vm_translator::translator::Backup;
+
+#[test]
+fn backups_keep_the_old_output() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("backup");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let asm = dir.join("Main.asm");
+
+    // nothing to keep yet
+    assert_eq!(Backup::Simple.keep(&asm).unwrap(), None);
+
+    fs::write(&asm, "first").unwrap();
+    let bak = Backup::Simple.keep(&asm).unwrap().unwrap();
+    assert_eq!(bak, dir.join("Main.asm.bak"));
+    assert!(!asm.exists());
+
+    // a simple backup replaces the one before
+    fs::write(&asm, "second").unwrap();
+    Backup::Simple.keep(&asm).unwrap();
+    assert_eq!(fs::read_to_string(&bak).unwrap(), "second");
+
+    for (n, text) in ["third", "fourth"].iter().enumerate() {
+        fs::write(&asm, text).unwrap();
+        let kept = Backup::Numbered.keep(&asm).unwrap().unwrap();
+        assert_eq!(kept, dir.join(format!("Main.asm.~{}~", n + 1)));
+        assert_eq!(fs::read_to_string(&kept).unwrap(), *text);
+    }
+}
@@ -0,0 +1,130 @@
+use std::io::Cursor;
+
+use n2t_vm_translator::dap::Server;
+use serde_json::{json, Value};
+
+// run a scripted session, returning everything the server sent
+fn session(requests: &[(&str, Value)]) -> Vec<Value> {
+    let mut input = String::new();
+    for (seq, (command, arguments)) in requests.iter().enumerate() {
+        let body = json!({
+            "seq": seq + 1,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        })
+        .to_string();
+        input.push_str(&format!(
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+    }
+    let mut output = Vec::new();
+    Server::new()
+        .serve(&mut Cursor::new(input), &mut output)
+        .unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    output
+        .split("Content-Length: ")
+        .filter(|msg| !msg.is_empty())
+        .map(|msg| {
+            serde_json::from_str(msg.split_once("\r\n\r\n").unwrap().1).unwrap()
+        })
+        .collect()
+}
+
+fn response(messages: &[Value], request_seq: u64) -> &Value {
+    messages
+        .iter()
+        .find(|msg| {
+            msg["type"] == "response" && msg["request_seq"] == request_seq
+        })
+        .unwrap()
+}
+
+// the event sent after the response to a request
+fn event_after(messages: &[Value], request_seq: u64) -> &Value {
+    let at = messages
+        .iter()
+        .position(|msg| {
+            msg["type"] == "response" && msg["request_seq"] == request_seq
+        })
+        .unwrap();
+    &messages[at + 1]
+}
+
+#[test]
+fn debug_session() {
+    let main = "tests/fixtures/Program/Main.vm";
+    let messages = session(&[
+        ("initialize", json!({})),
+        ("launch", json!({ "program": "tests/fixtures/Program" })),
+        (
+            "setBreakpoints",
+            json!({
+                "source": { "path": main },
+                "breakpoints": [{ "line": 16 }, { "line": 40 }],
+            }),
+        ),
+        ("configurationDone", json!({})),
+        ("stackTrace", json!({ "threadId": 1 })),
+        ("variables", json!({ "variablesReference": 1 })),
+        ("variables", json!({ "variablesReference": 2 })),
+        ("next", json!({ "threadId": 1 })),
+        (
+            "setBreakpoints",
+            json!({ "source": { "path": main }, "breakpoints": [] }),
+        ),
+        ("stepOut", json!({ "threadId": 1 })),
+        ("stackTrace", json!({ "threadId": 1 })),
+        ("continue", json!({ "threadId": 1 })),
+        ("disconnect", json!({})),
+    ]);
+    assert!(messages
+        .iter()
+        .all(|msg| msg["type"] != "response" || msg["success"] == true));
+    assert_eq!(event_after(&messages, 2)["event"], "initialized");
+
+    // past the end, with no command to stop at
+    let breakpoints = &response(&messages, 3)["body"]["breakpoints"];
+    assert_eq!(breakpoints[0]["verified"], true);
+    assert_eq!(breakpoints[1]["verified"], false);
+    assert_eq!(event_after(&messages, 4)["body"]["reason"], "breakpoint");
+
+    let frames = &response(&messages, 5)["body"]["stackFrames"];
+    let frames: Vec<(&str, u64)> = frames
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|frame| {
+            (
+                frame["name"].as_str().unwrap(),
+                frame["line"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        frames,
+        [("Main.multiply", 16), ("Main.square", 4), ("Sys.init", 4)]
+    );
+
+    let values = |seq| -> Vec<String> {
+        response(&messages, seq)["body"]["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|variable| variable["value"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(values(6), ["0"]);
+    assert_eq!(values(7), ["4", "4"]);
+
+    assert_eq!(event_after(&messages, 8)["body"]["reason"], "step");
+    assert_eq!(event_after(&messages, 10)["body"]["reason"], "step");
+    let top = &response(&messages, 11)["body"]["stackFrames"][0];
+    assert_eq!(top["name"], "Main.square");
+    assert_eq!(top["line"], 5);
+    assert_eq!(event_after(&messages, 12)["event"], "terminated");
+}
@@ -0,0 +1,45 @@
+use std::{fs, path::Path};
+
+use n2t_vm_translator::translator::Translator;
+
+#[test]
+fn diff_against_existing() {
+    let binname = Path::new(env!("CARGO_TARGET_TMPDIR")).join("Diff.asm");
+    let _ = fs::remove_file(&binname);
+    let mut translator =
+        Translator::from_source("Main", "push constant 1\npush constant 2\n");
+    translator.process().unwrap();
+
+    // nothing there yet, so every line is new
+    let diff = translator.diff(&binname).unwrap();
+    assert!(diff.contains("@@ -0,0 +1,"));
+
+    translator.write_bin(&binname).unwrap();
+    assert_eq!(translator.diff(&binname).unwrap(), "");
+
+    let edited = fs::read_to_string(&binname).unwrap().replace("@2", "@3");
+    fs::write(&binname, edited).unwrap();
+    let diff = translator.diff(&binname).unwrap();
+    assert!(diff.contains("\n-@3\n+@2\n"));
+}
+
+#[test]
+fn up_to_date_compares_everything() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("up_to_date");
+    let _ = fs::remove_dir_all(&dir);
+    let binname = dir.join("Main.asm");
+    let mut translator =
+        Translator::from_source("Main", "push constant 1\npush constant 2\n");
+    translator.process().unwrap();
+    assert!(!translator.up_to_date(&binname));
+
+    translator.write_bin(&binname).unwrap();
+    assert!(translator.up_to_date(&binname));
+    // only the output is left behind
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+    // an edit below the header makes it stale
+    let edited = fs::read_to_string(&binname).unwrap().replace("@2", "@3");
+    fs::write(&binname, edited).unwrap();
+    assert!(!translator.up_to_date(&binname));
+}
@@ -0,0 +1,23 @@
+#![cfg(feature = "e2e")]
+
+use std::path::Path;
+
+use n2t_vm_translator::grade::{self, Status};
+
+// the official project 7 and 8 programs, laid out as in the course's
+// projects folder, each with its test script and expected output
+const OFFICIAL: &str = "tests/official";
+
+#[test]
+fn official_programs() {
+    let official = Path::new(OFFICIAL);
+    let outcomes = grade::grade(official, official).unwrap();
+    assert_eq!(outcomes.len(), 11);
+
+    let failures: Vec<String> = outcomes
+        .iter()
+        .filter(|outcome| outcome.status != Status::Pass)
+        .map(|outcome| format!("{}: {}", outcome.test, outcome.detail))
+        .collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
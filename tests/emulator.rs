@@ -0,0 +1,211 @@
+use n2t_vm_translator::{
+    assembler,
+    debugger::{Breakpoint, Debugger, Stop},
+    emulator::{Cpu, KBD, SCREEN},
+    keyboard, screen,
+    script::Dump,
+    translator::Translator,
+};
+
+fn load(asm: &str) -> Cpu {
+    Cpu::new(assembler::assemble(asm).unwrap().words)
+}
+
+#[test]
+fn screen_and_keyboard() {
+    // copy the keyboard to the first screen word, forever, and try to
+    // overwrite the keyboard on the way
+    let mut cpu =
+        load("(LOOP)\n@KBD\nD=M\n@SCREEN\nM=D\n@KBD\nM=0\n@LOOP\n0; JMP\n");
+    cpu.run(100);
+    assert_eq!(cpu.screen()[0], 0);
+    assert!(cpu.take_screen_changed());
+    assert!(!cpu.take_screen_changed());
+
+    cpu.press(65);
+    cpu.run(100);
+    assert_eq!(cpu.ram[KBD], 65);
+    assert_eq!(cpu.ram[SCREEN], 65);
+    assert!(cpu.take_screen_changed());
+    assert!(cpu.pixel(0, 0) && !cpu.pixel(1, 0) && cpu.pixel(6, 0));
+}
+
+#[test]
+fn screen_images() {
+    let mut cpu = load("@5\nD=A\n@SCREEN\nM=D\n(END)\n@END\n0; JMP\n");
+    cpu.run(100);
+
+    let pbm = screen::pbm(&cpu);
+    let header = b"P4\n512 256\n";
+    assert_eq!(&pbm[..header.len()], header);
+    assert_eq!(pbm.len(), header.len() + 512 * 256 / 8);
+    assert_eq!(pbm[header.len()], 0b1010_0000);
+
+    let png = screen::png(&cpu);
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+}
+
+#[test]
+fn scripted_keys() {
+    let events = keyboard::parse("50=up, 10=\"hi\"\n90=none").unwrap();
+    let typing = keyboard::TYPING;
+    assert_eq!(
+        events,
+        vec![
+            (10, 'h' as i16),
+            (50, 131),
+            (90, 0),
+            (10 + typing, 0),
+            (10 + 2 * typing, 'i' as i16),
+            (10 + 3 * typing, 0),
+        ]
+    );
+    assert!(keyboard::parse("10=f13").is_err());
+
+    // keep the last key seen in RAM[0]
+    let mut cpu =
+        load("(LOOP)\n@KBD\nD=M\n@LOOP\nD; JEQ\n@0\nM=D\n@LOOP\n0; JMP\n");
+    cpu.schedule_keys(keyboard::parse("20=65, 40=none").unwrap());
+    cpu.run(100);
+    assert_eq!(cpu.ram[0], 65);
+    assert_eq!(cpu.ram[KBD], 0);
+}
+
+#[test]
+fn dumps() {
+    let mut cpu = load("@7\nD=A\n@257\nM=D\n");
+    cpu.run(4);
+    let dump = Dump::parse("RAM[256..258],PC%D2.6.2").unwrap();
+    assert_eq!(
+        dump.render(&cpu),
+        "|RAM[256]|RAM[257]|    PC    |\n|      0 |      7 |       4  |\n"
+    );
+    assert!(Dump::parse("RAM[a..b]").is_err());
+    assert!(Dump::parse("RAM[5..2]").is_err());
+    assert!(Dump::parse("RAM[5..5]").is_err());
+}
+
+#[test]
+fn breakpoints() {
+    let asm = "@3\nD=A\n(AGAIN)\n@16\nM=M+1\nD=D-1\n@AGAIN\nD; JGT\n\
+               (END)\n@END\n0; JMP\n";
+    let assembled = assembler::assemble(asm).unwrap();
+    let mut cpu = Cpu::new(assembled.words);
+    let mut debugger = Debugger::new(
+        &[
+            Breakpoint::Label("AGAIN".to_string()),
+            Breakpoint::Watch(16),
+        ],
+        &assembled.symbols,
+        Vec::new(),
+    )
+    .unwrap();
+
+    let mut stops = Vec::new();
+    while let Some(stop) = debugger.run(&mut cpu, 100) {
+        stops.push(stop);
+    }
+    let watch = |old, new| Stop::Watch {
+        address: 16,
+        old,
+        new,
+    };
+    assert_eq!(
+        stops,
+        vec![
+            Stop::Break(2),
+            watch(0, 1),
+            Stop::Break(2),
+            watch(1, 2),
+            Stop::Break(2),
+            watch(2, 3),
+        ]
+    );
+    assert!(cpu.halted());
+    assert!(Debugger::new(
+        &[Breakpoint::Label("NOWHERE".to_string())],
+        &assembled.symbols,
+        Vec::new()
+    )
+    .is_err());
+}
+
+#[test]
+fn timing() {
+    let mut translator = Translator::from_source(
+        "Sys",
+        "function Sys.init 0\ncall Sys.f 0\npop temp 0\ncall Sys.f 0\n\
+         label END\ngoto END\n\
+         function Sys.f 1\npush local 0\nreturn\n",
+    );
+    translator.process().unwrap();
+    let mut cpu = load(&translator.render());
+    cpu.run(1000);
+
+    let timing = translator.timing(Some(cpu.executions()));
+    assert_eq!(timing.cycles, cpu.cycles);
+    assert_eq!(timing.functions["Sys.f"].0, 2);
+    assert_eq!(timing.commands["call"].0, 2);
+    assert_eq!(timing.commands["return"].0, 2);
+    assert!(timing.hottest[0].0.starts_with("Sys:"), "{:?}", timing);
+    assert!(timing.hottest.windows(2).all(|w| w[0].1 >= w[1].1));
+    assert!(timing
+        .to_markdown()
+        .starts_with(&format!("{} cycles\n", cpu.cycles)));
+
+    let estimate = translator.timing(None);
+    assert_eq!(estimate.commands["call"].0, 2);
+    assert_eq!(estimate.functions["Sys.f"].0, 1);
+    assert_eq!(estimate.cycles, cpu.rom().len() as u64);
+}
+
+#[test]
+fn step_command() {
+    let mut translator = Translator::from_source(
+        "Main",
+        "push constant 7\npush constant 8\nadd\nlabel END\ngoto END\n",
+    );
+    translator.process().unwrap();
+    let assembled = assembler::assemble(&translator.render()).unwrap();
+    assert_eq!(assembled.source.len(), assembled.words.len());
+    let mut cpu = Cpu::new(assembled.words);
+    cpu.preset_segments();
+    let mut debugger =
+        Debugger::new(&[], &assembled.symbols, translator.origins()).unwrap();
+
+    let mut commands = Vec::new();
+    while let Some(stop) = debugger.step_command(&mut cpu, 1000) {
+        assert_eq!(stop, Stop::Step);
+        let origin = debugger.origin(cpu.pc).unwrap();
+        assert_eq!(debugger.origins()[origin].rom.start, cpu.pc as usize);
+        commands.push(debugger.origins()[origin].command.clone());
+    }
+    assert_eq!(commands, ["push constant 8", "add", "goto END"]);
+    assert_eq!(cpu.ram[256], 15);
+}
+
+#[test]
+fn coverage() {
+    let source = "push constant 1\nif-goto SKIP\npush constant 2\n\
+                  label SKIP\npush constant 3\nlabel END\ngoto END\n";
+    let mut translator = Translator::from_source("Main", source);
+    translator.process().unwrap();
+    let mut cpu = load(&translator.render());
+    cpu.preset_segments();
+    cpu.run(1000);
+
+    let coverage = translator.coverage(cpu.executions(), cpu.pc);
+    assert_eq!(coverage.commands.len(), 7);
+    assert_eq!(coverage.covered(), 6);
+    assert_eq!(
+        coverage.to_text(),
+        "6 of 7 VM commands run (85.7%)\nMain:3: never run: push constant 2\n"
+    );
+    let annotated = coverage.annotate("Main", source);
+    let counts: Vec<&str> = annotated
+        .lines()
+        .map(|line| line.split(':').next().unwrap().trim())
+        .collect();
+    assert_eq!(counts, ["1", "1", "#####", "1", "1", "1", "1"]);
+}
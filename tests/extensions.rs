@@ -0,0 +1,93 @@
+// VM commands beyond the spec, run on the emulator.
+
+use n2t_vm_translator::{
+    assembler,
+    codegen::{ASSERT_ADDRESS, HALT_ADDRESS, PRINT_CURSOR},
+    emulator::Cpu,
+    translator::{Options, Translator},
+};
+
+fn run(source: &str, opt_level: u8) -> Cpu {
+    let options = Options {
+        extensions: true,
+        opt_level,
+        ..Options::default()
+    };
+    let mut translator =
+        Translator::from_source("Test", source).with_options(options);
+    translator.process().unwrap();
+    let assembled = assembler::assemble(&translator.render()).unwrap();
+    let mut cpu = Cpu::new(assembled.words);
+    cpu.preset_segments();
+    cpu.run(10_000);
+    cpu
+}
+
+#[test]
+fn assert() {
+    let source = "push constant 1\nassert\npush constant 3\npush constant 3\n\
+                  eq\nassert\npush constant 7\npop temp 0\npush constant 3\n\
+                  push constant 4\neq\nassert\npush constant 9\npop temp 0\n";
+    for level in 0..=2 {
+        let cpu = run(source, level);
+        // the third assert fails, before the second store
+        assert_eq!(cpu.ram[ASSERT_ADDRESS as usize], 3, "-O{}", level);
+        assert_eq!(cpu.ram[5], 7, "-O{}", level);
+    }
+}
+
+#[test]
+fn halt() {
+    let source = "push constant 1\nif-goto A\nhalt\nlabel A\npush constant 4\n\
+                  pop temp 0\nhalt\npush constant 9\npop temp 0\n";
+    for level in 0..=2 {
+        let cpu = run(source, level);
+        assert!(cpu.halted(), "-O{}", level);
+        assert_eq!(cpu.ram[HALT_ADDRESS as usize], 2, "-O{}", level);
+        assert_eq!(cpu.ram[5], 4, "-O{}", level);
+    }
+}
+
+#[test]
+fn print() {
+    let source = "push constant 5\npush constant 1234\nprint\n\
+                  push constant 0\nnot\nprint\npop temp 0\n";
+    for level in 0..=2 {
+        let cpu = run(source, level);
+        let row = 16384 + 248 * 32;
+        for pixels in 0..8 {
+            assert_eq!(cpu.ram[row + pixels * 32], 1234, "-O{}", level);
+            assert_eq!(cpu.ram[row + pixels * 32 + 1], -1, "-O{}", level);
+        }
+        assert_eq!(cpu.ram[PRINT_CURSOR as usize], 2, "-O{}", level);
+        assert_eq!(cpu.ram[5], 5, "-O{}", level);
+    }
+}
+
+#[test]
+fn strict_keeps_to_the_spec() {
+    let parse = |source: &str| {
+        let options = Options {
+            strict: true,
+            ..Options::default()
+        };
+        Translator::from_source("Test", source)
+            .with_options(options)
+            .parse()
+    };
+    for source in &[
+        "assert\n",
+        "push constant -1\n",
+        "push constant +5\n",
+        "push\tconstant 1\n",
+        "add 3\n",
+    ] {
+        assert!(parse(source).is_err(), "{:?}", source);
+    }
+    // the course's own files put tabs before their comments
+    let basic_loop = std::fs::read_to_string(
+        "tests/official/projects/08/ProgramFlow/BasicLoop/BasicLoop.vm",
+    )
+    .unwrap();
+    assert!(parse(&basic_loop).is_ok());
+}
@@ -0,0 +1,58 @@
+use std::{fs, path::Path};
+
+use n2t_vm_translator::{
+    glob,
+    translator::{Options, Translator},
+};
+
+#[test]
+fn patterns_find_files() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("glob");
+    let _ = fs::remove_dir_all(&dir);
+    for sub in ["a/b", ".hidden"] {
+        fs::create_dir_all(dir.join(sub)).unwrap();
+    }
+    for (file, source) in [
+        ("Main.vm", "function Main.main 0\ncall Lib.f 0\nreturn\n"),
+        ("notes.txt", ""),
+        ("a/Lib.vm", "function Lib.f 0\ncall Util.g 0\nreturn\n"),
+        (
+            "a/b/Util.vm",
+            "function Util.g 0\npush constant 1\nreturn\n",
+        ),
+        (".hidden/Old.vm", "function Old.f 0\nreturn\n"),
+    ] {
+        fs::write(dir.join(file), source).unwrap();
+    }
+
+    let found = |pattern: &str| -> Vec<String> {
+        let pattern = dir.join(pattern);
+        assert!(glob::is_glob(&pattern));
+        assert_eq!(glob::base(&pattern), dir);
+        glob::expand(&pattern)
+            .unwrap()
+            .iter()
+            .map(|path| {
+                let path = path.strip_prefix(&dir).unwrap();
+                path.to_string_lossy().replace('\\', "/")
+            })
+            .collect()
+    };
+    assert_eq!(found("*.vm"), ["Main.vm"]);
+    assert_eq!(found("**/*.vm"), ["Main.vm", "a/Lib.vm", "a/b/Util.vm"]);
+    assert_eq!(found("?/**/*.vm"), ["a/Lib.vm", "a/b/Util.vm"]);
+    assert_eq!(found("**/U*"), ["a/b/Util.vm"]);
+    assert_eq!(found(".*/*.vm"), [".hidden/Old.vm"]);
+    assert_eq!(
+        found("**"),
+        ["Main.vm", "a/Lib.vm", "a/b/Util.vm", "notes.txt"]
+    );
+    assert!(!glob::is_glob(&dir));
+
+    // the matches make up one program
+    let mut translator =
+        Translator::open(dir.join("**/*"), Options::default()).unwrap();
+    translator.process().unwrap();
+    assert_eq!(translator.stats().files.len(), 3);
+    assert!(Translator::open(dir.join("*.txt"), Options::default()).is_err());
+}
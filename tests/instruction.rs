@@ -0,0 +1,65 @@
+use n2t_vm_translator::{assembler, instruction::Instruction};
+
+#[test]
+fn written_as_read() {
+    for raw in [
+        "@SP",
+        "@32767",
+        "(Main.f$ret.3)",
+        "0; JMP",
+        "D; JGE",
+        "AM=M-1",
+        "MD=D|M",
+        "AMD=!A",
+        "M=M+1",
+    ] {
+        let instruction = Instruction::parse(raw).unwrap();
+        assert_eq!(instruction.to_string(), raw);
+        assert_eq!(instruction.text_len(), raw.len(), "{}", raw);
+    }
+    for raw in ["D=M+D", "MM=D", "D; JMPS", "(open"] {
+        assert!(Instruction::parse(raw).is_none(), "{}", raw);
+    }
+}
+
+// words from the book, so the table isn't only checked against itself
+#[test]
+fn words_match_the_book() {
+    for (raw, word) in [
+        ("D=A", 0b1110110000010000),
+        ("D=D+A", 0b1110000010010000),
+        ("M=D", 0b1110001100001000),
+        ("D=M", 0b1111110000010000),
+        ("0; JMP", 0b1110101010000111),
+        ("D; JGE", 0b1110001100000011),
+        ("AM=M-1", 0b1111110010101000),
+        ("MD=D|M", 0b1111010101011000),
+        ("A=-1", 0b1110111010100000),
+    ] {
+        let instruction = Instruction::parse(raw).unwrap();
+        assert_eq!(instruction.word(), Some(word), "{}", raw);
+        assert_eq!(assembler::assemble(raw).unwrap().words, [word], "{}", raw);
+    }
+}
+
+#[test]
+fn the_assembler_takes_either_order() {
+    for (commuted, raw) in [
+        ("D=A+D", "D=D+A"),
+        ("D=1+D", "D=D+1"),
+        ("M=M|D", "M=D|M"),
+        ("AM=M&D", "AM=D&M"),
+        ("DM=1+M", "MD=M+1"),
+    ] {
+        assert_eq!(
+            assembler::assemble(commuted).unwrap().words,
+            assembler::assemble(raw).unwrap().words,
+            "{}",
+            commuted
+        );
+        assert!(Instruction::parse(commuted).is_none(), "{}", commuted);
+    }
+    for raw in ["D=A-D+1", "MM=D", "=D", "0;", "D=D+A;JNO"] {
+        assert!(assembler::assemble(raw).is_err(), "{}", raw);
+    }
+}
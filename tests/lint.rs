@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use n2t_vm_translator::{
+    diagnostics::Report,
+    lint::{self, Baseline, Level},
+    translator::{Options, Translator},
+};
+
+#[test]
+fn unreachable_functions() {
+    let mut translator = Translator::from_source(
+        "Main",
+        "function Sys.init 0\ncall Main.a 0\nreturn\n\
+         function Main.a 0\npush constant 1\nreturn\n\
+         function Main.b 2\ncall Main.c 0\nreturn\n\
+         function Main.c 0\ncall Main.b 0\nreturn\n",
+    );
+    translator.process().unwrap();
+    let warnings: Vec<String> = translator
+        .warnings()
+        .iter()
+        .map(|warning| warning.to_string())
+        .collect();
+    assert_eq!(warnings.len(), 2, "{:?}", warnings);
+    assert!(warnings[0].starts_with("Main:7: warning: function Main.b"));
+    assert!(warnings[1].starts_with("Main:10: warning: function Main.c"));
+}
+
+#[test]
+fn budgets() {
+    let mut budgets = BTreeMap::new();
+    budgets.insert("Main.big".to_string(), 100);
+    let options = Options {
+        budget: Some(10),
+        budgets,
+        ..Options::default()
+    };
+    let mut translator = Translator::from_source(
+        "Main",
+        "function Main.main 0\ncall Main.big 0\nreturn\n\
+         function Main.big 0\npush constant 1\npush constant 2\nadd\nreturn\n",
+    )
+    .with_options(options);
+    translator.process().unwrap();
+    let warnings: Vec<String> = translator
+        .warnings()
+        .iter()
+        .map(|warning| warning.to_string())
+        .collect();
+    assert_eq!(warnings.len(), 1, "{:?}", warnings);
+    assert!(warnings[0].starts_with("Main:1: warning: function Main.main is"));
+    assert!(warnings[0].ends_with("over its budget of 10"));
+}
+
+#[test]
+fn levels() {
+    // Main.b is never called and over budget; Main.c is never called
+    let source = "function Sys.init 0\nreturn\n\
+                  function Main.b 0\npush constant 1\npush constant 2\n\
+                  add\nreturn\nfunction Main.c 0\nreturn\n";
+    let run = |levels: &[(&str, Level)]| {
+        let options = Options {
+            budgets: BTreeMap::from([("Main.b".to_string(), 10)]),
+            lints: levels
+                .iter()
+                .map(|(name, level)| (name.to_string(), *level))
+                .collect(),
+            ..Options::default()
+        };
+        let mut translator =
+            Translator::from_source("Main", source).with_options(options);
+        translator.process().map(|_| translator.warnings().len())
+    };
+    assert_eq!(run(&[]).unwrap(), 3);
+    assert_eq!(run(&[("unreachable-code", Level::Allow)]).unwrap(), 1);
+    assert_eq!(run(&[("warnings", Level::Allow)]).unwrap(), 0);
+
+    let denied = run(&[("over-budget", Level::Deny)]).unwrap_err();
+    let report = denied.downcast_ref::<Report>().unwrap();
+    assert_eq!((report.errors(), report.warnings()), (1, 2));
+
+    // a lint's own allow holds against --deny warnings
+    assert!(run(&[("warnings", Level::Deny)]).is_err());
+    let allowed = run(&[
+        ("warnings", Level::Deny),
+        ("unreachable-code", Level::Allow),
+        ("over-budget", Level::Allow),
+    ]);
+    assert_eq!(allowed.unwrap(), 0);
+
+    assert!(lint::check("warnings").is_ok());
+    assert!(lint::check("unused").is_err());
+}
+
+#[test]
+fn static_overflow() {
+    let source: String = (0..250)
+        .map(|i| format!("push constant {}\npop static {}\n", i, i))
+        .collect();
+    let mut translator = Translator::from_source("Main", &source);
+    translator.process().unwrap();
+    assert_eq!(translator.warnings().len(), 1);
+    assert_eq!(translator.warnings()[0].lint, Some("static-overflow"));
+}
+
+#[test]
+fn allowed_in_comments() {
+    let source = "function Sys.init 0\nreturn\n\
+                  // vm-lint: allow(unreachable-code)\n\
+                  function Main.b 0\nreturn\n\
+                  function Main.c 0 // vm-lint: allow(warnings)\nreturn\n\
+                  function Main.d 0 // vm-lint: allow(over-budget)\nreturn\n";
+    let mut translator = Translator::from_source("Main", source);
+    translator.process().unwrap();
+    let warnings: Vec<String> = translator
+        .warnings()
+        .iter()
+        .map(|warning| warning.to_string())
+        .collect();
+    assert_eq!(warnings.len(), 1, "{:?}", warnings);
+    assert!(warnings[0].starts_with("Main:8: warning: function Main.d"));
+
+    for comment in ["vm-lint: allow(unused)", "vm-lint: deny(warnings)"] {
+        let source = format!("// {}\npush constant 1\n", comment);
+        let mut translator = Translator::from_source("Main", &source);
+        assert!(translator.parse().is_err(), "{}", comment);
+    }
+}
+
+#[test]
+fn baseline() {
+    let program = |extra: &str| {
+        format!(
+            "function Sys.init 0\nreturn\n{}function Main.b 0\nreturn\n",
+            extra
+        )
+    };
+    let warnings = |source: &str, baseline: &Baseline| {
+        let options = Options {
+            baseline: baseline.clone(),
+            ..Options::default()
+        };
+        let mut translator =
+            Translator::from_source("Main", source).with_options(options);
+        translator.process().unwrap();
+        (translator.warnings().len(), translator.baseline())
+    };
+
+    let (found, baseline) = warnings(&program(""), &Baseline::default());
+    assert_eq!((found, baseline.len()), (1, 1));
+    let baseline = Baseline::parse(&baseline.to_json()).unwrap();
+
+    // known, even after lines move; a new one is still reported
+    let moved = program("function Main.a 0\npush constant 1\nreturn\n");
+    assert_eq!(warnings(&moved, &baseline).0, 1);
+    assert_eq!(warnings(&program("\n\n"), &baseline).0, 0);
+
+    assert!(Baseline::parse("{\"warnings\": [{\"lint\": \"x\"}]}").is_err());
+}
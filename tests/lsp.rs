@@ -0,0 +1,221 @@
+use std::{fs, io::Cursor, path::Path};
+
+use n2t_vm_translator::lsp::Server;
+use serde_json::{json, Value};
+
+// run a scripted session, returning everything the server sent
+fn session(messages: &[(Option<u64>, &str, Value)]) -> Vec<Value> {
+    let mut input = String::new();
+    for (id, method, params) in messages {
+        let mut msg = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        });
+        if let Some(id) = id {
+            msg["id"] = json!(id);
+        }
+        let body = msg.to_string();
+        input.push_str(&format!(
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+    }
+    let mut output = Vec::new();
+    Server::new()
+        .serve(&mut Cursor::new(input), &mut output)
+        .unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    output
+        .split("Content-Length: ")
+        .filter(|msg| !msg.is_empty())
+        .map(|msg| {
+            serde_json::from_str(msg.split_once("\r\n\r\n").unwrap().1).unwrap()
+        })
+        .collect()
+}
+
+fn result(messages: &[Value], id: u64) -> &Value {
+    &messages.iter().find(|msg| msg["id"] == id).unwrap()["result"]
+}
+
+fn uri(file: &str) -> String {
+    format!(
+        "file://{}/tests/fixtures/Program/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        file
+    )
+}
+
+// where each location is, as (file, line, character)
+fn places(locations: &Value) -> Vec<(String, u64, u64)> {
+    locations
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|location| {
+            let uri = location["uri"].as_str().unwrap();
+            let start = &location["range"]["start"];
+            (
+                uri.rsplit('/').next().unwrap().to_string(),
+                start["line"].as_u64().unwrap(),
+                start["character"].as_u64().unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn navigation() {
+    // only Sys.vm is open; Main.vm is read from disk
+    let sys = uri("Sys.vm");
+    let text = fs::read_to_string("tests/fixtures/Program/Sys.vm").unwrap();
+    let at = |line: u64, character: u64| {
+        json!({
+            "textDocument": { "uri": sys },
+            "position": { "line": line, "character": character },
+            "context": { "includeDeclaration": true },
+        })
+    };
+    let main = uri("Main.vm");
+    let messages = session(&[
+        (Some(1), "initialize", json!({})),
+        (
+            None,
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": sys, "text": text } }),
+        ),
+        // call Main.square
+        (Some(2), "textDocument/definition", at(3, 12)),
+        // goto HALT
+        (Some(3), "textDocument/references", at(6, 10)),
+        (
+            None,
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": main,
+                    "text": fs::read_to_string(
+                        "tests/fixtures/Program/Main.vm"
+                    ).unwrap(),
+                },
+            }),
+        ),
+        // function Main.square
+        (
+            Some(4),
+            "textDocument/references",
+            json!({
+                "textDocument": { "uri": main },
+                "position": { "line": 0, "character": 12 },
+                "context": { "includeDeclaration": false },
+            }),
+        ),
+        // if-goto DONE
+        (
+            Some(5),
+            "textDocument/definition",
+            json!({
+                "textDocument": { "uri": main },
+                "position": { "line": 14, "character": 13 },
+            }),
+        ),
+        (Some(6), "shutdown", Value::Null),
+        (None, "exit", Value::Null),
+    ]);
+
+    let capabilities = &result(&messages, 1)["capabilities"];
+    assert_eq!(capabilities["definitionProvider"], true);
+    assert_eq!(capabilities["referencesProvider"], true);
+
+    assert_eq!(
+        places(result(&messages, 2)),
+        [("Main.vm".to_string(), 0, 9)]
+    );
+    // the label, and the jump to it
+    assert_eq!(
+        places(result(&messages, 3)),
+        [("Sys.vm".to_string(), 5, 6), ("Sys.vm".to_string(), 6, 9)]
+    );
+    assert_eq!(places(result(&messages, 4)), [("Sys.vm".to_string(), 3, 9)]);
+    assert_eq!(
+        places(result(&messages, 5)),
+        [("Main.vm".to_string(), 24, 6)]
+    );
+}
+
+#[test]
+fn labels_are_scoped_to_their_function() {
+    let uri = "untitled:Main.vm";
+    let text = "function Main.f 0\nlabel L\ngoto L\n\
+                function Main.g 0\nlabel L\ngoto L\n";
+    let messages = session(&[
+        (
+            None,
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": uri, "text": text } }),
+        ),
+        (
+            Some(1),
+            "textDocument/definition",
+            json!({
+                "textDocument": { "uri": uri },
+                "position": { "line": 5, "character": 5 },
+            }),
+        ),
+        (Some(2), "shutdown", Value::Null),
+        (None, "exit", Value::Null),
+    ]);
+    assert_eq!(
+        places(result(&messages, 1)),
+        [("untitled:Main.vm".to_string(), 4, 6)]
+    );
+}
+
+#[test]
+fn uris_are_percent_encoded() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("my program");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for file in ["Main.vm", "Sys.vm"] {
+        fs::copy(
+            Path::new("tests/fixtures/Program").join(file),
+            dir.join(file),
+        )
+        .unwrap();
+    }
+    let uri = |file: &str| {
+        let path = dir.join(file).display().to_string();
+        format!("file://{}", path.replace(' ', "%20"))
+    };
+
+    let sys = uri("Sys.vm");
+    let messages = session(&[
+        (
+            None,
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": sys,
+                    "text": fs::read_to_string(dir.join("Sys.vm")).unwrap(),
+                },
+            }),
+        ),
+        // call Main.square, defined in a file that isn't open
+        (
+            Some(1),
+            "textDocument/definition",
+            json!({
+                "textDocument": { "uri": sys },
+                "position": { "line": 3, "character": 12 },
+            }),
+        ),
+        (Some(2), "shutdown", Value::Null),
+        (None, "exit", Value::Null),
+    ]);
+    let found = result(&messages, 1);
+    assert_eq!(places(found), [("Main.vm".to_string(), 0, 9)]);
+    assert_eq!(found[0]["uri"], uri("Main.vm"));
+}
@@ -0,0 +1,38 @@
+// Inputs that used to panic, found by the fuzz targets in fuzz/. Each has
+// to translate cleanly or fail with an error.
+
+use n2t_vm_translator::{assembler, translator::Translator};
+
+fn translate(text: &str) -> anyhow::Result<String> {
+    let mut translator = Translator::from_source("Regression", text);
+    translator.process()?;
+    Ok(translator.render())
+}
+
+#[test]
+fn many_comparisons() {
+    let asm = translate(&"push constant 1\npush constant 1\neq\n".repeat(300))
+        .unwrap();
+    assert!(assembler::assemble(&asm).is_ok());
+}
+
+#[test]
+fn many_pops() {
+    let asm = translate(&"push constant 1\npop local 0\n".repeat(300)).unwrap();
+    assert!(assembler::assemble(&asm).is_ok());
+}
+
+#[test]
+fn many_calls() {
+    assert!(translate(&"call Foo.bar 0\n".repeat(70_000)).is_ok());
+}
+
+#[test]
+fn call_with_too_many_arguments() {
+    assert!(translate("call Foo.bar 65535\n").is_err());
+}
+
+#[test]
+fn blank_and_indented_comment_lines() {
+    assert!(translate("push constant 1\n \t\n    // comment\n\r\n").is_ok());
+}
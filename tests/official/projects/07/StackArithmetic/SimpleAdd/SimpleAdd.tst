@@ -0,0 +1,15 @@
+// SimpleAdd: translate SimpleAdd.vm to SimpleAdd.asm, then
+// run it for 60 cycles and compare the results with SimpleAdd.cmp
+
+load SimpleAdd.asm,
+output-file SimpleAdd.out,
+compare-to SimpleAdd.cmp,
+output-list RAM[0]%D1.6.1 RAM[256]%D1.6.1;
+
+set RAM[0] 256,
+
+repeat 60 {
+  ticktock;
+}
+
+output;
@@ -0,0 +1,13 @@
+// FibonacciElement: translate FibonacciElement/ to FibonacciElement.asm, then
+// run it for 6000 cycles and compare the results with FibonacciElement.cmp
+
+load FibonacciElement.asm,
+output-file FibonacciElement.out,
+compare-to FibonacciElement.cmp,
+output-list RAM[0]%D1.6.1 RAM[261]%D1.6.1;
+
+repeat 6000 {
+  ticktock;
+}
+
+output;
@@ -0,0 +1,15 @@
+// StaticsTest: translate StaticsTest/ to StaticsTest.asm, then
+// run it for 2500 cycles and compare the results with StaticsTest.cmp
+
+load StaticsTest.asm,
+output-file StaticsTest.out,
+compare-to StaticsTest.cmp,
+output-list RAM[0]%D1.6.1 RAM[261]%D1.6.1 RAM[262]%D1.6.1;
+
+set RAM[0] 256,
+
+repeat 2500 {
+  ticktock;
+}
+
+output;
@@ -0,0 +1,453 @@
+// Optimized output has to leave the machine as unoptimized output does.
+
+use std::collections::BTreeSet;
+
+use n2t_vm_translator::{
+    assembler,
+    emulator::Cpu,
+    optimize,
+    parser::{IrFormat, Line},
+    passes,
+    pgo::Profile,
+    translator::{Options, Translator},
+};
+
+fn run(source: &str, opt_level: u8) -> Cpu {
+    run_with(
+        source,
+        Options {
+            opt_level,
+            ..Options::default()
+        },
+    )
+}
+
+fn run_with(source: &str, mut options: Options) -> Cpu {
+    options.end_loop = true;
+    let mut translator =
+        Translator::from_source("Test", source).with_options(options);
+    translator.process().unwrap();
+    let assembled = assembler::assemble(&translator.render()).unwrap();
+    let mut cpu = Cpu::new(assembled.words);
+    cpu.preset_segments();
+    cpu.run(10_000);
+    cpu
+}
+
+// the stack above SP is garbage either way
+fn diverged(plain: &Cpu, other: &Cpu) -> Vec<usize> {
+    let sp = plain.ram[0] as usize;
+    plain
+        .divergence(other)
+        .into_iter()
+        .filter(|&address| !(sp..2048).contains(&address))
+        .collect()
+}
+
+fn assert_same(source: &str) {
+    let plain = run(source, 0);
+    for level in 1..=2 {
+        let optimized = run(source, level);
+        assert_eq!(
+            diverged(&plain, &optimized),
+            Vec::<usize>::new(),
+            "-O{}\n{}",
+            level,
+            source
+        );
+    }
+}
+
+#[test]
+fn push_then_binary() {
+    for op in &["add", "sub", "and", "or"] {
+        for push in &[
+            "push constant 1",
+            "push constant 12",
+            "push local 0",
+            "push argument 5",
+            "push temp 3",
+            "push static 2",
+        ] {
+            assert_same(&format!(
+                "push constant 7\npop local 0\npush constant 9\n\
+                 pop argument 5\npush constant 11\npop temp 3\n\
+                 push constant 13\npop static 2\n\
+                 push constant 22\n{}\n{}\npop temp 0\n",
+                push, op
+            ));
+        }
+    }
+}
+
+#[test]
+fn batched_pushes_then_binary() {
+    for n in 1..7 {
+        let pushes: String = (0..n)
+            .map(|i| format!("push constant {}\n", i + 3))
+            .collect();
+        let ops = "add\n".repeat(n - 1);
+        assert_same(&format!("{}{}sub\npop temp 0\n", pushes, ops));
+    }
+}
+
+#[test]
+fn comparison_then_if_goto() {
+    for op in &["eq", "gt", "lt"] {
+        for (x, y) in &[(3, 3), (3, 4), (4, 3)] {
+            assert_same(&format!(
+                "push constant {}\npush constant {}\n{}\nif-goto T\n\
+                 push constant 5\npop temp 0\ngoto E\nlabel T\n\
+                 push constant 6\npop temp 0\nlabel E\n",
+                x, y, op
+            ));
+        }
+    }
+}
+
+#[test]
+fn not_then_if_goto() {
+    let branch = "if-goto T\npush constant 5\npop temp 0\ngoto E\nlabel T\n\
+                  push constant 6\npop temp 0\nlabel E\n";
+    for value in &[0, 1, 9] {
+        assert_same(&format!("push constant {}\nnot\n{}", value, branch));
+        assert_same(&format!("push constant {}\nnot\nnot\n{}", value, branch));
+    }
+    for op in &["eq", "gt", "lt"] {
+        for (x, y) in &[(3, 3), (3, 4), (4, 3)] {
+            let compare =
+                format!("push constant {}\npush constant {}\n{}\n", x, y, op);
+            assert_same(&format!("{}not\n{}", compare, branch));
+            assert_same(&format!("{}not\nnot\n{}", compare, branch));
+            assert_same(&format!("{}not\npop temp 1\n", compare));
+        }
+    }
+}
+
+#[test]
+fn binary_chains() {
+    let ops = ["add", "sub", "and", "or"];
+    for (i, first) in ops.iter().enumerate() {
+        for second in &ops {
+            let third = ops[(i + 1) % ops.len()];
+            assert_same(&format!(
+                "push constant 12\npush constant 10\npush constant 7\n\
+                 push constant 1\n{}\n{}\n{}\npop temp 0\n",
+                first, second, third
+            ));
+            assert_same(&format!(
+                "push constant 12\npush constant 10\n{}\npush constant 1\n\
+                 {}\npush local 0\n{}\npop temp 0\n",
+                first, second, third
+            ));
+        }
+    }
+}
+
+#[test]
+fn dead_stores() {
+    let lines: Vec<Line> = [
+        "push constant 1",
+        "pop local 0",
+        "push constant 2",
+        "pop local 0",
+        "push constant 3",
+        "pop temp 1",
+        "push temp 1",
+        "pop temp 1",
+        "push constant 4",
+        "pop static 0",
+        "label L",
+        "push constant 5",
+        "pop static 0",
+        "push constant 6",
+        "pop local 0",
+        "push that 0",
+        "pop local 0",
+    ]
+    .iter()
+    .map(|raw| Line::new(raw).unwrap())
+    .collect();
+    assert_eq!(optimize::dead_stores(&lines), vec![1]);
+
+    let source: String =
+        lines.iter().map(|line| format!("{}\n", line)).collect();
+    assert_same(&source);
+}
+
+#[test]
+fn values_kept_in_d() {
+    for (pop, push) in &[
+        ("pop local 0", "push local 0"),
+        ("pop local 12", "push local 12"),
+        ("pop temp 2", "push temp 2"),
+        ("pop static 1", "push static 1"),
+        ("pop pointer 1", "push pointer 1"),
+        ("pop that 3", "push that 3"),
+    ] {
+        assert_same(&format!(
+            "push constant 3010\npop pointer 1\npush constant 8\n{}\n\
+             {}\n{}\nadd\n{}\n{}\npop temp 0\n",
+            pop, push, push, pop, push
+        ));
+    }
+    assert_same(
+        "push constant 5\npush constant 5\npush constant 5\npush constant 5\n\
+         push constant 5\nadd\nadd\nadd\nadd\npop temp 0\n",
+    );
+}
+
+#[test]
+fn shared_routines() {
+    let mut sources = Vec::new();
+    for op in &["eq", "gt", "lt"] {
+        for (x, y) in &[(3, 3), (3, 4), (4, 3)] {
+            sources.push(format!(
+                "push constant {}\npush constant {}\n{}\npop temp 0\n",
+                x, y, op
+            ));
+        }
+    }
+    sources.push(
+        "push constant 3\ncall Main.double 1\npop temp 0\nlabel STOP\n\
+         goto STOP\nfunction Main.double 0\npush argument 0\n\
+         push argument 0\nlt\npush argument 0\npush argument 0\nadd\n\
+         add\nreturn\n"
+            .to_string(),
+    );
+    for source in &sources {
+        let plain = run(source, 0);
+        let shared = run_with(
+            source,
+            Options {
+                shared_routines: true,
+                ..Options::default()
+            },
+        );
+        assert_eq!(
+            diverged(&plain, &shared),
+            Vec::<usize>::new(),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn optimize_size() {
+    for locals in 0..6 {
+        let source = format!(
+            "push constant 7\npop local 3\npush constant 9\ncall Main.sum 0\n\
+             pop temp 0\nlabel STOP\ngoto STOP\nfunction Main.sum {}\n\
+             push constant 0\npush constant 0\npush constant 0\n\
+             push constant 0\npush constant 0\nadd\nadd\nadd\nadd\nreturn\n",
+            locals
+        );
+        let plain = run(&source, 0);
+        for options in [
+            Options {
+                opt_level: 2,
+                optimize_size: true,
+                ..Options::default()
+            },
+            Options {
+                init_loop: Some(1),
+                ..Options::default()
+            },
+        ] {
+            let small = run_with(&source, options);
+            assert_eq!(
+                diverged(&plain, &small),
+                Vec::<usize>::new(),
+                "{}",
+                source
+            );
+        }
+    }
+}
+
+#[test]
+fn outline() {
+    let mut source = String::new();
+    for i in 0..12 {
+        source.push_str(&format!(
+            "push constant {}\npop local 2\npush local 5\npop static 3\n\
+             push local 5\npop static 3\n",
+            i
+        ));
+    }
+    let plain = run(&source, 0);
+    for opt_level in 0..=2 {
+        let outlined = run_with(
+            &source,
+            Options {
+                opt_level,
+                outline: true,
+                ..Options::default()
+            },
+        );
+        assert_eq!(
+            diverged(&plain, &outlined),
+            Vec::<usize>::new(),
+            "-O{}",
+            opt_level
+        );
+        assert!(outlined.rom().len() < run(&source, opt_level).rom().len());
+    }
+
+    // the return address needs somewhere to go
+    let mut translator =
+        Translator::from_source("Test", &source).with_options(Options {
+            outline: true,
+            scratch: vec![13, 14],
+            ..Options::default()
+        });
+    assert!(translator.process().is_err());
+}
+
+#[test]
+fn profile_guided() {
+    let profile = Profile::parse(
+        r#"{"functions": {
+            "Main.hot": {"calls": 9, "cycles": 950},
+            "Main.cold": {"calls": 1, "cycles": 50},
+            "Main.never": {"calls": 0, "cycles": 0}
+        }}"#,
+    )
+    .unwrap();
+    assert_eq!(Profile::parse(&profile.to_json()).unwrap(), profile);
+    let hot = profile.hot();
+    assert_eq!(hot, BTreeSet::from(["Main.hot".to_string()]));
+
+    let source = "push constant 4\ncall Main.cold 1\npop temp 0\n\
+                  label STOP\ngoto STOP\n\
+                  function Main.cold 3\npush argument 0\ncall Main.hot 1\n\
+                  push argument 0\neq\nreturn\n\
+                  function Main.hot 3\npush argument 0\npush argument 0\n\
+                  call Main.never 2\nreturn\n\
+                  function Main.never 0\npush argument 0\n\
+                  push argument 1\nadd\nreturn\n";
+    let options = Options {
+        hot: Some(hot.clone()),
+        ..Options::default()
+    };
+    let plain = run(source, 0);
+    let guided = run_with(source, options);
+    assert_eq!(diverged(&plain, &guided), Vec::<usize>::new());
+
+    // hot code inlines what cold code jumps to a shared routine for
+    let options = Options {
+        hot: Some(hot),
+        ..Options::default()
+    };
+    let mut translator =
+        Translator::from_source("Main", source).with_options(options);
+    translator.process().unwrap();
+    let shared: BTreeSet<&str> = translator
+        .asm()
+        .iter()
+        .filter(|asm| {
+            asm.bin
+                .iter()
+                .any(|line| line.to_string().starts_with("@__VM_"))
+        })
+        .filter_map(|asm| asm.function.as_deref())
+        .collect();
+    assert_eq!(shared, BTreeSet::from(["Main.cold", "Main.never"]));
+}
+
+#[test]
+fn ir_passes() {
+    let lines = |source: &str| -> Vec<Line> {
+        source.lines().map(|raw| Line::new(raw).unwrap()).collect()
+    };
+    let pass = |name, source| -> Vec<String> {
+        passes::run(name, &lines(source))
+            .unwrap()
+            .iter()
+            .map(|(line, at)| format!("{} @{}", line, at))
+            .collect()
+    };
+
+    // -1 is as close as a constant gets to a negative one
+    assert_eq!(
+        pass(
+            "fold-constants",
+            "push constant 2\npush constant 3\nadd\npush constant 9\nlt\n\
+             push local 0\nneg\n"
+        ),
+        ["push constant 0 @4", "not @4", "push local 0 @5", "neg @6"]
+    );
+    assert_eq!(
+        pass(
+            "peephole",
+            "push local 1\npop local 1\nnot\nnot\npush constant 1\n\
+             if-goto L\nlabel L\npush constant 0\nif-goto L\n"
+        ),
+        ["label L @6"]
+    );
+
+    for source in [
+        "push constant 30000\npush constant 30000\nadd\npush constant 1\n\
+         gt\npop temp 0\n",
+        "push constant 7\nneg\nnot\npush constant 3\nor\npop temp 1\n\
+         push temp 1\npop temp 1\n",
+        "push constant 0\nif-goto T\npush constant 4\nif-goto T\n\
+         push constant 5\npop temp 0\nlabel T\n",
+    ] {
+        assert_same(source);
+    }
+}
+
+#[test]
+fn dumps_after_passes() {
+    let source = "push constant 2\npush constant 3\nadd\nnot\nnot\n\
+                  pop temp 0\n";
+    let dump = |pass: &str| {
+        let options = Options {
+            opt_level: 1,
+            dump_after: Some((pass.to_string(), IrFormat::Text)),
+            ..Options::default()
+        };
+        let mut translator =
+            Translator::from_source("Main", source).with_options(options);
+        translator
+            .process()
+            .map(|_| translator.snapshots().to_vec())
+    };
+
+    let all = dump("all").unwrap();
+    assert_eq!(all.len(), 2);
+    // as VM code, so one pass's dump diffs against the next
+    assert_eq!(
+        all[0],
+        "// after fold-constants\n// Main\npush constant 5\npop temp 0\n"
+    );
+    assert_eq!(
+        all[1],
+        "// after peephole\n// Main\npush constant 5\npop temp 0\n"
+    );
+    assert_eq!(dump("peephole").unwrap(), all[1..]);
+    assert!(dump("inline").is_err());
+}
+
+#[test]
+fn statics_keep_their_addresses() {
+    // the first store to static 5 is dead, so -O2 drops it
+    let source = "push constant 1\npop static 5\npush constant 2\n\
+                  pop static 0\npush constant 3\npop static 5\n\
+                  push static 5\npush static 0\nadd\n";
+    let mem = |opt_level: u8| {
+        let options = Options {
+            opt_level,
+            static_addresses: true,
+            ..Options::default()
+        };
+        let mut translator =
+            Translator::from_source("Main", source).with_options(options);
+        translator.process().unwrap();
+        translator.render_mem().unwrap()
+    };
+    assert_eq!(mem(0), "16 Main.0\n17 Main.5\n");
+    assert_eq!(mem(2), mem(0));
+}
@@ -0,0 +1,75 @@
+// Codegen split across threads has to give what one thread gives.
+
+use n2t_vm_translator::translator::{Options, Translator, PARALLEL_MIN};
+
+// enough functions, each with a bit of everything, to be split
+fn program() -> String {
+    let mut source = String::from("push constant 1\npop static 0\n");
+    for i in 0.. {
+        if source.lines().count() > 2 * PARALLEL_MIN {
+            break;
+        }
+        source.push_str(&format!(
+            "function Main.f{i} 2\n\
+             push argument 0\npush constant {i}\nadd\npush constant 3\nlt\n\
+             if-goto L\npush local 0\npush local 1\neq\nnot\nassert\n\
+             label L\npush static {s}\npop local 0\npop local 0\n\
+             push constant 7\ncall Main.f{j} 1\npop temp 0\n\
+             push local 0\ngt\nhalt\nreturn\n",
+            i = i,
+            s = i % 9,
+            j = i / 2,
+        ));
+    }
+    source
+}
+
+fn render(source: &str, jobs: usize, options: impl Fn() -> Options) -> String {
+    let options = Options {
+        jobs: Some(jobs),
+        ..options()
+    };
+    let mut translator =
+        Translator::from_source("Main", source).with_options(options);
+    translator.process().unwrap();
+    translator.render()
+}
+
+#[test]
+fn threads_give_the_same_asm() {
+    let source = program();
+    for opt_level in 0..=2 {
+        for shared_routines in [false, true] {
+            let options = || Options {
+                opt_level,
+                shared_routines,
+                extensions: true,
+                ..Options::default()
+            };
+            assert_eq!(
+                render(&source, 1, options),
+                render(&source, 8, options),
+                "-O{} shared routines {}",
+                opt_level,
+                shared_routines
+            );
+        }
+    }
+}
+
+#[test]
+fn threads_give_the_same_stats() {
+    let source = program();
+    let stats = |jobs| {
+        let options = Options {
+            jobs: Some(jobs),
+            extensions: true,
+            ..Options::default()
+        };
+        let mut translator =
+            Translator::from_source("Main", &source).with_options(options);
+        translator.process().unwrap();
+        format!("{:?}", translator.stats())
+    };
+    assert_eq!(stats(1), stats(8));
+}
@@ -0,0 +1,85 @@
+use n2t_vm_translator::parser::Parser;
+#[cfg(feature = "plugins")]
+use n2t_vm_translator::translator::{Options, Translator};
+
+#[test]
+fn ir_json_round_trip() {
+    let mut parser = Parser::new("Main".to_string(), "Main.vm".to_string());
+    for line in [
+        "function Main.f 2\n",
+        "push local 1\n",
+        "if-goto END\n",
+        "call Main.g 3\n",
+        "label END\n",
+        "return\n",
+    ] {
+        parser.parse_line(line).unwrap();
+    }
+    let read = Parser::from_json(&parser.to_json()).unwrap();
+    assert_eq!(read.lines(), parser.lines());
+    assert_eq!(read.spans(), parser.spans());
+    assert_eq!(read.to_json(), parser.to_json());
+}
+
+// a pass that turns every add into a sub, and says so
+#[cfg(feature = "plugins")]
+const PLUGIN: &str = r#"
+#include <stdlib.h>
+#include <string.h>
+
+char *n2t_pass(const char *ir) {
+    const char *head = "{\"files\":";
+    const char *tail = ",\"diagnostics\":[{\"severity\":\"warning\","
+        "\"message\":\"adds are subs now\",\"file\":\"Main.vm\",\"line\":3}]}";
+    char *out = malloc(strlen(head) + strlen(ir) + strlen(tail) + 1);
+    strcpy(out, head);
+    strcat(out, ir);
+    strcat(out, tail);
+    for (char *at = out; (at = strstr(at, "\"add\"")); at++)
+        memcpy(at, "\"sub\"", 5);
+    return out;
+}
+
+void n2t_free(char *result) {
+    free(result);
+}
+"#;
+
+#[cfg(feature = "plugins")]
+#[test]
+fn plugin_rewrites_ir() {
+    use std::{fs, path::Path, process::Command};
+
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("plugin");
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("swap.c");
+    let library = dir.join("libswap.so");
+    fs::write(&source, PLUGIN).unwrap();
+    let status = Command::new("cc")
+        .args(["-shared", "-fPIC", "-o"])
+        .arg(&library)
+        .arg(&source)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let options = Options {
+        plugins: vec![library],
+        retain_ir: true,
+        ..Options::default()
+    };
+    let mut translator = Translator::from_source(
+        "Main",
+        "push constant 7\npush constant 8\nadd\n",
+    )
+    .with_options(options);
+    translator.process().unwrap();
+
+    assert_eq!(translator.lines().last().unwrap().to_string(), "sub");
+    let warnings = translator.warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(
+        warnings[0].to_string(),
+        "Main.vm:3: warning: adds are subs now"
+    );
+}
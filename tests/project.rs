@@ -0,0 +1,206 @@
+use std::{fs, path::Path};
+
+use n2t_vm_translator::{
+    lint::Level,
+    project::{self, Project},
+    translator::{Comments, Options, Order, Translator},
+};
+
+#[test]
+fn parses_every_key() {
+    let project = Project::parse(
+        r#"
+        bootstrap = false
+        opt-level = 2
+        comments = "none"
+        end-loop = true
+        max-errors = 5
+        exclude = ["*Test.vm", "Scratch.vm"]
+        budget = 300
+        passes = ["peephole", "fold-constants"]
+
+        [budgets]
+        "Main.main" = 1000
+
+        [lints]
+        warnings = "deny"
+        unreachable-code = "allow"
+        "#,
+    )
+    .unwrap();
+
+    let mut options = Options::default();
+    project.apply(&mut options, None).unwrap();
+    assert_eq!(options.bootstrap, Some(false));
+    assert_eq!(options.opt_level, 2);
+    assert_eq!(options.comments, Comments::None);
+    assert!(options.end_loop);
+    assert_eq!(options.max_errors, 5);
+    assert_eq!(options.exclude, ["*Test.vm", "Scratch.vm"]);
+    assert_eq!(options.budget, Some(300));
+    assert_eq!(options.budgets["Main.main"], 1000);
+    assert_eq!(options.passes(), ["peephole", "fold-constants"]);
+    assert_eq!(options.lints["warnings"], Level::Deny);
+    assert_eq!(options.lints["unreachable-code"], Level::Allow);
+}
+
+#[test]
+fn profiles_override_defaults() {
+    let project = Project::parse(
+        r#"
+        comments = "canonical"
+        max-errors = 5
+
+        [profile.ci]
+        max-errors = 0
+        end-loop = true
+        "#,
+    )
+    .unwrap();
+
+    let mut options = Options::default();
+    project.apply(&mut options, Some("ci")).unwrap();
+    assert_eq!(options.comments, Comments::Canonical);
+    assert_eq!(options.max_errors, 0);
+    assert!(options.end_loop);
+
+    // built in unless the file redefines them
+    let mut options = Options::default();
+    project.apply(&mut options, Some("release")).unwrap();
+    assert_eq!(options.opt_level, 2);
+    assert_eq!(options.comments, Comments::None);
+
+    assert!(project
+        .apply(&mut Options::default(), Some("fast"))
+        .is_err());
+    assert!(Project::parse("[profile.ci]\nboostrap = true").is_err());
+}
+
+#[test]
+fn rejects_unknown_keys_and_bad_values() {
+    assert!(Project::parse("boostrap = true").is_err());
+    assert!(Project::parse("opt-level = 3").is_err());
+    assert!(Project::parse("comments = \"all\"").is_err());
+    assert!(Project::parse("blank-lines = 99999999999").is_err());
+    assert!(Project::parse("exclude = \"*.vm\"").is_err());
+    assert!(Project::parse("passes = [\"inline\"]").is_err());
+    assert!(Project::parse("[lints]\nunused = \"deny\"").is_err());
+    assert!(Project::parse("[lints]\nwarnings = \"forbid\"").is_err());
+}
+
+#[test]
+fn matches_wildcards() {
+    assert!(project::matches("*Test.vm", "MainTest.vm"));
+    assert!(project::matches("*Test.vm", "Test.vm"));
+    assert!(project::matches("Sys?.vm", "Sys2.vm"));
+    assert!(project::matches("*", "Main.vm"));
+    assert!(!project::matches("*Test.vm", "Main.vm"));
+    assert!(!project::matches("Sys?.vm", "Sys.vm"));
+}
+
+#[test]
+fn excluded_files_are_left_out() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("exclude");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for file in ["Main.vm", "MainTest.vm", "Scratch.vm"] {
+        fs::write(dir.join(file), "function Main.main 0\nreturn\n").unwrap();
+    }
+
+    let translate = |exclude: &[&str]| {
+        let options = Options {
+            exclude: exclude.iter().map(|p| p.to_string()).collect(),
+            ..Options::default()
+        };
+        let mut translator = Translator::open(&dir, options)?;
+        translator.process()?;
+        Ok::<_, anyhow::Error>(translator.stats().files.len())
+    };
+    assert_eq!(translate(&[]).unwrap(), 3);
+    assert_eq!(translate(&["*Test.vm", "Scratch.vm"]).unwrap(), 1);
+    assert!(translate(&["*.vm"]).is_err());
+}
+
+#[test]
+fn files_go_into_rom_in_order() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("order");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    for file in ["Main.vm", "Sys.vm", "lib/Array.vm", "lib/Zeta.vm"] {
+        let name = Path::new(file).file_stem().unwrap().to_string_lossy();
+        let source = format!("function {}.f 0\nreturn\n", name);
+        fs::write(dir.join(file), source).unwrap();
+    }
+
+    let files = |order: Order, manifest: &[&str]| {
+        let options = Options {
+            order,
+            manifest: manifest.iter().map(|name| name.to_string()).collect(),
+            ..Options::default()
+        };
+        let mut translator = Translator::open(dir.join("**/*.vm"), options)?;
+        translator.process()?;
+        Ok::<_, anyhow::Error>(
+            translator
+                .stats()
+                .files
+                .iter()
+                .map(|file| file.file.clone())
+                .collect::<Vec<_>>(),
+        )
+    };
+    let order = |order| files(order, &[]).unwrap();
+    assert_eq!(order(Order::Input), ["Main", "Sys", "Array", "Zeta"]);
+    assert_eq!(order(Order::Alpha), ["Array", "Main", "Sys", "Zeta"]);
+    assert_eq!(order(Order::SysFirst), ["Sys", "Array", "Main", "Zeta"]);
+    assert_eq!(
+        files(Order::Manifest, &["Zeta.vm", "Sys.vm"]).unwrap(),
+        ["Zeta", "Sys", "Main", "Array"]
+    );
+    assert!(files(Order::Manifest, &[]).is_err());
+    assert!(files(Order::Manifest, &["Missing.vm"]).is_err());
+
+    let project = Project::parse(
+        "order = \"manifest\"\nmanifest = [\"Sys.vm\", \"Main.vm\"]",
+    )
+    .unwrap();
+    let mut options = Options::default();
+    project.apply(&mut options, None).unwrap();
+    assert_eq!(options.order, Order::Manifest);
+    assert_eq!(options.manifest, ["Sys.vm", "Main.vm"]);
+}
+
+#[test]
+fn cross_references() {
+    let options = Options {
+        retain_ir: true,
+        ..Options::default()
+    };
+    let mut translator =
+        Translator::open("tests/fixtures/Program", options).unwrap();
+    translator.process().unwrap();
+    let xref = translator.xref().unwrap();
+
+    let (defined, called) = &xref.functions["Main.square"];
+    assert_eq!(defined, &["tests/fixtures/Program/Main.vm:1"]);
+    assert_eq!(called, &["tests/fixtures/Program/Sys.vm:4"]);
+    assert!(xref.functions["Sys.init"].1.is_empty());
+
+    let (read, written) = &xref.statics[&("Main".to_string(), 0)];
+    assert!(read.contains("Main.square") && written.contains("Main.square"));
+    let (read, written) = &xref.statics[&("Sys".to_string(), 0)];
+    assert!(read.is_empty() && written.contains("Sys.init"));
+
+    let markdown = xref.to_markdown();
+    assert!(
+        markdown.contains("| Sys.0 | - | Sys.init |"),
+        "{}",
+        markdown
+    );
+
+    // without the IR there's nothing to cross-reference
+    let mut translator =
+        Translator::open("tests/fixtures/Program", Options::default()).unwrap();
+    translator.process().unwrap();
+    assert!(translator.xref().is_err());
+}
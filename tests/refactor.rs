@@ -0,0 +1,59 @@
+use std::{fs, path::Path};
+
+use n2t_vm_translator::{
+    refactor,
+    translator::{Options, Translator},
+};
+
+fn parsed(dir: &Path) -> Translator {
+    let mut translator = Translator::open(dir, Options::default()).unwrap();
+    translator.parse().unwrap();
+    translator
+}
+
+#[test]
+fn rename() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("rename");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("Main.vm"),
+        "// squares its argument\nfunction Main.square 0\n\
+         \tpush argument 0\n  call   Main.multiply  1 // x * x\nreturn\n\
+         function Main.multiply 0\npush argument 0\nreturn\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("Sys.vm"),
+        "function Sys.init 0\npush constant 4\ncall Main.square 1\n\
+         label END\ngoto END\n",
+    )
+    .unwrap();
+    fs::write(dir.join("Other.vm"), "function Other.f 0\nreturn\n").unwrap();
+
+    let changes =
+        refactor::rename(&parsed(&dir), "Main.square", "Math.square").unwrap();
+    let changed: Vec<_> = changes
+        .iter()
+        .map(|(path, _)| path.file_name().unwrap().to_str().unwrap())
+        .collect();
+    assert_eq!(changed, ["Main.vm", "Sys.vm"]);
+    assert_eq!(
+        changes[0].1,
+        "// squares its argument\nfunction Math.square 0\n\
+         \tpush argument 0\n  call   Main.multiply  1 // x * x\nreturn\n\
+         function Main.multiply 0\npush argument 0\nreturn\n",
+    );
+
+    // formatting and comments around the name are left alone
+    let changes =
+        refactor::rename(&parsed(&dir), "Main.multiply", "Main.times").unwrap();
+    assert_eq!(changes.len(), 1);
+    assert!(changes[0].1.contains("  call   Main.times  1 // x * x\n"));
+    assert!(changes[0].1.contains("function Main.times 0\n"));
+
+    let translator = parsed(&dir);
+    assert!(refactor::rename(&translator, "Main.missing", "Main.x").is_err());
+    assert!(refactor::rename(&translator, "Main.square", "Sys.init").is_err());
+    assert!(refactor::rename(&translator, "Main.square", "not ok").is_err());
+}
@@ -0,0 +1,123 @@
+// Golden asm for the .vm files in tests/fixtures. After an intended codegen
+// change, review and accept the new output with `cargo insta review`.
+
+use std::path::Path;
+
+use n2t_vm_translator::{
+    assembler,
+    style::{Spacing, Style},
+    translator::{Addresses, Options, Translator},
+};
+
+fn translate(path: &Path) -> String {
+    translate_with(path, Options::default())
+}
+
+fn translate_with(path: &Path, options: Options) -> String {
+    let mut translator = Translator::open(path, options).unwrap();
+    translator.process().unwrap();
+    translator.render()
+}
+
+#[test]
+fn files() {
+    insta::glob!("fixtures/*.vm", |path| {
+        insta::assert_snapshot!(translate(path));
+    });
+}
+
+#[test]
+fn program() {
+    let path =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/Program");
+    insta::assert_snapshot!(translate(&path));
+}
+
+#[test]
+fn program_optimized() {
+    let path =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/Program");
+    let options = Options {
+        opt_level: 1,
+        ..Options::default()
+    };
+    insta::assert_snapshot!(translate_with(&path, options));
+}
+
+#[test]
+fn program_explained() {
+    let path =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/Program");
+    let options = Options {
+        explain: true,
+        ..Options::default()
+    };
+    insta::assert_snapshot!(translate_with(&path, options));
+}
+
+// nothing but instructions, which assemble to what the commented output
+// does
+#[test]
+fn program_minified() {
+    let path =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/Program");
+    let options = Options {
+        minify: true,
+        preserve_comments: true,
+        addresses: Addresses::Instruction,
+        ..Options::default()
+    };
+    let minified = translate_with(&path, options);
+    assert_eq!(
+        assembler::assemble(&minified).unwrap().words,
+        assembler::assemble(&translate(&path)).unwrap().words
+    );
+    insta::assert_snapshot!(minified);
+}
+
+#[test]
+fn program_styled() {
+    let path =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/Program");
+    let options = Options {
+        style: Style {
+            dest: Spacing::Spaced,
+            jump: Spacing::Tight,
+            indent: true,
+            blank_lines: Some(1),
+        },
+        ..Options::default()
+    };
+    let mut translator = Translator::open(&path, options).unwrap();
+    translator.process().unwrap();
+    let styled = translator.render();
+    assert_eq!(
+        assembler::assemble(&styled).unwrap().words,
+        assembler::assemble(&translate(&path)).unwrap().words
+    );
+    // the map still points at each block's comment
+    let lines: Vec<&str> = styled.lines().collect();
+    for (origin, asm) in translator.origins().iter().zip(translator.asm()) {
+        assert_eq!(lines[origin.lines.start - 1], asm.src);
+    }
+    insta::assert_snapshot!(styled);
+}
+
+// an edit to one function leaves the names generated in another alone
+#[test]
+fn stable_names() {
+    let render = |first: &str| {
+        let source = format!(
+            "function Main.first 0\n{}push constant 1\nreturn\n\
+             function Main.second 0\npush constant 1\npush constant 2\n\
+             lt\ncall Main.first 0\nreturn\n",
+            first
+        );
+        let mut translator = Translator::from_source("Main", &source);
+        translator.process().unwrap();
+        let asm = translator.render();
+        let second = asm.find("(Main.second)").unwrap();
+        asm[second..].to_string()
+    };
+    assert_eq!(render(""), render("push constant 0\npop temp 0\n"));
+}
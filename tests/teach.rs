@@ -0,0 +1,53 @@
+use n2t_vm_translator::{
+    interpreter::Interpreter,
+    teach,
+    translator::{Options, Translator},
+};
+
+fn parse(path: &str) -> Translator {
+    let mut translator = Translator::open(path, Options::default()).unwrap();
+    translator.parse().unwrap();
+    translator
+}
+
+#[test]
+fn interpreter() {
+    let translator =
+        parse("tests/official/projects/08/FunctionCalls/FibonacciElement");
+    let mut vm = Interpreter::new(translator.parsers()).unwrap();
+    while vm.step().unwrap() {}
+    assert_eq!(vm.stopped(), Some("looping for good"));
+    assert_eq!(vm.ram[0], 262);
+    assert_eq!(vm.ram[261], 3);
+
+    // without Sys.init, from the first command with the segments set
+    let translator = parse("tests/official/projects/07/MemoryAccess/BasicTest");
+    let mut vm = Interpreter::new(translator.parsers()).unwrap();
+    while vm.step().unwrap() {}
+    assert_eq!(vm.stopped(), Some("ran past the last command"));
+    assert_eq!(vm.ram[256], 472);
+    assert_eq!(vm.ram[11], 510);
+}
+
+#[test]
+fn diagrams() {
+    let translator =
+        parse("tests/official/projects/07/StackArithmetic/SimpleAdd");
+    let mut out = Vec::new();
+    teach::run(translator.parsers(), 100, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    let after_add = out.split("\n\n").nth(3).unwrap();
+    let lines: Vec<&str> = after_add.lines().collect();
+    assert!(lines[0].starts_with("3. add  ("));
+    assert_eq!(
+        lines[1..],
+        [
+            "        +--------+",
+            "    256 |     15 |",
+            "        +--------+",
+            "  SP=257 LCL=300 ARG=400 THIS=3000 THAT=3010",
+        ]
+    );
+    assert!(out.ends_with("stopped: ran past the last command\n"));
+}
@@ -0,0 +1,62 @@
+use n2t_vm_translator::{
+    assembler,
+    emulator::Cpu,
+    trace::{Replay, Trace},
+};
+
+// RAM[16] counts down from 3, and RAM[17] is set once it gets to 0
+const ASM: &str = "@3\nD=A\n@16\nM=D\n(AGAIN)\n@16\nMD=M-1\n@AGAIN\nD; JGT\n\
+                   @17\nM=-1\n(END)\n@END\n0; JMP\n";
+
+fn recorded() -> Cpu {
+    let mut cpu = Cpu::new(assembler::assemble(ASM).unwrap().words);
+    cpu.ram[0] = 256;
+    cpu.record();
+    cpu.run(100);
+    cpu
+}
+
+#[test]
+fn round_trip() {
+    let cpu = recorded();
+    let trace = cpu.trace().unwrap();
+    assert_eq!(trace.entries.len() as u64, cpu.cycles);
+    assert_eq!(trace.entries[0].pc, 0);
+    assert!(trace.entries.iter().all(|entry| entry.sp == 256));
+
+    let bytes = trace.to_bytes();
+    assert_eq!(&Trace::from_bytes(&bytes).unwrap(), trace);
+    assert!(Trace::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert!(Trace::from_bytes(b"trace").is_err());
+}
+
+#[test]
+fn replay() {
+    let cpu = recorded();
+    let trace = cpu.trace().unwrap();
+    let changes: Vec<(i16, i16)> = trace
+        .changes(16)
+        .map(|(_, entry)| entry.write.unwrap())
+        .map(|(_, old, new)| (old, new))
+        .collect();
+    assert_eq!(changes, [(0, 3), (3, 2), (2, 1), (1, 0)]);
+
+    let mut replay = Replay::new(trace);
+    replay.forward(usize::MAX);
+    assert_eq!(replay.at(), trace.entries.len());
+    assert_eq!(replay.ram(), &cpu.ram[..]);
+
+    // back to just after RAM[16] went from 2 to 1
+    let (step, _) = trace.changes(16).nth(2).unwrap();
+    replay.seek(step + 1);
+    assert_eq!(replay.ram()[16], 1);
+    assert_eq!(replay.ram()[17], 0);
+    replay.back(1);
+    assert_eq!(replay.ram()[16], 2);
+
+    replay.back(usize::MAX);
+    assert_eq!(replay.at(), 0);
+    assert_eq!(replay.entry(), None);
+    assert_eq!(replay.ram()[0], 256);
+    assert!(replay.ram()[1..].iter().all(|&word| word == 0));
+}
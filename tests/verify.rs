@@ -0,0 +1,132 @@
+use std::collections::BTreeSet;
+
+use n2t_vm_translator::{
+    instruction::Instruction,
+    parser::{Line, Parser, Segment, StackToken},
+    translator::{Options, Translator},
+    verify::{check_ir, check_line, undefined_symbols},
+};
+
+#[test]
+fn accepts_the_hack_grammar() {
+    for line in &[
+        "@17",
+        "@32767",
+        "@Main.loop$ret.3",
+        "(Sys.init)",
+        "AM=M-1",
+        "D=D|M",
+        "0; JMP",
+        "D;JGE",
+        "M=-1 // comment",
+        "",
+    ] {
+        assert!(check_line(line).is_ok(), "{}", line);
+    }
+}
+
+#[test]
+fn rejects_what_the_assembler_would() {
+    for line in &[
+        "@32768",
+        "@1abc",
+        "@a-b",
+        "(unclosed",
+        "MA=D",
+        "D=1+D",
+        "D=M+D",
+        "0;JMPS",
+        "=D",
+        "D=",
+    ] {
+        assert!(check_line(line).is_err(), "{}", line);
+    }
+}
+
+// used to generate @40000, which no assembler takes
+#[test]
+fn large_indices_are_rejected_at_parse() {
+    let mut translator = Translator::from_source("Large", "push local 40000\n");
+    assert!(translator.parse().is_err());
+}
+
+#[test]
+fn checks_ir_invariants() {
+    let file = |name: &str, lines: &[Line]| {
+        Parser::from_lines(name.to_string(), lines.to_vec())
+    };
+    let line = |raw| Line::new(raw).unwrap();
+    let main = file(
+        "Main",
+        &[
+            line("function Main.f 0"),
+            line("label L"),
+            line("label L"),
+            line("goto M"),
+            line("call Sys.g 0"),
+            line("call Main.f 0"),
+            // built directly, so never validated
+            Line::Stack(StackToken::Pop {
+                segment: Segment::Temp,
+                index: 9,
+            }),
+        ],
+    );
+    let problems: Vec<String> = check_ir(&[main]).into_iter().collect();
+    assert_eq!(
+        problems,
+        [
+            "Main.f: `pop temp 9`: temp index out of range: 9 (max 7)",
+            "Main.f: label L is defined twice",
+            "Main.f: no label M to jump to",
+            "call to undefined function Sys.g",
+        ]
+    );
+
+    // the passes keep a sound program sound
+    let options = Options {
+        opt_level: 2,
+        verify_ir: true,
+        ..Options::default()
+    };
+    let mut translator =
+        Translator::open("tests/fixtures/Program", options).unwrap();
+    translator.process().unwrap();
+}
+
+#[test]
+fn finds_undefined_symbols() {
+    let variables = BTreeSet::from(["Main.0".to_string()]);
+    let first: Vec<Instruction> = vec![
+        "@LOOP".into(),
+        "0; JMP".into(),
+        "@Main.0".into(),
+        "@17".into(),
+    ];
+    let second: Vec<Instruction> = vec![
+        "(LOOP)".into(),
+        "@R13".into(),
+        "@END".into(),
+        "D; JGT".into(),
+    ];
+    assert_eq!(
+        undefined_symbols(&[&first, &second], &variables),
+        [(1, "END")]
+    );
+
+    // what codegen makes is all defined, whatever the options
+    for (opt_level, shared_routines, outline) in
+        [(0, false, false), (2, true, false), (1, false, true)]
+    {
+        let options = Options {
+            opt_level,
+            shared_routines,
+            outline,
+            validate_labels: true,
+            ..Options::default()
+        };
+        let mut translator =
+            Translator::open("tests/fixtures/Program", options).unwrap();
+        translator.process().unwrap();
+    }
+}